@@ -0,0 +1,250 @@
+use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::connection::KeepAlive;
+
+const USAGE: &str = "Usage: web-server [--config FILE] [--bind ADDR:PORT] [--threads N] \
+                     [--max-threads N] [--queue N] [--root DIR] [--keepalive-timeout SECS] \
+                     [--request-timeout SECS] [--max-requests N]";
+
+/// Server settings, from a config file and command line flags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Address to listen on
+    pub bind: SocketAddr,
+    /// Number of worker threads, each serving one connection at a time
+    pub threads: usize,
+    /// More threads are started up to this many when all are busy, and
+    /// stopped once idle. `None` keeps `threads` running.
+    pub max_threads: Option<usize>,
+    /// Connections waiting for a thread; more are answered with 503
+    pub queue: usize,
+    /// Directory of the static files
+    pub root: PathBuf,
+    /// Idle connections are closed after this long
+    pub keepalive_timeout: Duration,
+    /// Requests must be received whole within this long of their first
+    /// byte
+    pub request_timeout: Duration,
+    /// Connections are closed after serving this many requests
+    pub max_requests: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: SocketAddr::from(([127, 0, 0, 1], 7878)),
+            threads: 4,
+            max_threads: None,
+            queue: 64,
+            root: PathBuf::from("public"),
+            keepalive_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+            max_requests: 100,
+        }
+    }
+}
+
+impl Config {
+    /// Build the config from the command line arguments, the first one
+    /// being the command.
+    ///
+    /// Each `--name value` flag (or `--name=value`) sets the directive of
+    /// the same name. `--config FILE` reads a config file first, wherever
+    /// it is given, so flags override the file.
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+        // Discard the command
+        args.next();
+
+        let mut file = None;
+        let mut flags = Vec::new();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(USAGE.to_string());
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("Unknown argument: {arg}\n{USAGE}"));
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("Missing value for --{flag}"))?;
+                    (flag.to_string(), value)
+                }
+            };
+            if name == "config" {
+                file = Some(value);
+            } else {
+                flags.push((name, value));
+            }
+        }
+
+        let mut config = match file {
+            Some(path) => Config::from_file(&path)?,
+            None => Config::default(),
+        };
+        for (name, value) in flags {
+            config
+                .set(&name, &value)
+                .map_err(|err| format!("--{name}: {err}"))?;
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Read a config file: one `directive value` per line, `#` starting a
+    /// comment. Unset directives keep their default. Directives that depend
+    /// on each other are checked by `build`, once the flags are applied.
+    pub fn from_file(path: &str) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+        let mut config = Config::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            config
+                .set(name, value.trim())
+                .map_err(|err| format!("{path}:{}: {err}", number + 1))?;
+        }
+        Ok(config)
+    }
+
+    /// Set the directive `name` from its textual `value`.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "bind" => {
+                self.bind = value
+                    .parse()
+                    .map_err(|_| format!("invalid address {value:?}, expected ADDR:PORT"))?
+            }
+            "threads" => self.threads = parse_positive(value)?,
+            "max-threads" => self.max_threads = Some(parse_positive(value)?),
+            "queue" => {
+                self.queue = value
+                    .parse()
+                    .map_err(|_| format!("expected a number, got {value:?}"))?
+            }
+            "root" => self.root = PathBuf::from(value),
+            "keepalive-timeout" => {
+                self.keepalive_timeout = Duration::from_secs(parse_positive(value)? as u64)
+            }
+            "request-timeout" => {
+                self.request_timeout = Duration::from_secs(parse_positive(value)? as u64)
+            }
+            "max-requests" => self.max_requests = parse_positive(value)?,
+            _ => return Err(format!("unknown directive {name:?}")),
+        }
+        Ok(())
+    }
+
+    /// Check the directives that depend on each other.
+    fn check(&self) -> Result<(), String> {
+        if self.max_threads() < self.threads {
+            return Err("max-threads can't be below threads".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn max_threads(&self) -> usize {
+        self.max_threads.unwrap_or(self.threads)
+    }
+
+    pub fn keep_alive(&self) -> KeepAlive {
+        KeepAlive::new()
+            .timeout(self.keepalive_timeout)
+            .request_timeout(self.request_timeout)
+            .max_requests(self.max_requests)
+    }
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) | Err(_) => Err(format!("expected a positive number, got {value:?}")),
+        Ok(number) => Ok(number),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &str) -> impl Iterator<Item = String> + '_ {
+        command.split_whitespace().map(|arg| arg.to_string())
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        assert_eq!(Config::build(args("web-server")), Ok(Config::default()));
+
+        let config = Config::build(args(
+            "web-server --bind 0.0.0.0:8080 --threads=8 --queue 0 --root /srv/www --max-requests 1",
+        ))
+        .unwrap();
+        assert_eq!(config.bind, SocketAddr::from(([0, 0, 0, 0], 8080)));
+        assert_eq!(config.threads, 8);
+        assert_eq!(config.queue, 0);
+        assert_eq!(config.max_threads(), 8);
+        assert_eq!(config.root, PathBuf::from("/srv/www"));
+        assert_eq!(config.max_requests, 1);
+        assert_eq!(config.keepalive_timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let path = std::env::temp_dir().join(format!("web-server-{}.conf", std::process::id()));
+        fs::write(
+            &path,
+            "# Public server\nbind 0.0.0.0:80\n\nthreads 16\nmax-threads 16\nkeepalive-timeout 30\nrequest-timeout 60\n",
+        )
+        .unwrap();
+
+        let command = format!("web-server --threads 2 --config {}", path.display());
+        let config = Config::build(args(&command)).unwrap();
+        assert_eq!(config.bind, SocketAddr::from(([0, 0, 0, 0], 80)));
+        assert_eq!(config.threads, 2);
+        assert_eq!(config.max_threads(), 16);
+        assert_eq!(config.keepalive_timeout, Duration::from_secs(30));
+        assert_eq!(config.request_timeout, Duration::from_secs(60));
+
+        // Only the merged settings must be consistent
+        fs::write(&path, "max-threads 2\n").unwrap();
+        let command = format!("web-server --config {} --threads 1", path.display());
+        let config = Config::build(args(&command)).unwrap();
+        assert_eq!((config.threads, config.max_threads()), (1, 2));
+        let command = format!("web-server --config {}", path.display());
+        let err = Config::build(args(&command)).unwrap_err();
+        assert_eq!(err, "max-threads can't be below threads");
+
+        fs::write(&path, "threads 4\nport 80\n").unwrap();
+        let err = Config::from_file(path.to_str().unwrap()).unwrap_err();
+        assert!(err.ends_with(":2: unknown directive \"port\""), "{err}");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_flags_are_rejected() {
+        for (command, expected) in [
+            (
+                "web-server --threads 0",
+                "--threads: expected a positive number",
+            ),
+            ("web-server --bind localhost", "--bind: invalid address"),
+            (
+                "web-server --threads 8 --max-threads 4",
+                "max-threads can't be below threads",
+            ),
+            ("web-server --threads", "Missing value for --threads"),
+            ("web-server public", "Unknown argument: public"),
+            ("web-server --port 80", "--port: unknown directive"),
+            ("web-server --help", "Usage:"),
+        ] {
+            let err = Config::build(args(command)).unwrap_err();
+            assert!(err.starts_with(expected), "{command}: {err}");
+        }
+    }
+}
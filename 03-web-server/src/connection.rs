@@ -0,0 +1,558 @@
+use std::{
+    io::{self, BufReader, Read},
+    net::{Shutdown, TcpStream},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    request::{Method, ParseError, Request, Version},
+    response::Response,
+    router::Router,
+};
+
+/// How long connections are kept open between requests, and for how many.
+/// Also how long clients have to send a whole request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    timeout: Duration,
+    request_timeout: Duration,
+    max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive::new()
+    }
+}
+
+impl KeepAlive {
+    /// Close idle connections after 5 seconds, and any after 100 requests.
+    /// Requests must be received within 10 seconds of their first byte.
+    pub fn new() -> KeepAlive {
+        KeepAlive {
+            timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+            max_requests: 100,
+        }
+    }
+
+    /// Close connections that stay idle for `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `timeout` is zero.
+    pub fn timeout(mut self, timeout: Duration) -> KeepAlive {
+        assert!(!timeout.is_zero(), "the keep-alive timeout can't be zero");
+        self.timeout = timeout;
+        self
+    }
+
+    /// Answer `408 Request Timeout` and close the connection when a request
+    /// isn't received whole within `timeout` of its first byte, however
+    /// slowly it trickles in. Also close it when a write of the response
+    /// waits on the client for longer than `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `timeout` is zero.
+    pub fn request_timeout(mut self, timeout: Duration) -> KeepAlive {
+        assert!(!timeout.is_zero(), "the request timeout can't be zero");
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Close connections after they served `max_requests` requests. With
+    /// 1, every connection serves a single request.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_requests` is zero.
+    pub fn max_requests(mut self, max_requests: usize) -> KeepAlive {
+        assert!(
+            max_requests > 0,
+            "connections must serve at least one request"
+        );
+        self.max_requests = max_requests;
+        self
+    }
+}
+
+/// Answer the requests sent over `stream` with `router`, until the client
+/// or the limits of `keep_alive` close it.
+///
+/// HTTP/1.1 connections stay open unless a request says `Connection:
+/// close`, HTTP/1.0 ones only if a request asks for `keep-alive`. Requests
+/// sent without waiting for the previous response are answered in order.
+/// A handler can close the connection by setting `Connection: close`.
+/// Requests not received whole in time are answered with `408 Request
+/// Timeout`, and the connection closed. So are clients that stop reading
+/// their response.
+pub fn handle_connection(stream: TcpStream, router: &Router, keep_alive: &KeepAlive) {
+    if let Err(err) = stream.set_write_timeout(Some(keep_alive.request_timeout)) {
+        println!("Failed to set up the connection: {err}");
+        return;
+    }
+    let mut reader = BufReader::new(TimedReader {
+        stream: &stream,
+        keep_alive,
+        deadline: None,
+    });
+    let mut writer = &stream;
+
+    for served in 1..=keep_alive.max_requests {
+        // Unless a pipelined request already started arriving, the next one
+        // starts with the client's next byte
+        let started = !reader.buffer().is_empty();
+        reader.get_mut().deadline = started.then(|| Instant::now() + keep_alive.request_timeout);
+
+        let request = match Request::read_from(&mut reader) {
+            Ok(Some(request)) => request,
+            // Closed without sending anything more
+            Ok(None) => return,
+            Err(ParseError::BadRequest(reason)) => {
+                println!("Bad request: {reason}");
+                let response = Response::new(400).header("Connection", "close");
+                let _ = response.write_to(&mut writer, &Method::Get);
+                return;
+            }
+            // Idle, or too slow to send the request
+            Err(ParseError::Io(err)) if is_timeout(&err) => {
+                if reader.get_ref().deadline.is_some() {
+                    println!("Request timed out");
+                    let response = Response::new(408).header("Connection", "close");
+                    let _ = response.write_to(&mut writer, &Method::Get);
+                }
+                return;
+            }
+            Err(ParseError::Io(err)) => {
+                println!("Failed to read request: {err}");
+                return;
+            }
+        };
+        println!("Request: {} {}", request.method(), request.target());
+
+        let mut response = router.handle(&request);
+        let close = served == keep_alive.max_requests
+            || !wants_keep_alive(&request)
+            || response.headers().has_token("Connection", "close");
+        let headers = response.headers_mut();
+        if close {
+            headers.set("Connection", "close");
+        } else {
+            if request.version() == Version::Http10 {
+                headers.set("Connection", "keep-alive");
+            }
+            let remaining = keep_alive.max_requests - served;
+            let timeout = keep_alive.timeout.as_secs().max(1);
+            headers.set("Keep-Alive", format!("timeout={timeout}, max={remaining}"));
+        }
+
+        if let Err(err) = response.write_to(&mut writer, request.method()) {
+            println!("Failed to write response: {err}");
+            return;
+        }
+        if close {
+            return;
+        }
+    }
+}
+
+/// How long a refused connection is drained before it is closed.
+const LINGER: Duration = Duration::from_millis(250);
+
+/// How often the refused connections are drained.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Refused connections waiting to be drained. More are closed at once.
+const MAX_LINGERING: usize = 1024;
+
+/// Answers connections that can't be served, and closes them.
+///
+/// Closing a socket with unread input resets the connection, which can make
+/// the client drop the response. So the write side is shut down first, and
+/// the request is read and discarded until the client closes too, for a
+/// short while at most. That draining happens on a thread of its own, so
+/// the thread refusing connections never waits on clients.
+pub struct Refuser {
+    sender: Option<mpsc::SyncSender<TcpStream>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Default for Refuser {
+    fn default() -> Self {
+        Refuser::new()
+    }
+}
+
+impl Refuser {
+    /// Start the draining thread.
+    pub fn new() -> Refuser {
+        let (sender, receiver) = mpsc::sync_channel(MAX_LINGERING);
+        let thread = thread::spawn(move || drain(receiver));
+        Refuser {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    /// Answer `stream` with `response`, and close it.
+    ///
+    /// The response is written without blocking: if the socket can't take
+    /// it at once, the connection is closed without one.
+    pub fn refuse(&self, stream: TcpStream, response: &Response) {
+        if stream.set_nonblocking(true).is_err() {
+            return;
+        }
+        let mut writer = &stream;
+        if response.write_to(&mut writer, &Method::Get).is_err() {
+            return;
+        }
+        let _ = stream.shutdown(Shutdown::Write);
+
+        if let Some(sender) = &self.sender {
+            // Too many connections lingering already: close this one now
+            let _ = sender.try_send(stream);
+        }
+    }
+}
+
+impl Drop for Refuser {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Drain the refused connections sent on `receiver` until they are closed
+/// by their client or linger too long. Returns once the `Refuser` is gone.
+fn drain(receiver: mpsc::Receiver<TcpStream>) {
+    let mut lingering: Vec<(TcpStream, Instant)> = vec![];
+    loop {
+        let received = if lingering.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            receiver.recv_timeout(DRAIN_INTERVAL)
+        };
+        match received {
+            Ok(stream) => lingering.push((stream, Instant::now() + LINGER)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        lingering.retain(|(stream, deadline)| now < *deadline && discard_input(stream));
+    }
+}
+
+/// Read and discard what a non-blocking `stream` received. Returns `false`
+/// once the client closed it.
+fn discard_input(stream: &TcpStream) -> bool {
+    let mut reader = stream;
+    let mut buf = [0; 4096];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return false,
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Reads from a connection, waiting for the keep-alive timeout until a
+/// request starts, then until the deadline of that request.
+struct TimedReader<'a> {
+    stream: &'a TcpStream,
+    keep_alive: &'a KeepAlive,
+    /// Set once the first byte of the current request arrived
+    deadline: Option<Instant>,
+}
+
+impl Read for TimedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            None => self.keep_alive.timeout,
+            Some(deadline) => deadline
+                .checked_duration_since(Instant::now())
+                .filter(|left| !left.is_zero())
+                .ok_or(io::ErrorKind::TimedOut)?,
+        };
+        let mut stream = self.stream;
+        stream.set_read_timeout(Some(timeout))?;
+
+        let read = stream.read(buf)?;
+        if read > 0 && self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + self.keep_alive.request_timeout);
+        }
+        Ok(read)
+    }
+}
+
+/// Check if the client of `request` wants to send more on its connection.
+fn wants_keep_alive(request: &Request) -> bool {
+    let headers = request.headers();
+    match request.version() {
+        Version::Http11 => !headers.has_token("Connection", "close"),
+        Version::Http10 => headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+/// Read timeouts show up as one kind or the other depending on the system.
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, Write},
+        net::{SocketAddr, TcpListener},
+    };
+
+    /// Serve one connection on a local port, in the background.
+    fn serve(keep_alive: KeepAlive) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let router = Router::new()
+                .get("/hello", |_, _| Response::ok().text("hello"))
+                .get("/bye", |_, _| {
+                    Response::ok().header("Connection", "close").text("bye")
+                })
+                .get("/big", |_, _| Response::ok().text("x".repeat(64 << 20)));
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &router, &keep_alive);
+        });
+        (addr, server)
+    }
+
+    /// Read one response: its status, headers and body.
+    fn read_response(reader: &mut impl BufRead) -> (u16, String, String) {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            assert!(reader.read_line(&mut line).unwrap() > 0, "closed early");
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let status = head[9..12].parse().unwrap();
+        let len: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        (status, head, String::from_utf8(body).unwrap())
+    }
+
+    fn is_closed(reader: &mut impl BufRead) -> bool {
+        reader.fill_buf().unwrap().is_empty()
+    }
+
+    #[test]
+    fn refused_clients_get_the_response() {
+        // One worker, kept busy, and no room to queue connections
+        let pool = crate::ThreadPool::build_bounded(1, 0).unwrap();
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = blocked.recv();
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let refused = stream.try_clone().unwrap();
+        let queued = pool.try_execute(move || drop(stream));
+        assert_eq!(queued, Err(crate::PoolError::QueueFull));
+        let refuser = Refuser::new();
+        let start = Instant::now();
+        refuser.refuse(refused, &Response::new(503).text("busy"));
+        // The client is still connected: draining it is left to the refuser
+        assert!(start.elapsed() < LINGER);
+
+        // The request was still unread when the server closed
+        let mut reader = BufReader::new(client);
+        let (status, _, body) = read_response(&mut reader);
+        assert_eq!((status, body.as_str()), (503, "busy"));
+        assert!(is_closed(&mut reader));
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn serves_several_requests_per_connection() {
+        let (addr, server) = serve(KeepAlive::new());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        for _ in 0..3 {
+            stream
+                .write_all(b"GET /hello HTTP/1.1\r\nHost: a\r\n\r\n")
+                .unwrap();
+            let (status, head, body) = read_response(&mut reader);
+            assert_eq!((status, body.as_str()), (200, "hello"));
+            assert!(head.contains("Keep-Alive: timeout=5"), "{head}");
+        }
+
+        // Pipelined, then closed by the client
+        stream
+            .write_all(
+                b"GET /hello HTTP/1.1\r\nHost: a\r\n\r\n\
+                  GET /missing HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        assert_eq!(read_response(&mut reader).0, 200);
+        let (status, head, _) = read_response(&mut reader);
+        assert_eq!(status, 404);
+        assert!(head.contains("Connection: close"), "{head}");
+        assert!(is_closed(&mut reader));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn closes_after_the_last_allowed_request() {
+        let (addr, server) = serve(KeepAlive::new().max_requests(2));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        let (_, head, _) = read_response(&mut reader);
+        assert!(head.contains("Keep-Alive: timeout=5, max=1"), "{head}");
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        let (_, head, _) = read_response(&mut reader);
+        assert!(head.contains("Connection: close"), "{head}");
+        assert!(is_closed(&mut reader));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn closes_idle_connections() {
+        let keep_alive = KeepAlive::new().timeout(Duration::from_millis(100));
+        let (addr, server) = serve(keep_alive);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_response(&mut reader).0, 200);
+        // Nothing more is sent
+        assert!(is_closed(&mut reader));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn handlers_can_close_connections() {
+        let (addr, server) = serve(KeepAlive::new());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        stream
+            .write_all(b"GET /bye HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        let (_, head, body) = read_response(&mut reader);
+        assert_eq!(body, "bye");
+        assert!(!head.contains("Keep-Alive"), "{head}");
+        assert!(is_closed(&mut reader));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn keeps_http_1_0_connections_only_on_request() {
+        let (addr, server) = serve(KeepAlive::new());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        stream
+            .write_all(b"GET /hello HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let (_, head, _) = read_response(&mut reader);
+        assert!(head.contains("Connection: keep-alive"), "{head}");
+
+        stream.write_all(b"GET /hello HTTP/1.0\r\n\r\n").unwrap();
+        let (_, head, _) = read_response(&mut reader);
+        assert!(head.contains("Connection: close"), "{head}");
+        assert!(is_closed(&mut reader));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn times_out_requests_sent_too_slowly() {
+        let keep_alive = KeepAlive::new()
+            .timeout(Duration::from_millis(200))
+            .request_timeout(Duration::from_millis(500));
+        let (addr, server) = serve(keep_alive);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        // Each byte comes well within the idle timeout, the whole request
+        // never does
+        let start = Instant::now();
+        for byte in b"GET /hello HTTP/1.1\r\nHost: a\r\nX-Slow: ".iter().cycle() {
+            if stream.write_all(&[*byte]).is_err() || start.elapsed() > Duration::from_secs(2) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let (status, head, _) = read_response(&mut reader);
+        assert_eq!(status, 408);
+        assert!(head.contains("Connection: close"), "{head}");
+        assert!(is_closed(&mut reader));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn times_out_half_sent_requests() {
+        let keep_alive = KeepAlive::new().timeout(Duration::from_millis(100));
+        let (addr, server) = serve(keep_alive.request_timeout(Duration::from_millis(100)));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: a\r\n\r\nGET /hello HTTP/1.1\r\n")
+            .unwrap();
+        assert_eq!(read_response(&mut reader).0, 200);
+        assert_eq!(read_response(&mut reader).0, 408);
+        assert!(is_closed(&mut reader));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn drops_clients_that_stop_reading() {
+        let keep_alive = KeepAlive::new().request_timeout(Duration::from_millis(200));
+        let (addr, server) = serve(keep_alive);
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        // More than the socket buffers hold, and never read
+        stream
+            .write_all(b"GET /big HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        let start = Instant::now();
+        while !server.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(5), "still writing");
+            thread::sleep(Duration::from_millis(50));
+        }
+        server.join().unwrap();
+    }
+}
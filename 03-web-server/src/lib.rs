@@ -1,32 +1,146 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, PoisonError,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
+pub mod config;
+pub mod connection;
+mod date;
+pub mod headers;
+pub mod metrics;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod static_files;
+pub mod task;
+
+pub use config::Config;
+pub use connection::{handle_connection, KeepAlive, Refuser};
+pub use headers::Headers;
+pub use request::{Method, ParseError, Request, Version};
+pub use response::Response;
+pub use router::{Params, Router};
+pub use static_files::StaticFiles;
+pub use task::{join_all, JoinHandle, Scope, ScopedJoinHandle};
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum PoolError {
     InvalidPoolSizeError,
+    /// The queue of jobs waiting for a worker is full.
+    QueueFull,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// A job, with when it was queued to measure its latency.
+struct Task {
+    job: Job,
+    queued_at: Instant,
+}
+
+/// Jobs waiting for a worker, per worker, unless set with `queue`.
+const QUEUE_PER_WORKER: usize = 16;
+
+/// How long extra workers stay without a job before exiting.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Numbers about the work of a pool, from `ThreadPool::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Worker threads running
+    pub workers: usize,
+    /// Workers busy with a job
+    pub active: usize,
+    /// Jobs waiting for a worker
+    pub queued: usize,
+    /// Jobs finished, panicked ones included
+    pub completed: u64,
+    pub panicked: u64,
+    /// Time from queueing to the end, summed over completed jobs
+    pub total_latency: Duration,
+}
+
+impl PoolStats {
+    /// Mean time from queueing to the end of completed jobs.
+    pub fn average_latency(&self) -> Duration {
+        match u32::try_from(self.completed) {
+            Ok(0) => Duration::ZERO,
+            Ok(completed) => self.total_latency / completed,
+            Err(_) => {
+                Duration::from_secs_f64(self.total_latency.as_secs_f64() / self.completed as f64)
+            }
+        }
+    }
+}
+
+/// State of a pool, shared with its workers.
+struct Shared {
+    receiver: Mutex<mpsc::Receiver<Task>>,
+    min_workers: usize,
+    max_workers: usize,
+    idle_timeout: Duration,
+    /// Taken to add or remove workers
+    workers: Mutex<Workers>,
+    active: AtomicUsize,
+    queued: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    latency_nanos: AtomicU64,
+}
+
+#[derive(Default)]
+struct Workers {
+    /// Workers started, some may have exited since
+    threads: Vec<Worker>,
+    /// Workers still running
+    alive: usize,
+    next_id: usize,
+}
+
 struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
-
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
+    /// Start a worker, running `first` before waiting for queued jobs.
+    fn new(id: usize, shared: Arc<Shared>, first: Option<Task>) -> Self {
+        let thread = thread::spawn(move || {
+            if let Some(task) = first {
+                shared.run(id, task);
+            }
+            loop {
+                // The lock is released before running the job. A panic while
+                // holding it can't leave the receiver in a bad state either.
+                let message = shared
+                    .receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv_timeout(shared.idle_timeout);
+
+                match message {
+                    Ok(task) => {
+                        shared.active.fetch_add(1, Ordering::SeqCst);
+                        shared.queued.fetch_sub(1, Ordering::SeqCst);
+                        shared.run(id, task);
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if shared.retire() {
+                            println!("Worker {id} idle; shutting down.");
+                            break;
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        shared.lock_workers().alive -= 1;
+                        println!("Worker {id} disconnected; shutting down.");
+                        break;
+                    }
                 }
             }
         });
@@ -38,46 +152,275 @@ impl Worker {
     // fn run(self) {}
 }
 
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+impl Shared {
+    fn lock_workers(&self) -> MutexGuard<'_, Workers> {
+        self.workers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run a job counted as active.
+    fn run(&self, id: usize, task: Task) {
+        println!("Worker {id} got a job; executing.");
+        // A panicking job must not take its worker down with it
+        if panic::catch_unwind(AssertUnwindSafe(task.job)).is_err() {
+            println!("Worker {id} job panicked; recovering.");
+            self.panicked.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let latency = u64::try_from(task.queued_at.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.latency_nanos.fetch_add(latency, Ordering::SeqCst);
+        self.completed.fetch_add(1, Ordering::SeqCst);
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Count an idle worker out, unless the pool is at its minimum or has
+    /// jobs waiting.
+    fn retire(&self) -> bool {
+        let mut workers = self.lock_workers();
+        // Jobs are counted as queued with the lock held, before being sent
+        if workers.alive <= self.min_workers || self.queued.load(Ordering::SeqCst) > 0 {
+            return false;
+        }
+        workers.alive -= 1;
+        true
+    }
 }
 
-impl ThreadPool {
-    /// Create a new ThreadPool.
-    ///
-    /// The size is the number of threads in the pool.
+/// Settings of a `ThreadPool`, from `ThreadPool::builder`.
+#[derive(Debug, Clone)]
+pub struct PoolBuilder {
+    min_workers: usize,
+    max_workers: usize,
+    capacity: Option<usize>,
+    idle_timeout: Duration,
+}
+
+impl PoolBuilder {
+    /// Keep `min` workers running, and start more up to `max` when jobs
+    /// arrive with every worker busy. Workers above `min` exit after
+    /// staying idle for the idle timeout, one timeout at a time.
+    pub fn workers(mut self, min: usize, max: usize) -> PoolBuilder {
+        self.min_workers = min;
+        self.max_workers = max;
+        self
+    }
+
+    /// Let at most `capacity` jobs wait for a worker. With 0, jobs are only
+    /// accepted when a worker is free. Defaults to 16 jobs per worker.
+    pub fn queue(mut self, capacity: usize) -> PoolBuilder {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// How long extra workers wait for a job before exiting. Defaults to
+    /// 30 seconds.
+    pub fn idle_timeout(mut self, timeout: Duration) -> PoolBuilder {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Start the pool with its minimum number of workers.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// The `new` function will panic if the size is zero.
-    pub fn build(size: usize) -> Result<ThreadPool, PoolError> {
-        if size <= 0 {
+    /// Fails if the maximum number of workers is zero or below the
+    /// minimum.
+    pub fn build(self) -> Result<ThreadPool, PoolError> {
+        if self.max_workers == 0 || self.min_workers > self.max_workers {
             return Err(PoolError::InvalidPoolSizeError);
         }
+        let capacity = self
+            .capacity
+            .unwrap_or(self.max_workers.saturating_mul(QUEUE_PER_WORKER));
 
-        let (sender, receiver) = mpsc::channel();
+        let (sender, receiver) = mpsc::sync_channel(capacity);
 
-        let receiver = Arc::new(Mutex::new(receiver));
-
-        let mut workers = Vec::with_capacity(size);
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            min_workers: self.min_workers,
+            max_workers: self.max_workers,
+            idle_timeout: self.idle_timeout,
+            workers: Mutex::default(),
+            active: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            latency_nanos: AtomicU64::new(0),
+        });
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        let mut workers = shared.lock_workers();
+        for id in 0..self.min_workers {
+            workers
+                .threads
+                .push(Worker::new(id, Arc::clone(&shared), None));
         }
+        workers.alive = self.min_workers;
+        workers.next_id = self.min_workers;
+        drop(workers);
 
         Ok(ThreadPool {
-            workers,
+            shared,
             sender: Some(sender),
         })
     }
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    sender: Option<mpsc::SyncSender<Task>>,
+}
 
+/// Reads the stats of a pool from elsewhere, like a request handler.
+#[derive(Clone)]
+pub struct StatsHandle {
+    shared: Arc<Shared>,
+}
+
+impl StatsHandle {
+    pub fn stats(&self) -> PoolStats {
+        let shared = &self.shared;
+        PoolStats {
+            workers: shared.lock_workers().alive,
+            active: shared.active.load(Ordering::SeqCst),
+            queued: shared.queued.load(Ordering::SeqCst),
+            completed: shared.completed.load(Ordering::SeqCst),
+            panicked: shared.panicked.load(Ordering::SeqCst),
+            total_latency: Duration::from_nanos(shared.latency_nanos.load(Ordering::SeqCst)),
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Create a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool. Up to 16 jobs per
+    /// thread can wait in the queue.
+    ///
+    /// # Errors
+    ///
+    /// The `build` function fails if the size is zero.
+    pub fn build(size: usize) -> Result<ThreadPool, PoolError> {
+        ThreadPool::builder().workers(size, size).build()
+    }
+
+    /// Create a new ThreadPool where at most `capacity` jobs can wait for a
+    /// worker. With 0, jobs are only accepted when a worker is free.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the size is zero.
+    pub fn build_bounded(size: usize, capacity: usize) -> Result<ThreadPool, PoolError> {
+        ThreadPool::builder()
+            .workers(size, size)
+            .queue(capacity)
+            .build()
+    }
+
+    /// Configure a pool, with a number of workers that can change:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use web_server::ThreadPool;
+    ///
+    /// let pool = ThreadPool::builder()
+    ///     .workers(2, 8)
+    ///     .idle_timeout(Duration::from_secs(10))
+    ///     .build()
+    ///     .unwrap_or_else(|_| panic!("Error creating the pool"));
+    /// assert_eq!(pool.stats().workers, 2);
+    /// ```
+    pub fn builder() -> PoolBuilder {
+        PoolBuilder {
+            min_workers: 1,
+            max_workers: 1,
+            capacity: None,
+            idle_timeout: IDLE_TIMEOUT,
+        }
+    }
+
+    /// Queue `f` to run on a worker, waiting for room in the queue if
+    /// it's full.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job: Job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.send(Box::new(f));
+    }
+
+    /// Queue a job, waiting for room in the queue if it's full.
+    fn send(&self, job: Job) {
+        if let Some(task) = self.enqueue(job) {
+            self.sender.as_ref().unwrap().send(task).unwrap();
+        }
+    }
+
+    /// Queue `f` to run on a worker, unless the queue is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolError::QueueFull` without running `f` if the queue is
+    /// full, so callers can shed load instead of waiting.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let Some(task) = self.enqueue(Box::new(f)) else {
+            return Ok(());
+        };
+        match self.sender.as_ref().unwrap().try_send(task) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+                Err(PoolError::QueueFull)
+            }
+            // The workers only stop once the sender is dropped
+            Err(mpsc::TrySendError::Disconnected(_)) => unreachable!("workers are gone"),
+        }
+    }
+
+    /// Start a worker for `job` if every worker is busy and the pool can
+    /// grow. Otherwise count the job as queued, and give it back to send.
+    fn enqueue(&self, job: Job) -> Option<Task> {
+        let task = Task {
+            job,
+            queued_at: Instant::now(),
+        };
+        let shared = &self.shared;
+        let mut workers = shared.lock_workers();
+
+        let busy = shared.active.load(Ordering::SeqCst) + shared.queued.load(Ordering::SeqCst);
+        if workers.alive > busy || workers.alive >= shared.max_workers {
+            shared.queued.fetch_add(1, Ordering::SeqCst);
+            return Some(task);
+        }
+
+        // Forget the workers that exited while idle
+        workers.threads.retain(|worker| {
+            worker
+                .thread
+                .as_ref()
+                .is_some_and(|thread| !thread.is_finished())
+        });
+        let id = workers.next_id;
+        workers.next_id += 1;
+        workers.alive += 1;
+        shared.active.fetch_add(1, Ordering::SeqCst);
+        println!("Starting worker {id}");
+        workers
+            .threads
+            .push(Worker::new(id, Arc::clone(shared), Some(task)));
+        None
+    }
+
+    /// Numbers about the workers and the jobs they ran so far.
+    pub fn stats(&self) -> PoolStats {
+        self.stats_handle().stats()
+    }
+
+    /// A handle to read `stats` that can be kept after the pool is gone.
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle {
+            shared: Arc::clone(&self.shared),
+        }
     }
 }
 
@@ -85,7 +428,9 @@ impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.sender.take());
 
-        for worker in &mut self.workers {
+        // Workers take the lock to exit: join them without it
+        let workers = mem::take(&mut self.shared.lock_workers().threads);
+        for mut worker in workers {
             println!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
@@ -94,3 +439,125 @@ impl Drop for ThreadPool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wait for `condition` on the stats of `pool`, for a few seconds.
+    fn wait_for(pool: &ThreadPool, condition: impl Fn(&PoolStats) -> bool) -> PoolStats {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let stats = pool.stats();
+            if condition(&stats) || Instant::now() > deadline {
+                return stats;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn rejects_jobs_when_the_queue_is_full() {
+        let pool = ThreadPool::build_bounded(1, 1).unwrap();
+        let (started_tx, started) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel::<()>();
+        let (done_tx, done) = mpsc::channel();
+
+        // Keep the only worker busy
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        started.recv().unwrap();
+
+        let done_first = done_tx.clone();
+        assert_eq!(
+            pool.try_execute(move || done_first.send(1).unwrap()),
+            Ok(())
+        );
+        assert_eq!(
+            pool.try_execute(move || done_tx.send(2).unwrap()),
+            Err(PoolError::QueueFull)
+        );
+
+        release.send(()).unwrap();
+        assert_eq!(done.recv_timeout(Duration::from_secs(5)), Ok(1));
+        // The rejected job was dropped without running
+        assert!(done.recv_timeout(Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let pool = ThreadPool::build(2).unwrap();
+        let (done_tx, done) = mpsc::channel();
+
+        for i in 0..4 {
+            pool.execute(|| panic!("job failed"));
+            let done_tx = done_tx.clone();
+            pool.execute(move || done_tx.send(i).unwrap());
+        }
+
+        let mut results: Vec<_> = (0..4)
+            .map(|_| done.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        results.sort_unstable();
+        assert_eq!(results, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn grows_under_pressure_and_shrinks_when_idle() {
+        let pool = ThreadPool::builder()
+            .workers(1, 3)
+            .idle_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert_eq!(pool.stats().workers, 1);
+        let (release, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+
+        for _ in 0..4 {
+            let release_rx = Arc::clone(&release_rx);
+            pool.execute(move || {
+                let _ = release_rx.lock().unwrap().recv();
+            });
+        }
+        let stats = wait_for(&pool, |stats| stats.active == 3);
+        assert_eq!((stats.workers, stats.active, stats.queued), (3, 3, 1));
+
+        drop(release);
+        let stats = wait_for(&pool, |stats| stats.workers == 1);
+        assert_eq!((stats.workers, stats.active, stats.queued), (1, 0, 0));
+        assert_eq!(stats.completed, 4);
+
+        // Still able to grow again
+        let handles: Vec<_> = (0..3).map(|i| pool.spawn(move || i)).collect();
+        assert_eq!(join_all(handles).len(), 3);
+    }
+
+    #[test]
+    fn counts_completed_jobs_and_latency() {
+        let pool = ThreadPool::build(2).unwrap();
+        assert_eq!(pool.stats().average_latency(), Duration::ZERO);
+
+        for _ in 0..4 {
+            pool.execute(|| thread::sleep(Duration::from_millis(10)));
+        }
+        pool.execute(|| panic!("job failed"));
+        let stats = wait_for(&pool, |stats| stats.completed == 5);
+
+        assert_eq!((stats.completed, stats.panicked), (5, 1));
+        assert_eq!((stats.workers, stats.active, stats.queued), (2, 0, 0));
+        assert!(stats.total_latency >= Duration::from_millis(40));
+        assert!(stats.average_latency() >= Duration::from_millis(8));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(ThreadPool::build(0).is_err());
+        assert!(ThreadPool::builder().workers(3, 2).build().is_err());
+        // No worker until the first job
+        let pool = ThreadPool::builder().workers(0, 1).build().unwrap();
+        assert_eq!(pool.stats().workers, 0);
+        assert_eq!(pool.spawn(|| 7).join().unwrap(), 7);
+    }
+}
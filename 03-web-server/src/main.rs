@@ -1,57 +1,146 @@
 use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    env, fs, io,
+    net::TcpListener,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread,
     time::Duration,
 };
-use web_server::ThreadPool;
+use web_server::{
+    handle_connection, metrics, Config, PoolError, Refuser, Response, Router, StaticFiles,
+    StatsHandle, ThreadPool,
+};
+
+/// Set by SIGINT or SIGTERM: stop accepting connections.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878")
-        .expect("Connection to port failed! Maybe is already being used?");
-    let pool = ThreadPool::build(4).unwrap_or_else(|_| panic!("Error creating the pool"));
+    let config = Config::build(env::args()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    let listener = TcpListener::bind(config.bind).unwrap_or_else(|err| {
+        eprintln!("Failed to listen on {}: {err}", config.bind);
+        process::exit(1);
+    });
+    // Accept without blocking, to notice shutdown requests
+    listener
+        .set_nonblocking(true)
+        .expect("Failed to make the listener non-blocking");
+    handle_signals();
+
+    let pool = ThreadPool::builder()
+        .workers(config.threads, config.max_threads())
+        .queue(config.queue)
+        .build()
+        .unwrap_or_else(|_| panic!("Error creating the pool"));
+    let router = Arc::new(router(&config, pool.stats_handle()));
+    let keep_alive = config.keep_alive();
+    let refuser = Refuser::new();
+    println!(
+        "Listening on {} with {} to {} threads",
+        config.bind,
+        config.threads,
+        config.max_threads()
+    );
 
-    // Stop after receiving 2 requests for demonstration purposes
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap();
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            Err(err) => {
+                println!("Failed to accept a connection: {err}");
+                continue;
+            }
+        };
+        // Some systems pass the non-blocking mode on to accepted sockets
+        if let Err(err) = stream.set_nonblocking(false) {
+            println!("Failed to set up the connection: {err}");
+            continue;
+        }
+        // Kept to answer the client if no thread can take the connection
+        let busy = match stream.try_clone() {
+            Ok(busy) => busy,
+            Err(err) => {
+                println!("Failed to set up the connection: {err}");
+                continue;
+            }
+        };
+        let router = Arc::clone(&router);
 
-        pool.execute(|| {
-            handle_connection(stream);
+        let queued = pool.try_execute(move || {
+            handle_connection(stream, &router, &keep_alive);
         });
+        if let Err(PoolError::QueueFull) = queued {
+            println!("Too many connections, rejecting one.");
+            let response = Response::new(503)
+                .header("Retry-After", "1")
+                .header("Connection", "close")
+                .text("Service Unavailable");
+            refuser.refuse(busy, &response);
+        }
     }
+
+    // Dropping the pool waits for the connections being served, idle
+    // ones closing at the end of their keep-alive timeout
     println!("Shutting down.");
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
+/// Set `SHUTDOWN` on SIGINT and SIGTERM instead of exiting right away.
+#[cfg(unix)]
+fn handle_signals() {
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
 
-    println!("Request: {request_line}");
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
 
-    let mut words = request_line.split_whitespace();
-    let _method = words.next().unwrap();
-    let resource = words.next().unwrap();
+    // Only async-signal-safe work here: an atomic store
+    extern "C" fn on_signal(_: i32) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
 
-    let (status_line, filename) = match resource {
-        "/" => ("200 OK", "hello.html"),
-        "/favicon.ico" => ("200 OK", "rust-logo.svg"),
-        "/sleep" => {
-            thread::sleep(Duration::from_secs(5));
-            ("200 OK", "hello.html")
-        }
-        _ => ("404 NOT FOUND", "404.html"),
-    };
+    // SAFETY: the handler only stores to an atomic
+    unsafe {
+        signal(SIGINT, on_signal);
+        signal(SIGTERM, on_signal);
+    }
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
+/// Ctrl-C ends the process right away on other systems.
+#[cfg(not(unix))]
+fn handle_signals() {}
 
-    let response = format!(
-        "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
-    );
+fn router(config: &Config, pool: StatsHandle) -> Router {
+    let files = Arc::new(StaticFiles::new(&config.root).index_files(["hello.html"]));
+    let favicon = Arc::clone(&files);
+    let sleep = Arc::clone(&files);
 
-    stream.write_all(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+    Router::new()
+        .get("/metrics", move |_, _| metrics::response(&pool.stats()))
+        .get("/favicon.ico", move |request, _| {
+            favicon.serve(request, "rust-logo.svg")
+        })
+        .get("/sleep", move |request, _| {
+            thread::sleep(Duration::from_secs(5));
+            sleep.serve(request, "hello.html")
+        })
+        .get("/*path", move |request, params| {
+            let response = files.serve(request, params.get("path").unwrap_or(""));
+            if response.status() != 404 {
+                return response;
+            }
+            // Without the conditional and range headers of the request
+            let page = fs::read(files.root().join("404.html")).unwrap_or_default();
+            Response::not_found()
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(page)
+        })
 }
@@ -0,0 +1,109 @@
+//! Metrics in the Prometheus text format, for a `/metrics` route.
+
+use std::fmt::Write;
+
+use crate::{response::Response, PoolStats};
+
+/// Media type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The stats of a thread pool, as Prometheus metrics.
+pub fn render(stats: &PoolStats) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} {kind}");
+        let _ = writeln!(text, "{name} {value}");
+    };
+
+    metric(
+        "web_server_pool_workers",
+        "gauge",
+        "Worker threads running.",
+        &stats.workers,
+    );
+    metric(
+        "web_server_pool_active_workers",
+        "gauge",
+        "Workers busy with a job.",
+        &stats.active,
+    );
+    metric(
+        "web_server_pool_queued_jobs",
+        "gauge",
+        "Jobs waiting for a worker.",
+        &stats.queued,
+    );
+    metric(
+        "web_server_pool_jobs_completed_total",
+        "counter",
+        "Jobs finished, panicked ones included.",
+        &stats.completed,
+    );
+    metric(
+        "web_server_pool_jobs_panicked_total",
+        "counter",
+        "Jobs that panicked.",
+        &stats.panicked,
+    );
+    metric(
+        "web_server_pool_job_average_latency_seconds",
+        "gauge",
+        "Mean time from queueing to the end of completed jobs.",
+        &stats.average_latency().as_secs_f64(),
+    );
+
+    // A summary without quantiles: rates of its sum and count give the
+    // latency over any period
+    let name = "web_server_pool_job_latency_seconds";
+    let _ = writeln!(text, "# HELP {name} Time from queueing to the end of jobs.");
+    let _ = writeln!(text, "# TYPE {name} summary");
+    let _ = writeln!(text, "{name}_sum {}", stats.total_latency.as_secs_f64());
+    let _ = writeln!(text, "{name}_count {}", stats.completed);
+    text
+}
+
+/// Answer a scrape with the stats of a thread pool.
+pub fn response(stats: &PoolStats) -> Response {
+    Response::ok()
+        .header("Content-Type", CONTENT_TYPE)
+        .body(render(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn renders_the_text_format() {
+        let stats = PoolStats {
+            workers: 4,
+            active: 1,
+            queued: 0,
+            completed: 8,
+            panicked: 1,
+            total_latency: Duration::from_millis(2000),
+        };
+        let text = render(&stats);
+
+        assert!(text.starts_with(
+            "# HELP web_server_pool_workers Worker threads running.\n\
+             # TYPE web_server_pool_workers gauge\n\
+             web_server_pool_workers 4\n"
+        ));
+        for line in [
+            "web_server_pool_active_workers 1\n",
+            "web_server_pool_queued_jobs 0\n",
+            "# TYPE web_server_pool_jobs_completed_total counter\n",
+            "web_server_pool_jobs_completed_total 8\n",
+            "web_server_pool_jobs_panicked_total 1\n",
+            "web_server_pool_job_average_latency_seconds 0.25\n",
+            "web_server_pool_job_latency_seconds_sum 2\n",
+            "web_server_pool_job_latency_seconds_count 8\n",
+        ] {
+            assert!(text.contains(line), "{line}");
+        }
+        assert!(text.ends_with('\n'));
+    }
+}
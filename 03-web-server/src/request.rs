@@ -0,0 +1,638 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read},
+};
+
+use crate::headers::Headers;
+
+/// Longest request line or header line accepted, without its line ending.
+const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// Most header fields accepted in a request, trailers included.
+const MAX_HEADERS: usize = 100;
+
+/// Largest body accepted, once chunks are put together.
+const MAX_BODY_LEN: usize = 10 * 1024 * 1024;
+
+/// Why a request could not be read.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The connection failed while reading.
+    Io(io::Error),
+    /// The request is malformed or too large: answer with 400 Bad Request.
+    BadRequest(&'static str),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(err) => write!(f, "{err}"),
+            ParseError::BadRequest(reason) => write!(f, "bad request: {reason}"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        ParseError::Io(err)
+    }
+}
+
+/// The method of a request. Methods are case-sensitive.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Other(String),
+}
+
+impl Method {
+    fn parse(token: &str) -> Result<Method, ParseError> {
+        if !is_token(token) {
+            return Err(ParseError::BadRequest("invalid method"));
+        }
+        Ok(match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "PATCH" => Method::Patch,
+            other => Method::Other(other.to_string()),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Other(other) => other,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The HTTP versions the server speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// An HTTP/1.x request, body included.
+#[derive(Debug, Clone)]
+pub struct Request {
+    method: Method,
+    target: String,
+    path: String,
+    query: Vec<(String, String)>,
+    version: Version,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Read the next request from `reader`.
+    ///
+    /// Returns `Ok(None)` if the connection is closed before a request
+    /// starts, so several requests can be read from one connection. Bodies
+    /// are read whole, whether they are sent with `Content-Length` or
+    /// chunked; trailer fields of chunked bodies are added to the headers.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Option<Request>, ParseError> {
+        // Empty lines before the request line are ignored
+        let line = loop {
+            match read_line(reader)? {
+                None => return Ok(None),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+
+        let mut parts = line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::BadRequest("malformed request line"));
+        };
+
+        let method = Method::parse(method)?;
+        let (path, query) = parse_target(target)?;
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            _ => return Err(ParseError::BadRequest("unsupported HTTP version")),
+        };
+
+        let mut headers = Headers::new();
+        read_fields(reader, &mut headers)?;
+        match headers.get_all("host").count() {
+            0 if version == Version::Http11 => {
+                return Err(ParseError::BadRequest("missing Host header"))
+            }
+            0 | 1 => {}
+            _ => return Err(ParseError::BadRequest("several Host headers")),
+        }
+
+        let body = read_body(reader, &mut headers)?;
+
+        Ok(Some(Request {
+            method,
+            target: target.to_string(),
+            path,
+            query,
+            version,
+            headers,
+            body,
+        }))
+    }
+
+    /// Parse a single request from raw bytes.
+    pub fn parse(mut bytes: &[u8]) -> Result<Request, ParseError> {
+        Request::read_from(&mut bytes)?.ok_or(ParseError::BadRequest("empty request"))
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The request target, as sent.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The path of the target, percent-decoded.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The first value of the query parameter `name`, decoded.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every query parameter, decoded, in order.
+    pub fn query_params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.query
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// The first value of the header `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+/// Read a line without its line ending, or `None` at the end of the input.
+/// Lines may end with CRLF or a bare LF.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let limit = (MAX_LINE_LEN + 2) as u64;
+    reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    if line.pop() != Some(b'\n') {
+        return Err(if line.len() >= MAX_LINE_LEN {
+            ParseError::BadRequest("line too long")
+        } else {
+            ParseError::BadRequest("incomplete request")
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::BadRequest("invalid UTF-8"))
+}
+
+/// Read header fields up to the empty line that ends them.
+fn read_fields<R: BufRead>(reader: &mut R, headers: &mut Headers) -> Result<(), ParseError> {
+    loop {
+        let line = read_line(reader)?.ok_or(ParseError::BadRequest("incomplete request"))?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        if headers.len() >= MAX_HEADERS {
+            return Err(ParseError::BadRequest("too many headers"));
+        }
+        // Values continued on the next line are obsolete
+        if line.starts_with([' ', '\t']) {
+            return Err(ParseError::BadRequest("obsolete line folding"));
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(ParseError::BadRequest("malformed header"))?;
+        // Also rejects whitespace before the colon
+        if !is_token(name) {
+            return Err(ParseError::BadRequest("invalid header name"));
+        }
+        headers.append(name, value.trim_matches([' ', '\t']));
+    }
+}
+
+/// Read the body the headers announce, if any.
+fn read_body<R: BufRead>(reader: &mut R, headers: &mut Headers) -> Result<Vec<u8>, ParseError> {
+    if headers.contains("transfer-encoding") {
+        // Both would let a proxy and us disagree on where the body ends
+        if headers.contains("content-length") {
+            return Err(ParseError::BadRequest(
+                "both Content-Length and Transfer-Encoding",
+            ));
+        }
+        let codings: Vec<_> = headers
+            .get_all("transfer-encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        if codings.len() != 1 || !codings[0].eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::BadRequest("unsupported transfer coding"));
+        }
+        return read_chunked(reader, headers);
+    }
+
+    let mut lengths = headers
+        .get_all("content-length")
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+    let Some(length) = lengths.next() else {
+        return Ok(Vec::new());
+    };
+    if lengths.any(|other| other != length) {
+        return Err(ParseError::BadRequest("conflicting Content-Length"));
+    }
+    if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::BadRequest("invalid Content-Length"));
+    }
+    let length = length
+        .parse()
+        .ok()
+        .filter(|&length| length <= MAX_BODY_LEN)
+        .ok_or(ParseError::BadRequest("body too large"))?;
+
+    let mut body = Vec::new();
+    read_exact(reader, &mut body, length)?;
+    Ok(body)
+}
+
+/// Read a chunked body and its trailer fields.
+fn read_chunked<R: BufRead>(reader: &mut R, headers: &mut Headers) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or(ParseError::BadRequest("incomplete request"))?;
+        // Chunk extensions are ignored
+        let size = line.split(';').next().unwrap_or_default().trim_end();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseError::BadRequest("invalid chunk size"));
+        }
+        let size = usize::from_str_radix(size, 16)
+            .ok()
+            .filter(|&size| size <= MAX_BODY_LEN - body.len())
+            .ok_or(ParseError::BadRequest("body too large"))?;
+        if size == 0 {
+            break;
+        }
+
+        read_exact(reader, &mut body, size)?;
+        if read_line(reader)?.as_deref() != Some("") {
+            return Err(ParseError::BadRequest("malformed chunk"));
+        }
+    }
+
+    read_fields(reader, headers)?;
+    Ok(body)
+}
+
+/// Append exactly `len` bytes from `reader` to `buf`.
+///
+/// `buf` grows as the bytes arrive, so a client announcing a large body
+/// without sending it doesn't get it allocated.
+fn read_exact<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>, len: usize) -> Result<(), ParseError> {
+    let read = reader.take(len as u64).read_to_end(buf)?;
+    if read < len {
+        return Err(ParseError::BadRequest("incomplete request"));
+    }
+    Ok(())
+}
+
+/// Split a request target into its decoded path and query parameters.
+///
+/// Targets are usually a path (`/a/b?x=1`), but may be an absolute URL
+/// (`http://host/a/b`) or `*` for `OPTIONS *`.
+fn parse_target(target: &str) -> Result<(String, Vec<(String, String)>), ParseError> {
+    if target == "*" {
+        return Ok((target.to_string(), Vec::new()));
+    }
+
+    let lowercase = target.to_ascii_lowercase();
+    let target = match ["http://", "https://"]
+        .iter()
+        .find(|scheme| lowercase.starts_with(*scheme))
+    {
+        Some(scheme) => {
+            let rest = &target[scheme.len()..];
+            rest.find(['/', '?']).map_or("/", |start| &rest[start..])
+        }
+        None => target,
+    };
+    if !target.starts_with(['/', '?']) {
+        return Err(ParseError::BadRequest("invalid request target"));
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = match percent_decode(path, false)? {
+        path if path.is_empty() => "/".to_string(),
+        path => path,
+    };
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(name, true)?, percent_decode(value, true)?))
+        })
+        .collect::<Result<_, ParseError>>()?;
+    Ok((path, query))
+}
+
+/// Decode `%XX` escapes, and `+` as a space in query strings.
+fn percent_decode(text: &str, plus_as_space: bool) -> Result<String, ParseError> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'%' => {
+                let hex = rest.get(..2).ok_or_else(invalid_encoding)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid_encoding())?;
+                let value = u8::from_str_radix(hex, 16).map_err(|_| invalid_encoding())?;
+                bytes.push(value);
+                rest = &rest[2..];
+            }
+            b'+' if plus_as_space => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid_encoding())
+}
+
+fn invalid_encoding() -> ParseError {
+    ParseError::BadRequest("invalid percent-encoding")
+}
+
+/// Check if `text` is a token, as method and header names must be.
+fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bad_request(bytes: &[u8]) -> &'static str {
+        match Request::parse(bytes) {
+            Err(ParseError::BadRequest(reason)) => reason,
+            other => panic!("expected a bad request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_request_line_headers_and_query() {
+        let request = Request::parse(
+            b"GET /search/caf%C3%A9?q=rust+web&page=2&flag HTTP/1.1\r\n\
+              Host: localhost:7878\r\n\
+              Accept: text/html\r\n\
+              accept:  */*  \r\n\
+              \r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.method(), &Method::Get);
+        assert_eq!(request.target(), "/search/caf%C3%A9?q=rust+web&page=2&flag");
+        assert_eq!(request.path(), "/search/café");
+        assert_eq!(request.query("q"), Some("rust web"));
+        assert_eq!(request.query("page"), Some("2"));
+        assert_eq!(request.query("flag"), Some(""));
+        assert_eq!(request.query("missing"), None);
+        assert_eq!(request.version(), Version::Http11);
+        assert_eq!(request.header("HOST"), Some("localhost:7878"));
+        let accept: Vec<_> = request.headers().get_all("Accept").collect();
+        assert_eq!(accept, ["text/html", "*/*"]);
+        assert!(request.body().is_empty());
+    }
+
+    #[test]
+    fn accepts_other_targets_and_methods() {
+        let request = Request::parse(b"OPTIONS * HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(request.method(), &Method::Options);
+        assert_eq!(request.path(), "*");
+        assert_eq!(request.version(), Version::Http10);
+
+        let request =
+            Request::parse(b"PURGE http://example.com?x=1 HTTP/1.1\nHost: example.com\n\n")
+                .unwrap();
+        assert_eq!(request.method(), &Method::Other("PURGE".to_string()));
+        assert_eq!(request.path(), "/");
+        assert_eq!(request.query("x"), Some("1"));
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let request = Request::parse(
+            b"POST /form HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhello world",
+        )
+        .unwrap();
+        assert_eq!(request.body(), b"hello world");
+    }
+
+    #[test]
+    fn reads_chunked_body_and_trailers() {
+        let request = Request::parse(
+            b"POST /upload HTTP/1.1\r\n\
+              Host: a\r\n\
+              Transfer-Encoding: chunked\r\n\
+              \r\n\
+              5;name=value\r\nhello\r\n\
+              7\r\n, world\r\n\
+              0\r\n\
+              Checksum: abc\r\n\
+              \r\n",
+        )
+        .unwrap();
+        assert_eq!(request.body(), b"hello, world");
+        assert_eq!(request.header("checksum"), Some("abc"));
+    }
+
+    #[test]
+    fn reads_requests_one_after_the_other() {
+        let mut bytes: &[u8] = b"\r\nGET /a HTTP/1.1\r\nHost: a\r\n\r\n\
+            POST /b HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhi\
+            GET /c HTTP/1.1\r\nHost: a\r\n\r\n";
+
+        let paths: Vec<_> = std::iter::from_fn(|| Request::read_from(&mut bytes).unwrap())
+            .map(|request| request.path().to_string())
+            .collect();
+        assert_eq!(paths, ["/a", "/b", "/c"]);
+        assert!(Request::read_from(&mut &b""[..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let fixtures: &[(&[u8], &str)] = &[
+            (b"\r\n", "empty request"),
+            (b"GET /\r\n\r\n", "malformed request line"),
+            (b"GET  / HTTP/1.1\r\n\r\n", "malformed request line"),
+            (b"G(T / HTTP/1.1\r\n\r\n", "invalid method"),
+            (
+                b"GET / HTTP/2.0\r\nHost: a\r\n\r\n",
+                "unsupported HTTP version",
+            ),
+            (b"GET index.html HTTP/1.0\r\n\r\n", "invalid request target"),
+            (b"GET /%zz HTTP/1.0\r\n\r\n", "invalid percent-encoding"),
+            (b"GET /%C3 HTTP/1.0\r\n\r\n", "invalid percent-encoding"),
+            (b"GET / HTTP/1.1\r\n\r\n", "missing Host header"),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+                "several Host headers",
+            ),
+            (b"GET / HTTP/1.1\r\nHost: a\r\n", "incomplete request"),
+            (b"GET / HTTP/1.1\r\nHost a\r\n\r\n", "malformed header"),
+            (b"GET / HTTP/1.1\r\nHost : a\r\n\r\n", "invalid header name"),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\n b\r\n\r\n",
+                "obsolete line folding",
+            ),
+            (b"GET / HTTP/1.0\r\nX: \xff\r\n\r\n", "invalid UTF-8"),
+        ];
+        for (bytes, reason) in fixtures {
+            assert_eq!(
+                bad_request(bytes),
+                *reason,
+                "{}",
+                String::from_utf8_lossy(bytes)
+            );
+        }
+
+        let long_line = format!("GET /{} HTTP/1.0\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+        assert_eq!(bad_request(long_line.as_bytes()), "line too long");
+        let many_headers = format!(
+            "GET / HTTP/1.0\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(bad_request(many_headers.as_bytes()), "too many headers");
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        let fixtures: &[(&[u8], &str)] = &[
+            (b"Content-Length: abc\r\n\r\n", "invalid Content-Length"),
+            (b"Content-Length: -1\r\n\r\n", "invalid Content-Length"),
+            (
+                b"Content-Length: 2\r\nContent-Length: 3\r\n\r\nabc",
+                "conflicting Content-Length",
+            ),
+            (b"Content-Length: 99999999999\r\n\r\n", "body too large"),
+            (b"Content-Length: 5\r\n\r\nabc", "incomplete request"),
+            (b"Content-Length: 10485760\r\n\r\nabc", "incomplete request"),
+            (
+                b"Content-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+                "both Content-Length and Transfer-Encoding",
+            ),
+            (
+                b"Transfer-Encoding: gzip\r\n\r\n",
+                "unsupported transfer coding",
+            ),
+            (
+                b"Transfer-Encoding: chunked\r\n\r\nzz\r\n",
+                "invalid chunk size",
+            ),
+            (
+                b"Transfer-Encoding: chunked\r\n\r\n-1\r\n",
+                "invalid chunk size",
+            ),
+            (
+                b"Transfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n0\r\n\r\n",
+                "malformed chunk",
+            ),
+            (
+                b"Transfer-Encoding: chunked\r\n\r\n3\r\nab",
+                "incomplete request",
+            ),
+            (
+                b"Transfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n",
+                "incomplete request",
+            ),
+            (
+                b"Transfer-Encoding: chunked\r\n\r\nffffffffffffff\r\n",
+                "body too large",
+            ),
+        ];
+        for (rest, reason) in fixtures {
+            let bytes = [b"POST / HTTP/1.1\r\nHost: a\r\n", *rest].concat();
+            assert_eq!(
+                bad_request(&bytes),
+                *reason,
+                "{}",
+                String::from_utf8_lossy(rest)
+            );
+        }
+    }
+
+    #[test]
+    fn bodies_are_allocated_as_they_arrive() {
+        let mut body = Vec::new();
+        let result = read_exact(&mut &b"abc"[..], &mut body, MAX_BODY_LEN);
+        assert!(matches!(result, Err(ParseError::BadRequest("incomplete request"))));
+        assert_eq!(body, b"abc");
+        assert!(body.capacity() < MAX_BODY_LEN);
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::Arc,
+};
+
+use crate::{headers::Headers, request::Method};
+
+/// Size of the pieces a file body is read and written in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// An HTTP response, built one part at a time:
+///
+/// ```
+/// use web_server::Response;
+///
+/// let response = Response::ok()
+///     .header("Cache-Control", "no-cache")
+///     .html("<h1>Hello!</h1>");
+/// assert_eq!(response.status(), 200);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: u16,
+    headers: Headers,
+    body: Body,
+}
+
+/// What follows the headers of a response.
+#[derive(Debug, Clone)]
+enum Body {
+    Bytes(Vec<u8>),
+    /// `len` bytes of a file, from `start`, read only as they are written.
+    File {
+        file: Arc<File>,
+        start: u64,
+        len: u64,
+    },
+}
+
+impl Body {
+    fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } => *len,
+        }
+    }
+}
+
+impl Response {
+    /// An empty response with the status code `status`.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+        }
+    }
+
+    /// 200 OK
+    pub fn ok() -> Response {
+        Response::new(200)
+    }
+
+    /// 404 Not Found
+    pub fn not_found() -> Response {
+        Response::new(404)
+    }
+
+    /// Set the header `name`, replacing any previous value. A name or value
+    /// with CR or LF would split the response, so `write_to` refuses it.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// Send `len` bytes of `file`, from `start`, as the body. They are read
+    /// in chunks while the response is written, so large files are never
+    /// held in memory.
+    pub fn file(mut self, file: File, start: u64, len: u64) -> Response {
+        self.body = Body::File {
+            file: Arc::new(file),
+            start,
+            len,
+        };
+        self
+    }
+
+    /// Set a plain text body.
+    pub fn text(self, body: impl Into<String>) -> Response {
+        self.header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.into())
+    }
+
+    /// Set an HTML body.
+    pub fn html(self, body: impl Into<String>) -> Response {
+        self.header("Content-Type", "text/html; charset=utf-8")
+            .body(body.into())
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// The body set with `body`, `text` or `html`. Empty for a `file` body,
+    /// which is only read when the response is written.
+    pub fn body_bytes(&self) -> &[u8] {
+        match &self.body {
+            Body::Bytes(bytes) => bytes,
+            Body::File { .. } => &[],
+        }
+    }
+
+    /// Write the response to `writer`, as the answer to a `method` request.
+    ///
+    /// `Content-Length` is added unless already set. Answers to HEAD
+    /// requests leave the body out but keep its length, and 204 and 304
+    /// responses never have one. Fails if a file body ends before its
+    /// length, as the headers already promised it, and writes nothing if a
+    /// header contains CR or LF.
+    pub fn write_to<W: Write>(&self, writer: &mut W, method: &Method) -> io::Result<()> {
+        let split = |text: &str| text.contains(['\r', '\n']);
+        if let Some((name, _)) = self
+            .headers
+            .iter()
+            .find(|(name, value)| split(name) || split(value))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CR or LF in header {name:?}"),
+            ));
+        }
+        let bodiless = matches!(self.status, 204 | 304);
+
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !bodiless && !self.headers.contains("content-length") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        if bodiless || *method == Method::Head {
+            writer.write_all(&bytes)?;
+            return writer.flush();
+        }
+        match &self.body {
+            // A single write, so small responses go out in one packet
+            Body::Bytes(body) => {
+                bytes.extend_from_slice(body);
+                writer.write_all(&bytes)?;
+            }
+            Body::File { file, start, len } => {
+                writer.write_all(&bytes)?;
+                write_file(writer, file, *start, *len)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Copy `len` bytes of `file`, from `start`, to `writer` one chunk at a time.
+fn write_file<W: Write>(writer: &mut W, mut file: &File, start: u64, len: u64) -> io::Result<()> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut left = len;
+    while left > 0 {
+        let size = chunk.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        // The file shrank since the response was made
+        file.read_exact(&mut chunk[..size])?;
+        writer.write_all(&chunk[..size])?;
+        left -= size as u64;
+    }
+    Ok(())
+}
+
+/// The reason phrase of a status code.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(response: &Response, method: Method) -> String {
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes, &method).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn writes_status_headers_and_body() {
+        let response = Response::ok().header("X-Test", "1").text("hello");
+        assert_eq!(
+            written(&response, Method::Get),
+            "HTTP/1.1 200 OK\r\n\
+             X-Test: 1\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 5\r\n\
+             \r\n\
+             hello"
+        );
+    }
+
+    #[test]
+    fn head_and_bodiless_responses_have_no_body() {
+        let response = Response::ok().html("<p>hi</p>");
+        let head = written(&response, Method::Head);
+        assert!(head.ends_with("Content-Length: 9\r\n\r\n"));
+
+        let response = Response::new(304).body("ignored");
+        assert_eq!(
+            written(&response, Method::Get),
+            "HTTP/1.1 304 Not Modified\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn refuses_headers_that_split_lines() {
+        for response in [
+            Response::ok().header("Location", "/a\r\nSet-Cookie: x=1"),
+            Response::ok().header("Location", "/a\nb"),
+            Response::ok().header("X-A\rB", "1"),
+        ] {
+            let mut bytes = Vec::new();
+            let err = response.write_to(&mut bytes, &Method::Get).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn keeps_an_explicit_content_length() {
+        let response = Response::ok()
+            .header("content-length", "100")
+            .header("Content-Length", "3")
+            .body("abc");
+        let written = written(&response, Method::Get);
+        assert_eq!(written.matches("ength:").count(), 1);
+        assert!(written.ends_with("Content-Length: 3\r\n\r\nabc"));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{
+    request::{Method, Request},
+    response::Response,
+};
+
+/// A function answering the requests of a route.
+pub type Handler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync>;
+
+/// Values of the `:name` and `*name` segments of a matched route.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// One segment of a route pattern.
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    /// `:name` matches one non-empty segment.
+    Param(String),
+    /// `*name` matches the rest of the path, slashes included.
+    Wildcard(String),
+}
+
+struct Route {
+    method: Method,
+    pattern: Vec<Segment>,
+    handler: Handler,
+}
+
+/// Sends each request to the handler registered for its method and path.
+///
+/// Patterns are paths where a `:name` segment matches any single segment,
+/// and a final `*name` segment matches the rest of the path:
+///
+/// ```
+/// use web_server::{Method, Request, Response, Router};
+///
+/// let router = Router::new()
+///     .get("/users/:id", |_, params| {
+///         Response::ok().text(format!("user {}", params.get("id").unwrap()))
+///     })
+///     .get("/static/*path", |_, params| {
+///         Response::ok().text(params.get("path").unwrap().to_string())
+///     });
+///
+/// let request = Request::parse(b"GET /users/7 HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+/// assert_eq!(router.handle(&request).body_bytes(), b"user 7");
+/// ```
+///
+/// Routes are tried in the order they were added. A path that matches no
+/// route gets 404 Not Found; one that matches only routes of other methods
+/// gets 405 Method Not Allowed with an `Allow` header. HEAD requests are
+/// answered by GET routes when no HEAD route matches.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_, _| Response::not_found().text("Not Found")),
+        }
+    }
+
+    /// Answer `method` requests on paths matching `pattern` with `handler`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern does not start with `/`, or has a `*` segment
+    /// before its last one.
+    pub fn route<F>(mut self, method: Method, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Answer requests that match no route with `handler` instead of a
+    /// plain 404 Not Found.
+    pub fn not_found<F>(mut self, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// Answer `request` with the handler of the first matching route.
+    pub fn handle(&self, request: &Request) -> Response {
+        let matching: Vec<_> = self
+            .routes
+            .iter()
+            .filter_map(|route| Some((route, match_path(&route.pattern, request.path())?)))
+            .collect();
+
+        let method = request.method();
+        let found = matching
+            .iter()
+            .find(|(route, _)| route.method == *method)
+            .or_else(|| {
+                // HEAD is answered like GET, the body being left out later
+                let head = *method == Method::Head;
+                matching
+                    .iter()
+                    .find(|(route, _)| head && route.method == Method::Get)
+            });
+        if let Some((route, params)) = found {
+            return (route.handler)(request, params);
+        }
+
+        if matching.is_empty() {
+            return (self.not_found)(request, &Params::default());
+        }
+
+        let mut allowed: Vec<&str> = Vec::new();
+        for (route, _) in &matching {
+            if route.method == Method::Get {
+                allowed.push(Method::Head.as_str());
+            }
+            allowed.push(route.method.as_str());
+        }
+        allowed.sort_unstable();
+        allowed.dedup();
+        Response::new(405)
+            .header("Allow", allowed.join(", "))
+            .text("Method Not Allowed")
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let Some(path) = pattern.strip_prefix('/') else {
+        panic!("route pattern {pattern:?} must start with '/'");
+    };
+    let parts: Vec<_> = path.split('/').collect();
+
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                if i != parts.len() - 1 {
+                    panic!("route pattern {pattern:?} has a '*' segment before the end");
+                }
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            }
+        })
+        .collect()
+}
+
+/// The parameters of `path` if it matches `pattern`.
+fn match_path(pattern: &[Segment], path: &str) -> Option<Params> {
+    let mut rest = path.strip_prefix('/')?;
+    let mut params = Params::default();
+
+    for (i, segment) in pattern.iter().enumerate() {
+        if let Segment::Wildcard(name) = segment {
+            params.values.push((name.clone(), rest.to_string()));
+            return Some(params);
+        }
+
+        let (part, tail) = rest.split_once('/').unwrap_or((rest, ""));
+        match segment {
+            Segment::Literal(literal) if literal == part => {}
+            Segment::Param(name) if !part.is_empty() => {
+                params.values.push((name.clone(), part.to_string()));
+            }
+            _ => return None,
+        }
+
+        let last = i == pattern.len() - 1;
+        match (last, rest.contains('/')) {
+            // The path has more segments than the pattern
+            (true, true) => return None,
+            (true, false) => return Some(params),
+            // The pattern has more segments than the path
+            (false, false) => return None,
+            (false, true) => rest = tail,
+        }
+    }
+    Some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> Request {
+        let bytes = format!("{method} {path} HTTP/1.1\r\nHost: a\r\n\r\n");
+        Request::parse(bytes.as_bytes()).unwrap()
+    }
+
+    fn echo(name: &'static str) -> impl Fn(&Request, &Params) -> Response {
+        move |request, params| {
+            let values: Vec<_> = params
+                .values
+                .iter()
+                .map(|(param, value)| format!("{param}={value}"))
+                .collect();
+            Response::ok().text(format!("{name} {} {}", request.method(), values.join(",")))
+        }
+    }
+
+    fn body(router: &Router, method: &str, path: &str) -> String {
+        let response = router.handle(&request(method, path));
+        format!(
+            "{} {}",
+            response.status(),
+            String::from_utf8_lossy(response.body_bytes())
+        )
+    }
+
+    #[test]
+    fn matches_literals_params_and_wildcards() {
+        let router = Router::new()
+            .get("/", echo("root"))
+            .get("/users/new", echo("new"))
+            .get("/users/:id", echo("user"))
+            .get("/users/:id/posts/:post", echo("post"))
+            .get("/static/*path", echo("static"));
+
+        assert_eq!(body(&router, "GET", "/"), "200 root GET ");
+        assert_eq!(body(&router, "GET", "/users/new"), "200 new GET ");
+        assert_eq!(body(&router, "GET", "/users/42"), "200 user GET id=42");
+        assert_eq!(
+            body(&router, "GET", "/users/42/posts/7"),
+            "200 post GET id=42,post=7"
+        );
+        assert_eq!(
+            body(&router, "GET", "/static/css/site.css"),
+            "200 static GET path=css/site.css"
+        );
+        assert_eq!(body(&router, "GET", "/static/"), "200 static GET path=");
+
+        for path in [
+            "/users",
+            "/users/",
+            "/users/42/posts",
+            "/users/42/x",
+            "/static",
+            "/other",
+        ] {
+            assert_eq!(body(&router, "GET", path), "404 Not Found", "{path}");
+        }
+    }
+
+    #[test]
+    fn answers_wrong_methods_with_405() {
+        let router = Router::new()
+            .get("/items/:id", echo("get"))
+            .put("/items/:id", echo("put"))
+            .delete("/items/:id", echo("delete"));
+
+        assert_eq!(body(&router, "PUT", "/items/1"), "200 put PUT id=1");
+        // HEAD falls back to GET
+        assert_eq!(body(&router, "HEAD", "/items/1"), "200 get HEAD id=1");
+
+        let response = router.handle(&request("POST", "/items/1"));
+        assert_eq!(response.status(), 405);
+        assert_eq!(
+            response.headers().get("Allow"),
+            Some("DELETE, GET, HEAD, PUT")
+        );
+        assert_eq!(body(&router, "POST", "/nothing"), "404 Not Found");
+    }
+
+    #[test]
+    fn uses_the_first_matching_route_and_custom_not_found() {
+        let router = Router::new()
+            .get("/:page", echo("first"))
+            .get("/about", echo("second"))
+            .not_found(|_, _| Response::not_found().text("nope"));
+
+        assert_eq!(body(&router, "GET", "/about"), "200 first GET page=about");
+        assert_eq!(body(&router, "GET", "/a/b"), "404 nope");
+    }
+
+    #[test]
+    #[should_panic(expected = "before the end")]
+    fn rejects_wildcards_before_the_end() {
+        let _ = Router::new().get("/*path/edit", echo("edit"));
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    date,
+    request::{Method, Request},
+    response::Response,
+};
+
+/// Serves the files of a directory, for routes like `/static/*path`:
+///
+/// ```no_run
+/// use web_server::{Router, StaticFiles};
+///
+/// let files = StaticFiles::new("public");
+/// let router = Router::new().get("/static/*path", move |request, params| {
+///     files.serve(request, params.get("path").unwrap_or(""))
+/// });
+/// ```
+///
+/// Files are sent with a Content-Type guessed from their extension, an
+/// ETag and a Last-Modified date, so clients can revalidate them with a
+/// conditional request and get a 304 Not Modified. Single byte ranges are
+/// supported. A directory is served through its index file, if it has one.
+/// Paths can't lead outside the directory, with `..` or symbolic links.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+}
+
+/// The part of a file to send.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// First and last byte, included.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+impl StaticFiles {
+    /// Serve the files under `root`, with `index.html` as index file.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index_files: vec!["index.html".to_string()],
+        }
+    }
+
+    /// Look for these files, in order, when a directory is requested.
+    pub fn index_files<I, S>(mut self, names: I) -> StaticFiles
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.index_files = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Answer `request` with the file at `path`, relative to the root.
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        match self.try_serve(request, path) {
+            Ok(response) => response,
+            Err(err) => match err.kind() {
+                io::ErrorKind::NotFound => Response::not_found().text("Not Found"),
+                io::ErrorKind::PermissionDenied => Response::new(403).text("Forbidden"),
+                _ => {
+                    println!("Failed to serve {path}: {err}");
+                    Response::new(500).text("Internal Server Error")
+                }
+            },
+        }
+    }
+
+    fn try_serve(&self, request: &Request, path: &str) -> io::Result<Response> {
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            return Ok(Response::new(405)
+                .header("Allow", "GET, HEAD")
+                .text("Method Not Allowed"));
+        }
+        let Some(relative) = relative_path(path) else {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        };
+
+        let mut file_path = self.root.join(relative);
+        let mut metadata = fs::metadata(&file_path)?;
+        if metadata.is_dir() {
+            // Relative links of the index must resolve inside the directory
+            if !request.path().ends_with('/') {
+                // The path was decoded: spaces, `?` or CR/LF must not get
+                // into the header as they are
+                return Ok(Response::new(301)
+                    .header("Location", format!("{}/", percent_encode(request.path())))
+                    .text("Moved Permanently"));
+            }
+            file_path = self
+                .index_files
+                .iter()
+                .map(|name| file_path.join(name))
+                .find(|index| index.is_file())
+                .ok_or(io::ErrorKind::NotFound)?;
+            metadata = fs::metadata(&file_path)?;
+        }
+        // Symbolic links must not lead outside the root either
+        if !file_path
+            .canonicalize()?
+            .starts_with(self.root.canonicalize()?)
+        {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let len = metadata.len();
+        let modified = metadata.modified().ok().map(truncate_to_secs);
+        let etag = etag(len, modified);
+        let last_modified = modified.map(date::format);
+
+        let mut validators = Response::new(304).header("ETag", &etag);
+        if let Some(last_modified) = &last_modified {
+            validators = validators.header("Last-Modified", last_modified);
+        }
+        if is_not_modified(request, &etag, modified) {
+            return Ok(validators);
+        }
+
+        let (status, start, count) = match byte_range(request, len, &etag, &last_modified) {
+            ByteRange::Full => (200, 0, len),
+            ByteRange::Partial(first, last) => (206, first, last - first + 1),
+            ByteRange::Unsatisfiable => {
+                return Ok(Response::new(416)
+                    .header("Content-Range", format!("bytes */{len}"))
+                    .text("Range Not Satisfiable"))
+            }
+        };
+
+        let mut response = Response::new(status);
+        for (name, value) in validators.headers().iter() {
+            response = response.header(name, value);
+        }
+        response = response
+            .header("Content-Type", content_type(&file_path))
+            .header("Accept-Ranges", "bytes")
+            .header("Content-Length", count.to_string());
+        if status == 206 {
+            let last = start + count - 1;
+            response = response.header("Content-Range", format!("bytes {start}-{last}/{len}"));
+        }
+        if *request.method() == Method::Head {
+            return Ok(response);
+        }
+
+        // Read while the response is written, not kept in memory
+        let file = File::open(&file_path)?;
+        Ok(response.file(file, start, count))
+    }
+}
+
+/// Turn the path of a request into a path relative to the root, or `None`
+/// if it would lead outside of it.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            // Separators or drive prefixes on some systems
+            _ if segment.contains(['\\', ':', '\0']) => return None,
+            _ => relative.push(segment),
+        }
+    }
+    Some(relative)
+}
+
+/// Percent-encode a decoded path to use it in a URL again. Only `/` and
+/// the characters that are never special in a path are kept.
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// HTTP dates have a precision of one second.
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// An entity tag that changes with the size or modification time of a file.
+fn etag(len: u64, modified: Option<SystemTime>) -> String {
+    let secs = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs());
+    format!("\"{len:x}-{secs:x}\"")
+}
+
+/// Check the conditional headers of a request: `If-None-Match`, or else
+/// `If-Modified-Since`.
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = request.header("If-None-Match") {
+        // Weak comparison: W/ prefixes are ignored
+        let etag = etag.trim_start_matches("W/");
+        return tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    let since = request.header("If-Modified-Since").and_then(date::parse);
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// The part of a `len` bytes file a GET request asks for with `Range`.
+///
+/// Only single byte ranges are honored: anything else gets the whole file,
+/// as does a range made for another version of the file (`If-Range`).
+fn byte_range(
+    request: &Request,
+    len: u64,
+    etag: &str,
+    last_modified: &Option<String>,
+) -> ByteRange {
+    let Some(range) = request.header("Range") else {
+        return ByteRange::Full;
+    };
+    if *request.method() != Method::Get {
+        return ByteRange::Full;
+    }
+    if let Some(validator) = request.header("If-Range") {
+        let current = if validator.starts_with('"') {
+            validator == etag
+        } else {
+            Some(validator) == last_modified.as_deref()
+        };
+        if !current {
+            return ByteRange::Full;
+        }
+    }
+
+    let Some((first, last)) = range
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.trim().split_once('-'))
+    else {
+        return ByteRange::Full;
+    };
+
+    match (digits(first), digits(last)) {
+        // The last `suffix` bytes
+        (None, Some(suffix)) if first.is_empty() => {
+            if suffix == 0 || len == 0 {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(len - suffix.min(len), len - 1)
+            }
+        }
+        (Some(first), None) if last.is_empty() => {
+            if first < len {
+                ByteRange::Partial(first, len - 1)
+            } else {
+                ByteRange::Unsatisfiable
+            }
+        }
+        (Some(first), Some(last)) if first <= last => {
+            if first < len {
+                ByteRange::Partial(first, last.min(len - 1))
+            } else {
+                ByteRange::Unsatisfiable
+            }
+        }
+        _ => ByteRange::Full,
+    }
+}
+
+fn digits(text: &str) -> Option<u64> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Guess the media type of a file from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match &extension[..] {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        ops::Deref,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// A directory with a few files, unique to each test, removed when the
+    /// test ends.
+    struct Fixture {
+        files: StaticFiles,
+        dir: PathBuf,
+    }
+
+    impl Deref for Fixture {
+        type Target = StaticFiles;
+
+        fn deref(&self) -> &StaticFiles {
+            &self.files
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn fixture() -> Fixture {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "web-server-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        );
+        let dir = std::env::temp_dir().join(name);
+        let root = dir.join("public");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+
+        fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(root.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+        fs::write(root.join("logo.PNG"), (0..=255).collect::<Vec<u8>>()).unwrap();
+        // Outside the root, where requests must not reach
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        Fixture {
+            files: StaticFiles::new(root),
+            dir,
+        }
+    }
+
+    /// The body of `response` as it is sent, file bodies included.
+    fn body(response: &Response) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes, &Method::Get).unwrap();
+        let start = bytes.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        bytes.split_off(start)
+    }
+
+    fn get(files: &StaticFiles, target: &str, headers: &[(&str, &str)]) -> Response {
+        request(files, "GET", target, headers)
+    }
+
+    fn request(
+        files: &StaticFiles,
+        method: &str,
+        target: &str,
+        headers: &[(&str, &str)],
+    ) -> Response {
+        let headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect();
+        let bytes = format!("{method} {target} HTTP/1.1\r\nHost: a\r\n{headers}\r\n");
+        let request = Request::parse(bytes.as_bytes()).unwrap();
+        files.serve(&request, request.path())
+    }
+
+    #[test]
+    fn serves_bytes_with_content_type() {
+        let files = fixture();
+
+        let response = get(&files, "/logo.PNG", &[]);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("Content-Type"), Some("image/png"));
+        assert_eq!(body(&response), (0..=255).collect::<Vec<u8>>());
+        assert_eq!(response.headers().get("Content-Length"), Some("256"));
+        assert_eq!(response.headers().get("Accept-Ranges"), Some("bytes"));
+
+        let response = request(&files, "HEAD", "/logo.PNG", &[]);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("Content-Length"), Some("256"));
+        assert!(body(&response).is_empty());
+
+        assert_eq!(request(&files, "POST", "/logo.PNG", &[]).status(), 405);
+        assert_eq!(get(&files, "/missing.txt", &[]).status(), 404);
+    }
+
+    #[test]
+    fn streams_files_instead_of_loading_them() {
+        let files = fixture();
+        let content: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let path = files.root().join("large.bin");
+        fs::write(&path, &content).unwrap();
+
+        let response = get(&files, "/large.bin", &[]);
+        assert!(response.body_bytes().is_empty());
+        assert_eq!(body(&response), content);
+        let response = get(&files, "/large.bin", &[("Range", "bytes=100000-")]);
+        assert_eq!(body(&response), &content[100_000..]);
+
+        // Shrunk after the headers were made: the promised length can't be sent
+        let response = get(&files, "/large.bin", &[]);
+        fs::write(&path, &content[..10]).unwrap();
+        let mut bytes = Vec::new();
+        let err = response.write_to(&mut bytes, &Method::Get).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn serves_index_files_of_directories() {
+        let files = fixture();
+
+        assert_eq!(body(&get(&files, "/", &[])), b"<h1>Home</h1>");
+        assert_eq!(body(&get(&files, "/docs/", &[])), b"<h1>Docs</h1>");
+        let response = get(&files, "/docs", &[]);
+        assert_eq!(response.status(), 301);
+        assert_eq!(response.headers().get("Location"), Some("/docs/"));
+        // Redirected to the same directory, encoded again
+        #[cfg(unix)]
+        {
+            fs::create_dir_all(files.root().join("my docs?#\r\nX 1")).unwrap();
+            let response = get(&files, "/my%20docs%3F%23%0D%0AX%201", &[]);
+            assert_eq!(response.status(), 301);
+            assert_eq!(
+                response.headers().get("Location"),
+                Some("/my%20docs%3F%23%0D%0AX%201/")
+            );
+        }
+        // No listing of directories without index
+        assert_eq!(get(&files, "/empty/", &[]).status(), 404);
+
+        let indexed = StaticFiles::new(files.root()).index_files(["missing.html", "logo.PNG"]);
+        assert_eq!(get(&indexed, "/", &[]).status(), 200);
+        assert_eq!(
+            get(&indexed, "/", &[]).headers().get("Content-Type"),
+            Some("image/png")
+        );
+    }
+
+    #[test]
+    fn answers_conditional_requests_with_304() {
+        let files = fixture();
+        let response = get(&files, "/index.html", &[]);
+        let etag = response.headers().get("ETag").unwrap().to_string();
+        let last_modified = response.headers().get("Last-Modified").unwrap().to_string();
+
+        let response = get(&files, "/index.html", &[("If-None-Match", &etag)]);
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.headers().get("ETag"), Some(etag.as_str()));
+        assert!(body(&response).is_empty());
+        let weak = format!("\"other\", W/{etag}");
+        assert_eq!(
+            get(&files, "/index.html", &[("If-None-Match", &weak)]).status(),
+            304
+        );
+        assert_eq!(
+            get(&files, "/index.html", &[("If-None-Match", "*")]).status(),
+            304
+        );
+        assert_eq!(
+            get(&files, "/index.html", &[("If-None-Match", "\"other\"")]).status(),
+            200
+        );
+
+        let since = [("If-Modified-Since", last_modified.as_str())];
+        assert_eq!(get(&files, "/index.html", &since).status(), 304);
+        let since = [("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")];
+        assert_eq!(get(&files, "/index.html", &since).status(), 200);
+        // If-None-Match wins over If-Modified-Since
+        let both = [
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", last_modified.as_str()),
+        ];
+        assert_eq!(get(&files, "/index.html", &both).status(), 200);
+    }
+
+    #[test]
+    fn serves_byte_ranges() {
+        let files = fixture();
+        let range = |value: &str| get(&files, "/logo.PNG", &[("Range", value)]);
+
+        let response = range("bytes=10-19");
+        assert_eq!(response.status(), 206);
+        assert_eq!(body(&response), (10..20).collect::<Vec<u8>>());
+        assert_eq!(
+            response.headers().get("Content-Range"),
+            Some("bytes 10-19/256")
+        );
+        assert_eq!(response.headers().get("Content-Length"), Some("10"));
+
+        assert_eq!(body(&range("bytes=250-")), [250, 251, 252, 253, 254, 255]);
+        assert_eq!(body(&range("bytes=-2")), [254, 255]);
+        assert_eq!(body(&range("bytes=-1000")).len(), 256);
+        assert_eq!(body(&range("bytes=200-1000")).len(), 56);
+
+        let response = range("bytes=256-");
+        assert_eq!(response.status(), 416);
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes */256"));
+        assert_eq!(range("bytes=-0").status(), 416);
+
+        // Ignored: the whole file is sent
+        for value in ["bytes=5-1", "bytes=0-1,5-6", "lines=1-2", "bytes=x-"] {
+            assert_eq!(range(value).status(), 200, "{value}");
+        }
+        let stale = [("Range", "bytes=0-1"), ("If-Range", "\"old\"")];
+        assert_eq!(get(&files, "/logo.PNG", &stale).status(), 200);
+        let etag = range("bytes=0-1")
+            .headers()
+            .get("ETag")
+            .unwrap()
+            .to_string();
+        let fresh = [("Range", "bytes=0-1"), ("If-Range", etag.as_str())];
+        assert_eq!(get(&files, "/logo.PNG", &fresh).status(), 206);
+    }
+
+    #[test]
+    fn stays_inside_the_root() {
+        let files = fixture();
+
+        for target in [
+            "/../secret.txt",
+            "/docs/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/..%5Csecret.txt",
+        ] {
+            assert_eq!(get(&files, target, &[]).status(), 403, "{target}");
+        }
+        // Still fine when it stays inside
+        assert_eq!(get(&files, "/docs/./index.html", &[]).status(), 200);
+
+        #[cfg(unix)]
+        {
+            let link = files.root().join("link.txt");
+            std::os::unix::fs::symlink(files.root().join("../secret.txt"), link).unwrap();
+            assert_eq!(get(&files, "/link.txt", &[]).status(), 403);
+        }
+    }
+
+    #[test]
+    fn guesses_content_types() {
+        assert_eq!(
+            content_type(Path::new("a/b.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(content_type(Path::new("logo.SVG")), "image/svg+xml");
+        assert_eq!(
+            content_type(Path::new("archive.tar.gz")),
+            "application/gzip"
+        );
+        assert_eq!(
+            content_type(Path::new("Makefile")),
+            "application/octet-stream"
+        );
+    }
+}
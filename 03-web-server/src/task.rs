@@ -0,0 +1,283 @@
+//! Jobs with results: `ThreadPool::spawn` and `ThreadPool::scope`.
+//!
+//! Waiting on a job from inside another job of the same pool can deadlock
+//! when every worker is waiting, so join from outside the pool.
+
+use std::{
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, PoisonError,
+    },
+    thread,
+};
+
+use crate::{Job, ThreadPool};
+
+/// Owned permission to wait for a job spawned with `ThreadPool::spawn`.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    result: mpsc::Receiver<thread::Result<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Wait for the job, and get what it returned, or what it panicked
+    /// with.
+    pub fn join(self) -> thread::Result<T> {
+        receive(&self.result)
+    }
+}
+
+/// Wait for every job of `handles`, and get their results in order.
+pub fn join_all<T>(handles: impl IntoIterator<Item = JoinHandle<T>>) -> Vec<thread::Result<T>> {
+    handles.into_iter().map(JoinHandle::join).collect()
+}
+
+/// Jobs that may borrow from outside, created by `ThreadPool::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    data: Arc<ScopeData>,
+    // Invariant lifetimes, like `std::thread::Scope`
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// Permission to wait for a job spawned with `Scope::spawn`.
+#[derive(Debug)]
+pub struct ScopedJoinHandle<'scope, T> {
+    result: mpsc::Receiver<thread::Result<T>>,
+    data: Arc<ScopeData>,
+    scope: PhantomData<&'scope ()>,
+}
+
+/// What a scope knows about its jobs.
+#[derive(Debug, Default)]
+struct ScopeData {
+    /// Jobs not finished yet
+    running: Mutex<usize>,
+    finished: Condvar,
+    /// Jobs that panicked, without their handle being joined
+    unjoined_panics: AtomicUsize,
+}
+
+impl ThreadPool {
+    /// Run `f` on a worker, and get a handle to wait for its result.
+    ///
+    /// ```
+    /// use web_server::{join_all, ThreadPool};
+    ///
+    /// let pool = ThreadPool::build(4).unwrap_or_else(|_| panic!());
+    /// let squares: Vec<_> = (1..=4).map(|i| pool.spawn(move || i * i)).collect();
+    /// let squares: Vec<u64> = join_all(squares).into_iter().map(Result::unwrap).collect();
+    /// assert_eq!(squares, [1, 4, 9, 16]);
+    /// ```
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, result) = mpsc::channel();
+        self.execute(move || {
+            // Nobody may wait for the result: that's fine
+            let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        JoinHandle { result }
+    }
+
+    /// Run jobs that borrow local data, waiting for all of them before
+    /// returning, like `std::thread::scope`:
+    ///
+    /// ```
+    /// use web_server::ThreadPool;
+    ///
+    /// let pool = ThreadPool::build(4).unwrap_or_else(|_| panic!());
+    /// let mut numbers = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    /// let total: u64 = pool.scope(|scope| {
+    ///     let sums: Vec<_> = numbers
+    ///         .chunks_mut(3)
+    ///         .map(|chunk| {
+    ///             scope.spawn(move || {
+    ///                 chunk.iter_mut().for_each(|n| *n *= 10);
+    ///                 chunk.iter().sum::<u64>()
+    ///             })
+    ///         })
+    ///         .collect();
+    ///     sums.into_iter().map(|sum| sum.join().unwrap()).sum()
+    /// });
+    /// assert_eq!(total, 360);
+    /// assert_eq!(numbers[7], 80);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` panics, or if a job panicked and its handle wasn't
+    /// joined.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            data: Arc::new(ScopeData::default()),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        // Even on panic: the jobs still borrow what `f` could see
+        scope.data.wait();
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.data.unjoined_panics.load(Ordering::SeqCst) > 0 => {
+                panic!("a job of the scope panicked")
+            }
+            Ok(result) => result,
+        }
+    }
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Run `f` on a worker of the pool. It can borrow anything that
+    /// outlives the scope.
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (sender, result) = mpsc::channel();
+        let data = Arc::clone(&self.data);
+        *data.running.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+            if outcome.is_err() {
+                data.unjoined_panics.fetch_add(1, Ordering::SeqCst);
+            }
+            // Dropped right away, with what it borrows, if the handle is gone
+            let _ = sender.send(outcome);
+            drop(sender);
+            data.finish();
+        });
+        // SAFETY: `ThreadPool::scope` doesn't return before every job of
+        // the scope called `finish`, after dropping everything borrowed for
+        // 'scope. Jobs can't outlive the pool either: it's borrowed.
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.send(job);
+
+        ScopedJoinHandle {
+            result,
+            data: Arc::clone(&self.data),
+            scope: PhantomData,
+        }
+    }
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    /// Wait for the job, and get what it returned, or what it panicked
+    /// with. A panic taken here doesn't make the scope panic.
+    pub fn join(self) -> thread::Result<T> {
+        let result = receive(&self.result);
+        if result.is_err() {
+            self.data.unjoined_panics.fetch_sub(1, Ordering::SeqCst);
+        }
+        result
+    }
+}
+
+impl ScopeData {
+    fn finish(&self) {
+        let mut running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        *running -= 1;
+        if *running == 0 {
+            self.finished.notify_all();
+        }
+    }
+
+    /// Block until every job of the scope finished.
+    fn wait(&self) {
+        let running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        let _running = self
+            .finished
+            .wait_while(running, |running| *running > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+fn receive<T>(result: &mpsc::Receiver<thread::Result<T>>) -> thread::Result<T> {
+    // Jobs are only dropped unrun when the pool is, which waits for them
+    result.recv().expect("the job was dropped without running")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::atomic::AtomicBool, time::Duration};
+
+    #[test]
+    fn spawned_jobs_return_results_or_panics() {
+        let pool = ThreadPool::build(2).unwrap();
+
+        let handles: Vec<_> = (0..10).map(|i| pool.spawn(move || i * 2)).collect();
+        let results: Vec<_> = join_all(handles).into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+
+        let payload = pool.spawn(|| panic!("boom")).join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+        // The pool still works
+        assert_eq!(pool.spawn(|| "fine").join().unwrap(), "fine");
+    }
+
+    #[test]
+    fn scoped_jobs_borrow_local_data() {
+        let pool = ThreadPool::build(3).unwrap();
+        let words = ["a".to_string(), "bb".to_string(), "ccc".to_string()];
+        let mut lengths = vec![0; words.len()];
+
+        pool.scope(|scope| {
+            for (word, length) in words.iter().zip(&mut lengths) {
+                // Not joined: the scope waits anyway
+                scope.spawn(move || {
+                    thread::sleep(Duration::from_millis(20));
+                    *length = word.len();
+                });
+            }
+        });
+        assert_eq!(lengths, [1, 2, 3]);
+
+        let total = pool.scope(|scope| {
+            let handle = scope.spawn(|| words.iter().map(String::len).sum::<usize>());
+            handle.join().unwrap()
+        });
+        assert_eq!(total, 6);
+    }
+
+    #[test]
+    fn joined_panics_are_handled() {
+        let pool = ThreadPool::build(2).unwrap();
+        let message = pool.scope(|scope| {
+            let handle = scope.spawn(|| -> () { panic!("handled") });
+            handle.join().unwrap_err().downcast::<&str>().unwrap()
+        });
+        assert_eq!(*message, "handled");
+    }
+
+    #[test]
+    fn scope_waits_for_jobs_then_panics_on_unjoined_panics() {
+        let pool = ThreadPool::build(2).unwrap();
+        let finished = AtomicBool::new(false);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|scope| {
+                scope.spawn(|| panic!("unjoined"));
+                scope.spawn(|| {
+                    thread::sleep(Duration::from_millis(50));
+                    finished.store(true, Ordering::SeqCst);
+                });
+            })
+        }));
+        assert!(result.is_err());
+        assert!(finished.load(Ordering::SeqCst));
+    }
+}
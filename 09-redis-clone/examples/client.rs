@@ -0,0 +1,25 @@
+//! Several tasks sharing one pipelined connection to a running server.
+//!
+//! ```text
+//! cargo run --bin server &
+//! cargo run --example client
+//! ```
+
+use redis_clone::Client;
+
+#[tokio::main]
+async fn main() -> redis_clone::Result<()> {
+    let client = Client::connect("127.0.0.1:6379").await?;
+
+    // Handles are cheap to clone: every task gets its own
+    let setter = client.clone();
+    let t1 = tokio::spawn(async move { setter.set("foo", "bar").await });
+    let getter = client.clone();
+    let t2 = tokio::spawn(async move { getter.get("foo").await });
+
+    println!("SET: {:?}", t1.await?);
+    println!("GET: {:?}", t2.await?);
+    println!("GET: {:?}", client.get("foo").await?);
+
+    Ok(())
+}
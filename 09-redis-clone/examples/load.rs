@@ -0,0 +1,102 @@
+//! Load benchmark: many concurrent clients hammering an in-process server.
+//!
+//! The same workload runs against a single-lock keyspace (1 shard), then
+//! against a sharded one, and the throughput of both is printed.
+//!
+//! ```text
+//! cargo run --release --example load -- [clients] [seconds] [shards]
+//! ```
+
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use mini_redis::Frame;
+use redis_clone::{server, Config, Connection};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Distinct keys touched by the clients
+const KEYS: u64 = 10_000;
+
+#[tokio::main]
+async fn main() -> redis_clone::Result<()> {
+    let mut args = env::args().skip(1).map(|arg| arg.parse::<u64>());
+    let clients = args.next().transpose()?.unwrap_or(64);
+    let seconds = args.next().transpose()?.unwrap_or(5);
+    let shards = args.next().transpose()?.unwrap_or(16) as usize;
+
+    println!("{} clients, {}s per run", clients, seconds);
+    let before = run(1, clients, Duration::from_secs(seconds)).await?;
+    println!("  1 shard:   {:>10.0} ops/s", before);
+    let after = run(shards, clients, Duration::from_secs(seconds)).await?;
+    println!(
+        "{:>3} shards: {:>10.0} ops/s ({:+.1}%)",
+        shards,
+        after,
+        (after / before - 1.0) * 100.0
+    );
+
+    Ok(())
+}
+
+/// Run the workload against a fresh server and return its throughput
+async fn run(shards: usize, clients: u64, duration: Duration) -> redis_clone::Result<f64> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let config = Config {
+        dir: env::temp_dir(),
+        shards,
+        ..Config::default()
+    };
+    let server = tokio::spawn(server::run(listener, config, std::future::pending::<()>()));
+
+    let done = Arc::new(AtomicBool::new(false));
+    let ops = Arc::new(AtomicU64::new(0));
+
+    let mut tasks = Vec::new();
+    for client in 0..clients {
+        let mut conn = Connection::new(TcpStream::connect(addr).await?);
+        let (done, ops) = (done.clone(), ops.clone());
+        tasks.push(tokio::spawn(async move {
+            // Each client walks the keys from its own offset: half SET, half GET
+            let mut i = client * 7919;
+            while !done.load(Ordering::Relaxed) {
+                let key = format!("key:{}", i % KEYS);
+                let frame = match i % 2 {
+                    0 => command(&["SET", &key, "value"]),
+                    _ => command(&["GET", &key]),
+                };
+                conn.write_frame(&frame).await?;
+                conn.read_frame().await?;
+                ops.fetch_add(1, Ordering::Relaxed);
+                i += 1;
+            }
+            Ok::<_, redis_clone::Error>(())
+        }));
+    }
+
+    let start = Instant::now();
+    tokio::time::sleep(duration).await;
+    done.store(true, Ordering::Relaxed);
+    for task in tasks {
+        task.await??;
+    }
+    let elapsed = start.elapsed();
+    server.abort();
+
+    Ok(ops.load(Ordering::Relaxed) as f64 / elapsed.as_secs_f64())
+}
+
+fn command(parts: &[&str]) -> Frame {
+    Frame::Array(
+        parts
+            .iter()
+            .map(|part| Frame::Bulk(part.to_string().into()))
+            .collect(),
+    )
+}
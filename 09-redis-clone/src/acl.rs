@@ -0,0 +1,480 @@
+//! Users and what they may do, for AUTH and ACL.
+//!
+//! Each connection runs as a user. The `default` user has every
+//! permission; it is the user of new connections, unless it has a
+//! password (`requirepass`), in which case they must AUTH first. ACL
+//! SETUSER creates users or changes them with rules, applied in order:
+//!
+//! - `on` / `off` enable or disable the user, `reset` removes everything
+//! - `>password` adds a password, `<password` removes it, `nopass` lets
+//!   any password in, `resetpass` removes them all
+//! - `+@category` / `-@category` allow or deny a category of commands,
+//!   `+command` / `-command` a single one. `allcommands` is `+@all`,
+//!   `nocommands` is `-@all`.
+//! - `~pattern` allows the keys matching a glob pattern, `allkeys` is `~*`,
+//!   `resetkeys` denies every key
+//!
+//! Commands without keys (e.g. KEYS or DBSIZE) only need the command to be
+//! allowed.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem,
+    sync::Arc,
+};
+
+use crate::{glob::glob_match, script::sha1_hex, Command};
+
+/// Error for commands sent before AUTH
+pub const NOAUTH: &str = "NOAUTH Authentication required.";
+
+/// Error for AUTH with a wrong password, or a disabled user
+pub const WRONGPASS: &str = "WRONGPASS invalid username-password pair or user is disabled.";
+
+/// Name of the user of new connections
+pub const DEFAULT_USER: &str = "default";
+
+/// Categories of commands, as used by `+@category`. `all` is every command.
+const CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "read",
+        &[
+            "get", "exists", "type", "keys", "scan", "dbsize", "zscore", "zrange", "zrank", "llen",
+            "lrange", "xlen", "xrange", "xread", "xpending",
+        ],
+    ),
+    (
+        "write",
+        &[
+            "set",
+            "expire",
+            "del",
+            "rename",
+            "zadd",
+            "zrem",
+            "lpush",
+            "rpush",
+            "lpop",
+            "rpop",
+            "blpop",
+            "brpop",
+            "xadd",
+            "xreadgroup",
+            "xgroup",
+            "xack",
+        ],
+    ),
+    (
+        "keyspace",
+        &[
+            "expire", "del", "exists", "type", "rename", "keys", "scan", "dbsize",
+        ],
+    ),
+    ("string", &["get", "set"]),
+    ("sortedset", &["zadd", "zscore", "zrange", "zrank", "zrem"]),
+    (
+        "list",
+        &[
+            "lpush", "rpush", "lpop", "rpop", "llen", "lrange", "blpop", "brpop",
+        ],
+    ),
+    (
+        "stream",
+        &[
+            "xadd",
+            "xlen",
+            "xrange",
+            "xread",
+            "xreadgroup",
+            "xgroup",
+            "xack",
+            "xpending",
+        ],
+    ),
+    ("blocking", &["blpop", "brpop", "xread", "xreadgroup"]),
+    (
+        "pubsub",
+        &[
+            "publish",
+            "subscribe",
+            "unsubscribe",
+            "psubscribe",
+            "punsubscribe",
+        ],
+    ),
+    (
+        "transaction",
+        &["multi", "exec", "discard", "watch", "unwatch"],
+    ),
+    ("scripting", &["eval", "script"]),
+    ("connection", &["auth", "client", "ping"]),
+    (
+        "admin",
+        &[
+            "save",
+            "bgsave",
+            "bgrewriteaof",
+            "info",
+            "client",
+            "replicaof",
+            "sync",
+            "acl",
+        ],
+    ),
+    (
+        "dangerous",
+        &[
+            "keys",
+            "save",
+            "bgsave",
+            "bgrewriteaof",
+            "info",
+            "client",
+            "replicaof",
+            "sync",
+            "acl",
+        ],
+    ),
+];
+
+/// Every command name
+fn all_commands() -> impl Iterator<Item = &'static str> {
+    CATEGORIES
+        .iter()
+        .flat_map(|(_, commands)| commands.iter().copied())
+}
+
+/// Names of the categories, for ACL CAT
+pub fn categories() -> impl Iterator<Item = &'static str> {
+    CATEGORIES.iter().map(|(name, _)| *name)
+}
+
+/// Commands of `category`, or `None` if it does not exist
+pub fn category(name: &str) -> Option<BTreeSet<&'static str>> {
+    if name == "all" {
+        return Some(all_commands().collect());
+    }
+    let (_, commands) = CATEGORIES.iter().find(|(other, _)| *other == name)?;
+    Some(commands.iter().copied().collect())
+}
+
+/// A user: how it authenticates and what it may run
+#[derive(Debug, Clone, Default)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    // Any password is accepted
+    nopass: bool,
+    // SHA1 of the passwords, so they are not kept in memory
+    passwords: BTreeSet<String>,
+    commands: BTreeSet<&'static str>,
+    key_patterns: Vec<String>,
+}
+
+impl User {
+    /// A disabled user, without passwords or permissions
+    pub fn new(name: impl Into<String>) -> User {
+        User {
+            name: name.into(),
+            ..User::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check `password` against those of the user
+    pub fn check_password(&self, password: &str) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&sha1_hex(password)))
+    }
+
+    /// Check if the user may run the command named `name`, in lowercase
+    pub fn can_run(&self, name: &str) -> bool {
+        self.commands.contains(name)
+    }
+
+    /// Check if the user may read or write `key`
+    pub fn can_access(&self, key: &str) -> bool {
+        self.key_patterns
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), key.as_bytes()))
+    }
+
+    /// Check if the user may access every key
+    pub fn all_keys(&self) -> bool {
+        self.key_patterns.iter().any(|pattern| pattern == "*")
+    }
+
+    /// Check that the user may run `cmd` on its keys, with a NOPERM error
+    /// if not. Unknown commands are left to fail on their own.
+    pub fn check(&self, cmd: &Command) -> crate::Result<()> {
+        if let Command::Unknown(_) = cmd {
+            return Ok(());
+        }
+
+        let name = cmd.get_name();
+        if !self.can_run(name) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                self.name, name
+            )
+            .into());
+        }
+        if !self.all_keys() && cmd.keys().iter().any(|key| !self.can_access(key)) {
+            return Err("NOPERM No permissions to access a key".into());
+        }
+        Ok(())
+    }
+
+    /// Apply one ACL SETUSER rule
+    pub fn apply_rule(&mut self, rule: &str) -> crate::Result<()> {
+        match &rule.to_lowercase()[..] {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allcommands" => self.commands = all_commands().collect(),
+            "nocommands" => self.commands.clear(),
+            "allkeys" => self.key_patterns = vec!["*".to_string()],
+            "resetkeys" => self.key_patterns.clear(),
+            "reset" => *self = User::new(mem::take(&mut self.name)),
+            _ => return self.apply_argument_rule(rule),
+        }
+        Ok(())
+    }
+
+    /// Apply a rule with an argument, like `>password` or `+@read`
+    fn apply_argument_rule(&mut self, rule: &str) -> crate::Result<()> {
+        let invalid = || format!("ERR Error in ACL SETUSER modifier '{}': Syntax error", rule);
+
+        if let Some(password) = rule.strip_prefix('>') {
+            self.nopass = false;
+            self.passwords.insert(sha1_hex(password));
+        } else if let Some(password) = rule.strip_prefix('<') {
+            self.passwords.remove(&sha1_hex(password));
+        } else if let Some(pattern) = rule.strip_prefix('~') {
+            self.key_patterns.push(pattern.to_string());
+        } else if let Some(name) = rule.strip_prefix("+@") {
+            let commands = category(&name.to_lowercase()).ok_or_else(invalid)?;
+            self.commands.extend(commands);
+        } else if let Some(name) = rule.strip_prefix("-@") {
+            let commands = category(&name.to_lowercase()).ok_or_else(invalid)?;
+            self.commands.retain(|command| !commands.contains(command));
+        } else if let Some(name) = rule.strip_prefix('+') {
+            let command = find_command(name).ok_or_else(invalid)?;
+            self.commands.insert(command);
+        } else if let Some(name) = rule.strip_prefix('-') {
+            let command = find_command(name).ok_or_else(invalid)?;
+            self.commands.remove(command);
+        } else {
+            return Err(invalid().into());
+        }
+        Ok(())
+    }
+
+    /// The user and the rules that rebuild it, as listed by ACL LIST
+    pub fn describe(&self) -> String {
+        let mut rules = vec![
+            format!("user {}", self.name),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        rules.extend(
+            self.key_patterns
+                .iter()
+                .map(|pattern| format!("~{}", pattern)),
+        );
+
+        if self.commands.len() == all_commands().collect::<BTreeSet<_>>().len() {
+            rules.push("+@all".to_string());
+        } else {
+            rules.push("-@all".to_string());
+            rules.extend(self.commands.iter().map(|command| format!("+{}", command)));
+        }
+        rules.join(" ")
+    }
+}
+
+fn find_command(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    all_commands().find(|command| *command == name)
+}
+
+/// Every user, by name
+#[derive(Debug)]
+pub struct Acl {
+    // Shared with the connections of each user, and replaced on change
+    users: BTreeMap<String, Arc<User>>,
+}
+
+impl Acl {
+    /// Users of a new server: `default`, with `requirepass` as its password
+    /// if given
+    pub fn new(requirepass: Option<&str>) -> Acl {
+        let mut default = User::new(DEFAULT_USER);
+        let password = requirepass.map(|password| format!(">{}", password));
+        let rules = ["on", "allcommands", "allkeys"]
+            .into_iter()
+            .map(str::to_string)
+            .chain(Some(password.unwrap_or_else(|| "nopass".to_string())));
+        for rule in rules {
+            default.apply_rule(&rule).unwrap();
+        }
+
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), Arc::new(default));
+        Acl { users }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<User>> {
+        self.users.get(name).cloned()
+    }
+
+    /// User of new connections, if they need no AUTH
+    pub fn default_login(&self) -> Option<&'static str> {
+        let default = self.users.get(DEFAULT_USER)?;
+        (default.enabled && default.nopass).then_some(DEFAULT_USER)
+    }
+
+    /// Check a username and password. Returns the user if they match.
+    pub fn authenticate(&self, name: &str, password: &str) -> Option<Arc<User>> {
+        self.get(name).filter(|user| user.check_password(password))
+    }
+
+    /// Create the user `name` if needed, and apply `rules` to it. Nothing
+    /// changes if a rule is invalid.
+    pub fn set_user(&mut self, name: &str, rules: &[String]) -> crate::Result<()> {
+        let mut user = self
+            .users
+            .get(name)
+            .map_or_else(|| User::new(name), |user| (**user).clone());
+        for rule in rules {
+            user.apply_rule(rule)?;
+        }
+        self.users.insert(name.to_string(), Arc::new(user));
+        Ok(())
+    }
+
+    /// Delete the user `name`. Returns `false` if it does not exist.
+    pub fn delete_user(&mut self, name: &str) -> bool {
+        self.users.remove(name).is_some()
+    }
+
+    /// Every user, in name order
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values().map(|user| &**user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(rules: &str) -> User {
+        let mut user = User::new("test");
+        for rule in rules.split_whitespace() {
+            user.apply_rule(rule).unwrap();
+        }
+        user
+    }
+
+    #[test]
+    fn categories_and_commands() {
+        let reader = user("on +@read -keys +set");
+        assert!(reader.can_run("get"));
+        assert!(reader.can_run("xrange"));
+        assert!(reader.can_run("set"));
+        assert!(!reader.can_run("keys"));
+        assert!(!reader.can_run("del"));
+
+        let admin = user("allcommands -@dangerous");
+        assert!(admin.can_run("del"));
+        assert!(!admin.can_run("acl"));
+        assert!(!user("allcommands nocommands").can_run("get"));
+
+        assert!(user("").apply_rule("+@nope").is_err());
+        assert!(user("").apply_rule("+nope").is_err());
+        assert!(user("").apply_rule("whatever").is_err());
+        assert_eq!(user("allcommands reset").describe(), "user test off -@all");
+    }
+
+    #[test]
+    fn commands_are_checked_with_their_keys() {
+        let check = |user: &User, line: &str| {
+            let frame = mini_redis::Frame::Array(
+                line.split_whitespace()
+                    .map(|arg| mini_redis::Frame::Bulk(arg.to_string().into()))
+                    .collect(),
+            );
+            user.check(&Command::from_frame(frame).unwrap())
+                .map_err(|err| err.to_string())
+        };
+
+        let cache = user("on +@string +del ~cache:*");
+        assert!(check(&cache, "SET cache:a 1").is_ok());
+        assert!(check(&cache, "DEL cache:a cache:b").is_ok());
+        assert!(check(&cache, "NOPE").is_ok(), "unknown commands fail later");
+        let err = check(&cache, "DEL cache:a users").unwrap_err();
+        assert_eq!(err, "NOPERM No permissions to access a key");
+        let err = check(&cache, "LPUSH cache:a 1").unwrap_err();
+        assert_eq!(
+            err,
+            "NOPERM User test has no permissions to run the 'lpush' command"
+        );
+    }
+
+    #[test]
+    fn key_patterns() {
+        let cache = user("~cache:* ~session:?");
+        assert!(cache.can_access("cache:users"));
+        assert!(cache.can_access("session:1"));
+        assert!(!cache.can_access("session:10"));
+        assert!(!cache.all_keys());
+        assert!(user("~a allkeys").all_keys());
+        assert!(!user("allkeys resetkeys").can_access("a"));
+    }
+
+    #[test]
+    fn passwords() {
+        let alice = user("on >secret >other <other");
+        assert!(alice.check_password("secret"));
+        assert!(!alice.check_password("other"));
+        assert!(!user(">secret").check_password("secret"), "disabled");
+        assert!(user("on nopass").check_password("anything"));
+        assert!(!user("on nopass resetpass").check_password("anything"));
+        // Hashes only
+        assert!(!alice.describe().contains("secret"));
+    }
+
+    #[test]
+    fn default_user() {
+        let acl = Acl::new(None);
+        assert_eq!(acl.default_login(), Some(DEFAULT_USER));
+        assert!(acl.authenticate(DEFAULT_USER, "anything").is_some());
+
+        let mut acl = Acl::new(Some("secret"));
+        assert_eq!(acl.default_login(), None);
+        assert!(acl.authenticate(DEFAULT_USER, "secret").is_some());
+        assert!(acl.authenticate(DEFAULT_USER, "wrong").is_none());
+        assert_eq!(
+            acl.get(DEFAULT_USER).unwrap().describe(),
+            format!("user default on #{} ~* +@all", sha1_hex("secret"))
+        );
+
+        // An invalid rule changes nothing
+        let rules = ["on".to_string(), "+@nope".to_string()];
+        assert!(acl.set_user("alice", &rules).is_err());
+        assert!(acl.get("alice").is_none());
+        assert!(acl.delete_user(DEFAULT_USER));
+    }
+}
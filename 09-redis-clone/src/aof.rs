@@ -0,0 +1,457 @@
+//! Append-only file: every write command is logged as RESP, and the log is
+//! replayed at startup to rebuild the keyspace.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Cursor, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use mini_redis::{frame::Error::Incomplete, Frame};
+use tracing::warn;
+
+use crate::{
+    cmd::command_frame,
+    db::{to_unix_ms, Keyspace, Value},
+    stream::{Stream, StreamId},
+    AppendFsync, Command,
+};
+
+/// Members per ZADD or RPUSH when rewriting big sorted sets or lists
+const REWRITE_BATCH: usize = 64;
+
+/// Open append-only file
+#[derive(Debug)]
+pub struct Aof {
+    path: PathBuf,
+    file: File,
+    fsync: AppendFsync,
+    // While BGREWRITEAOF runs, new writes are also kept here, to be appended
+    // to the rewritten file before it replaces this one
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl Aof {
+    /// Open (or create) the file at `path` for appending
+    pub fn open(path: &Path, fsync: AppendFsync) -> io::Result<Aof> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Aof {
+            path: path.to_path_buf(),
+            file,
+            fsync,
+            rewrite_buffer: None,
+        })
+    }
+
+    /// Log a write command
+    pub fn append(&mut self, frame: &Frame) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode(frame, &mut buf);
+
+        self.file.write_all(&buf)?;
+        if let Some(rewrite_buffer) = &mut self.rewrite_buffer {
+            rewrite_buffer.extend_from_slice(&buf);
+        }
+        if self.fsync == AppendFsync::Always {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Handle to the file, to fsync it without holding the `Aof` lock
+    pub fn file(&self) -> io::Result<File> {
+        self.file.try_clone()
+    }
+
+    /// Start buffering writes for a rewrite
+    pub(crate) fn start_rewrite(&mut self) {
+        self.rewrite_buffer = Some(Vec::new());
+    }
+
+    /// Stop buffering writes after a failed rewrite
+    pub(crate) fn abort_rewrite(&mut self) {
+        self.rewrite_buffer = None;
+    }
+
+    /// Finish a rewrite: append the writes received meanwhile to `tmp`, then
+    /// atomically replace the log with it.
+    pub(crate) fn finish_rewrite(&mut self, tmp: &Path) -> io::Result<()> {
+        let pending = self.rewrite_buffer.take().unwrap_or_default();
+
+        let mut file = OpenOptions::new().append(true).open(tmp)?;
+        file.write_all(&pending)?;
+        file.sync_data()?;
+
+        fs::rename(tmp, &self.path)?;
+        self.file = file;
+        Ok(())
+    }
+}
+
+/// Encode `frame` as RESP into `buf`
+pub(crate) fn encode(frame: &Frame, buf: &mut Vec<u8>) {
+    match frame {
+        Frame::Simple(val) => {
+            buf.push(b'+');
+            buf.extend_from_slice(val.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+        Frame::Error(val) => {
+            buf.push(b'-');
+            buf.extend_from_slice(val.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+        Frame::Integer(val) => {
+            buf.extend_from_slice(format!(":{}\r\n", val).as_bytes());
+        }
+        Frame::Null => buf.extend_from_slice(b"$-1\r\n"),
+        Frame::Bulk(val) => {
+            buf.extend_from_slice(format!("${}\r\n", val.len()).as_bytes());
+            buf.extend_from_slice(val);
+            buf.extend_from_slice(b"\r\n");
+        }
+        Frame::Array(val) => {
+            buf.extend_from_slice(format!("*{}\r\n", val.len()).as_bytes());
+            for entry in val {
+                encode(entry, buf);
+            }
+        }
+    }
+}
+
+/// The shortest log that rebuilds the keyspace `shards`: one command per key
+/// (or batch of members).
+pub fn rewrite<'a>(shards: impl IntoIterator<Item = &'a Keyspace>) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    for (key, value, expires_at) in shards.into_iter().flat_map(Keyspace::iter) {
+        let key = Bytes::from(key.clone());
+        match value {
+            Value::String(data) => {
+                let frame = command_frame([Bytes::from("SET"), key.clone(), data.clone()]);
+                encode(&frame, &mut buf);
+            }
+            Value::SortedSet(set) => {
+                let members: Vec<_> = set.iter().collect();
+                for batch in members.chunks(REWRITE_BATCH) {
+                    let mut parts = vec![Bytes::from("ZADD"), key.clone()];
+                    for (member, score) in batch {
+                        parts.push(Bytes::from(score.to_string()));
+                        parts.push((*member).clone());
+                    }
+                    encode(&command_frame(parts), &mut buf);
+                }
+            }
+            Value::Stream(stream) => rewrite_stream(&key, stream, &mut buf),
+            Value::List(list) => {
+                let items: Vec<_> = list.iter().collect();
+                for batch in items.chunks(REWRITE_BATCH) {
+                    let mut parts = vec![Bytes::from("RPUSH"), key.clone()];
+                    parts.extend(batch.iter().map(|item| (*item).clone()));
+                    encode(&command_frame(parts), &mut buf);
+                }
+            }
+        }
+
+        if let Some(at) = expires_at {
+            let at = Bytes::from(to_unix_ms(at).to_string());
+            let frame = command_frame([Bytes::from("PEXPIREAT"), key, at]);
+            encode(&frame, &mut buf);
+        }
+    }
+
+    buf
+}
+
+/// Rebuild a stream: its entries with their IDs, then each group by
+/// reading its entries again.
+///
+/// Consumers without pending entries are not kept, and the entries pending
+/// are as if delivered once, at replay time.
+fn rewrite_stream(key: &Bytes, stream: &Stream, buf: &mut Vec<u8>) {
+    for (id, fields) in stream.iter() {
+        let mut parts = vec![
+            Bytes::from("XADD"),
+            key.clone(),
+            Bytes::from(id.to_string()),
+        ];
+        for (field, value) in fields {
+            parts.push(field.clone());
+            parts.push(value.clone());
+        }
+        encode(&command_frame(parts), buf);
+    }
+
+    for (name, group) in stream.groups() {
+        let xgroup = |subcommand: &str, id: StreamId| {
+            let mut parts = vec![
+                Bytes::from("XGROUP"),
+                Bytes::from(subcommand.to_string()),
+                key.clone(),
+                Bytes::from(name.to_string()),
+                Bytes::from(id.to_string()),
+            ];
+            if subcommand == "CREATE" && stream.is_empty() {
+                parts.push(Bytes::from("MKSTREAM"));
+            }
+            command_frame(parts)
+        };
+
+        let pending = group.pending();
+        let Some((first, pending_for)) = pending.first_key_value() else {
+            encode(&xgroup("CREATE", group.last_delivered()), buf);
+            continue;
+        };
+        // Start right before the first pending entry, and deliver every
+        // entry up to the last delivered one: to their consumer if pending,
+        // without tracking them otherwise
+        let start = stream
+            .range(Bound::Unbounded, Bound::Excluded(*first))
+            .next_back()
+            .map_or(StreamId::MIN, |(id, _)| id);
+        encode(&xgroup("CREATE", start), buf);
+
+        let delivered = stream.range(
+            Bound::Excluded(start),
+            Bound::Included(group.last_delivered()),
+        );
+        let owners: Vec<_> = delivered
+            .map(|(id, _)| pending.get(&id).map(|pending| &pending.consumer))
+            .collect();
+        for run in owners.chunk_by(|a, b| a == b) {
+            let mut parts = vec![
+                Bytes::from("XREADGROUP"),
+                Bytes::from("GROUP"),
+                Bytes::from(name.to_string()),
+                Bytes::from(run[0].unwrap_or(&pending_for.consumer).clone()),
+                Bytes::from("COUNT"),
+                Bytes::from(run.len().to_string()),
+            ];
+            if run[0].is_none() {
+                parts.push(Bytes::from("NOACK"));
+            }
+            parts.extend([Bytes::from("STREAMS"), key.clone(), Bytes::from(">")]);
+            encode(&command_frame(parts), buf);
+        }
+        encode(&xgroup("SETID", group.last_delivered()), buf);
+    }
+}
+
+/// Replay the log at `path` into `ks`.
+///
+/// A command cut in half at the end of the file (a crash mid-write) is
+/// ignored, and so is a MULTI block missing its EXEC, so a transaction is
+/// never half applied. Returns the length of the valid part of the file.
+pub fn replay(path: &Path, ks: &mut Keyspace) -> crate::Result<u64> {
+    let data = fs::read(path)?;
+    let mut buf = Cursor::new(&data[..]);
+    // Start of the MULTI block being read, and its commands
+    let mut block: Option<(u64, Vec<Command>)> = None;
+    // End of the last complete command
+    let mut end = 0;
+
+    while (buf.position() as usize) < data.len() {
+        let start = buf.position();
+        match Frame::check(&mut buf) {
+            Ok(()) => {
+                buf.set_position(start);
+                let frame = Frame::parse(&mut buf)?;
+                end = buf.position();
+                let cmds = match (Command::from_frame(frame)?, &mut block) {
+                    (Command::Multi, _) => {
+                        block = Some((start, Vec::new()));
+                        continue;
+                    }
+                    (Command::Exec, Some(_)) => {
+                        block.take().map(|(_, cmds)| cmds).unwrap_or_default()
+                    }
+                    (cmd, Some((_, cmds))) => {
+                        cmds.push(cmd);
+                        continue;
+                    }
+                    (cmd, None) => vec![cmd],
+                };
+                for cmd in cmds {
+                    if let Frame::Error(err) = cmd.apply(ks) {
+                        return Err(format!("bad command in append-only file: {}", err).into());
+                    }
+                }
+            }
+            Err(Incomplete) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let valid = match &block {
+        Some((start, _)) => *start,
+        None => end,
+    };
+    if valid < data.len() as u64 {
+        warn!(
+            ignored_bytes = data.len() as u64 - valid,
+            "append-only file is truncated"
+        );
+    }
+    Ok(valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn apply(ks: &mut Keyspace, line: &str) {
+        let frame = command_frame(
+            line.split_whitespace()
+                .map(|arg| Bytes::from(arg.to_string())),
+        );
+        Command::from_frame(frame).unwrap().apply(ks);
+    }
+
+    /// A file path unique to the test, removed when it ends
+    struct TempFile(PathBuf);
+
+    impl std::ops::Deref for TempFile {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempFile {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn temp_file(name: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("aof-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        TempFile(path)
+    }
+
+    #[test]
+    fn rewrite_rebuilds_the_keyspace() {
+        let mut ks = Keyspace::default();
+        apply(&mut ks, "SET name redis");
+        apply(&mut ks, "SET session token EX 60");
+        for i in 0..100 {
+            apply(&mut ks, &format!("ZADD board {} m{}", i, i));
+            apply(&mut ks, &format!("LPUSH queue {}", i));
+        }
+
+        let path = temp_file("rewrite");
+        fs::write(&path, rewrite([&ks])).unwrap();
+
+        let mut replayed = Keyspace::default();
+        replay(&path, &mut replayed).unwrap();
+        assert_eq!(replayed.len(), 4);
+        assert_eq!(replayed.sorted_set("board").unwrap().unwrap().len(), 100);
+        let queue = replayed.list("queue").unwrap().unwrap();
+        assert_eq!(queue.len(), 100);
+        assert_eq!(queue.iter().next().unwrap(), "99");
+
+        let remaining = replayed.expires_at("session").unwrap() - Instant::now();
+        assert!(remaining > Duration::from_secs(58));
+    }
+
+    #[test]
+    fn rewrite_rebuilds_streams_and_groups() {
+        let mut ks = Keyspace::default();
+        for i in 1..=6 {
+            apply(&mut ks, &format!("XADD jobs {} n {}", i, i));
+        }
+        apply(&mut ks, "XGROUP CREATE jobs workers 0");
+        apply(
+            &mut ks,
+            "XREADGROUP GROUP workers alice COUNT 2 STREAMS jobs >",
+        );
+        apply(
+            &mut ks,
+            "XREADGROUP GROUP workers bob COUNT 2 STREAMS jobs >",
+        );
+        apply(
+            &mut ks,
+            "XREADGROUP GROUP workers alice COUNT 1 STREAMS jobs >",
+        );
+        apply(&mut ks, "XACK jobs workers 1-0 3-0");
+        apply(&mut ks, "XGROUP CREATE jobs idle $");
+        apply(&mut ks, "XGROUP CREATE empty workers $ MKSTREAM");
+
+        let path = temp_file("rewrite-streams");
+        fs::write(&path, rewrite([&ks])).unwrap();
+        let mut replayed = Keyspace::default();
+        replay(&path, &mut replayed).unwrap();
+
+        let summary = |ks: &Keyspace, key: &str| {
+            let stream = ks.stream(key).unwrap().unwrap();
+            let groups: Vec<_> = stream
+                .groups()
+                .map(|(name, group)| {
+                    let pending: Vec<_> = group
+                        .pending()
+                        .iter()
+                        .map(|(id, pending)| format!("{}:{}", id, pending.consumer))
+                        .collect();
+                    format!("{} {} {:?}", name, group.last_delivered(), pending)
+                })
+                .collect();
+            (stream.len(), stream.last_id(), groups)
+        };
+        assert_eq!(summary(&replayed, "jobs"), summary(&ks, "jobs"));
+        assert_eq!(summary(&replayed, "empty"), summary(&ks, "empty"));
+    }
+
+    #[test]
+    fn truncated_tail_is_ignored() {
+        let mut data = Vec::new();
+        encode(
+            &command_frame([Bytes::from("SET"), "a".into(), "1".into()]),
+            &mut data,
+        );
+        let valid = data.len() as u64;
+        data.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nb");
+
+        let path = temp_file("truncated");
+        fs::write(&path, &data).unwrap();
+
+        let mut ks = Keyspace::default();
+        assert_eq!(replay(&path, &mut ks).unwrap(), valid);
+        assert_eq!(ks.len(), 1);
+    }
+
+    #[test]
+    fn unterminated_transaction_is_ignored() {
+        let line = |line: &str| {
+            command_frame(
+                line.split_whitespace()
+                    .map(|arg| Bytes::from(arg.to_string())),
+            )
+        };
+        let mut data = Vec::new();
+        for frame in ["SET a 1", "MULTI", "SET b 2", "SET c 3", "EXEC"] {
+            encode(&line(frame), &mut data);
+        }
+        let valid = data.len() as u64;
+        for frame in ["MULTI", "SET d 4"] {
+            encode(&line(frame), &mut data);
+        }
+
+        let path = temp_file("unterminated");
+        fs::write(&path, &data).unwrap();
+
+        let mut ks = Keyspace::default();
+        assert_eq!(replay(&path, &mut ks).unwrap(), valid);
+        assert_eq!(ks.len(), 3);
+    }
+}
@@ -0,0 +1,389 @@
+use std::{env, path::PathBuf};
+
+use bytes::Bytes;
+use clap::Parser;
+use mini_redis::Frame;
+use redis_clone::{client::Message, Client};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use tokio::signal;
+
+/// Name of the history file, in the home directory
+const HISTORY_FILE: &str = ".redis_clone_cli_history";
+
+#[derive(Parser)]
+#[command(name = "cli")]
+#[command(version, about = "Interactive prompt for the Redis clone server", long_about = None)]
+struct Cli {
+    /// Server host
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// Server port
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+
+    /// User to log in as, with --pass
+    #[arg(long, requires = "pass")]
+    user: Option<String>,
+
+    /// Password to log in with, sent with AUTH right after connecting
+    #[arg(short = 'a', long)]
+    pass: Option<String>,
+
+    /// Run a command line, quoted as in the prompt, and exit
+    #[arg(short, long, value_name = "LINE", conflicts_with = "command")]
+    eval: Option<String>,
+
+    /// Run this command and exit instead of starting the prompt
+    #[arg(trailing_var_arg = true)]
+    command: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> redis_clone::Result<()> {
+    let cli = Cli::parse();
+    let addr = format!("{}:{}", cli.host, cli.port);
+    let client = Client::connect(&addr).await?;
+    // Subscriber connections log in the same way
+    if let Some(pass) = &cli.pass {
+        client.auth(cli.user.as_deref(), pass).await?;
+    }
+
+    if let Some(line) = cli.eval {
+        let args = split_args(&line).ok_or("Invalid argument(s)")?;
+        return run(&client, args).await;
+    }
+    if !cli.command.is_empty() {
+        let args = cli.command.into_iter().map(Bytes::from).collect();
+        return run(&client, args).await;
+    }
+
+    repl(&client, &format!("{}> ", addr)).await
+}
+
+/// Read commands from the prompt until `quit` or end of input
+async fn repl(client: &Client, prompt: &str) -> redis_clone::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(path) = &history {
+        // No history yet on the first run
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        // The line editor blocks: keep the runtime free for the client task
+        let line = match tokio::task::block_in_place(|| editor.readline(prompt)) {
+            Ok(line) => line,
+            // Ctrl-C clears the line, Ctrl-D quits
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(&line)?;
+
+        let Some(args) = split_args(&line) else {
+            println!("Invalid argument(s)");
+            continue;
+        };
+        if let [name] = &args[..] {
+            if name.eq_ignore_ascii_case(b"quit") || name.eq_ignore_ascii_case(b"exit") {
+                break;
+            }
+        }
+
+        if let Err(err) = run(client, args).await {
+            println!("(error) {}", err);
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+/// Send one command and print its reply
+async fn run(client: &Client, args: Vec<Bytes>) -> redis_clone::Result<()> {
+    let name = command_name(&args)?;
+    if name == "subscribe" || name == "psubscribe" {
+        return subscribe(client, &name, &args[1..]).await;
+    }
+
+    let frame = Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+    for reply in client.send(vec![frame]).await? {
+        println!("{}", format_reply(&reply));
+    }
+    Ok(())
+}
+
+/// The lowercase name of the command in `args`, e.g. from `--eval "  "`
+fn command_name(args: &[Bytes]) -> redis_clone::Result<String> {
+    let name = args.first().ok_or("No command given")?;
+    Ok(String::from_utf8_lossy(name).to_lowercase())
+}
+
+/// Print published messages until Ctrl-C
+async fn subscribe(client: &Client, kind: &str, names: &[Bytes]) -> redis_clone::Result<()> {
+    let names: Vec<String> = names
+        .iter()
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+    let mut subscriber = match kind {
+        "subscribe" => client.subscribe(&names).await?,
+        _ => client.psubscribe(&names).await?,
+    };
+    println!("Reading messages... (press Ctrl-C to quit)");
+
+    loop {
+        tokio::select! {
+            message = subscriber.next_message() => match message? {
+                Some(message) => println!("{}", format_reply(&message_frame(message))),
+                None => return Ok(()),
+            },
+            _ = signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// The message as the server pushed it
+fn message_frame(message: Message) -> Frame {
+    let mut parts = vec![];
+    match message.pattern {
+        Some(pattern) => {
+            parts.push(Frame::Bulk("pmessage".into()));
+            parts.push(Frame::Bulk(pattern.into()));
+        }
+        None => parts.push(Frame::Bulk("message".into())),
+    }
+    parts.push(Frame::Bulk(message.channel.into()));
+    parts.push(Frame::Bulk(message.content));
+    Frame::Array(parts)
+}
+
+/// Split a command line into arguments, with the quoting rules of redis-cli.
+///
+/// Arguments are separated by whitespace. Double quotes allow the escapes
+/// `\n`, `\r`, `\t`, `\b`, `\a`, `\\`, `\"` and `\xHH`; single quotes only
+/// `\'`. A closing quote must be followed by whitespace or the end of the
+/// line. Returns `None` on unbalanced or misplaced quotes.
+fn split_args(line: &str) -> Option<Vec<Bytes>> {
+    let line = line.as_bytes();
+    let mut args = vec![];
+    let mut i = 0;
+
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+
+        let mut arg = vec![];
+        let (mut in_double, mut in_single) = (false, false);
+        loop {
+            let c = line.get(i).copied();
+            if in_double {
+                match (c?, line.get(i + 1).copied()) {
+                    (b'\\', Some(b'x'))
+                        if i + 3 < line.len()
+                            && line[i + 2].is_ascii_hexdigit()
+                            && line[i + 3].is_ascii_hexdigit() =>
+                    {
+                        let hex = std::str::from_utf8(&line[i + 2..i + 4]).ok()?;
+                        arg.push(u8::from_str_radix(hex, 16).ok()?);
+                        i += 3;
+                    }
+                    (b'\\', Some(escaped)) => {
+                        arg.push(match escaped {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                        i += 1;
+                    }
+                    (b'"', next) => {
+                        if next.is_some_and(|next| !next.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    (c, _) => arg.push(c),
+                }
+            } else if in_single {
+                match (c?, line.get(i + 1).copied()) {
+                    (b'\\', Some(b'\'')) => {
+                        arg.push(b'\'');
+                        i += 1;
+                    }
+                    (b'\'', next) => {
+                        if next.is_some_and(|next| !next.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    (c, _) => arg.push(c),
+                }
+            } else {
+                match c {
+                    None => break,
+                    Some(c) if c.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(c) => arg.push(c),
+                }
+            }
+            i += 1;
+        }
+
+        args.push(Bytes::from(arg));
+    }
+}
+
+/// Format a reply like redis-cli does on a terminal
+fn format_reply(frame: &Frame) -> String {
+    match frame {
+        Frame::Simple(value) => value.clone(),
+        Frame::Error(err) => format!("(error) {}", err),
+        Frame::Integer(value) => format!("(integer) {}", value),
+        Frame::Bulk(data) => quote(data),
+        Frame::Null => "(nil)".to_string(),
+        Frame::Array(items) if items.is_empty() => "(empty array)".to_string(),
+        Frame::Array(items) => {
+            // Indexes are right aligned, nested replies indented under them
+            let width = items.len().to_string().len();
+            let mut lines = vec![];
+            for (index, item) in items.iter().enumerate() {
+                let prefix = format!("{:>width$}) ", index + 1);
+                let indent = " ".repeat(prefix.len());
+                for (n, line) in format_reply(item).split('\n').enumerate() {
+                    let lead = if n == 0 { &prefix } else { &indent };
+                    lines.push(format!("{}{}", lead, line));
+                }
+            }
+            lines.join("\n")
+        }
+    }
+}
+
+/// Quote a bulk string, escaping special and non printable bytes
+fn quote(data: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in data {
+        match byte {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Option<Vec<String>> {
+        split_args(line).map(|args| {
+            args.iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn arguments_are_split_like_redis_cli() {
+        assert_eq!(split("  SET  key value ").unwrap(), ["SET", "key", "value"]);
+        assert_eq!(
+            split("SET key \"hello world\"").unwrap(),
+            ["SET", "key", "hello world"]
+        );
+        assert_eq!(split("SET key 'it\\'s'").unwrap(), ["SET", "key", "it's"]);
+        assert_eq!(
+            split(r#"SET key "a\"b\n\x41""#).unwrap(),
+            ["SET", "key", "a\"b\nA"]
+        );
+        assert_eq!(split("SET key ''").unwrap(), ["SET", "key", ""]);
+        assert_eq!(split("SET key'x' v").unwrap(), ["SET", "keyx", "v"]);
+        assert_eq!(split("").unwrap(), Vec::<String>::new());
+
+        assert_eq!(split_args(r#"SET k "\xff""#).unwrap()[2][..], [0xff]);
+    }
+
+    #[test]
+    fn bad_quotes_are_rejected() {
+        assert!(split("SET key \"unbalanced").is_none());
+        assert!(split("SET key 'unbalanced").is_none());
+        assert!(split("SET key \"a\"b").is_none());
+    }
+
+    #[test]
+    fn blank_commands_are_rejected() {
+        let args = split_args("   ").unwrap();
+        assert_eq!(
+            command_name(&args).unwrap_err().to_string(),
+            "No command given"
+        );
+        let args = split_args("PSubscribe news.*").unwrap();
+        assert_eq!(command_name(&args).unwrap(), "psubscribe");
+    }
+
+    #[test]
+    fn replies_are_pretty_printed() {
+        assert_eq!(format_reply(&Frame::Simple("OK".into())), "OK");
+        assert_eq!(
+            format_reply(&Frame::Error("ERR nope".into())),
+            "(error) ERR nope"
+        );
+        assert_eq!(format_reply(&Frame::Integer(3)), "(integer) 3");
+        assert_eq!(format_reply(&Frame::Null), "(nil)");
+        assert_eq!(
+            format_reply(&Frame::Bulk("a \"b\"\n".into())),
+            r#""a \"b\"\n""#
+        );
+        assert_eq!(format_reply(&Frame::Array(vec![])), "(empty array)");
+
+        let nested = Frame::Array(vec![
+            Frame::Array(vec![Frame::Bulk("a".into()), Frame::Bulk("b".into())]),
+            Frame::Integer(1),
+        ]);
+        assert_eq!(
+            format_reply(&nested),
+            "1) 1) \"a\"\n   2) \"b\"\n2) (integer) 1"
+        );
+
+        let long = Frame::Array((0..10).map(Frame::Integer).collect());
+        let formatted = format_reply(&long);
+        assert!(formatted.starts_with(" 1) (integer) 0\n"));
+        assert!(formatted.ends_with("10) (integer) 9"));
+    }
+
+    #[test]
+    fn credentials_are_optional_but_need_a_password() {
+        let cli = Cli::try_parse_from(["cli", "--user", "alice", "-a", "secret", "PING"]).unwrap();
+        assert_eq!(cli.user.as_deref(), Some("alice"));
+        assert_eq!(cli.pass.as_deref(), Some("secret"));
+        assert_eq!(cli.command, ["PING"]);
+
+        let cli = Cli::try_parse_from(["cli", "--pass", "secret"]).unwrap();
+        assert_eq!((cli.user, cli.pass.as_deref()), (None, Some("secret")));
+        assert!(Cli::try_parse_from(["cli", "--user", "alice"]).is_err());
+    }
+}
@@ -1,67 +1,80 @@
-use std::{
-    collections::HashMap,
-    net::{Ipv4Addr, SocketAddrV4},
-    sync::{Arc, Mutex},
-};
+use std::{net::IpAddr, path::PathBuf, time::Duration};
 
-use bytes::Bytes;
-use mini_redis::{Connection, Frame};
-use tokio::net::{TcpListener, TcpStream};
+use clap::Parser;
+use redis_clone::{server, Config};
+use tokio::{net::TcpListener, signal};
+use tracing::info;
 
-const REDIS_PORT: u16 = 6379;
+#[derive(Parser)]
+#[command(name = "redis-clone-server")]
+#[command(version, about = "A Redis compatible key-value server", long_about = None)]
+struct Cli {
+    /// Config file in the redis.conf format. Flags take precedence over it.
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-// Note: a std::sync::Mutex is used to only block the current thread
-// and not the entire set of tokio tasks with tokio::sync::Mutex,
-// as tokio can manage it without generating races.
-type Db = Arc<Mutex<HashMap<String, Bytes>>>;
+    /// Address to listen on [default: 127.0.0.1]
+    #[arg(long)]
+    bind: Option<IpAddr>,
 
-#[tokio::main]
-async fn main() {
-    // Bind the listener to the address
-    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, REDIS_PORT);
-    let listener = TcpListener::bind(addr).await.unwrap();
+    /// Port to listen on [default: 6379]
+    #[arg(long)]
+    port: Option<u16>,
 
-    // Global state dict
-    let db = Arc::new(Mutex::new(HashMap::new()));
+    /// Maximum number of simultaneous clients [default: 10000]
+    #[arg(long)]
+    maxclients: Option<usize>,
 
-    loop {
-        let (socket, _) = listener.accept().await.unwrap();
-
-        // Create a new task to process the request
-        // Note: concurrent tasks are not necessarily parallel (green-threads)
-        let db = db.clone();
-        println!("Processing request...");
-        tokio::spawn(async move {
-            process(socket, db).await;
-        });
-    }
+    /// Start as a read-only replica of this primary
+    #[arg(long, value_name = "HOST PORT")]
+    replicaof: Option<String>,
 }
 
-async fn process(socket: TcpStream, db: Db) {
-    use mini_redis::Command::{self, Get, Set};
+#[tokio::main]
+async fn main() -> redis_clone::Result<()> {
+    tracing_subscriber::fmt::init();
 
-    // Connection used to read/write redis frames
-    let mut connection = Connection::new(socket);
+    let cli = Cli::parse();
+    let mut config = match &cli.config {
+        Some(path) => Config::from_file(path)?,
+        // Snapshot the keyspace to ./dump.rdb every minute
+        None => Config {
+            save_interval: Some(Duration::from_secs(60)),
+            ..Config::default()
+        },
+    };
+    if let Some(bind) = cli.bind {
+        config.bind = bind;
+    }
+    if let Some(port) = cli.port {
+        config.port = port;
+    }
+    if let Some(maxclients) = cli.maxclients {
+        config.set("maxclients", &maxclients.to_string())?;
+    }
+    if let Some(primary) = &cli.replicaof {
+        config.set("replicaof", primary)?;
+    }
+
+    // Bind the listener to the address
+    let listener = TcpListener::bind((config.bind, config.port)).await?;
+    info!(addr = %listener.local_addr()?, "listening");
 
-    while let Some(frame) = connection.read_frame().await.unwrap() {
-        let response = match Command::from_frame(frame).unwrap() {
-            Set(cmd) => {
-                let mut db = db.lock().unwrap();
-                db.insert(cmd.key().to_string(), cmd.value().clone());
-                Frame::Simple("OK".into())
-            }
-            Get(cmd) => {
-                let db = db.lock().unwrap();
-                if let Some(value) = db.get(cmd.key()) {
-                    Frame::Bulk(value.clone())
-                } else {
-                    Frame::Null
-                }
-            }
-            cmd => unimplemented!("{:?}", cmd),
-        };
+    server::run(listener, config, shutdown_signal()).await
+}
 
-        // Write the response to the client
-        connection.write_frame(&response).await.unwrap();
+/// Resolve on Ctrl-C, or on SIGTERM on unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
     }
+
+    #[cfg(not(unix))]
+    let _ = signal::ctrl_c().await;
 }
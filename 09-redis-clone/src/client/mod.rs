@@ -0,0 +1,971 @@
+//! Async client for the server.
+//!
+//! A `Client` is a cheap, cloneable handle to one connection owned by a
+//! background task. Commands sent from any clone are written as soon as they
+//! arrive, without waiting for the replies of the previous ones (pipelining),
+//! and replies are handed back in order. If the connection drops, the
+//! commands waiting for a reply fail and the next one reconnects.
+
+mod pipeline;
+pub use pipeline::Pipeline;
+
+mod pool;
+pub use pool::Pool;
+
+mod subscriber;
+pub use subscriber::{Message, Subscriber};
+
+use std::{
+    collections::VecDeque,
+    future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::{
+    net::{TcpStream, ToSocketAddrs},
+    sync::{mpsc, oneshot},
+    time,
+};
+use tracing::debug;
+
+use crate::Connection;
+
+/// Commands queued for the connection task before callers have to wait
+const REQUEST_BUFFER: usize = 1024;
+
+/// Connection attempts before a command fails
+const CONNECT_ATTEMPTS: u32 = 3;
+
+/// Pause after the first failed attempt, doubled after each one
+const CONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// An entry of a stream: its ID, then its fields and values
+pub type StreamEntry = (String, Vec<(Bytes, Bytes)>);
+
+/// Argument of a command, sent as a bulk string
+pub trait ToArg {
+    fn to_arg(&self) -> Bytes;
+}
+
+impl ToArg for str {
+    fn to_arg(&self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl ToArg for String {
+    fn to_arg(&self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl ToArg for [u8] {
+    fn to_arg(&self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl ToArg for Bytes {
+    fn to_arg(&self) -> Bytes {
+        self.clone()
+    }
+}
+
+macro_rules! to_arg_display {
+    ($($ty:ty),*) => {
+        $(impl ToArg for $ty {
+            fn to_arg(&self) -> Bytes {
+                Bytes::from(self.to_string())
+            }
+        })*
+    };
+}
+
+to_arg_display!(i64, u64, usize, f64);
+
+impl<T: ToArg + ?Sized> ToArg for &T {
+    fn to_arg(&self) -> Bytes {
+        (**self).to_arg()
+    }
+}
+
+/// A command, built one argument at a time
+#[derive(Debug, Clone)]
+pub struct Cmd {
+    parts: Vec<Frame>,
+}
+
+impl Cmd {
+    pub fn new(name: &str) -> Cmd {
+        Cmd {
+            parts: vec![Frame::Bulk(name.to_arg())],
+        }
+    }
+
+    pub fn arg(mut self, arg: impl ToArg) -> Cmd {
+        self.parts.push(Frame::Bulk(arg.to_arg()));
+        self
+    }
+
+    pub fn into_frame(self) -> Frame {
+        Frame::Array(self.parts)
+    }
+}
+
+/// Commands sent together, and where to send their replies
+struct Request {
+    frames: Vec<Frame>,
+    reply: oneshot::Sender<crate::Result<Vec<Frame>>>,
+}
+
+/// A request written to the connection, waiting for its replies
+struct Pending {
+    expected: usize,
+    replies: Vec<Frame>,
+    reply: oneshot::Sender<crate::Result<Vec<Frame>>>,
+}
+
+/// Who `Client::auth` logged in as, to log in again on new connections
+#[derive(Debug, Clone)]
+pub(crate) struct Credentials {
+    user: Option<String>,
+    password: String,
+}
+
+/// Handle to a pipelined connection. Clones share the connection.
+#[derive(Debug, Clone)]
+pub struct Client {
+    addr: SocketAddr,
+    requests: mpsc::Sender<Request>,
+    // Set by `auth`, read by the connection task when it reconnects
+    credentials: Arc<Mutex<Option<Credentials>>>,
+}
+
+impl Client {
+    /// Connect to the server at `addr`
+    pub async fn connect(addr: impl ToSocketAddrs) -> crate::Result<Client> {
+        let socket = TcpStream::connect(addr).await?;
+        let addr = socket.peer_addr()?;
+
+        let (requests, rx) = mpsc::channel(REQUEST_BUFFER);
+        let credentials = Arc::new(Mutex::new(None));
+        tokio::spawn(run(
+            addr,
+            Some(Connection::new(socket)),
+            rx,
+            credentials.clone(),
+        ));
+        Ok(Client {
+            addr,
+            requests,
+            credentials,
+        })
+    }
+
+    /// Address of the server
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send `frames` in one go and return their replies, error replies
+    /// included. Commands from other clones are never interleaved with them.
+    pub async fn send(&self, frames: Vec<Frame>) -> crate::Result<Vec<Frame>> {
+        if frames.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let (reply, rx) = oneshot::channel();
+        self.requests
+            .send(Request { frames, reply })
+            .await
+            .map_err(|_| "client connection task stopped")?;
+        rx.await.map_err(|_| "client connection task stopped")?
+    }
+
+    /// Send one command and return its reply. Error replies become errors.
+    pub async fn call(&self, cmd: Cmd) -> crate::Result<Frame> {
+        let mut replies = self.send(vec![cmd.into_frame()]).await?;
+        check(replies.pop().ok_or("missing reply")?)
+    }
+
+    /// Start a batch of commands, sent together
+    pub fn pipeline(&self) -> Pipeline {
+        Pipeline::new(self.clone())
+    }
+
+    /// WATCH key [key ...]: abort the next transaction (see
+    /// `Pipeline::exec`) if one of the keys changes before it runs.
+    ///
+    /// Watches belong to the connection, so they are shared with the clones
+    /// of this client, and dropped if it reconnects.
+    pub async fn watch(&self, keys: &[&str]) -> crate::Result<()> {
+        let cmd = keys.iter().fold(Cmd::new("WATCH"), |cmd, key| cmd.arg(key));
+        self.call(cmd).await?;
+        Ok(())
+    }
+
+    /// UNWATCH: forget every watched key
+    pub async fn unwatch(&self) -> crate::Result<()> {
+        self.call(Cmd::new("UNWATCH")).await?;
+        Ok(())
+    }
+
+    /// Open a dedicated connection subscribed to `channels`. It logs in like
+    /// the client, if `auth` was called.
+    pub async fn subscribe(&self, channels: &[&str]) -> crate::Result<Subscriber> {
+        let mut subscriber = self.open_subscriber().await?;
+        subscriber.subscribe(channels).await?;
+        Ok(subscriber)
+    }
+
+    /// Open a dedicated connection subscribed to `patterns`
+    pub async fn psubscribe(&self, patterns: &[&str]) -> crate::Result<Subscriber> {
+        let mut subscriber = self.open_subscriber().await?;
+        subscriber.psubscribe(patterns).await?;
+        Ok(subscriber)
+    }
+
+    /// Open a dedicated connection for a subscriber, logged in like this one
+    async fn open_subscriber(&self) -> crate::Result<Subscriber> {
+        let credentials = self.credentials.lock().unwrap().clone();
+        Subscriber::connect(self.addr, credentials.as_ref()).await
+    }
+
+    /// GET key
+    pub async fn get(&self, key: &str) -> crate::Result<Option<Bytes>> {
+        bulk(self.call(Cmd::new("GET").arg(key)).await?)
+    }
+
+    /// SET key value
+    pub async fn set(&self, key: &str, value: impl ToArg) -> crate::Result<()> {
+        self.call(Cmd::new("SET").arg(key).arg(value)).await?;
+        Ok(())
+    }
+
+    /// SET key value PX milliseconds
+    pub async fn set_expires(
+        &self,
+        key: &str,
+        value: impl ToArg,
+        expiration: Duration,
+    ) -> crate::Result<()> {
+        let ms = u64::try_from(expiration.as_millis()).map_err(|_| "expiration out of range")?;
+        let cmd = Cmd::new("SET").arg(key).arg(value).arg("PX").arg(ms);
+        self.call(cmd).await?;
+        Ok(())
+    }
+
+    /// EXPIRE key seconds. Returns whether the key exists.
+    pub async fn expire(&self, key: &str, seconds: i64) -> crate::Result<bool> {
+        Ok(integer(self.call(Cmd::new("EXPIRE").arg(key).arg(seconds)).await?)? == 1)
+    }
+
+    /// PEXPIREAT key timestamp-ms. Returns whether the key exists.
+    pub async fn pexpireat(&self, key: &str, unix_ms: u64) -> crate::Result<bool> {
+        let cmd = Cmd::new("PEXPIREAT").arg(key).arg(unix_ms);
+        Ok(integer(self.call(cmd).await?)? == 1)
+    }
+
+    /// DEL key [key ...]. Returns the number of removed keys.
+    pub async fn del(&self, keys: &[&str]) -> crate::Result<u64> {
+        let cmd = keys.iter().fold(Cmd::new("DEL"), |cmd, key| cmd.arg(*key));
+        integer(self.call(cmd).await?)
+    }
+
+    /// EXISTS key [key ...]. Returns the number of existing keys.
+    pub async fn exists(&self, keys: &[&str]) -> crate::Result<u64> {
+        let cmd = keys
+            .iter()
+            .fold(Cmd::new("EXISTS"), |cmd, key| cmd.arg(*key));
+        integer(self.call(cmd).await?)
+    }
+
+    /// TYPE key. Returns `none` if the key does not exist.
+    pub async fn key_type(&self, key: &str) -> crate::Result<String> {
+        match self.call(Cmd::new("TYPE").arg(key)).await? {
+            Frame::Simple(name) => Ok(name),
+            frame => Err(unexpected(frame)),
+        }
+    }
+
+    /// RENAME key newkey
+    pub async fn rename(&self, key: &str, new_key: &str) -> crate::Result<()> {
+        self.call(Cmd::new("RENAME").arg(key).arg(new_key)).await?;
+        Ok(())
+    }
+
+    /// KEYS pattern
+    pub async fn keys(&self, pattern: &str) -> crate::Result<Vec<Bytes>> {
+        bulks(self.call(Cmd::new("KEYS").arg(pattern)).await?)
+    }
+
+    /// SCAN cursor MATCH pattern COUNT count. Returns the next cursor, 0
+    /// once done, and a page of keys.
+    pub async fn scan(
+        &self,
+        cursor: u64,
+        pattern: &str,
+        count: usize,
+    ) -> crate::Result<(u64, Vec<Bytes>)> {
+        let cmd = Cmd::new("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(count);
+        let mut reply = array(self.call(cmd).await?)?.into_iter();
+        match (reply.next(), reply.next(), reply.next()) {
+            (Some(Frame::Bulk(cursor)), Some(keys), None) => {
+                let cursor = std::str::from_utf8(&cursor)?.parse()?;
+                Ok((cursor, bulks(keys)?))
+            }
+            _ => Err("unexpected reply to SCAN".into()),
+        }
+    }
+
+    /// DBSIZE
+    pub async fn dbsize(&self) -> crate::Result<u64> {
+        integer(self.call(Cmd::new("DBSIZE")).await?)
+    }
+
+    /// ZADD key score member [score member ...]. Returns the number of new members.
+    pub async fn zadd<M: ToArg>(
+        &self,
+        key: &str,
+        members: impl IntoIterator<Item = (f64, M)>,
+    ) -> crate::Result<u64> {
+        let mut cmd = Cmd::new("ZADD").arg(key);
+        for (score, member) in members {
+            cmd = cmd.arg(score).arg(member);
+        }
+        integer(self.call(cmd).await?)
+    }
+
+    /// ZSCORE key member
+    pub async fn zscore(&self, key: &str, member: impl ToArg) -> crate::Result<Option<f64>> {
+        let score = bulk(self.call(Cmd::new("ZSCORE").arg(key).arg(member)).await?)?;
+        score.map(|score| parse_float(&score)).transpose()
+    }
+
+    /// ZRANGE key start stop
+    pub async fn zrange(&self, key: &str, start: i64, stop: i64) -> crate::Result<Vec<Bytes>> {
+        bulks(
+            self.call(Cmd::new("ZRANGE").arg(key).arg(start).arg(stop))
+                .await?,
+        )
+    }
+
+    /// ZRANGE key start stop WITHSCORES
+    pub async fn zrange_with_scores(
+        &self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> crate::Result<Vec<(Bytes, f64)>> {
+        let cmd = Cmd::new("ZRANGE")
+            .arg(key)
+            .arg(start)
+            .arg(stop)
+            .arg("WITHSCORES");
+        with_scores(self.call(cmd).await?)
+    }
+
+    /// ZRANGE key min max BYSCORE. Bounds are written as in Redis: `1.5`,
+    /// `(1.5` for an exclusive bound, `-inf` or `+inf`.
+    pub async fn zrange_by_score(
+        &self,
+        key: &str,
+        min: &str,
+        max: &str,
+    ) -> crate::Result<Vec<Bytes>> {
+        let cmd = Cmd::new("ZRANGE").arg(key).arg(min).arg(max).arg("BYSCORE");
+        bulks(self.call(cmd).await?)
+    }
+
+    /// ZRANK key member
+    pub async fn zrank(&self, key: &str, member: impl ToArg) -> crate::Result<Option<u64>> {
+        match self.call(Cmd::new("ZRANK").arg(key).arg(member)).await? {
+            Frame::Null => Ok(None),
+            frame => integer(frame).map(Some),
+        }
+    }
+
+    /// ZREM key member [member ...]. Returns the number of removed members.
+    pub async fn zrem<M: ToArg>(
+        &self,
+        key: &str,
+        members: impl IntoIterator<Item = M>,
+    ) -> crate::Result<u64> {
+        let mut cmd = Cmd::new("ZREM").arg(key);
+        for member in members {
+            cmd = cmd.arg(member);
+        }
+        integer(self.call(cmd).await?)
+    }
+
+    /// PUBLISH channel message. Returns the number of receivers.
+    pub async fn publish(&self, channel: &str, message: impl ToArg) -> crate::Result<u64> {
+        integer(
+            self.call(Cmd::new("PUBLISH").arg(channel).arg(message))
+                .await?,
+        )
+    }
+
+    /// LPUSH key element [element ...]. Returns the length of the list.
+    pub async fn lpush<V: ToArg>(
+        &self,
+        key: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> crate::Result<u64> {
+        self.push("LPUSH", key, values).await
+    }
+
+    /// RPUSH key element [element ...]. Returns the length of the list.
+    pub async fn rpush<V: ToArg>(
+        &self,
+        key: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> crate::Result<u64> {
+        self.push("RPUSH", key, values).await
+    }
+
+    async fn push<V: ToArg>(
+        &self,
+        name: &str,
+        key: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> crate::Result<u64> {
+        let cmd = Cmd::new(name).arg(key);
+        integer(
+            self.call(values.into_iter().fold(cmd, |cmd, value| cmd.arg(value)))
+                .await?,
+        )
+    }
+
+    /// LPOP key
+    pub async fn lpop(&self, key: &str) -> crate::Result<Option<Bytes>> {
+        bulk(self.call(Cmd::new("LPOP").arg(key)).await?)
+    }
+
+    /// RPOP key
+    pub async fn rpop(&self, key: &str) -> crate::Result<Option<Bytes>> {
+        bulk(self.call(Cmd::new("RPOP").arg(key)).await?)
+    }
+
+    /// LLEN key
+    pub async fn llen(&self, key: &str) -> crate::Result<u64> {
+        integer(self.call(Cmd::new("LLEN").arg(key)).await?)
+    }
+
+    /// LRANGE key start stop
+    pub async fn lrange(&self, key: &str, start: i64, stop: i64) -> crate::Result<Vec<Bytes>> {
+        bulks(
+            self.call(Cmd::new("LRANGE").arg(key).arg(start).arg(stop))
+                .await?,
+        )
+    }
+
+    /// BLPOP key [key ...] timeout. Returns the list popped from and the
+    /// element, or `None` at the timeout. A zero timeout waits for ever.
+    ///
+    /// Other commands sent through this connection wait meanwhile.
+    pub async fn blpop(
+        &self,
+        keys: &[&str],
+        timeout: Duration,
+    ) -> crate::Result<Option<(String, Bytes)>> {
+        self.bpop("BLPOP", keys, timeout).await
+    }
+
+    /// BRPOP key [key ...] timeout, see `blpop`
+    pub async fn brpop(
+        &self,
+        keys: &[&str],
+        timeout: Duration,
+    ) -> crate::Result<Option<(String, Bytes)>> {
+        self.bpop("BRPOP", keys, timeout).await
+    }
+
+    async fn bpop(
+        &self,
+        name: &str,
+        keys: &[&str],
+        timeout: Duration,
+    ) -> crate::Result<Option<(String, Bytes)>> {
+        let cmd = keys.iter().fold(Cmd::new(name), |cmd, key| cmd.arg(key));
+        match self.call(cmd.arg(timeout.as_secs_f64())).await? {
+            Frame::Null => Ok(None),
+            frame => match &bulks(frame)?[..] {
+                [key, value] => Ok(Some((String::from_utf8(key.to_vec())?, value.clone()))),
+                _ => Err(format!("unexpected reply to {}", name).into()),
+            },
+        }
+    }
+
+    /// XADD key id field value [field value ...]. `id` is usually `*`.
+    /// Returns the ID of the new entry.
+    pub async fn xadd<F: ToArg, V: ToArg>(
+        &self,
+        key: &str,
+        id: &str,
+        fields: impl IntoIterator<Item = (F, V)>,
+    ) -> crate::Result<String> {
+        let mut cmd = Cmd::new("XADD").arg(key).arg(id);
+        for (field, value) in fields {
+            cmd = cmd.arg(field).arg(value);
+        }
+        match bulk(self.call(cmd).await?)? {
+            Some(id) => Ok(String::from_utf8(id.to_vec())?),
+            None => Err("unexpected reply to XADD".into()),
+        }
+    }
+
+    /// XLEN key
+    pub async fn xlen(&self, key: &str) -> crate::Result<u64> {
+        integer(self.call(Cmd::new("XLEN").arg(key)).await?)
+    }
+
+    /// XGROUP CREATE key group id MKSTREAM
+    pub async fn xgroup_create(&self, key: &str, group: &str, id: &str) -> crate::Result<()> {
+        let cmd = Cmd::new("XGROUP")
+            .arg("CREATE")
+            .arg(key)
+            .arg(group)
+            .arg(id)
+            .arg("MKSTREAM");
+        self.call(cmd).await?;
+        Ok(())
+    }
+
+    /// XACK key group id [id ...]. Returns the number of acknowledged entries.
+    pub async fn xack(&self, key: &str, group: &str, ids: &[&str]) -> crate::Result<u64> {
+        let cmd = Cmd::new("XACK").arg(key).arg(group);
+        integer(
+            self.call(ids.iter().fold(cmd, |cmd, id| cmd.arg(id)))
+                .await?,
+        )
+    }
+
+    /// XRANGE key start end [COUNT count]. `-` and `+` are the smallest and
+    /// greatest IDs.
+    pub async fn xrange(
+        &self,
+        key: &str,
+        start: &str,
+        end: &str,
+        count: Option<usize>,
+    ) -> crate::Result<Vec<StreamEntry>> {
+        let cmd = Cmd::new("XRANGE").arg(key).arg(start).arg(end);
+        let cmd = count
+            .iter()
+            .fold(cmd, |cmd, count| cmd.arg("COUNT").arg(count));
+        entries(self.call(cmd).await?)
+    }
+
+    /// XREAD [COUNT count] [BLOCK ms] STREAMS key ... id .... Reads the
+    /// entries after the ID given with each key, `$` for the last one.
+    /// Returns the streams with new entries, none at the timeout. A zero
+    /// `block` waits for ever.
+    ///
+    /// Other commands sent through this connection wait while it blocks.
+    pub async fn xread(
+        &self,
+        streams: &[(&str, &str)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> crate::Result<Vec<(String, Vec<StreamEntry>)>> {
+        let cmd = read_options(Cmd::new("XREAD"), count, block);
+        self.call(streams_args(cmd, streams))
+            .await
+            .and_then(read_streams)
+    }
+
+    /// XREADGROUP GROUP group consumer [COUNT count] [BLOCK ms] STREAMS key
+    /// ... id ..., see `xread`. With `>` as ID, delivers entries never
+    /// delivered to the group. With another ID, delivers again the entries
+    /// pending for the consumer after it; deleted ones have no fields.
+    pub async fn xreadgroup(
+        &self,
+        group: &str,
+        consumer: &str,
+        streams: &[(&str, &str)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> crate::Result<Vec<(String, Vec<StreamEntry>)>> {
+        let cmd = Cmd::new("XREADGROUP").arg("GROUP").arg(group).arg(consumer);
+        let cmd = read_options(cmd, count, block);
+        self.call(streams_args(cmd, streams))
+            .await
+            .and_then(read_streams)
+    }
+
+    /// XPENDING key group. Returns the number of pending entries, the
+    /// smallest and greatest pending IDs, and the number of pending entries
+    /// of each consumer.
+    pub async fn xpending(
+        &self,
+        key: &str,
+        group: &str,
+    ) -> crate::Result<(u64, Option<(String, String)>, Vec<(String, u64)>)> {
+        let reply = array(self.call(Cmd::new("XPENDING").arg(key).arg(group)).await?)?;
+        let mut reply = reply.into_iter();
+        match (reply.next(), reply.next(), reply.next(), reply.next()) {
+            (Some(Frame::Integer(0)), _, _, _) => Ok((0, None, vec![])),
+            (Some(Frame::Integer(count)), Some(first), Some(last), Some(consumers)) => {
+                let first = string(first)?;
+                let last = string(last)?;
+                let consumers = array(consumers)?
+                    .into_iter()
+                    .map(|consumer| match &bulks(consumer)?[..] {
+                        [name, count] => Ok((
+                            String::from_utf8(name.to_vec())?,
+                            std::str::from_utf8(count)?.parse()?,
+                        )),
+                        _ => Err("unexpected reply to XPENDING".into()),
+                    })
+                    .collect::<crate::Result<_>>()?;
+                Ok((count, Some((first, last)), consumers))
+            }
+            _ => Err("unexpected reply to XPENDING".into()),
+        }
+    }
+
+    /// EVAL script numkeys key ... arg .... Returns the reply of the script.
+    pub async fn eval(&self, script: &str, keys: &[&str], args: &[&str]) -> crate::Result<Frame> {
+        self.call(script_cmd("EVAL", script, keys, args)).await
+    }
+
+    /// EVALSHA sha1 numkeys key ... arg ..., running a loaded script
+    pub async fn evalsha(&self, sha: &str, keys: &[&str], args: &[&str]) -> crate::Result<Frame> {
+        self.call(script_cmd("EVALSHA", sha, keys, args)).await
+    }
+
+    /// SCRIPT LOAD script. Returns the SHA1 to pass to `evalsha`.
+    pub async fn script_load(&self, script: &str) -> crate::Result<String> {
+        match bulk(
+            self.call(Cmd::new("SCRIPT").arg("LOAD").arg(script))
+                .await?,
+        )? {
+            Some(sha) => Ok(String::from_utf8(sha.to_vec())?),
+            None => Err("unexpected reply to SCRIPT LOAD".into()),
+        }
+    }
+
+    /// AUTH [username] password. Without `user`, authenticates the default
+    /// user. Reconnections authenticate again with the same credentials.
+    pub async fn auth(&self, user: Option<&str>, password: &str) -> crate::Result<()> {
+        let credentials = Credentials {
+            user: user.map(str::to_string),
+            password: password.to_string(),
+        };
+        self.call(credentials.auth_cmd()).await?;
+        *self.credentials.lock().unwrap() = Some(credentials);
+        Ok(())
+    }
+
+    /// SAVE
+    pub async fn save(&self) -> crate::Result<()> {
+        self.call(Cmd::new("SAVE")).await?;
+        Ok(())
+    }
+
+    /// BGSAVE
+    pub async fn bgsave(&self) -> crate::Result<()> {
+        self.call(Cmd::new("BGSAVE")).await?;
+        Ok(())
+    }
+
+    /// BGREWRITEAOF
+    pub async fn bgrewriteaof(&self) -> crate::Result<()> {
+        self.call(Cmd::new("BGREWRITEAOF")).await?;
+        Ok(())
+    }
+}
+
+impl Credentials {
+    fn auth_cmd(&self) -> Cmd {
+        let cmd = self
+            .user
+            .iter()
+            .fold(Cmd::new("AUTH"), |cmd, user| cmd.arg(user));
+        cmd.arg(&self.password)
+    }
+}
+
+/// The COUNT and BLOCK options of XREAD and XREADGROUP
+fn read_options(cmd: Cmd, count: Option<usize>, block: Option<Duration>) -> Cmd {
+    let cmd = count
+        .iter()
+        .fold(cmd, |cmd, count| cmd.arg("COUNT").arg(count));
+    block.iter().fold(cmd, |cmd, block| {
+        cmd.arg("BLOCK").arg(block.as_millis() as u64)
+    })
+}
+
+/// STREAMS, then the keys, then the IDs
+fn streams_args(cmd: Cmd, streams: &[(&str, &str)]) -> Cmd {
+    let cmd = streams
+        .iter()
+        .fold(cmd.arg("STREAMS"), |cmd, (key, _)| cmd.arg(key));
+    streams.iter().fold(cmd, |cmd, (_, id)| cmd.arg(id))
+}
+
+/// EVAL or EVALSHA with its keys and arguments
+fn script_cmd(name: &str, script: &str, keys: &[&str], args: &[&str]) -> Cmd {
+    let cmd = Cmd::new(name).arg(script).arg(keys.len());
+    keys.iter().chain(args).fold(cmd, |cmd, arg| cmd.arg(arg))
+}
+
+/// Own the connection: write requests as they come, match replies in order
+/// and reconnect when needed. Stops when every handle is dropped and every
+/// reply was received.
+async fn run(
+    addr: SocketAddr,
+    mut conn: Option<Connection>,
+    mut requests: mpsc::Receiver<Request>,
+    credentials: Arc<Mutex<Option<Credentials>>>,
+) {
+    let mut pending: VecDeque<Pending> = VecDeque::new();
+    let mut open = true;
+
+    while open || !pending.is_empty() {
+        tokio::select! {
+            request = requests.recv(), if open => {
+                let Some(request) = request else {
+                    open = false;
+                    continue;
+                };
+
+                if conn.is_none() {
+                    let credentials = credentials.lock().unwrap().clone();
+                    match connect(addr, credentials.as_ref()).await {
+                        Ok(new_conn) => conn = Some(new_conn),
+                        Err(err) => {
+                            let _ = request.reply.send(Err(err));
+                            continue;
+                        }
+                    }
+                }
+
+                // Write everything queued meanwhile with a single flush
+                let mut frames = vec![];
+                let mut next = Some(request);
+                while let Some(request) = next {
+                    pending.push_back(Pending {
+                        expected: request.frames.len(),
+                        replies: Vec::with_capacity(request.frames.len()),
+                        reply: request.reply,
+                    });
+                    frames.extend(request.frames);
+                    next = requests.try_recv().ok();
+                }
+
+                // Replies are read while writing: a big pipeline would
+                // otherwise fill the server's output buffer, and the server
+                // would stop reading it
+                let mut unexpected = false;
+                let written = conn
+                    .as_mut()
+                    .unwrap()
+                    .write_frames_reading(&frames, |frame| unexpected |= !deliver(&mut pending, frame))
+                    .await;
+                if let Err(err) = written {
+                    debug!(%addr, cause = %err, "connection lost");
+                    fail_all(&mut pending, "connection lost");
+                    conn = None;
+                } else if unexpected {
+                    debug!(%addr, "unexpected reply, dropping the connection");
+                    conn = None;
+                }
+            }
+            // Also polled while idle, to notice a closed connection early
+            frame = read_frame(&mut conn) => match frame {
+                Ok(Some(frame)) => {
+                    if !deliver(&mut pending, frame) {
+                        debug!(%addr, "unexpected reply, dropping the connection");
+                        conn = None;
+                    }
+                }
+                Ok(None) | Err(_) => {
+                    debug!(%addr, "connection lost");
+                    fail_all(&mut pending, "connection lost");
+                    conn = None;
+                }
+            },
+        }
+    }
+}
+
+/// Hand `frame` to the oldest request waiting for replies. Returns false
+/// if no request was waiting.
+fn deliver(pending: &mut VecDeque<Pending>, frame: Frame) -> bool {
+    let Some(first) = pending.front_mut() else {
+        return false;
+    };
+    first.replies.push(frame);
+    if first.replies.len() == first.expected {
+        let done = pending.pop_front().unwrap();
+        let _ = done.reply.send(Ok(done.replies));
+    }
+    true
+}
+
+/// Read a frame, or wait forever without a connection
+async fn read_frame(conn: &mut Option<Connection>) -> mini_redis::Result<Option<Frame>> {
+    match conn {
+        Some(conn) => conn.read_frame().await,
+        None => future::pending().await,
+    }
+}
+
+/// Connect to `addr`, retrying a few times, and log in with `credentials`
+/// before anything else is sent
+async fn connect(addr: SocketAddr, credentials: Option<&Credentials>) -> crate::Result<Connection> {
+    let mut backoff = CONNECT_BACKOFF;
+    let mut attempt = 1;
+    let socket = loop {
+        match TcpStream::connect(addr).await {
+            Ok(socket) => break socket,
+            Err(err) if attempt == CONNECT_ATTEMPTS => return Err(err.into()),
+            Err(_) => {
+                time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    };
+
+    let mut conn = Connection::new(socket);
+    if let Some(credentials) = credentials {
+        authenticate(&mut conn, credentials).await?;
+    }
+    Ok(conn)
+}
+
+/// Send AUTH with `credentials` on `conn` and check the reply
+pub(crate) async fn authenticate(
+    conn: &mut Connection,
+    credentials: &Credentials,
+) -> crate::Result<()> {
+    conn.write_frame(&credentials.auth_cmd().into_frame())
+        .await?;
+    match conn.read_frame().await? {
+        Some(frame) => check(frame).map(drop),
+        None => Err("connection closed by the server".into()),
+    }
+}
+
+fn fail_all(pending: &mut VecDeque<Pending>, reason: &str) {
+    for request in pending.drain(..) {
+        let _ = request.reply.send(Err(reason.into()));
+    }
+}
+
+/// Turn an error reply into an error
+pub(crate) fn check(frame: Frame) -> crate::Result<Frame> {
+    match frame {
+        Frame::Error(err) => Err(err.into()),
+        frame => Ok(frame),
+    }
+}
+
+fn unexpected(frame: Frame) -> crate::Error {
+    format!("unexpected reply: {:?}", frame).into()
+}
+
+fn bulk(frame: Frame) -> crate::Result<Option<Bytes>> {
+    match frame {
+        Frame::Bulk(data) => Ok(Some(data)),
+        Frame::Null => Ok(None),
+        frame => Err(unexpected(frame)),
+    }
+}
+
+fn integer(frame: Frame) -> crate::Result<u64> {
+    match frame {
+        Frame::Integer(value) => Ok(value),
+        frame => Err(unexpected(frame)),
+    }
+}
+
+fn array(frame: Frame) -> crate::Result<Vec<Frame>> {
+    match frame {
+        Frame::Array(frames) => Ok(frames),
+        frame => Err(unexpected(frame)),
+    }
+}
+
+fn bulks(frame: Frame) -> crate::Result<Vec<Bytes>> {
+    array(frame)?
+        .into_iter()
+        .map(|frame| bulk(frame)?.ok_or_else(|| "unexpected null".into()))
+        .collect()
+}
+
+fn string(frame: Frame) -> crate::Result<String> {
+    match bulk(frame)? {
+        Some(data) => Ok(String::from_utf8(data.to_vec())?),
+        None => Err("unexpected null".into()),
+    }
+}
+
+/// Entries as replied by XRANGE: pairs of an ID and fields. The fields of
+/// deleted entries are null.
+fn entries(frame: Frame) -> crate::Result<Vec<StreamEntry>> {
+    array(frame)?
+        .into_iter()
+        .map(|entry| {
+            let mut parts = array(entry)?.into_iter();
+            let (Some(id), Some(fields), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err("unexpected stream entry".into());
+            };
+            let fields = match fields {
+                Frame::Null => vec![],
+                fields => bulks(fields)?
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        [field, value] => Ok((field.clone(), value.clone())),
+                        _ => Err("odd number of fields in a stream entry".into()),
+                    })
+                    .collect::<crate::Result<_>>()?,
+            };
+            Ok((string(id)?, fields))
+        })
+        .collect()
+}
+
+/// Streams as replied by XREAD: pairs of a key and entries, or null
+fn read_streams(frame: Frame) -> crate::Result<Vec<(String, Vec<StreamEntry>)>> {
+    if let Frame::Null = frame {
+        return Ok(vec![]);
+    }
+    array(frame)?
+        .into_iter()
+        .map(|stream| {
+            let mut parts = array(stream)?.into_iter();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(key), Some(stream), None) => Ok((string(key)?, entries(stream)?)),
+                _ => Err("unexpected stream in reply".into()),
+            }
+        })
+        .collect()
+}
+
+fn with_scores(frame: Frame) -> crate::Result<Vec<(Bytes, f64)>> {
+    let parts = bulks(frame)?;
+    parts
+        .chunks(2)
+        .map(|pair| match pair {
+            [member, score] => Ok((member.clone(), parse_float(score)?)),
+            _ => Err("odd number of elements in a WITHSCORES reply".into()),
+        })
+        .collect()
+}
+
+fn parse_float(data: &[u8]) -> crate::Result<f64> {
+    Ok(std::str::from_utf8(data)?.parse()?)
+}
@@ -0,0 +1,76 @@
+use mini_redis::Frame;
+
+use crate::client::{Client, Cmd};
+
+/// Commands sent together over a `Client` connection, with a single flush.
+///
+/// With `atomic`, they are wrapped in MULTI/EXEC and run as a transaction.
+/// `exec` does the same, and also tells when the transaction was aborted
+/// because a key watched with `Client::watch` changed.
+#[derive(Debug)]
+pub struct Pipeline {
+    client: Client,
+    commands: Vec<Frame>,
+    atomic: bool,
+}
+
+impl Pipeline {
+    pub(crate) fn new(client: Client) -> Pipeline {
+        Pipeline {
+            client,
+            commands: vec![],
+            atomic: false,
+        }
+    }
+
+    /// Run the commands as a MULTI/EXEC transaction
+    pub fn atomic(&mut self) -> &mut Pipeline {
+        self.atomic = true;
+        self
+    }
+
+    pub fn add(&mut self, cmd: Cmd) -> &mut Pipeline {
+        self.commands.push(cmd.into_frame());
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Send the commands and return one reply per command.
+    ///
+    /// Error replies are returned as error frames, so one failing command
+    /// does not hide the replies of the others. A transaction that could not
+    /// run at all is an error.
+    pub async fn execute(&self) -> crate::Result<Vec<Frame>> {
+        if !self.atomic {
+            return self.client.send(self.commands.clone()).await;
+        }
+
+        self.exec()
+            .await?
+            .ok_or_else(|| "transaction aborted".into())
+    }
+
+    /// Run the commands as a MULTI/EXEC transaction, even without `atomic`, and
+    /// return one reply per command. `None` if the transaction was aborted
+    /// because a watched key changed, which an empty transaction never is.
+    pub async fn exec(&self) -> crate::Result<Option<Vec<Frame>>> {
+        let mut frames = vec![Cmd::new("MULTI").into_frame()];
+        frames.extend(self.commands.iter().cloned());
+        frames.push(Cmd::new("EXEC").into_frame());
+
+        // MULTI and the queued commands reply first, EXEC holds the results
+        match self.client.send(frames).await?.pop() {
+            Some(Frame::Array(replies)) => Ok(Some(replies)),
+            Some(Frame::Error(err)) => Err(err.into()),
+            Some(Frame::Null) => Ok(None),
+            other => Err(format!("unexpected EXEC reply: {:?}", other).into()),
+        }
+    }
+}
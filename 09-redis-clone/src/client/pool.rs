@@ -0,0 +1,49 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::net::ToSocketAddrs;
+
+use crate::client::Client;
+
+/// A fixed number of `Client` connections, handed out in turn.
+///
+/// Each client already pipelines its commands, so a pool is mostly useful to
+/// spread the load over several server connections: the server runs the
+/// commands of one connection one after another.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    clients: Arc<[Client]>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Pool {
+    /// Open `size` connections to `addr`
+    pub async fn connect(addr: impl ToSocketAddrs, size: usize) -> crate::Result<Pool> {
+        if size == 0 {
+            return Err("a pool needs at least one connection".into());
+        }
+
+        let first = Client::connect(addr).await?;
+        let mut clients = vec![first.clone()];
+        for _ in 1..size {
+            clients.push(Client::connect(first.addr()).await?);
+        }
+
+        Ok(Pool {
+            clients: clients.into(),
+            next: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Next client, round robin
+    pub fn get(&self) -> Client {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len();
+        self.clients[index].clone()
+    }
+
+    pub fn size(&self) -> usize {
+        self.clients.len()
+    }
+}
@@ -0,0 +1,190 @@
+use std::{collections::VecDeque, net::SocketAddr};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::net::TcpStream;
+
+use crate::{
+    client::{authenticate, check, Cmd, Credentials},
+    Connection,
+};
+
+/// A message published to a subscribed channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub channel: String,
+    /// Pattern the channel matched, for pattern subscriptions
+    pub pattern: Option<String>,
+    pub content: Bytes,
+}
+
+/// Frames the server pushes to subscribers
+enum Push {
+    Message(Message),
+    /// (P)(UN)SUBSCRIBE confirmation: kind, channel or pattern
+    Confirmation(String, Option<String>),
+}
+
+/// A dedicated connection in subscriber mode.
+///
+/// It is not shared nor reconnected: once subscribed, a connection only
+/// accepts subscription commands.
+#[derive(Debug)]
+pub struct Subscriber {
+    connection: Connection,
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    // Messages received while waiting for a confirmation
+    buffered: VecDeque<Message>,
+}
+
+impl Subscriber {
+    /// Connect to `addr`, logging in with `credentials` before subscribing
+    pub(crate) async fn connect(
+        addr: SocketAddr,
+        credentials: Option<&Credentials>,
+    ) -> crate::Result<Subscriber> {
+        let mut connection = Connection::new(TcpStream::connect(addr).await?);
+        if let Some(credentials) = credentials {
+            authenticate(&mut connection, credentials).await?;
+        }
+        Ok(Subscriber {
+            connection,
+            channels: vec![],
+            patterns: vec![],
+            buffered: VecDeque::new(),
+        })
+    }
+
+    /// Channels currently subscribed to
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Patterns currently subscribed to
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub async fn subscribe(&mut self, channels: &[&str]) -> crate::Result<()> {
+        self.request("SUBSCRIBE", channels, channels.len()).await
+    }
+
+    pub async fn psubscribe(&mut self, patterns: &[&str]) -> crate::Result<()> {
+        self.request("PSUBSCRIBE", patterns, patterns.len()).await
+    }
+
+    /// Unsubscribe from `channels`, or from all of them if empty
+    pub async fn unsubscribe(&mut self, channels: &[&str]) -> crate::Result<()> {
+        // One confirmation per channel, and at least one
+        let expected = match channels.len() {
+            0 => self.channels.len().max(1),
+            len => len,
+        };
+        self.request("UNSUBSCRIBE", channels, expected).await
+    }
+
+    /// Unsubscribe from `patterns`, or from all of them if empty
+    pub async fn punsubscribe(&mut self, patterns: &[&str]) -> crate::Result<()> {
+        let expected = match patterns.len() {
+            0 => self.patterns.len().max(1),
+            len => len,
+        };
+        self.request("PUNSUBSCRIBE", patterns, expected).await
+    }
+
+    /// Wait for the next published message. `None` if the server closed
+    /// the connection.
+    pub async fn next_message(&mut self) -> crate::Result<Option<Message>> {
+        if let Some(message) = self.buffered.pop_front() {
+            return Ok(Some(message));
+        }
+
+        loop {
+            match self.read_push().await? {
+                Some(Push::Message(message)) => return Ok(Some(message)),
+                // Late confirmation, nothing to do
+                Some(Push::Confirmation(..)) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Send a subscription command and wait for its `expected` confirmations
+    async fn request(&mut self, name: &str, names: &[&str], expected: usize) -> crate::Result<()> {
+        let cmd = names
+            .iter()
+            .fold(Cmd::new(name), |cmd, name| cmd.arg(*name));
+        self.connection.write_frame(&cmd.into_frame()).await?;
+
+        let mut confirmed = 0;
+        while confirmed < expected {
+            match self.read_push().await? {
+                Some(Push::Message(message)) => self.buffered.push_back(message),
+                Some(Push::Confirmation(kind, name)) => {
+                    confirmed += 1;
+                    self.track(&kind, name);
+                }
+                None => return Err("connection closed by the server".into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the subscription lists in sync with a confirmation
+    fn track(&mut self, kind: &str, name: Option<String>) {
+        let Some(name) = name else {
+            return;
+        };
+        match kind {
+            "subscribe" => self.channels.push(name),
+            "psubscribe" => self.patterns.push(name),
+            "unsubscribe" => self.channels.retain(|channel| *channel != name),
+            "punsubscribe" => self.patterns.retain(|pattern| *pattern != name),
+            _ => {}
+        }
+    }
+
+    async fn read_push(&mut self) -> crate::Result<Option<Push>> {
+        let Some(frame) = self.connection.read_frame().await? else {
+            return Ok(None);
+        };
+        let Frame::Array(parts) = check(frame)? else {
+            return Err("unexpected frame in subscriber mode".into());
+        };
+
+        let parts: Vec<_> = parts
+            .into_iter()
+            .map(|part| match part {
+                Frame::Bulk(data) => Some(data),
+                _ => None,
+            })
+            .collect();
+        let bytes = |index: usize| -> crate::Result<Bytes> {
+            Ok(parts
+                .get(index)
+                .cloned()
+                .flatten()
+                .ok_or("malformed push")?)
+        };
+        let string = |index: usize| -> crate::Result<String> {
+            Ok(String::from_utf8(bytes(index)?.to_vec())?)
+        };
+
+        let push = match &bytes(0)?[..] {
+            b"message" => Push::Message(Message {
+                channel: string(1)?,
+                pattern: None,
+                content: bytes(2)?,
+            }),
+            b"pmessage" => Push::Message(Message {
+                pattern: Some(string(1)?),
+                channel: string(2)?,
+                content: bytes(3)?,
+            }),
+            // Unsubscribing from nothing confirms with a null name
+            _ => Push::Confirmation(string(0)?, string(1).ok()),
+        };
+        Ok(Some(push))
+    }
+}
@@ -0,0 +1,157 @@
+//! Registry of connected clients, for CLIENT LIST/KILL and INFO
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+
+/// Every connected client, by id
+#[derive(Debug, Default)]
+pub struct Clients {
+    next_id: u64,
+    connected: BTreeMap<u64, Arc<ConnectedClient>>,
+}
+
+impl Clients {
+    /// Add a client connected from `addr`, with a new id. `user` is the
+    /// user it runs as, or `None` if it must AUTH first.
+    pub(crate) fn register(
+        &mut self,
+        addr: SocketAddr,
+        user: Option<String>,
+    ) -> Arc<ConnectedClient> {
+        self.next_id += 1;
+        let now = Instant::now();
+        let client = Arc::new(ConnectedClient {
+            id: self.next_id,
+            addr,
+            created: now,
+            info: Mutex::new(ClientInfo {
+                name: None,
+                user,
+                last_command: "NULL".to_string(),
+                last_interaction: now,
+                subscriptions: 0,
+                patterns: 0,
+                multi: None,
+                commands: 0,
+                net_input_bytes: 0,
+                net_output_bytes: 0,
+            }),
+            killed: Notify::new(),
+        });
+        self.connected.insert(client.id, client.clone());
+        client
+    }
+
+    /// Remove a client once its connection is closed
+    pub(crate) fn unregister(&mut self, id: u64) {
+        self.connected.remove(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.connected.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connected.is_empty()
+    }
+
+    /// Connected clients, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<ConnectedClient>> {
+        self.connected.values()
+    }
+}
+
+/// A connected client: fixed identity plus metadata its connection updates
+#[derive(Debug)]
+pub struct ConnectedClient {
+    id: u64,
+    addr: SocketAddr,
+    created: Instant,
+    info: Mutex<ClientInfo>,
+    // Tells the connection to close
+    killed: Notify,
+}
+
+/// What the connection reports about itself after each command
+#[derive(Debug)]
+pub struct ClientInfo {
+    /// Set by CLIENT SETNAME
+    pub name: Option<String>,
+    /// Set by AUTH
+    pub user: Option<String>,
+    pub last_command: String,
+    pub last_interaction: Instant,
+    pub subscriptions: usize,
+    pub patterns: usize,
+    /// Number of queued commands, inside MULTI
+    pub multi: Option<usize>,
+    pub commands: u64,
+    pub net_input_bytes: u64,
+    pub net_output_bytes: u64,
+}
+
+impl ConnectedClient {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Lock the metadata of the client. It is only contended by CLIENT LIST.
+    pub fn info(&self) -> MutexGuard<'_, ClientInfo> {
+        self.info.lock().unwrap()
+    }
+
+    /// Close the connection once its current command is done
+    pub fn kill(&self) {
+        // The permit is kept if the connection is not waiting right now
+        self.killed.notify_one();
+    }
+
+    /// Resolve when the client is killed
+    pub(crate) async fn killed(&self) {
+        self.killed.notified().await
+    }
+
+    /// One line of CLIENT LIST
+    pub fn describe(&self) -> String {
+        let info = self.info();
+        let now = Instant::now();
+
+        let flags = if info.multi.is_some() {
+            "x"
+        } else if info.subscriptions + info.patterns > 0 {
+            "P"
+        } else {
+            "N"
+        };
+        let multi = info.multi.map_or(-1, |queued| queued as i64);
+
+        format!(
+            "id={} addr={} name={} age={} idle={} flags={} sub={} psub={} multi={} \
+             tot-cmds={} tot-net-in={} tot-net-out={} cmd={} user={}",
+            self.id,
+            self.addr,
+            info.name.as_deref().unwrap_or(""),
+            now.duration_since(self.created).as_secs(),
+            now.duration_since(info.last_interaction).as_secs(),
+            flags,
+            info.subscriptions,
+            info.patterns,
+            multi,
+            info.commands,
+            info.net_input_bytes,
+            info.net_output_bytes,
+            info.last_command,
+            info.user.as_deref().unwrap_or(""),
+        )
+    }
+}
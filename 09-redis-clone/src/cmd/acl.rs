@@ -0,0 +1,151 @@
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{
+    acl::{self, DEFAULT_USER, WRONGPASS},
+    clients::ConnectedClient,
+    parse::Parse,
+    Db,
+};
+
+/// AUTH [username] password
+#[derive(Debug)]
+pub struct Auth {
+    // `None` for the old form, which authenticates the default user
+    user: Option<String>,
+    password: String,
+}
+
+impl Auth {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Auth> {
+        let first = parse.next_string()?;
+        if parse.remaining() == 0 {
+            return Ok(Auth {
+                user: None,
+                password: first,
+            });
+        }
+        Ok(Auth {
+            user: Some(first),
+            password: parse.next_string()?,
+        })
+    }
+
+    /// Switch `client` to the user, if the password matches
+    pub(crate) fn apply(self, db: &Db, client: &ConnectedClient) -> crate::Result<Frame> {
+        let acl = db.acl();
+        let name = match self.user {
+            Some(name) => name,
+            None if acl.default_login().is_some() => {
+                return Err(
+                    "ERR AUTH <password> called without any password configured \
+                            for the default user. Are you sure your configuration is correct?"
+                        .into(),
+                )
+            }
+            None => DEFAULT_USER.to_string(),
+        };
+
+        if acl.authenticate(&name, &self.password).is_none() {
+            return Err(WRONGPASS.into());
+        }
+        drop(acl);
+        client.info().user = Some(name);
+        Ok(Frame::Simple("OK".into()))
+    }
+}
+
+/// ACL SETUSER username [rule ...] | DELUSER username [username ...] | LIST
+/// | USERS | WHOAMI | CAT [category]
+#[derive(Debug)]
+pub enum AclCommand {
+    SetUser(String, Vec<String>),
+    DelUser(Vec<String>),
+    List,
+    Users,
+    WhoAmI,
+    Cat(Option<String>),
+}
+
+impl AclCommand {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<AclCommand> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let command = match &subcommand[..] {
+            "setuser" => {
+                let name = parse.next_string()?;
+                let mut rules = vec![];
+                while parse.remaining() > 0 {
+                    rules.push(parse.next_string()?);
+                }
+                AclCommand::SetUser(name, rules)
+            }
+            "deluser" => {
+                let mut names = vec![parse.next_string()?];
+                while parse.remaining() > 0 {
+                    names.push(parse.next_string()?);
+                }
+                AclCommand::DelUser(names)
+            }
+            "list" => AclCommand::List,
+            "users" => AclCommand::Users,
+            "whoami" => AclCommand::WhoAmI,
+            "cat" => match parse.remaining() {
+                0 => AclCommand::Cat(None),
+                _ => AclCommand::Cat(Some(parse.next_string()?.to_lowercase())),
+            },
+            _ => {
+                return Err(
+                    format!("ERR unknown subcommand '{}'. Try ACL HELP.", subcommand).into(),
+                )
+            }
+        };
+        Ok(command)
+    }
+
+    /// Run the command on behalf of `client`
+    pub(crate) fn apply(self, db: &Db, client: &ConnectedClient) -> crate::Result<Frame> {
+        match self {
+            AclCommand::SetUser(name, rules) => {
+                db.acl().set_user(&name, &rules)?;
+                Ok(Frame::Simple("OK".into()))
+            }
+            AclCommand::DelUser(names) => {
+                if names.iter().any(|name| name == DEFAULT_USER) {
+                    return Err("ERR The 'default' user cannot be removed".into());
+                }
+                let deleted: Vec<_> = {
+                    let mut acl = db.acl();
+                    names
+                        .into_iter()
+                        .filter(|name| acl.delete_user(name))
+                        .collect()
+                };
+
+                // Connections of deleted users are closed
+                for other in db.clients().iter() {
+                    let user = other.info().user.clone();
+                    if user.is_some_and(|user| deleted.contains(&user)) {
+                        other.kill();
+                    }
+                }
+                Ok(Frame::Integer(deleted.len() as u64))
+            }
+            AclCommand::List => Ok(bulks(db.acl().users().map(|user| user.describe()))),
+            AclCommand::Users => Ok(bulks(db.acl().users().map(|user| user.name().to_string()))),
+            AclCommand::WhoAmI => match &client.info().user {
+                Some(name) => Ok(Frame::Bulk(Bytes::from(name.clone()))),
+                None => Ok(Frame::Null),
+            },
+            AclCommand::Cat(None) => Ok(bulks(acl::categories().map(str::to_string))),
+            AclCommand::Cat(Some(name)) => {
+                let commands = acl::category(&name)
+                    .ok_or_else(|| format!("ERR Unknown category '{}'", name))?;
+                Ok(bulks(commands.into_iter().map(str::to_string)))
+            }
+        }
+    }
+}
+
+fn bulks(items: impl Iterator<Item = String>) -> Frame {
+    Frame::Array(items.map(|item| Frame::Bulk(Bytes::from(item))).collect())
+}
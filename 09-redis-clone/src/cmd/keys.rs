@@ -0,0 +1,399 @@
+use std::{iter, ops::DerefMut};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{
+    cmd::command_frame,
+    db::{from_unix_ms, now_ms, Keyspace, Value},
+    glob::glob_match,
+    parse::Parse,
+};
+
+/// Keys returned by SCAN when COUNT is not given
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// EXPIRE key seconds, or PEXPIREAT key timestamp-ms
+#[derive(Debug)]
+pub struct Expire {
+    key: String,
+    // Unix time in ms
+    at: i64,
+}
+
+impl Expire {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Expire> {
+        let key = parse.next_string()?;
+        let seconds = parse.next_int()?;
+        let at = (now_ms() as i64).saturating_add(seconds.saturating_mul(1000));
+        Ok(Expire { key, at })
+    }
+
+    pub(crate) fn parse_pexpireat(parse: &mut Parse) -> crate::Result<Expire> {
+        let key = parse.next_string()?;
+        let at = parse.next_int()?;
+        Ok(Expire { key, at })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        if ks.get(&self.key).is_none() {
+            return Ok(Frame::Integer(0));
+        }
+
+        // A deadline in the past deletes the key right away
+        if self.at <= now_ms() as i64 {
+            ks.remove(&self.key);
+        } else {
+            ks.set_expiry(&self.key, Some(from_unix_ms(self.at as u64)));
+        }
+        Ok(Frame::Integer(1))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        command_frame([
+            Bytes::from("PEXPIREAT"),
+            Bytes::from(self.key.clone()),
+            Bytes::from(self.at.to_string()),
+        ])
+    }
+}
+
+/// DEL key [key ...]
+#[derive(Debug)]
+pub struct Del {
+    keys: Vec<String>,
+}
+
+impl Del {
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Del> {
+        Ok(Del {
+            keys: parse_keys(parse)?,
+        })
+    }
+
+    /// Remove the keys, each from its shard. Replies with the number removed.
+    pub(crate) fn apply<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &mut [S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> crate::Result<Frame> {
+        let removed = self
+            .keys
+            .iter()
+            .filter(|key| shards[shard_of(key)].remove(key).is_some())
+            .count();
+        Ok(Frame::Integer(removed as u64))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        let keys = self.keys.iter().map(|key| Bytes::from(key.clone()));
+        command_frame(iter::once(Bytes::from("DEL")).chain(keys))
+    }
+}
+
+/// EXISTS key [key ...]
+#[derive(Debug)]
+pub struct Exists {
+    keys: Vec<String>,
+}
+
+impl Exists {
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Exists> {
+        Ok(Exists {
+            keys: parse_keys(parse)?,
+        })
+    }
+
+    /// Reply with the number of existing keys. A key given twice counts twice.
+    pub(crate) fn apply<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &mut [S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> crate::Result<Frame> {
+        let existing = self
+            .keys
+            .iter()
+            .filter(|key| shards[shard_of(key)].get(key).is_some())
+            .count();
+        Ok(Frame::Integer(existing as u64))
+    }
+}
+
+/// TYPE key
+#[derive(Debug)]
+pub struct Type {
+    key: String,
+}
+
+impl Type {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Type> {
+        let key = parse.next_string()?;
+        Ok(Type { key })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let name = ks.get(&self.key).map_or("none", Value::type_name);
+        Ok(Frame::Simple(name.to_string()))
+    }
+}
+
+/// RENAME key newkey
+#[derive(Debug)]
+pub struct Rename {
+    key: String,
+    new_key: String,
+}
+
+impl Rename {
+    pub(crate) fn keys(&self) -> [&str; 2] {
+        [&self.key, &self.new_key]
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Rename> {
+        let key = parse.next_string()?;
+        let new_key = parse.next_string()?;
+        Ok(Rename { key, new_key })
+    }
+
+    /// Move the value and expiration of `key` to `new_key`, which is
+    /// overwritten. Both may be in different shards.
+    pub(crate) fn apply<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &mut [S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> crate::Result<Frame> {
+        let source = &mut shards[shard_of(&self.key)];
+        if source.get(&self.key).is_none() {
+            return Err("ERR no such key".into());
+        }
+        if self.key == self.new_key {
+            return Ok(Frame::Simple("OK".into()));
+        }
+
+        let expires_at = source.expires_at(&self.key);
+        let value = source.remove(&self.key).unwrap();
+        let target = &mut shards[shard_of(&self.new_key)];
+        target.insert(self.new_key.clone(), value);
+        target.set_expiry(&self.new_key, expires_at);
+        Ok(Frame::Simple("OK".into()))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        command_frame([
+            Bytes::from("RENAME"),
+            Bytes::from(self.key.clone()),
+            Bytes::from(self.new_key.clone()),
+        ])
+    }
+}
+
+/// KEYS pattern
+#[derive(Debug)]
+pub struct Keys {
+    pattern: String,
+}
+
+impl Keys {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Keys> {
+        let pattern = parse.next_string()?;
+        Ok(Keys { pattern })
+    }
+
+    /// Reply with every live key matching the pattern, in no particular order
+    pub(crate) fn apply<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &[S],
+    ) -> crate::Result<Frame> {
+        let keys = shards
+            .iter()
+            .flat_map(|ks| ks.iter())
+            .filter(|(key, _, _)| glob_match(self.pattern.as_bytes(), key.as_bytes()))
+            .map(|(key, _, _)| Frame::Bulk(Bytes::from(key.clone())))
+            .collect();
+        Ok(Frame::Array(keys))
+    }
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+///
+/// Keys are visited in the order of `scan_hash`, and the cursor is the hash
+/// to resume from. A key present for the whole iteration is therefore
+/// returned exactly once, whatever is written meanwhile. Like in Redis, COUNT
+/// is the number of keys examined: MATCH and TYPE filter them afterwards, so
+/// a page may be empty before the end.
+#[derive(Debug)]
+pub struct Scan {
+    cursor: u64,
+    pattern: Option<String>,
+    count: usize,
+    kind: Option<String>,
+}
+
+impl Scan {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Scan> {
+        let cursor = parse
+            .next_string()?
+            .parse()
+            .map_err(|_| "ERR invalid cursor")?;
+
+        let mut scan = Scan {
+            cursor,
+            pattern: None,
+            count: DEFAULT_SCAN_COUNT,
+            kind: None,
+        };
+        while parse.remaining() > 0 {
+            match &parse.next_string()?.to_uppercase()[..] {
+                "MATCH" => scan.pattern = Some(parse.next_string()?),
+                "COUNT" => {
+                    scan.count = match parse.next_int()? {
+                        count if count > 0 => count as usize,
+                        _ => return Err("ERR syntax error".into()),
+                    }
+                }
+                "TYPE" => scan.kind = Some(parse.next_string()?.to_lowercase()),
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Reply with the next cursor, 0 once done, and the keys of this page
+    pub(crate) fn apply<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &[S],
+    ) -> crate::Result<Frame> {
+        // One key more than needed from each shard tells if any is left
+        let mut page: Vec<_> = shards
+            .iter()
+            .enumerate()
+            .flat_map(|(index, ks)| {
+                ks.scan_from(self.cursor)
+                    .take(self.count + 1)
+                    .map(move |(hash, key)| (hash, index, key))
+            })
+            .collect();
+        page.sort_unstable();
+
+        let next = if page.len() <= self.count {
+            0
+        } else {
+            page.truncate(self.count);
+            // Keys sharing the last hash must be in the same page, as the
+            // cursor moves past it
+            let last = page[self.count - 1].0;
+            page.retain(|(hash, _, _)| *hash != last);
+            for (index, ks) in shards.iter().enumerate() {
+                let same = ks.scan_from(last).take_while(|(hash, _)| *hash == last);
+                page.extend(same.map(|(hash, key)| (hash, index, key)));
+            }
+            // Wrapping to 0 means the end
+            last.wrapping_add(1)
+        };
+
+        let keys = page
+            .into_iter()
+            .filter(|(_, index, key)| self.matches(&shards[*index], key))
+            .map(|(_, _, key)| Frame::Bulk(Bytes::from(key.to_string())))
+            .collect();
+        Ok(Frame::Array(vec![
+            Frame::Bulk(Bytes::from(next.to_string())),
+            Frame::Array(keys),
+        ]))
+    }
+
+    /// Check if `key` of the shard `ks` is live and passes the MATCH and
+    /// TYPE filters
+    fn matches(&self, ks: &Keyspace, key: &str) -> bool {
+        let Some(value) = ks.get(key) else {
+            return false;
+        };
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern.as_bytes(), key.as_bytes()))
+            && self
+                .kind
+                .as_ref()
+                .is_none_or(|kind| kind == value.type_name())
+    }
+}
+
+/// Reply to DBSIZE: the number of keys, including expired ones not purged yet
+pub(crate) fn dbsize<S: DerefMut<Target = Keyspace>>(shards: &[S]) -> Frame {
+    Frame::Integer(shards.iter().map(|ks| ks.len() as u64).sum())
+}
+
+/// One or more keys, up to the end of the command
+fn parse_keys(parse: &mut Parse) -> crate::Result<Vec<String>> {
+    let mut keys = vec![parse.next_string()?];
+    while parse.remaining() > 0 {
+        keys.push(parse.next_string()?);
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cmd::test_util::run, db::Keyspace};
+
+    #[test]
+    fn del_exists_and_type() {
+        let mut ks = Keyspace::default();
+        run(&mut ks, "SET a 1");
+        run(&mut ks, "ZADD b 1 x");
+        assert_eq!(run(&mut ks, "EXISTS a b c a"), "3");
+        assert_eq!(run(&mut ks, "TYPE a"), "string");
+        assert_eq!(run(&mut ks, "TYPE b"), "zset");
+        assert_eq!(run(&mut ks, "TYPE c"), "none");
+        assert_eq!(run(&mut ks, "DBSIZE"), "2");
+        assert_eq!(run(&mut ks, "DEL a c b"), "2");
+        assert_eq!(run(&mut ks, "DBSIZE"), "0");
+        assert!(run(&mut ks, "DEL").starts_with("error"));
+    }
+
+    #[test]
+    fn rename_moves_value_and_expiration() {
+        let mut ks = Keyspace::default();
+        run(&mut ks, "SET session token EX 100");
+        run(&mut ks, "SET other value");
+        assert_eq!(run(&mut ks, "RENAME session other"), "OK");
+        assert_eq!(run(&mut ks, "GET other"), "token");
+        assert_eq!(run(&mut ks, "EXISTS session"), "0");
+        assert!(ks.expires_at("other").is_some());
+        assert_eq!(run(&mut ks, "RENAME other other"), "OK");
+        assert!(run(&mut ks, "RENAME missing other").contains("no such key"));
+    }
+
+    #[test]
+    fn scan_options() {
+        let mut ks = Keyspace::default();
+        for i in 0..30 {
+            run(&mut ks, &format!("SET user:{} x", i));
+        }
+        run(&mut ks, "ZADD user:board 1 a");
+
+        // A page big enough for every key ends the iteration
+        let page = run(&mut ks, "SCAN 0 COUNT 100 TYPE zset");
+        assert_eq!(page, "0 user:board");
+        let page = run(&mut ks, "SCAN 0 MATCH user:1? COUNT 1000");
+        assert_eq!(page.split(' ').count(), 11);
+        assert!(run(&mut ks, "SCAN 0 COUNT 0").starts_with("error"));
+        assert!(run(&mut ks, "SCAN abc").starts_with("error"));
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::sync::Notify;
+
+use crate::{
+    cmd::command_frame,
+    db::Keyspace,
+    list::End,
+    parse::{parse_float, Parse},
+};
+
+/// LPUSH key element [element ...], or RPUSH
+#[derive(Debug)]
+pub struct Push {
+    key: String,
+    values: Vec<Bytes>,
+    end: End,
+}
+
+impl Push {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse, end: End) -> crate::Result<Push> {
+        let key = parse.next_string()?;
+        let mut values = vec![parse.next_bytes()?];
+        while parse.remaining() > 0 {
+            values.push(parse.next_bytes()?);
+        }
+        Ok(Push { key, values, end })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self.end {
+            End::Left => "lpush",
+            End::Right => "rpush",
+        }
+    }
+
+    /// Push the elements one after the other. Replies with the new length.
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let list = ks.list_mut(&self.key)?;
+        for value in self.values {
+            list.push(self.end, value);
+        }
+        Ok(Frame::Integer(list.len() as u64))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        let name = self.name().to_uppercase();
+        let mut parts = vec![Bytes::from(name), Bytes::from(self.key.clone())];
+        parts.extend(self.values.iter().cloned());
+        command_frame(parts)
+    }
+}
+
+/// LPOP key [count], or RPOP
+#[derive(Debug)]
+pub struct Pop {
+    key: String,
+    // Without count, the reply is a single element instead of an array
+    count: Option<usize>,
+    end: End,
+}
+
+impl Pop {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse, end: End) -> crate::Result<Pop> {
+        let key = parse.next_string()?;
+        let mut count = None;
+        if parse.remaining() > 0 {
+            let n = parse.next_int()?;
+            let n =
+                usize::try_from(n).map_err(|_| "ERR value is out of range, must be positive")?;
+            count = Some(n);
+        }
+        Ok(Pop { key, count, end })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self.end {
+            End::Left => "lpop",
+            End::Right => "rpop",
+        }
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        if ks.list(&self.key)?.is_none() {
+            return Ok(Frame::Null);
+        }
+
+        let list = ks.list_mut(&self.key)?;
+        let popped: Vec<_> = (0..self.count.unwrap_or(1))
+            .map_while(|_| list.pop(self.end))
+            .collect();
+        // Empty lists are deleted, like in Redis
+        if list.is_empty() {
+            ks.remove(&self.key);
+        }
+
+        Ok(match self.count {
+            Some(_) => Frame::Array(popped.into_iter().map(Frame::Bulk).collect()),
+            None => Frame::Bulk(popped.into_iter().next().unwrap()),
+        })
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        let name = self.name().to_uppercase();
+        let mut parts = vec![Bytes::from(name), Bytes::from(self.key.clone())];
+        if let Some(count) = self.count {
+            parts.push(Bytes::from(count.to_string()));
+        }
+        command_frame(parts)
+    }
+}
+
+/// LLEN key
+#[derive(Debug)]
+pub struct LLen {
+    key: String,
+}
+
+impl LLen {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<LLen> {
+        Ok(LLen {
+            key: parse.next_string()?,
+        })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let len = ks.list(&self.key)?.map_or(0, |list| list.len());
+        Ok(Frame::Integer(len as u64))
+    }
+}
+
+/// LRANGE key start stop
+#[derive(Debug)]
+pub struct LRange {
+    key: String,
+    start: i64,
+    stop: i64,
+}
+
+impl LRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<LRange> {
+        let key = parse.next_string()?;
+        let start = parse.next_int()?;
+        let stop = parse.next_int()?;
+        Ok(LRange { key, start, stop })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let Some(list) = ks.list(&self.key)? else {
+            return Ok(Frame::Array(vec![]));
+        };
+        let items = list.range(self.start, self.stop).cloned();
+        Ok(Frame::Array(items.map(Frame::Bulk).collect()))
+    }
+}
+
+/// BLPOP key [key ...] timeout, or BRPOP
+///
+/// Pops from the first non-empty list, or waits up to `timeout` seconds
+/// (0 for ever) for an element. Connections waiting on a list are served
+/// in the order they started waiting.
+#[derive(Debug)]
+pub struct BPop {
+    keys: Vec<String>,
+    timeout: Duration,
+    end: End,
+    // List with an element for this connection, found while blocking
+    ready: Option<String>,
+}
+
+impl BPop {
+    pub(crate) fn parse_frames(parse: &mut Parse, end: End) -> crate::Result<BPop> {
+        let mut args = vec![parse.next_string()?, parse.next_string()?];
+        while parse.remaining() > 0 {
+            args.push(parse.next_string()?);
+        }
+
+        let timeout = args.pop().unwrap();
+        let timeout = parse_float(&timeout).ok_or("ERR timeout is not a float or out of range")?;
+        if timeout < 0.0 {
+            return Err("ERR timeout is negative".into());
+        }
+        let timeout = Duration::try_from_secs_f64(timeout)
+            .map_err(|_| "ERR timeout is not a float or out of range")?;
+
+        Ok(BPop {
+            keys: args,
+            timeout,
+            end,
+            ready: None,
+        })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self.end {
+            End::Left => "blpop",
+            End::Right => "brpop",
+        }
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Check if a list has an element for the connection waiting with
+    /// `waker`: one for each connection that waits on it since before
+    pub(crate) fn is_ready<S: Deref<Target = Keyspace>>(
+        &mut self,
+        shards: &[S],
+        shard_of: impl Fn(&str) -> usize,
+        waker: &Arc<Notify>,
+    ) -> bool {
+        for key in &self.keys {
+            let ks = &shards[shard_of(key)];
+            match ks.list(key) {
+                Ok(Some(list)) if list.len() > ks.blocked_before(key, waker) => {
+                    self.ready = Some(key.clone());
+                    return true;
+                }
+                Ok(_) => {}
+                // Reply with the error
+                Err(_) => return true,
+            }
+        }
+        false
+    }
+
+    /// Pop an element, replying with its list and itself, or nil
+    pub(crate) fn apply<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &mut [S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> crate::Result<Frame> {
+        let keys = match self.ready {
+            Some(key) => vec![key],
+            None => self.keys,
+        };
+
+        for key in keys {
+            let ks = &mut shards[shard_of(&key)];
+            if ks.list(&key)?.is_none() {
+                continue;
+            }
+
+            let list = ks.list_mut(&key)?;
+            let value = list.pop(self.end).unwrap();
+            if list.is_empty() {
+                ks.remove(&key);
+            }
+            ks.record_access(&key);
+            return Ok(Frame::Array(vec![
+                Frame::Bulk(Bytes::from(key)),
+                Frame::Bulk(value),
+            ]));
+        }
+        Ok(Frame::Null)
+    }
+
+    /// Logged as is: see `popped_frame` for what is replayed
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut parts = vec![Bytes::from(self.name().to_uppercase())];
+        parts.extend(self.keys.iter().map(|key| Bytes::from(key.clone())));
+        parts.push(Bytes::from(self.timeout.as_secs_f64().to_string()));
+        command_frame(parts)
+    }
+}
+
+/// The pop a logged BLPOP or BRPOP did, found in its reply, as LPOP or
+/// RPOP. `None` if it popped nothing.
+pub(crate) fn popped_frame(frame: &Frame, response: &Frame) -> Option<Frame> {
+    let (Frame::Array(parts), Frame::Array(reply)) = (frame, response) else {
+        return None;
+    };
+    let name = match &parts[0] {
+        Frame::Bulk(name) if &name[..] == b"BRPOP" => "RPOP",
+        _ => "LPOP",
+    };
+    let Some(Frame::Bulk(key)) = reply.first() else {
+        return None;
+    };
+    Some(command_frame([Bytes::from(name), key.clone()]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cmd::test_util::run, db::Keyspace};
+
+    #[test]
+    fn push_pop_and_range() {
+        let mut ks = Keyspace::default();
+        assert_eq!(run(&mut ks, "RPUSH jobs b c"), "2");
+        assert_eq!(run(&mut ks, "LPUSH jobs a z"), "4");
+        assert_eq!(run(&mut ks, "LRANGE jobs 0 -1"), "z a b c");
+        assert_eq!(run(&mut ks, "LRANGE jobs -2 10"), "b c");
+        assert_eq!(run(&mut ks, "LLEN jobs"), "4");
+        assert_eq!(run(&mut ks, "TYPE jobs"), "list");
+
+        assert_eq!(run(&mut ks, "LPOP jobs"), "z");
+        assert_eq!(run(&mut ks, "RPOP jobs 2"), "c b");
+        assert_eq!(run(&mut ks, "RPOP jobs 5"), "a");
+        // Empty lists are deleted
+        assert_eq!(run(&mut ks, "EXISTS jobs"), "0");
+        assert_eq!(run(&mut ks, "LPOP jobs"), "(nil)");
+        assert_eq!(run(&mut ks, "LLEN jobs"), "0");
+        assert!(run(&mut ks, "LPOP jobs -1").contains("must be positive"));
+
+        run(&mut ks, "SET name x");
+        assert!(run(&mut ks, "LPUSH name a").starts_with("error: WRONGTYPE"));
+        assert!(run(&mut ks, "LRANGE name 0 -1").starts_with("error: WRONGTYPE"));
+    }
+
+    #[test]
+    fn blocking_pops_without_waiting() {
+        let mut ks = Keyspace::default();
+        run(&mut ks, "RPUSH b 1 2");
+        assert_eq!(run(&mut ks, "BLPOP a b 0"), "b 1");
+        assert_eq!(run(&mut ks, "BRPOP a b 0.5"), "b 2");
+        assert_eq!(run(&mut ks, "BLPOP a b 0"), "(nil)");
+
+        assert!(run(&mut ks, "BLPOP a").contains("wrong number"));
+        assert!(run(&mut ks, "BLPOP a -1").contains("negative"));
+        assert!(run(&mut ks, "BLPOP a soon").contains("not a float"));
+    }
+}
@@ -0,0 +1,511 @@
+mod string;
+pub use string::{Get, Set};
+
+mod keys;
+pub use keys::{Del, Exists, Expire, Keys, Rename, Scan, Type};
+
+mod zset;
+pub use zset::{ZAdd, ZRange, ZRank, ZRem, ZScore};
+
+mod list;
+pub use list::{BPop, LLen, LRange, Pop, Push};
+
+mod stream;
+pub use stream::{XAck, XAdd, XGroup, XLen, XPending, XRange, XRead, XReadGroup};
+
+mod pubsub;
+pub use pubsub::{PSubscribe, PUnsubscribe, Publish, Subscribe, Unsubscribe};
+
+mod transaction;
+pub use transaction::Watch;
+
+mod server;
+pub use server::{ClientCommand, Info, Kill, Ping, ReplicaOf};
+
+mod scripting;
+pub use scripting::{Eval, ScriptCommand};
+
+mod acl;
+pub use acl::{AclCommand, Auth};
+
+#[cfg(test)]
+mod test_util;
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use tokio::sync::Notify;
+
+use crate::{db::Keyspace, list::End, parse::Parse};
+
+/// Commands understood by the server
+#[derive(Debug)]
+pub enum Command {
+    Get(Get),
+    Set(Set),
+    Expire(Expire),
+    Del(Del),
+    Exists(Exists),
+    Type(Type),
+    Rename(Rename),
+    Keys(Keys),
+    Scan(Scan),
+    DbSize,
+    ZAdd(ZAdd),
+    ZScore(ZScore),
+    ZRange(ZRange),
+    ZRank(ZRank),
+    ZRem(ZRem),
+    Push(Push),
+    Pop(Pop),
+    LLen(LLen),
+    LRange(LRange),
+    BPop(BPop),
+    XAdd(XAdd),
+    XLen(XLen),
+    XRange(XRange),
+    XRead(XRead),
+    XReadGroup(XReadGroup),
+    XGroup(XGroup),
+    XAck(XAck),
+    XPending(XPending),
+    Publish(Publish),
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    PSubscribe(PSubscribe),
+    PUnsubscribe(PUnsubscribe),
+    Multi,
+    Exec,
+    Discard,
+    Watch(Watch),
+    Unwatch,
+    Save,
+    BgSave,
+    BgRewriteAof,
+    Info(Info),
+    Ping(Ping),
+    Client(ClientCommand),
+    ReplicaOf(ReplicaOf),
+    Sync,
+    Eval(Eval),
+    Script(ScriptCommand),
+    Auth(Auth),
+    Acl(AclCommand),
+    Unknown(String),
+}
+
+impl Command {
+    /// Parse a command from a received frame.
+    ///
+    /// The frame must be an array of bulk strings, the first one being the
+    /// command name (case insensitive).
+    pub fn from_frame(frame: Frame) -> crate::Result<Command> {
+        let mut parse = Parse::new(frame)?;
+        let name = parse.next_string()?.to_lowercase();
+
+        let command = match &name[..] {
+            "get" => Command::Get(Get::parse_frames(&mut parse)?),
+            "set" => Command::Set(Set::parse_frames(&mut parse)?),
+            "expire" => Command::Expire(Expire::parse_frames(&mut parse)?),
+            "pexpireat" => Command::Expire(Expire::parse_pexpireat(&mut parse)?),
+            "del" => Command::Del(Del::parse_frames(&mut parse)?),
+            "exists" => Command::Exists(Exists::parse_frames(&mut parse)?),
+            "type" => Command::Type(Type::parse_frames(&mut parse)?),
+            "rename" => Command::Rename(Rename::parse_frames(&mut parse)?),
+            "keys" => Command::Keys(Keys::parse_frames(&mut parse)?),
+            "scan" => Command::Scan(Scan::parse_frames(&mut parse)?),
+            "dbsize" => Command::DbSize,
+            "zadd" => Command::ZAdd(ZAdd::parse_frames(&mut parse)?),
+            "zscore" => Command::ZScore(ZScore::parse_frames(&mut parse)?),
+            "zrange" => Command::ZRange(ZRange::parse_frames(&mut parse)?),
+            "zrank" => Command::ZRank(ZRank::parse_frames(&mut parse)?),
+            "zrem" => Command::ZRem(ZRem::parse_frames(&mut parse)?),
+            "lpush" => Command::Push(Push::parse_frames(&mut parse, End::Left)?),
+            "rpush" => Command::Push(Push::parse_frames(&mut parse, End::Right)?),
+            "lpop" => Command::Pop(Pop::parse_frames(&mut parse, End::Left)?),
+            "rpop" => Command::Pop(Pop::parse_frames(&mut parse, End::Right)?),
+            "llen" => Command::LLen(LLen::parse_frames(&mut parse)?),
+            "lrange" => Command::LRange(LRange::parse_frames(&mut parse)?),
+            "blpop" => Command::BPop(BPop::parse_frames(&mut parse, End::Left)?),
+            "brpop" => Command::BPop(BPop::parse_frames(&mut parse, End::Right)?),
+            "xadd" => Command::XAdd(XAdd::parse_frames(&mut parse)?),
+            "xlen" => Command::XLen(XLen::parse_frames(&mut parse)?),
+            "xrange" => Command::XRange(XRange::parse_frames(&mut parse)?),
+            "xrevrange" => Command::XRange(XRange::parse_rev(&mut parse)?),
+            "xread" => Command::XRead(XRead::parse_frames(&mut parse)?),
+            "xreadgroup" => Command::XReadGroup(XReadGroup::parse_frames(&mut parse)?),
+            "xgroup" => Command::XGroup(XGroup::parse_frames(&mut parse)?),
+            "xack" => Command::XAck(XAck::parse_frames(&mut parse)?),
+            "xpending" => Command::XPending(XPending::parse_frames(&mut parse)?),
+            "publish" => Command::Publish(Publish::parse_frames(&mut parse)?),
+            "subscribe" => Command::Subscribe(Subscribe::parse_frames(&mut parse)?),
+            "unsubscribe" => Command::Unsubscribe(Unsubscribe::parse_frames(&mut parse)?),
+            "psubscribe" => Command::PSubscribe(PSubscribe::parse_frames(&mut parse)?),
+            "punsubscribe" => Command::PUnsubscribe(PUnsubscribe::parse_frames(&mut parse)?),
+            "multi" => Command::Multi,
+            "exec" => Command::Exec,
+            "discard" => Command::Discard,
+            "watch" => Command::Watch(Watch::parse_frames(&mut parse)?),
+            "unwatch" => Command::Unwatch,
+            "save" => Command::Save,
+            "bgsave" => Command::BgSave,
+            "bgrewriteaof" => Command::BgRewriteAof,
+            "info" => Command::Info(Info::parse_frames(&mut parse)?),
+            "ping" => Command::Ping(Ping::parse_frames(&mut parse)?),
+            "client" => Command::Client(ClientCommand::parse_frames(&mut parse)?),
+            "replicaof" | "slaveof" => Command::ReplicaOf(ReplicaOf::parse_frames(&mut parse)?),
+            "sync" => Command::Sync,
+            "eval" => Command::Eval(Eval::parse_frames(&mut parse)?),
+            "evalsha" => Command::Eval(Eval::parse_evalsha(&mut parse)?),
+            "script" => Command::Script(ScriptCommand::parse_frames(&mut parse)?),
+            "auth" => Command::Auth(Auth::parse_frames(&mut parse)?),
+            "acl" => Command::Acl(AclCommand::parse_frames(&mut parse)?),
+            _ => return Ok(Command::Unknown(name)),
+        };
+
+        // Trailing arguments are a syntax error
+        parse.finish()?;
+
+        Ok(command)
+    }
+
+    /// Apply the command to the shard holding its key and return the reply frame.
+    ///
+    /// Commands spanning several keys take `ks` as the whole keyspace.
+    /// Errors are turned into error frames for the client. Pub/sub,
+    /// transaction, persistence and server commands are not keyspace
+    /// commands and must be handled by the connection. Writes applied here
+    /// are not logged to the append-only file: see `Db::apply`.
+    pub fn apply(self, mut ks: &mut Keyspace) -> Frame {
+        if self.spans_shards() {
+            return self.apply_sharded(std::slice::from_mut(&mut ks), |_| 0);
+        }
+
+        let result = match self {
+            Command::Get(cmd) => cmd.apply(ks),
+            Command::Set(cmd) => cmd.apply(ks),
+            Command::Expire(cmd) => cmd.apply(ks),
+            Command::Type(cmd) => cmd.apply(ks),
+            Command::ZAdd(cmd) => cmd.apply(ks),
+            Command::ZScore(cmd) => cmd.apply(ks),
+            Command::ZRange(cmd) => cmd.apply(ks),
+            Command::ZRank(cmd) => cmd.apply(ks),
+            Command::ZRem(cmd) => cmd.apply(ks),
+            Command::Push(cmd) => cmd.apply(ks),
+            Command::Pop(cmd) => cmd.apply(ks),
+            Command::LLen(cmd) => cmd.apply(ks),
+            Command::LRange(cmd) => cmd.apply(ks),
+            Command::XAdd(cmd) => cmd.apply(ks),
+            Command::XLen(cmd) => cmd.apply(ks),
+            Command::XRange(cmd) => cmd.apply(ks),
+            Command::XGroup(cmd) => cmd.apply(ks),
+            Command::XAck(cmd) => cmd.apply(ks),
+            Command::XPending(cmd) => cmd.apply(ks),
+            Command::Unknown(name) => Err(format!("ERR unknown command '{}'", name).into()),
+            cmd => Err(format!("ERR '{}' is not allowed in this context", cmd.get_name()).into()),
+        };
+
+        result.unwrap_or_else(|err| Frame::Error(err.to_string()))
+    }
+
+    /// Apply the command to a keyspace split into `shards`, `shard_of`
+    /// giving the index of the shard holding a key. Commands that do not
+    /// span shards run against the shard of their key.
+    pub fn apply_sharded<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &mut [S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> Frame {
+        let result = match self {
+            Command::Del(cmd) => cmd.apply(shards, shard_of),
+            Command::Exists(cmd) => cmd.apply(shards, shard_of),
+            Command::Rename(cmd) => cmd.apply(shards, shard_of),
+            Command::Keys(cmd) => cmd.apply(shards),
+            Command::Scan(cmd) => cmd.apply(shards),
+            Command::DbSize => Ok(keys::dbsize(shards)),
+            Command::BPop(cmd) => cmd.apply(shards, shard_of),
+            Command::XRead(cmd) => cmd.apply(shards, shard_of),
+            Command::XReadGroup(cmd) => cmd.apply(shards, shard_of),
+            cmd => {
+                let index = cmd.key().map_or(0, shard_of);
+                return cmd.apply(&mut shards[index]);
+            }
+        };
+
+        result.unwrap_or_else(|err| Frame::Error(err.to_string()))
+    }
+
+    /// Key the command operates on, which selects the shard it runs against
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Command::Get(cmd) => Some(cmd.key()),
+            Command::Set(cmd) => Some(cmd.key()),
+            Command::Expire(cmd) => Some(cmd.key()),
+            Command::Type(cmd) => Some(cmd.key()),
+            Command::ZAdd(cmd) => Some(cmd.key()),
+            Command::ZScore(cmd) => Some(cmd.key()),
+            Command::ZRange(cmd) => Some(cmd.key()),
+            Command::ZRank(cmd) => Some(cmd.key()),
+            Command::ZRem(cmd) => Some(cmd.key()),
+            Command::Push(cmd) => Some(cmd.key()),
+            Command::Pop(cmd) => Some(cmd.key()),
+            Command::LLen(cmd) => Some(cmd.key()),
+            Command::LRange(cmd) => Some(cmd.key()),
+            Command::XAdd(cmd) => Some(cmd.key()),
+            Command::XLen(cmd) => Some(cmd.key()),
+            Command::XRange(cmd) => Some(cmd.key()),
+            Command::XGroup(cmd) => Some(cmd.key()),
+            Command::XAck(cmd) => Some(cmd.key()),
+            Command::XPending(cmd) => Some(cmd.key()),
+            _ => None,
+        }
+    }
+
+    /// Every key the command reads or writes, for ACL checks
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            Command::Del(cmd) => cmd.keys().to_vec(),
+            Command::Exists(cmd) => cmd.keys().to_vec(),
+            Command::Rename(cmd) => cmd.keys().map(str::to_string).to_vec(),
+            Command::Watch(cmd) => cmd.keys.clone(),
+            Command::Eval(cmd) => cmd.keys().collect(),
+            Command::BPop(_) | Command::XRead(_) | Command::XReadGroup(_) => self.blocking_keys(),
+            cmd => cmd.key().map(str::to_string).into_iter().collect(),
+        }
+    }
+
+    /// Check if this command works on several keys, or the whole keyspace,
+    /// so it needs every shard
+    pub fn spans_shards(&self) -> bool {
+        matches!(
+            self,
+            Command::Del(_)
+                | Command::Exists(_)
+                | Command::Rename(_)
+                | Command::Keys(_)
+                | Command::Scan(_)
+                | Command::DbSize
+                | Command::BPop(_)
+                | Command::XRead(_)
+                | Command::XReadGroup(_)
+        )
+    }
+
+    /// Check if this command modifies the keyspace
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::Expire(_)
+                | Command::Del(_)
+                | Command::Rename(_)
+                | Command::ZAdd(_)
+                | Command::ZRem(_)
+                | Command::Push(_)
+                | Command::Pop(_)
+                | Command::BPop(_)
+                | Command::XAdd(_)
+                | Command::XReadGroup(_)
+                | Command::XGroup(_)
+                | Command::XAck(_)
+        )
+    }
+
+    /// Check if this command may store more data, so it must respect
+    /// `maxmemory`
+    pub fn adds_memory(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::ZAdd(_)
+                | Command::Push(_)
+                | Command::XAdd(_)
+                | Command::XGroup(_)
+        )
+    }
+
+    /// Frame to log in the append-only file if this command is a write.
+    ///
+    /// It reproduces the effect of the command when replayed, so relative
+    /// expirations are turned into absolute ones.
+    pub fn to_write_frame(&self) -> Option<Frame> {
+        match self {
+            Command::Set(cmd) => Some(cmd.to_frame()),
+            Command::Expire(cmd) => Some(cmd.to_frame()),
+            Command::Del(cmd) => Some(cmd.to_frame()),
+            Command::Rename(cmd) => Some(cmd.to_frame()),
+            Command::ZAdd(cmd) => Some(cmd.to_frame()),
+            Command::ZRem(cmd) => Some(cmd.to_frame()),
+            Command::Push(cmd) => Some(cmd.to_frame()),
+            Command::Pop(cmd) => Some(cmd.to_frame()),
+            Command::BPop(cmd) => Some(cmd.to_frame()),
+            Command::XAdd(cmd) => Some(cmd.to_frame()),
+            Command::XReadGroup(cmd) => Some(cmd.to_frame()),
+            Command::XGroup(cmd) => Some(cmd.to_frame()),
+            Command::XAck(cmd) => Some(cmd.to_frame()),
+            _ => None,
+        }
+    }
+
+    /// How long the command may wait for data, if it blocks: zero means
+    /// forever.
+    ///
+    /// Blocking commands only block when run on their own by a connection.
+    /// In transactions and scripts, they reply at once.
+    pub fn block_timeout(&self) -> Option<Duration> {
+        match self {
+            Command::BPop(cmd) => Some(cmd.timeout()),
+            Command::XRead(cmd) => cmd.block(),
+            Command::XReadGroup(cmd) => cmd.block(),
+            _ => None,
+        }
+    }
+
+    /// Keys whose writes may unblock the command
+    pub fn blocking_keys(&self) -> Vec<String> {
+        match self {
+            Command::BPop(cmd) => cmd.keys().to_vec(),
+            Command::XRead(cmd) => cmd.keys(),
+            Command::XReadGroup(cmd) => cmd.keys(),
+            _ => vec![],
+        }
+    }
+
+    /// Check if a blocking command has something to reply now, or must wait
+    /// for writes to its keys. `waker` is the one it waits with, registered
+    /// with `Keyspace::block`. Non-blocking commands are always ready.
+    pub fn is_ready<S: Deref<Target = Keyspace>>(
+        &mut self,
+        shards: &[S],
+        shard_of: impl Fn(&str) -> usize,
+        waker: &Arc<Notify>,
+    ) -> bool {
+        match self {
+            Command::BPop(cmd) => cmd.is_ready(shards, shard_of, waker),
+            Command::XRead(cmd) => cmd.is_ready(shards, shard_of),
+            Command::XReadGroup(cmd) => cmd.is_ready(shards, shard_of),
+            _ => true,
+        }
+    }
+
+    /// Check if this command manages the subscriptions of the connection
+    pub fn is_subscription(&self) -> bool {
+        matches!(
+            self,
+            Command::Subscribe(_)
+                | Command::Unsubscribe(_)
+                | Command::PSubscribe(_)
+                | Command::PUnsubscribe(_)
+        )
+    }
+
+    /// Check if this command controls a transaction instead of being queued in it
+    pub fn is_transaction_control(&self) -> bool {
+        matches!(
+            self,
+            Command::Multi | Command::Exec | Command::Discard | Command::Watch(_)
+        )
+    }
+
+    /// Check if this command can be queued by MULTI. The others can't be run
+    /// from EXEC, so they abort the transaction when sent.
+    pub fn is_queueable(&self) -> bool {
+        !self.is_subscription()
+            && !matches!(
+                self,
+                Command::Save
+                    | Command::BgSave
+                    | Command::BgRewriteAof
+                    | Command::ReplicaOf(_)
+                    | Command::Sync
+            )
+    }
+
+    /// Lowercase name of the command
+    pub fn get_name(&self) -> &str {
+        match self {
+            Command::Get(_) => "get",
+            Command::Set(_) => "set",
+            Command::Expire(_) => "expire",
+            Command::Del(_) => "del",
+            Command::Exists(_) => "exists",
+            Command::Type(_) => "type",
+            Command::Rename(_) => "rename",
+            Command::Keys(_) => "keys",
+            Command::Scan(_) => "scan",
+            Command::DbSize => "dbsize",
+            Command::ZAdd(_) => "zadd",
+            Command::ZScore(_) => "zscore",
+            Command::ZRange(_) => "zrange",
+            Command::ZRank(_) => "zrank",
+            Command::ZRem(_) => "zrem",
+            Command::Push(cmd) => cmd.name(),
+            Command::Pop(cmd) => cmd.name(),
+            Command::LLen(_) => "llen",
+            Command::LRange(_) => "lrange",
+            Command::BPop(cmd) => cmd.name(),
+            Command::XAdd(_) => "xadd",
+            Command::XLen(_) => "xlen",
+            Command::XRange(_) => "xrange",
+            Command::XRead(_) => "xread",
+            Command::XReadGroup(_) => "xreadgroup",
+            Command::XGroup(_) => "xgroup",
+            Command::XAck(_) => "xack",
+            Command::XPending(_) => "xpending",
+            Command::Publish(_) => "publish",
+            Command::Subscribe(_) => "subscribe",
+            Command::Unsubscribe(_) => "unsubscribe",
+            Command::PSubscribe(_) => "psubscribe",
+            Command::PUnsubscribe(_) => "punsubscribe",
+            Command::Multi => "multi",
+            Command::Exec => "exec",
+            Command::Discard => "discard",
+            Command::Watch(_) => "watch",
+            Command::Unwatch => "unwatch",
+            Command::Save => "save",
+            Command::BgSave => "bgsave",
+            Command::BgRewriteAof => "bgrewriteaof",
+            Command::Info(_) => "info",
+            Command::Ping(_) => "ping",
+            Command::Client(_) => "client",
+            Command::ReplicaOf(_) => "replicaof",
+            Command::Sync => "sync",
+            Command::Eval(_) => "eval",
+            Command::Script(_) => "script",
+            Command::Auth(_) => "auth",
+            Command::Acl(_) => "acl",
+            Command::Unknown(name) => name,
+        }
+    }
+}
+
+/// Build a command frame: an array of bulk strings
+pub(crate) fn command_frame(parts: impl IntoIterator<Item = Bytes>) -> Frame {
+    Frame::Array(parts.into_iter().map(Frame::Bulk).collect())
+}
+
+/// Complete the write frame of a command with what only its reply knows,
+/// so replaying it has the same effect: the ID XADD picked for `*`, or the
+/// list BLPOP popped from. `None` if there is nothing to replay.
+pub(crate) fn complete_write_frame(frame: Frame, response: &Frame) -> Option<Frame> {
+    let Frame::Array(parts) = &frame else {
+        return Some(frame);
+    };
+    let arg = |index: usize| match parts.get(index) {
+        Some(Frame::Bulk(arg)) => &arg[..],
+        _ => b"",
+    };
+    if arg(0) == b"XADD" && (arg(2) == b"*" || arg(2).ends_with(b"-*")) {
+        return Some(stream::with_added_id(frame, response));
+    }
+    if arg(0) == b"BLPOP" || arg(0) == b"BRPOP" {
+        return list::popped_frame(&frame, response);
+    }
+    Some(frame)
+}
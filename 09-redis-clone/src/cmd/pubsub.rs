@@ -0,0 +1,95 @@
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{parse::Parse, Db};
+
+/// PUBLISH channel message
+#[derive(Debug)]
+pub struct Publish {
+    channel: String,
+    message: Bytes,
+}
+
+impl Publish {
+    pub fn new(channel: impl ToString, message: Bytes) -> Publish {
+        Publish {
+            channel: channel.to_string(),
+            message,
+        }
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Publish> {
+        let channel = parse.next_string()?;
+        let message = parse.next_bytes()?;
+        Ok(Publish { channel, message })
+    }
+
+    /// Publishing does not touch the keyspace, only the channel registry.
+    pub(crate) fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let receivers = db.pubsub().publish(&self.channel, self.message);
+        Ok(Frame::Integer(receivers as u64))
+    }
+}
+
+/// SUBSCRIBE channel [channel ...]
+#[derive(Debug)]
+pub struct Subscribe {
+    pub(crate) channels: Vec<String>,
+}
+
+/// UNSUBSCRIBE [channel ...]. No channels means all of them.
+#[derive(Debug)]
+pub struct Unsubscribe {
+    pub(crate) channels: Vec<String>,
+}
+
+/// PSUBSCRIBE pattern [pattern ...]
+#[derive(Debug)]
+pub struct PSubscribe {
+    pub(crate) patterns: Vec<String>,
+}
+
+/// PUNSUBSCRIBE [pattern ...]. No patterns means all of them.
+#[derive(Debug)]
+pub struct PUnsubscribe {
+    pub(crate) patterns: Vec<String>,
+}
+
+/// Read every remaining argument as a string
+fn parse_names(parse: &mut Parse) -> crate::Result<Vec<String>> {
+    let mut names = vec![];
+    while parse.remaining() > 0 {
+        names.push(parse.next_string()?);
+    }
+    Ok(names)
+}
+
+impl Subscribe {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Subscribe> {
+        let mut channels = vec![parse.next_string()?];
+        channels.extend(parse_names(parse)?);
+        Ok(Subscribe { channels })
+    }
+}
+
+impl Unsubscribe {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Unsubscribe> {
+        let channels = parse_names(parse)?;
+        Ok(Unsubscribe { channels })
+    }
+}
+
+impl PSubscribe {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<PSubscribe> {
+        let mut patterns = vec![parse.next_string()?];
+        patterns.extend(parse_names(parse)?);
+        Ok(PSubscribe { patterns })
+    }
+}
+
+impl PUnsubscribe {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<PUnsubscribe> {
+        let patterns = parse_names(parse)?;
+        Ok(PUnsubscribe { patterns })
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{
+    acl::User,
+    cmd::command_frame,
+    db::Shards,
+    parse::Parse,
+    replication::READONLY,
+    script::{Program, NOSCRIPT},
+    Command, Db,
+};
+
+/// EVAL script numkeys [key ...] [arg ...], or EVALSHA sha1 numkeys ...
+#[derive(Debug)]
+pub struct Eval {
+    script: Script,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+}
+
+/// The script run by EVAL or EVALSHA
+#[derive(Debug)]
+enum Script {
+    Source(String),
+    Sha(String),
+}
+
+impl Eval {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Eval> {
+        let source = parse.next_string()?;
+        Eval::parse_rest(parse, Script::Source(source))
+    }
+
+    /// Keys declared by the script, for ACL checks
+    pub(crate) fn keys(&self) -> impl Iterator<Item = String> + '_ {
+        self.keys
+            .iter()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+    }
+
+    pub(crate) fn parse_evalsha(parse: &mut Parse) -> crate::Result<Eval> {
+        let sha = parse.next_string()?;
+        Eval::parse_rest(parse, Script::Sha(sha))
+    }
+
+    fn parse_rest(parse: &mut Parse, script: Script) -> crate::Result<Eval> {
+        let numkeys = parse.next_int()?;
+        let numkeys =
+            usize::try_from(numkeys).map_err(|_| "ERR Number of keys can't be negative")?;
+        if numkeys > parse.remaining() {
+            return Err("ERR Number of keys can't be greater than number of args".into());
+        }
+
+        let keys = (0..numkeys)
+            .map(|_| parse.next_bytes())
+            .collect::<crate::Result<_>>()?;
+        let mut args = vec![];
+        while parse.remaining() > 0 {
+            args.push(parse.next_bytes()?);
+        }
+        Ok(Eval { script, keys, args })
+    }
+
+    /// Run the script with every shard locked, so it is atomic. The
+    /// commands it calls are checked against the permissions of `user`.
+    ///
+    /// The write commands of the script are logged and replicated, as one
+    /// MULTI/EXEC block, so neither the append-only file nor replicas need
+    /// the script.
+    pub(crate) fn apply(self, db: &Db, shards: &mut Shards<'_>, user: &User) -> Frame {
+        let program = match self.program(db) {
+            Ok(program) => program,
+            Err(err) => return Frame::Error(err.to_string()),
+        };
+
+        let max_steps = db.config().script_max_steps;
+        db.atomically(shards, |shards| {
+            program.run(&self.keys, &self.args, max_steps, |argv| {
+                let cmd = match Command::from_frame(command_frame(argv)) {
+                    Ok(cmd) => cmd,
+                    Err(err) => return Frame::Error(err.to_string()),
+                };
+                match cmd {
+                    Command::Unknown(name) => {
+                        Frame::Error(format!("ERR unknown command '{}'", name))
+                    }
+                    // Only keyspace commands: no transactions, pub/sub,
+                    // persistence or nested scripts
+                    cmd if cmd.key().is_none() && !cmd.spans_shards() => Frame::Error(format!(
+                        "ERR '{}' is not allowed from scripts",
+                        cmd.get_name()
+                    )),
+                    cmd if cmd.is_write() && db.is_replica() => Frame::Error(READONLY.into()),
+                    cmd => match user.check(&cmd) {
+                        Ok(()) => db.apply_locked(shards, cmd),
+                        Err(err) => Frame::Error(err.to_string()),
+                    },
+                }
+            })
+        })
+    }
+
+    /// Compile the script, or find it in the cache
+    fn program(&self, db: &Db) -> crate::Result<Arc<Program>> {
+        match &self.script {
+            Script::Source(source) => Ok(db.scripts().load(source)?.1),
+            Script::Sha(sha) => db.scripts().get(sha).ok_or_else(|| NOSCRIPT.into()),
+        }
+    }
+}
+
+/// SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC]
+#[derive(Debug)]
+pub enum ScriptCommand {
+    Load(String),
+    Exists(Vec<String>),
+    Flush,
+}
+
+impl ScriptCommand {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ScriptCommand> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let command = match &subcommand[..] {
+            "load" => ScriptCommand::Load(parse.next_string()?),
+            "exists" => {
+                let mut shas = vec![parse.next_string()?];
+                while parse.remaining() > 0 {
+                    shas.push(parse.next_string()?);
+                }
+                ScriptCommand::Exists(shas)
+            }
+            "flush" => {
+                // Flushing is always synchronous, the mode is only checked
+                if parse.remaining() > 0 {
+                    let mode = parse.next_string()?.to_lowercase();
+                    if mode != "async" && mode != "sync" {
+                        return Err("ERR syntax error".into());
+                    }
+                }
+                ScriptCommand::Flush
+            }
+            _ => {
+                return Err(
+                    format!("ERR unknown subcommand '{}'. Try SCRIPT HELP.", subcommand).into(),
+                )
+            }
+        };
+        Ok(command)
+    }
+
+    pub(crate) fn apply(self, db: &Db) -> crate::Result<Frame> {
+        let mut scripts = db.scripts();
+        match self {
+            ScriptCommand::Load(source) => {
+                let (sha, _) = scripts.load(&source)?;
+                Ok(Frame::Bulk(Bytes::from(sha)))
+            }
+            ScriptCommand::Exists(shas) => Ok(Frame::Array(
+                shas.iter()
+                    .map(|sha| Frame::Integer(scripts.contains(sha) as u64))
+                    .collect(),
+            )),
+            ScriptCommand::Flush => {
+                scripts.flush();
+                Ok(Frame::Simple("OK".into()))
+            }
+        }
+    }
+}
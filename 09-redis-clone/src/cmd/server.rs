@@ -0,0 +1,376 @@
+use std::fmt::Write;
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{clients::ConnectedClient, db::Shards, parse::Parse, Db};
+
+/// Sections of INFO, in output order
+const SECTIONS: [&str; 6] = [
+    "server",
+    "clients",
+    "memory",
+    "stats",
+    "replication",
+    "keyspace",
+];
+
+/// INFO [section ...]
+#[derive(Debug)]
+pub struct Info {
+    // Lowercase; empty means every section
+    sections: Vec<String>,
+}
+
+impl Info {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Info> {
+        let mut sections = vec![];
+        while parse.remaining() > 0 {
+            sections.push(parse.next_string()?.to_lowercase());
+        }
+        Ok(Info { sections })
+    }
+
+    /// Build the report. `shards` are every shard, locked, for the keyspace
+    /// section.
+    pub(crate) fn apply(self, db: &Db, shards: &Shards<'_>) -> Frame {
+        let all = self.sections.is_empty()
+            || self
+                .sections
+                .iter()
+                .any(|section| matches!(&section[..], "all" | "default" | "everything"));
+
+        let mut out = String::new();
+        for name in SECTIONS {
+            if !all && !self.sections.iter().any(|section| section == name) {
+                continue;
+            }
+            if !out.is_empty() {
+                out.push_str("\r\n");
+            }
+
+            let fields = match name {
+                "server" => server_section(db),
+                "clients" => clients_section(db),
+                "memory" => memory_section(db),
+                "stats" => stats_section(db),
+                "replication" => replication_section(db),
+                _ => keyspace_section(shards),
+            };
+            // Section names are capitalized in the title
+            let _ = write!(out, "# {}{}\r\n", name[..1].to_uppercase(), &name[1..]);
+            for (field, value) in fields {
+                let _ = write!(out, "{}:{}\r\n", field, value);
+            }
+        }
+
+        Frame::Bulk(Bytes::from(out))
+    }
+}
+
+fn server_section(db: &Db) -> Vec<(&'static str, String)> {
+    let uptime = db.stats().started().elapsed().as_secs();
+    vec![
+        ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("redis_mode", "standalone".to_string()),
+        ("os", std::env::consts::OS.to_string()),
+        ("arch_bits", usize::BITS.to_string()),
+        ("process_id", std::process::id().to_string()),
+        ("tcp_port", db.config().port.to_string()),
+        ("shards", db.config().shards.to_string()),
+        ("uptime_in_seconds", uptime.to_string()),
+        ("uptime_in_days", (uptime / 86400).to_string()),
+    ]
+}
+
+fn clients_section(db: &Db) -> Vec<(&'static str, String)> {
+    vec![
+        ("connected_clients", db.clients().len().to_string()),
+        ("maxclients", db.config().maxclients.to_string()),
+    ]
+}
+
+fn memory_section(db: &Db) -> Vec<(&'static str, String)> {
+    let config = db.config();
+    vec![
+        ("used_memory", db.used_memory().to_string()),
+        ("used_memory_human", human_bytes(db.used_memory())),
+        ("maxmemory", config.maxmemory.to_string()),
+        ("maxmemory_human", human_bytes(config.maxmemory)),
+        (
+            "maxmemory_policy",
+            config.maxmemory_policy.name().to_string(),
+        ),
+    ]
+}
+
+fn stats_section(db: &Db) -> Vec<(&'static str, String)> {
+    let stats = db.stats();
+    vec![
+        (
+            "total_connections_received",
+            stats.connections_received().to_string(),
+        ),
+        (
+            "total_commands_processed",
+            stats.commands_processed().to_string(),
+        ),
+        ("total_net_input_bytes", stats.net_input_bytes().to_string()),
+        (
+            "total_net_output_bytes",
+            stats.net_output_bytes().to_string(),
+        ),
+        (
+            "rejected_connections",
+            stats.rejected_connections().to_string(),
+        ),
+        ("expired_keys", stats.expired_keys().to_string()),
+        ("evicted_keys", stats.evicted_keys().to_string()),
+    ]
+}
+
+fn replication_section(db: &Db) -> Vec<(&'static str, String)> {
+    let replication = db.replication();
+    let mut fields = match replication.primary() {
+        Some((host, port, connected)) => vec![
+            ("role", "slave".to_string()),
+            ("master_host", host),
+            ("master_port", port.to_string()),
+            (
+                "master_link_status",
+                if connected { "up" } else { "down" }.to_string(),
+            ),
+        ],
+        None => vec![("role", "master".to_string())],
+    };
+    fields.push(("connected_slaves", replication.replica_count().to_string()));
+    fields
+}
+
+fn keyspace_section(shards: &Shards<'_>) -> Vec<(&'static str, String)> {
+    let keys: usize = shards.iter().map(|ks| ks.len()).sum();
+    let expires: usize = shards.iter().map(|ks| ks.expires()).sum();
+
+    // Like Redis, an empty database is not listed
+    if keys == 0 {
+        return vec![];
+    }
+    vec![("db0", format!("keys={},expires={}", keys, expires))]
+}
+
+/// Format a size like `1.50M`
+fn human_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", value, UNITS[unit])
+}
+
+/// CLIENT LIST | GETNAME | SETNAME name | ID | KILL
+#[derive(Debug)]
+pub enum ClientCommand {
+    List,
+    GetName,
+    SetName(String),
+    Id,
+    Kill(Kill),
+}
+
+/// Filters of CLIENT KILL.
+///
+/// `CLIENT KILL addr` is the old form: it replies OK or an error. The new
+/// form takes `ID id`, `ADDR addr` and `SKIPME yes|no` filters and replies
+/// with the number of killed clients.
+#[derive(Debug)]
+pub struct Kill {
+    id: Option<u64>,
+    addr: Option<String>,
+    skipme: bool,
+    old_form: bool,
+}
+
+impl ClientCommand {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ClientCommand> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let command = match &subcommand[..] {
+            "list" => ClientCommand::List,
+            "getname" => ClientCommand::GetName,
+            "setname" => ClientCommand::SetName(parse.next_string()?),
+            "id" => ClientCommand::Id,
+            "kill" => ClientCommand::Kill(Kill::parse_frames(parse)?),
+            _ => {
+                return Err(
+                    format!("ERR unknown subcommand '{}'. Try CLIENT HELP.", subcommand).into(),
+                )
+            }
+        };
+        Ok(command)
+    }
+
+    /// Run the command on behalf of `client`
+    pub(crate) fn apply(self, db: &Db, client: &ConnectedClient) -> crate::Result<Frame> {
+        match self {
+            ClientCommand::List => {
+                let list: String = db
+                    .clients()
+                    .iter()
+                    .map(|client| client.describe() + "\n")
+                    .collect();
+                Ok(Frame::Bulk(Bytes::from(list)))
+            }
+            ClientCommand::GetName => Ok(match &client.info().name {
+                Some(name) => Frame::Bulk(Bytes::from(name.clone())),
+                None => Frame::Null,
+            }),
+            ClientCommand::SetName(name) => {
+                if name.bytes().any(|c| !(b'!'..=b'~').contains(&c)) {
+                    return Err(
+                        "ERR Client names cannot contain spaces, newlines or special characters."
+                            .into(),
+                    );
+                }
+                // An empty name removes it
+                client.info().name = Some(name).filter(|name| !name.is_empty());
+                Ok(Frame::Simple("OK".into()))
+            }
+            ClientCommand::Id => Ok(Frame::Integer(client.id())),
+            ClientCommand::Kill(kill) => {
+                let killed: Vec<_> = db
+                    .clients()
+                    .iter()
+                    .filter(|other| kill.matches(other, client))
+                    .cloned()
+                    .collect();
+                for other in &killed {
+                    other.kill();
+                }
+
+                match (kill.old_form, killed.len()) {
+                    (true, 0) => Err("ERR No such client".into()),
+                    (true, _) => Ok(Frame::Simple("OK".into())),
+                    (false, count) => Ok(Frame::Integer(count as u64)),
+                }
+            }
+        }
+    }
+}
+
+impl Kill {
+    fn parse_frames(parse: &mut Parse) -> crate::Result<Kill> {
+        let first = parse.next_string()?;
+        if parse.remaining() == 0 {
+            return Ok(Kill {
+                id: None,
+                addr: Some(first),
+                skipme: false,
+                old_form: true,
+            });
+        }
+
+        let mut kill = Kill {
+            id: None,
+            addr: None,
+            skipme: true,
+            old_form: false,
+        };
+        let mut filter = Some(first);
+        while let Some(name) = filter {
+            match &name.to_lowercase()[..] {
+                "id" => {
+                    let id = parse.next_int()?;
+                    kill.id = Some(
+                        u64::try_from(id).map_err(|_| "ERR client-id should be greater than 0")?,
+                    );
+                }
+                "addr" => kill.addr = Some(parse.next_string()?),
+                "skipme" => {
+                    kill.skipme = match &parse.next_string()?.to_lowercase()[..] {
+                        "yes" => true,
+                        "no" => false,
+                        _ => return Err("ERR syntax error".into()),
+                    }
+                }
+                _ => return Err("ERR syntax error".into()),
+            }
+            filter = match parse.remaining() {
+                0 => None,
+                _ => Some(parse.next_string()?),
+            };
+        }
+        Ok(kill)
+    }
+
+    /// Check if `other` is killed when `me` runs the command
+    fn matches(&self, other: &ConnectedClient, me: &ConnectedClient) -> bool {
+        if self.skipme && other.id() == me.id() {
+            return false;
+        }
+        self.id.is_none_or(|id| id == other.id())
+            && self
+                .addr
+                .as_ref()
+                .is_none_or(|addr| *addr == other.addr().to_string())
+    }
+}
+
+/// REPLICAOF host port, or REPLICAOF NO ONE to stop replicating
+#[derive(Debug)]
+pub struct ReplicaOf {
+    pub(crate) primary: Option<(String, u16)>,
+}
+
+impl ReplicaOf {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ReplicaOf> {
+        let host = parse.next_string()?;
+        let port = parse.next_string()?;
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            return Ok(ReplicaOf { primary: None });
+        }
+
+        let port = port.parse().map_err(|_| "ERR Invalid master port")?;
+        Ok(ReplicaOf {
+            primary: Some((host, port)),
+        })
+    }
+}
+
+/// PING [message]
+#[derive(Debug)]
+pub struct Ping {
+    message: Option<Bytes>,
+}
+
+impl Ping {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Ping> {
+        let message = match parse.remaining() {
+            0 => None,
+            _ => Some(parse.next_bytes()?),
+        };
+        Ok(Ping { message })
+    }
+
+    /// PONG, or the message back as a bulk string
+    pub(crate) fn apply(self) -> Frame {
+        match self.message {
+            Some(message) => Frame::Bulk(message),
+            None => Frame::Simple("PONG".into()),
+        }
+    }
+
+    /// In subscriber mode, the reply is a `[pong, message]` array so it can't
+    /// be mistaken for a published message.
+    pub(crate) fn apply_subscribed(self) -> Frame {
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"pong")),
+            Frame::Bulk(self.message.unwrap_or_default()),
+        ])
+    }
+}
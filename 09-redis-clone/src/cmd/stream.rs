@@ -0,0 +1,855 @@
+use std::{
+    ops::{Bound, Deref, DerefMut},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{
+    cmd::command_frame,
+    db::{now_ms, Keyspace},
+    parse::Parse,
+    stream::{Fields, NewId, StreamId, INVALID_ID},
+};
+
+/// XADD key id field value [field value ...]
+///
+/// `id` is `*` for an ID made of the current time, `ms-*` for the next ID
+/// of a given ms, or a full `ms-seq`.
+#[derive(Debug)]
+pub struct XAdd {
+    key: String,
+    id: NewId,
+    fields: Fields,
+}
+
+impl XAdd {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XAdd> {
+        let key = parse.next_string()?;
+        let id = match &parse.next_string()?[..] {
+            "*" => NewId::Auto,
+            id => match id.strip_suffix("-*") {
+                Some(ms) => NewId::Seq(ms.parse().map_err(|_| INVALID_ID)?),
+                None => NewId::Exact(id.parse()?),
+            },
+        };
+
+        if parse.remaining() == 0 || !parse.remaining().is_multiple_of(2) {
+            return Err("ERR wrong number of arguments for 'xadd' command".into());
+        }
+        let mut fields = vec![];
+        while parse.remaining() > 0 {
+            fields.push((parse.next_bytes()?, parse.next_bytes()?));
+        }
+
+        Ok(XAdd { key, id, fields })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let created = ks.stream(&self.key)?.is_none();
+        let stream = ks.stream_mut(&self.key)?;
+        match stream.add(self.id, now_ms(), self.fields) {
+            Ok(id) => Ok(Frame::Bulk(Bytes::from(id.to_string()))),
+            Err(err) => {
+                if created {
+                    ks.remove(&self.key);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Logged with the ID as given: see `complete_write_frame` for `*`
+    pub(crate) fn to_frame(&self) -> Frame {
+        let id = match self.id {
+            NewId::Auto => "*".to_string(),
+            NewId::Seq(ms) => format!("{}-*", ms),
+            NewId::Exact(id) => id.to_string(),
+        };
+        let mut parts = vec![
+            Bytes::from("XADD"),
+            Bytes::from(self.key.clone()),
+            Bytes::from(id),
+        ];
+        for (field, value) in &self.fields {
+            parts.push(field.clone());
+            parts.push(value.clone());
+        }
+        command_frame(parts)
+    }
+}
+
+/// Replace the ID of a logged XADD by the one it got, found in its reply, so
+/// replaying it adds the same entry
+pub(crate) fn with_added_id(frame: Frame, response: &Frame) -> Frame {
+    let (Frame::Array(mut parts), Frame::Bulk(id)) = (frame, response) else {
+        unreachable!("XADD is logged as an array and replies with a bulk");
+    };
+    parts[2] = Frame::Bulk(id.clone());
+    Frame::Array(parts)
+}
+
+/// XLEN key
+#[derive(Debug)]
+pub struct XLen {
+    key: String,
+}
+
+impl XLen {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XLen> {
+        Ok(XLen {
+            key: parse.next_string()?,
+        })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let len = ks.stream(&self.key)?.map_or(0, |stream| stream.len());
+        Ok(Frame::Integer(len as u64))
+    }
+}
+
+/// XRANGE key start end [COUNT count], or XREVRANGE key end start [COUNT count]
+///
+/// `-` and `+` are the smallest and greatest IDs, and `(` before an ID
+/// excludes it. An ID without sequence number covers the whole ms.
+#[derive(Debug)]
+pub struct XRange {
+    key: String,
+    start: Bound<StreamId>,
+    end: Bound<StreamId>,
+    count: Option<usize>,
+    rev: bool,
+}
+
+impl XRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XRange> {
+        let key = parse.next_string()?;
+        let start = range_bound(&parse.next_string()?, "-", 0)?;
+        let end = range_bound(&parse.next_string()?, "+", u64::MAX)?;
+        XRange::parse_count(parse, key, start, end, false)
+    }
+
+    pub(crate) fn parse_rev(parse: &mut Parse) -> crate::Result<XRange> {
+        let key = parse.next_string()?;
+        let end = range_bound(&parse.next_string()?, "+", u64::MAX)?;
+        let start = range_bound(&parse.next_string()?, "-", 0)?;
+        XRange::parse_count(parse, key, start, end, true)
+    }
+
+    fn parse_count(
+        parse: &mut Parse,
+        key: String,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        rev: bool,
+    ) -> crate::Result<XRange> {
+        let mut count = None;
+        if parse.remaining() > 0 {
+            if !parse.next_string()?.eq_ignore_ascii_case("count") {
+                return Err("ERR syntax error".into());
+            }
+            count = parse_count(parse)?;
+        }
+        Ok(XRange {
+            key,
+            start,
+            end,
+            count,
+            rev,
+        })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let Some(stream) = ks.stream(&self.key)? else {
+            return Ok(Frame::Array(vec![]));
+        };
+
+        let count = self.count.unwrap_or(usize::MAX);
+        let range = stream.range(self.start, self.end);
+        let entries: Vec<_> = if self.rev {
+            range.rev().take(count).map(entry_frame).collect()
+        } else {
+            range.take(count).map(entry_frame).collect()
+        };
+        Ok(Frame::Array(entries))
+    }
+}
+
+/// Parse a bound of XRANGE: `unbounded` (`-` or `+`), `(id` or `id`,
+/// `seq` completing IDs given as a ms
+fn range_bound(arg: &str, unbounded: &str, seq: u64) -> crate::Result<Bound<StreamId>> {
+    if arg == unbounded {
+        return Ok(Bound::Unbounded);
+    }
+    match arg.strip_prefix('(') {
+        Some(id) => Ok(Bound::Excluded(StreamId::parse_or(id, seq)?)),
+        None => Ok(Bound::Included(StreamId::parse_or(arg, seq)?)),
+    }
+}
+
+/// XREAD [COUNT count] [BLOCK ms] STREAMS key [key ...] id [id ...]
+///
+/// Reads the entries after each ID. `$` stands for the last ID of the
+/// stream, to only get entries added while blocking.
+#[derive(Debug)]
+pub struct XRead {
+    count: Option<usize>,
+    block: Option<Duration>,
+    streams: Vec<(String, ReadFrom)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ReadFrom {
+    /// `$`
+    Last,
+    After(StreamId),
+}
+
+impl XRead {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XRead> {
+        let mut count = None;
+        let mut block = None;
+        loop {
+            match &parse.next_string()?.to_uppercase()[..] {
+                "COUNT" => count = parse_count(parse)?,
+                "BLOCK" => block = Some(parse_block(parse)?),
+                "STREAMS" => break,
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        let streams = parse_streams(parse, "xread", |id| match id {
+            "$" => Ok(ReadFrom::Last),
+            id => Ok(ReadFrom::After(id.parse()?)),
+        })?;
+        Ok(XRead {
+            count,
+            block,
+            streams,
+        })
+    }
+
+    pub(crate) fn block(&self) -> Option<Duration> {
+        self.block
+    }
+
+    pub(crate) fn keys(&self) -> Vec<String> {
+        self.streams.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// Check if a stream has entries to read. `$` is resolved the first
+    /// time, so entries added while blocking are read.
+    pub(crate) fn is_ready<S: Deref<Target = Keyspace>>(
+        &mut self,
+        shards: &[S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> bool {
+        let mut ready = false;
+        for (key, from) in &mut self.streams {
+            let stream = match shards[shard_of(key)].stream(key) {
+                Ok(stream) => stream,
+                // Reply with the error
+                Err(_) => return true,
+            };
+            let last = stream.map_or(StreamId::MIN, |stream| stream.last_id());
+            match *from {
+                ReadFrom::Last => *from = ReadFrom::After(last),
+                ReadFrom::After(id) => ready |= last > id && stream.is_some_and(|s| !s.is_empty()),
+            }
+        }
+        ready
+    }
+
+    pub(crate) fn apply<S: Deref<Target = Keyspace>>(
+        self,
+        shards: &[S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> crate::Result<Frame> {
+        let count = self.count.unwrap_or(usize::MAX);
+        let mut replies = vec![];
+        for (key, from) in self.streams {
+            let Some(stream) = shards[shard_of(&key)].stream(&key)? else {
+                continue;
+            };
+            // Without blocking, nothing comes after the last ID
+            let ReadFrom::After(id) = from else {
+                continue;
+            };
+            let entries: Vec<_> = stream
+                .range(Bound::Excluded(id), Bound::Unbounded)
+                .take(count)
+                .map(entry_frame)
+                .collect();
+            if !entries.is_empty() {
+                replies.push(stream_frame(key, entries));
+            }
+        }
+
+        Ok(match replies.is_empty() {
+            true => Frame::Null,
+            false => Frame::Array(replies),
+        })
+    }
+}
+
+/// XREADGROUP GROUP group consumer [COUNT count] [BLOCK ms] [NOACK]
+/// STREAMS key [key ...] id [id ...]
+///
+/// With `>`, delivers entries never delivered to the group. With an ID,
+/// delivers again the entries pending for the consumer after it.
+#[derive(Debug)]
+pub struct XReadGroup {
+    group: String,
+    consumer: String,
+    count: Option<usize>,
+    block: Option<Duration>,
+    noack: bool,
+    streams: Vec<(String, GroupFrom)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum GroupFrom {
+    /// `>`
+    New,
+    Pending(StreamId),
+}
+
+impl XReadGroup {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XReadGroup> {
+        if !parse.next_string()?.eq_ignore_ascii_case("group") {
+            return Err("ERR syntax error".into());
+        }
+        let group = parse.next_string()?;
+        let consumer = parse.next_string()?;
+
+        let mut count = None;
+        let mut block = None;
+        let mut noack = false;
+        loop {
+            match &parse.next_string()?.to_uppercase()[..] {
+                "COUNT" => count = parse_count(parse)?,
+                "BLOCK" => block = Some(parse_block(parse)?),
+                "NOACK" => noack = true,
+                "STREAMS" => break,
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        let streams = parse_streams(parse, "xreadgroup", |id| match id {
+            ">" => Ok(GroupFrom::New),
+            id => Ok(GroupFrom::Pending(id.parse()?)),
+        })?;
+        Ok(XReadGroup {
+            group,
+            consumer,
+            count,
+            block,
+            noack,
+            streams,
+        })
+    }
+
+    pub(crate) fn block(&self) -> Option<Duration> {
+        self.block
+    }
+
+    pub(crate) fn keys(&self) -> Vec<String> {
+        self.streams.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// Check if a stream has entries never delivered to the group. Pending
+    /// entries are read right away.
+    pub(crate) fn is_ready<S: Deref<Target = Keyspace>>(
+        &self,
+        shards: &[S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> bool {
+        self.streams.iter().any(|(key, from)| {
+            let group = match shards[shard_of(key)].stream(key) {
+                Ok(stream) => stream.and_then(|stream| Some((stream, stream.group(&self.group)?))),
+                Err(_) => None,
+            };
+            match (from, group) {
+                (GroupFrom::New, Some((stream, group))) => stream
+                    .range(Bound::Excluded(group.last_delivered()), Bound::Unbounded)
+                    .next()
+                    .is_some(),
+                // Reply with the history, or the error
+                _ => true,
+            }
+        })
+    }
+
+    pub(crate) fn apply<S: DerefMut<Target = Keyspace>>(
+        self,
+        shards: &mut [S],
+        shard_of: impl Fn(&str) -> usize,
+    ) -> crate::Result<Frame> {
+        // Fail before reading anything
+        for (key, _) in &self.streams {
+            let stream = shards[shard_of(key)].stream(key)?;
+            if stream
+                .and_then(|stream| stream.group(&self.group))
+                .is_none()
+            {
+                return Err(format!(
+                    "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    key, self.group
+                )
+                .into());
+            }
+        }
+
+        let count = self.count.unwrap_or(usize::MAX);
+        let now = now_ms();
+        let mut replies = vec![];
+        for (key, from) in self.streams {
+            let ks = &mut shards[shard_of(&key)];
+            let stream = ks.existing_stream_mut(&key)?.unwrap();
+
+            let entries: Vec<_> = match from {
+                GroupFrom::New => {
+                    let entries = stream
+                        .read_group(&self.group, &self.consumer, count, self.noack, now)
+                        .unwrap();
+                    entries
+                        .iter()
+                        .map(|(id, fields)| entry_frame((*id, fields)))
+                        .collect()
+                }
+                GroupFrom::Pending(after) => {
+                    let entries = stream
+                        .read_pending(&self.group, &self.consumer, after, count, now)
+                        .unwrap();
+                    entries
+                        .into_iter()
+                        .map(|(id, fields)| match fields {
+                            Some(fields) => entry_frame((id, &fields)),
+                            // The entry was deleted
+                            None => Frame::Array(vec![id_frame(id), Frame::Null]),
+                        })
+                        .collect()
+                }
+            };
+            // Pending entries take memory
+            ks.record_access(&key);
+
+            // Only streams with new entries are listed, like XREAD
+            if matches!(from, GroupFrom::Pending(_)) || !entries.is_empty() {
+                replies.push(stream_frame(key, entries));
+            }
+        }
+
+        Ok(match replies.is_empty() {
+            true => Frame::Null,
+            false => Frame::Array(replies),
+        })
+    }
+
+    /// Logged without BLOCK: replays read what was read then
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut parts = vec![
+            Bytes::from("XREADGROUP"),
+            Bytes::from("GROUP"),
+            Bytes::from(self.group.clone()),
+            Bytes::from(self.consumer.clone()),
+        ];
+        if let Some(count) = self.count {
+            parts.push(Bytes::from("COUNT"));
+            parts.push(Bytes::from(count.to_string()));
+        }
+        if self.noack {
+            parts.push(Bytes::from("NOACK"));
+        }
+        parts.push(Bytes::from("STREAMS"));
+        for (key, _) in &self.streams {
+            parts.push(Bytes::from(key.clone()));
+        }
+        for (_, from) in &self.streams {
+            parts.push(Bytes::from(match from {
+                GroupFrom::New => ">".to_string(),
+                GroupFrom::Pending(id) => id.to_string(),
+            }));
+        }
+        command_frame(parts)
+    }
+}
+
+/// XGROUP CREATE key group id|$ [MKSTREAM], or XGROUP SETID key group id|$
+#[derive(Debug)]
+pub struct XGroup {
+    key: String,
+    group: String,
+    // `None` for `$`, the last ID of the stream
+    id: Option<StreamId>,
+    // CREATE with MKSTREAM: create the stream if missing
+    mkstream: bool,
+    setid: bool,
+}
+
+impl XGroup {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XGroup> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let setid = match &subcommand[..] {
+            "create" => false,
+            "setid" => true,
+            _ => {
+                return Err(
+                    format!("ERR unknown subcommand '{}'. Try XGROUP HELP.", subcommand).into(),
+                )
+            }
+        };
+
+        let key = parse.next_string()?;
+        let group = parse.next_string()?;
+        let id = match &parse.next_string()?[..] {
+            "$" => None,
+            id => Some(id.parse()?),
+        };
+        let mut mkstream = false;
+        if !setid && parse.remaining() > 0 {
+            if !parse.next_string()?.eq_ignore_ascii_case("mkstream") {
+                return Err("ERR syntax error".into());
+            }
+            mkstream = true;
+        }
+
+        Ok(XGroup {
+            key,
+            group,
+            id,
+            mkstream,
+            setid,
+        })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        if ks.stream(&self.key)?.is_none() && (self.setid || !self.mkstream) {
+            return Err(
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+                    .into(),
+            );
+        }
+
+        let stream = ks.stream_mut(&self.key)?;
+        let id = self.id.unwrap_or(stream.last_id());
+        if self.setid {
+            let group = stream.group_mut(&self.group).ok_or_else(|| {
+                format!(
+                    "NOGROUP No such consumer group '{}' for key name '{}'",
+                    self.group, self.key
+                )
+            })?;
+            group.set_last_delivered(id);
+        } else if !stream.create_group(&self.group, id) {
+            return Err("BUSYGROUP Consumer Group name already exists".into());
+        }
+        Ok(Frame::Simple("OK".into()))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        let subcommand = if self.setid { "SETID" } else { "CREATE" };
+        let mut parts = vec![
+            Bytes::from("XGROUP"),
+            Bytes::from(subcommand),
+            Bytes::from(self.key.clone()),
+            Bytes::from(self.group.clone()),
+            Bytes::from(self.id.map_or("$".to_string(), |id| id.to_string())),
+        ];
+        if self.mkstream {
+            parts.push(Bytes::from("MKSTREAM"));
+        }
+        command_frame(parts)
+    }
+}
+
+/// XACK key group id [id ...]
+#[derive(Debug)]
+pub struct XAck {
+    key: String,
+    group: String,
+    ids: Vec<StreamId>,
+}
+
+impl XAck {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XAck> {
+        let key = parse.next_string()?;
+        let group = parse.next_string()?;
+        let mut ids = vec![parse.next_string()?.parse()?];
+        while parse.remaining() > 0 {
+            ids.push(parse.next_string()?.parse()?);
+        }
+        Ok(XAck { key, group, ids })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let Some(stream) = ks.existing_stream_mut(&self.key)? else {
+            return Ok(Frame::Integer(0));
+        };
+        let Some(group) = stream.group_mut(&self.group) else {
+            return Ok(Frame::Integer(0));
+        };
+        let acked = self.ids.iter().filter(|id| group.ack(**id)).count();
+        Ok(Frame::Integer(acked as u64))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut parts = vec![
+            Bytes::from("XACK"),
+            Bytes::from(self.key.clone()),
+            Bytes::from(self.group.clone()),
+        ];
+        parts.extend(self.ids.iter().map(|id| Bytes::from(id.to_string())));
+        command_frame(parts)
+    }
+}
+
+/// XPENDING key group: the number of pending entries, the smallest and
+/// greatest pending IDs, and the number of pending entries per consumer
+#[derive(Debug)]
+pub struct XPending {
+    key: String,
+    group: String,
+}
+
+impl XPending {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<XPending> {
+        let key = parse.next_string()?;
+        let group = parse.next_string()?;
+        Ok(XPending { key, group })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let group = ks
+            .stream(&self.key)?
+            .and_then(|stream| stream.group(&self.group))
+            .ok_or_else(|| {
+                format!(
+                    "NOGROUP No such key '{}' or consumer group '{}'",
+                    self.key, self.group
+                )
+            })?;
+
+        let pending = group.pending();
+        let (Some((first, _)), Some((last, _))) =
+            (pending.first_key_value(), pending.last_key_value())
+        else {
+            return Ok(Frame::Array(vec![
+                Frame::Integer(0),
+                Frame::Null,
+                Frame::Null,
+                Frame::Null,
+            ]));
+        };
+        let consumers = group
+            .consumers()
+            .filter(|(_, count, _)| *count > 0)
+            .map(|(name, count, _)| {
+                Frame::Array(vec![
+                    Frame::Bulk(Bytes::from(name.to_string())),
+                    Frame::Bulk(Bytes::from(count.to_string())),
+                ])
+            })
+            .collect();
+        Ok(Frame::Array(vec![
+            Frame::Integer(pending.len() as u64),
+            id_frame(*first),
+            id_frame(*last),
+            Frame::Array(consumers),
+        ]))
+    }
+}
+
+/// Parse a COUNT: like Redis, 0 or less means no limit
+fn parse_count(parse: &mut Parse) -> crate::Result<Option<usize>> {
+    let count = parse.next_int()?;
+    Ok(usize::try_from(count).ok().filter(|count| *count > 0))
+}
+
+fn parse_block(parse: &mut Parse) -> crate::Result<Duration> {
+    let ms = parse.next_int()?;
+    let ms = u64::try_from(ms).map_err(|_| "ERR timeout is negative")?;
+    Ok(Duration::from_millis(ms))
+}
+
+/// Parse the keys then the IDs after STREAMS, one ID per key
+fn parse_streams<T>(
+    parse: &mut Parse,
+    name: &str,
+    parse_id: impl Fn(&str) -> crate::Result<T>,
+) -> crate::Result<Vec<(String, T)>> {
+    let mut args = vec![];
+    while parse.remaining() > 0 {
+        args.push(parse.next_string()?);
+    }
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            name
+        )
+        .into());
+    }
+
+    let ids = args.split_off(args.len() / 2);
+    args.into_iter()
+        .zip(ids)
+        .map(|(key, id)| Ok((key, parse_id(&id)?)))
+        .collect()
+}
+
+fn id_frame(id: StreamId) -> Frame {
+    Frame::Bulk(Bytes::from(id.to_string()))
+}
+
+/// An entry as replied: its ID, then its fields and values
+fn entry_frame((id, fields): (StreamId, &Fields)) -> Frame {
+    let fields = fields
+        .iter()
+        .flat_map(|(field, value)| [Frame::Bulk(field.clone()), Frame::Bulk(value.clone())])
+        .collect();
+    Frame::Array(vec![id_frame(id), Frame::Array(fields)])
+}
+
+/// Entries read from a stream by XREAD or XREADGROUP
+fn stream_frame(key: String, entries: Vec<Frame>) -> Frame {
+    Frame::Array(vec![Frame::Bulk(Bytes::from(key)), Frame::Array(entries)])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cmd::test_util::run, db::Keyspace};
+
+    #[test]
+    fn xadd_and_xrange() {
+        let mut ks = Keyspace::default();
+        assert_eq!(run(&mut ks, "XADD jobs 1-1 name a"), "1-1");
+        assert_eq!(run(&mut ks, "XADD jobs 1-* name b"), "1-2");
+        assert_eq!(run(&mut ks, "XADD jobs 5 name c size 3"), "5-0");
+        assert!(run(&mut ks, "XADD jobs 5-0 name d").contains("equal or smaller"));
+        assert!(run(&mut ks, "XADD jobs 0-0 name d").contains("greater than 0-0"));
+        assert!(run(&mut ks, "XADD jobs * name").contains("wrong number"));
+        assert!(run(&mut ks, "XADD jobs x-1 name d").contains("Invalid stream ID"));
+        // A failed XADD does not create the key
+        assert!(run(&mut ks, "XADD other 0-0 name d").contains("greater than 0-0"));
+        assert_eq!(run(&mut ks, "TYPE other"), "none");
+        assert_eq!(run(&mut ks, "TYPE jobs"), "stream");
+        assert_eq!(run(&mut ks, "XLEN jobs"), "3");
+
+        assert_eq!(
+            run(&mut ks, "XRANGE jobs - +"),
+            "1-1 name a 1-2 name b 5-0 name c size 3"
+        );
+        assert_eq!(run(&mut ks, "XRANGE jobs 1 1"), "1-1 name a 1-2 name b");
+        assert_eq!(run(&mut ks, "XRANGE jobs (1-1 + COUNT 1"), "1-2 name b");
+        assert_eq!(
+            run(&mut ks, "XREVRANGE jobs + - COUNT 1"),
+            "5-0 name c size 3"
+        );
+        assert_eq!(run(&mut ks, "XRANGE jobs 9 +"), "");
+        assert_eq!(run(&mut ks, "XRANGE nope - +"), "");
+
+        run(&mut ks, "SET name x");
+        assert!(run(&mut ks, "XADD name * a b").starts_with("error: WRONGTYPE"));
+    }
+
+    #[test]
+    fn xread_without_blocking() {
+        let mut ks = Keyspace::default();
+        run(&mut ks, "XADD a 1 k v");
+        run(&mut ks, "XADD a 2 k w");
+        run(&mut ks, "XADD b 3 k x");
+        assert_eq!(
+            run(&mut ks, "XREAD COUNT 1 STREAMS a b nope 0 0 0"),
+            "a 1-0 k v b 3-0 k x"
+        );
+        assert_eq!(run(&mut ks, "XREAD STREAMS a 1"), "a 2-0 k w");
+        assert_eq!(run(&mut ks, "XREAD STREAMS a $"), "(nil)");
+        assert!(run(&mut ks, "XREAD STREAMS a b 0").contains("Unbalanced"));
+        assert!(run(&mut ks, "XREAD BLOCK -1 STREAMS a 0").contains("negative"));
+    }
+
+    #[test]
+    fn consumer_groups() {
+        let mut ks = Keyspace::default();
+        assert!(run(&mut ks, "XGROUP CREATE jobs workers $").contains("MKSTREAM"));
+        assert_eq!(run(&mut ks, "XGROUP CREATE jobs workers $ MKSTREAM"), "OK");
+        assert!(run(&mut ks, "XGROUP CREATE jobs workers 0").starts_with("error: BUSYGROUP"));
+        for id in 1..=3 {
+            run(&mut ks, &format!("XADD jobs {} job {}", id, id));
+        }
+
+        assert_eq!(
+            run(
+                &mut ks,
+                "XREADGROUP GROUP workers alice COUNT 2 STREAMS jobs >"
+            ),
+            "jobs 1-0 job 1 2-0 job 2"
+        );
+        assert_eq!(
+            run(&mut ks, "XREADGROUP GROUP workers bob STREAMS jobs >"),
+            "jobs 3-0 job 3"
+        );
+        assert_eq!(
+            run(&mut ks, "XREADGROUP GROUP workers bob STREAMS jobs >"),
+            "(nil)"
+        );
+        assert!(
+            run(&mut ks, "XREADGROUP GROUP nope bob STREAMS jobs >").starts_with("error: NOGROUP")
+        );
+
+        assert_eq!(
+            run(&mut ks, "XPENDING jobs workers"),
+            "3 1-0 3-0 alice 2 bob 1"
+        );
+        assert_eq!(run(&mut ks, "XACK jobs workers 1-0 3-0 9-0"), "2");
+        assert_eq!(run(&mut ks, "XPENDING jobs workers"), "1 2-0 2-0 alice 1");
+        // Alice's history
+        assert_eq!(
+            run(&mut ks, "XREADGROUP GROUP workers alice STREAMS jobs 0"),
+            "jobs 2-0 job 2"
+        );
+        // No history left, but the stream is listed with no entries
+        assert_eq!(
+            run(&mut ks, "XREADGROUP GROUP workers bob STREAMS jobs 0"),
+            "jobs "
+        );
+
+        // Start over: deliver everything again, without tracking
+        assert_eq!(run(&mut ks, "XGROUP SETID jobs workers 0"), "OK");
+        assert_eq!(
+            run(
+                &mut ks,
+                "XREADGROUP GROUP workers carol NOACK STREAMS jobs >"
+            ),
+            "jobs 1-0 job 1 2-0 job 2 3-0 job 3"
+        );
+        assert_eq!(run(&mut ks, "XPENDING jobs workers"), "1 2-0 2-0 alice 1");
+        assert_eq!(run(&mut ks, "XACK jobs workers 2-0"), "1");
+        assert_eq!(run(&mut ks, "XPENDING jobs workers"), "0 (nil) (nil) (nil)");
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{
+    cmd::command_frame,
+    db::{from_unix_ms, now_ms, Keyspace, Value, WRONGTYPE},
+    parse::Parse,
+};
+
+/// GET key
+#[derive(Debug)]
+pub struct Get {
+    key: String,
+}
+
+impl Get {
+    pub fn new(key: impl ToString) -> Get {
+        Get {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Get> {
+        let key = parse.next_string()?;
+        Ok(Get { key })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        match ks.get(&self.key) {
+            Some(Value::String(value)) => Ok(Frame::Bulk(value.clone())),
+            Some(_) => Err(WRONGTYPE.into()),
+            None => Ok(Frame::Null),
+        }
+    }
+}
+
+const INVALID_EXPIRE: &str = "ERR invalid expire time in 'set' command";
+
+/// SET key value [EX seconds | PX milliseconds | EXAT timestamp | PXAT timestamp-ms]
+#[derive(Debug)]
+pub struct Set {
+    key: String,
+    value: Bytes,
+    // Relative expirations are resolved to a unix time (in ms) when parsed
+    expires_at: Option<u64>,
+}
+
+impl Set {
+    /// Fails if `expire` is too far in the future to be represented
+    pub fn new(key: impl ToString, value: Bytes, expire: Option<Duration>) -> crate::Result<Set> {
+        let expires_at = match expire {
+            Some(expire) => {
+                let ms = u64::try_from(expire.as_millis()).unwrap_or(u64::MAX);
+                Some(expire_time(now_ms(), ms, 1)?)
+            }
+            None => None,
+        };
+        Ok(Set {
+            key: key.to_string(),
+            value,
+            expires_at,
+        })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn value(&self) -> &Bytes {
+        &self.value
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Set> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+
+        let expires_at = if parse.remaining() > 0 {
+            let unit = parse.next_string()?.to_uppercase();
+            let amount = parse.next_int()?;
+            if amount <= 0 {
+                return Err(INVALID_EXPIRE.into());
+            }
+            let amount = amount as u64;
+            match &unit[..] {
+                "EX" => Some(expire_time(now_ms(), amount, 1000)?),
+                "PX" => Some(expire_time(now_ms(), amount, 1)?),
+                "EXAT" => Some(expire_time(0, amount, 1000)?),
+                "PXAT" => Some(expire_time(0, amount, 1)?),
+                _ => return Err("ERR syntax error".into()),
+            }
+        } else {
+            None
+        };
+
+        Ok(Set {
+            key,
+            value,
+            expires_at,
+        })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        ks.insert(self.key.clone(), Value::String(self.value));
+        if let Some(at) = self.expires_at {
+            ks.set_expiry(&self.key, Some(from_unix_ms(at)));
+        }
+        Ok(Frame::Simple("OK".into()))
+    }
+
+    /// The expiration is written as an absolute time, so replaying it later
+    /// does not extend the life of the key.
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut parts = vec![
+            Bytes::from("SET"),
+            Bytes::from(self.key.clone()),
+            self.value.clone(),
+        ];
+        if let Some(at) = self.expires_at {
+            parts.push(Bytes::from("PXAT"));
+            parts.push(Bytes::from(at.to_string()));
+        }
+        command_frame(parts)
+    }
+}
+
+/// Unix time in ms that is `amount` times `unit_ms` after `base`. Times past
+/// `i64::MAX` are rejected like the real server does, as they could not be
+/// written back out as `PXAT`.
+fn expire_time(base: u64, amount: u64, unit_ms: u64) -> crate::Result<u64> {
+    amount
+        .checked_mul(unit_ms)
+        .and_then(|ms| ms.checked_add(base))
+        .filter(|&at| at <= i64::MAX as u64)
+        .ok_or_else(|| INVALID_EXPIRE.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_rejects_expirations_out_of_range() {
+        let set = Set::new("key", Bytes::from("v"), Some(Duration::from_secs(60))).unwrap();
+        assert!(set.expires_at.unwrap() > now_ms());
+
+        for expire in [Duration::MAX, Duration::from_millis(i64::MAX as u64)] {
+            let err = Set::new("key", Bytes::from("v"), Some(expire)).unwrap_err();
+            assert_eq!(err.to_string(), INVALID_EXPIRE);
+        }
+    }
+}
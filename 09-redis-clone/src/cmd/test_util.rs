@@ -0,0 +1,27 @@
+//! Helpers shared by the command tests
+
+use mini_redis::Frame;
+
+use crate::{db::Keyspace, Command};
+
+/// Render a reply as a flat, space separated string
+fn render(frame: Frame) -> String {
+    match frame {
+        Frame::Array(parts) => parts.into_iter().map(render).collect::<Vec<_>>().join(" "),
+        frame => frame.to_string(),
+    }
+}
+
+/// Run a command line against `ks`, returning the rendered reply or the
+/// parse error
+pub(crate) fn run(ks: &mut Keyspace, line: &str) -> String {
+    let frame = Frame::Array(
+        line.split_whitespace()
+            .map(|arg| Frame::Bulk(arg.to_string().into()))
+            .collect(),
+    );
+    match Command::from_frame(frame) {
+        Ok(cmd) => render(cmd.apply(ks)),
+        Err(err) => format!("error: {}", err),
+    }
+}
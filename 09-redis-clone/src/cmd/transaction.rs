@@ -0,0 +1,17 @@
+use crate::parse::Parse;
+
+/// WATCH key [key ...]
+#[derive(Debug)]
+pub struct Watch {
+    pub(crate) keys: Vec<String>,
+}
+
+impl Watch {
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Watch> {
+        let mut keys = vec![parse.next_string()?];
+        while parse.remaining() > 0 {
+            keys.push(parse.next_string()?);
+        }
+        Ok(Watch { keys })
+    }
+}
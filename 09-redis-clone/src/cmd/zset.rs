@@ -0,0 +1,389 @@
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::{
+    cmd::command_frame,
+    db::Keyspace,
+    parse::{parse_float, Parse},
+    sorted_set::ScoreBound,
+};
+
+/// ZADD key [NX|XX] [CH] score member [score member ...]
+#[derive(Debug)]
+pub struct ZAdd {
+    key: String,
+    // Only add new members
+    nx: bool,
+    // Only update existing members
+    xx: bool,
+    // Reply with the number of changed members instead of added ones
+    ch: bool,
+    members: Vec<(f64, Bytes)>,
+}
+
+impl ZAdd {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZAdd> {
+        let key = parse.next_string()?;
+        let (mut nx, mut xx, mut ch) = (false, false, false);
+
+        // Flags come first, the first non-flag argument is a score
+        let first_score = loop {
+            let arg = parse.next_string()?;
+            match &arg.to_uppercase()[..] {
+                "NX" => nx = true,
+                "XX" => xx = true,
+                "CH" => ch = true,
+                _ => break arg,
+            }
+        };
+
+        if nx && xx {
+            return Err("ERR XX and NX options at the same time are not compatible".into());
+        }
+
+        let score = parse_float(&first_score).ok_or("ERR value is not a valid float")?;
+        let mut members = vec![(score, parse.next_bytes()?)];
+        while parse.remaining() > 0 {
+            let score = parse.next_float()?;
+            members.push((score, parse.next_bytes()?));
+        }
+
+        Ok(ZAdd {
+            key,
+            nx,
+            xx,
+            ch,
+            members,
+        })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        // XX never creates the key
+        if self.xx && ks.sorted_set(&self.key)?.is_none() {
+            return Ok(Frame::Integer(0));
+        }
+
+        let set = ks.sorted_set_mut(&self.key)?;
+        let mut added = 0;
+        let mut changed = 0;
+
+        for (score, member) in self.members {
+            match set.score(&member) {
+                Some(_) if self.nx => {}
+                None if self.xx => {}
+                Some(old) => {
+                    if old != score {
+                        set.insert(member, score);
+                        changed += 1;
+                    }
+                }
+                None => {
+                    set.insert(member, score);
+                    added += 1;
+                }
+            }
+        }
+
+        // NX on an existing key may have added nothing to a fresh set
+        if set.is_empty() {
+            ks.remove(&self.key);
+        }
+
+        let reply = if self.ch { added + changed } else { added };
+        Ok(Frame::Integer(reply))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut parts = vec![Bytes::from("ZADD"), Bytes::from(self.key.clone())];
+        for (flag, set) in [("NX", self.nx), ("XX", self.xx), ("CH", self.ch)] {
+            if set {
+                parts.push(Bytes::from(flag));
+            }
+        }
+        for (score, member) in &self.members {
+            parts.push(Bytes::from(score.to_string()));
+            parts.push(member.clone());
+        }
+        command_frame(parts)
+    }
+}
+
+/// ZSCORE key member
+#[derive(Debug)]
+pub struct ZScore {
+    key: String,
+    member: Bytes,
+}
+
+impl ZScore {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZScore> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+        Ok(ZScore { key, member })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let score = ks
+            .sorted_set(&self.key)?
+            .and_then(|set| set.score(&self.member));
+
+        Ok(match score {
+            Some(score) => Frame::Bulk(score.to_string().into()),
+            None => Frame::Null,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum RangeBy {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+}
+
+/// ZRANGE key start stop [BYSCORE] [LIMIT offset count] [WITHSCORES]
+#[derive(Debug)]
+pub struct ZRange {
+    key: String,
+    range: RangeBy,
+    // (offset, count) applied to BYSCORE results. A negative count means all.
+    limit: Option<(usize, i64)>,
+    with_scores: bool,
+}
+
+impl ZRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZRange> {
+        let key = parse.next_string()?;
+        let start = parse.next_string()?;
+        let stop = parse.next_string()?;
+
+        let mut by_score = false;
+        let mut limit = None;
+        let mut with_scores = false;
+        while parse.remaining() > 0 {
+            match &parse.next_string()?.to_uppercase()[..] {
+                "BYSCORE" => by_score = true,
+                "WITHSCORES" => with_scores = true,
+                "LIMIT" => {
+                    let offset = parse.next_int()?;
+                    let count = parse.next_int()?;
+                    limit = Some((usize::try_from(offset).unwrap_or(0), count));
+                }
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        let range = if by_score {
+            RangeBy::Score(parse_bound(&start)?, parse_bound(&stop)?)
+        } else if limit.is_some() {
+            return Err(
+                "ERR syntax error, LIMIT is only supported in combination with BYSCORE".into(),
+            );
+        } else {
+            let parse_index = |s: &str| {
+                s.parse::<i64>()
+                    .map_err(|_| "ERR value is not an integer or out of range")
+            };
+            RangeBy::Rank(parse_index(&start)?, parse_index(&stop)?)
+        };
+
+        Ok(ZRange {
+            key,
+            range,
+            limit,
+            with_scores,
+        })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let Some(set) = ks.sorted_set(&self.key)? else {
+            return Ok(Frame::Array(vec![]));
+        };
+
+        let items: Vec<(&Bytes, f64)> = match self.range {
+            RangeBy::Rank(start, stop) => {
+                // Negative indexes count from the end
+                let len = set.len() as i64;
+                let start = if start < 0 { len + start } else { start }.max(0);
+                let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+
+                if start > stop {
+                    vec![]
+                } else {
+                    set.range_by_rank(start as usize, stop as usize).collect()
+                }
+            }
+            RangeBy::Score(min, max) => {
+                let items = set.range_by_score(min, max);
+                match self.limit {
+                    Some((offset, count)) if count >= 0 => {
+                        items.skip(offset).take(count as usize).collect()
+                    }
+                    Some((offset, _)) => items.skip(offset).collect(),
+                    None => items.collect(),
+                }
+            }
+        };
+
+        let mut reply = Vec::with_capacity(items.len() * if self.with_scores { 2 } else { 1 });
+        for (member, score) in items {
+            reply.push(Frame::Bulk(member.clone()));
+            if self.with_scores {
+                reply.push(Frame::Bulk(score.to_string().into()));
+            }
+        }
+        Ok(Frame::Array(reply))
+    }
+}
+
+/// Parse a score bound: `(` marks it as exclusive, `-inf`/`+inf` are accepted.
+fn parse_bound(src: &str) -> crate::Result<ScoreBound> {
+    let (exclusive, value) = match src.strip_prefix('(') {
+        Some(value) => (true, value),
+        None => (false, src),
+    };
+
+    let value = parse_float(value).ok_or("ERR min or max is not a float")?;
+    Ok(if exclusive {
+        ScoreBound::Exclusive(value)
+    } else {
+        ScoreBound::Inclusive(value)
+    })
+}
+
+/// ZRANK key member
+#[derive(Debug)]
+pub struct ZRank {
+    key: String,
+    member: Bytes,
+}
+
+impl ZRank {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZRank> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+        Ok(ZRank { key, member })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        let rank = ks
+            .sorted_set(&self.key)?
+            .and_then(|set| set.rank(&self.member));
+
+        Ok(match rank {
+            Some(rank) => Frame::Integer(rank as u64),
+            None => Frame::Null,
+        })
+    }
+}
+
+/// ZREM key member [member ...]
+#[derive(Debug)]
+pub struct ZRem {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+impl ZRem {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZRem> {
+        let key = parse.next_string()?;
+        let mut members = vec![parse.next_bytes()?];
+        while parse.remaining() > 0 {
+            members.push(parse.next_bytes()?);
+        }
+        Ok(ZRem { key, members })
+    }
+
+    pub(crate) fn apply(self, ks: &mut Keyspace) -> crate::Result<Frame> {
+        if ks.sorted_set(&self.key)?.is_none() {
+            return Ok(Frame::Integer(0));
+        }
+
+        let set = ks.sorted_set_mut(&self.key)?;
+        let removed = self.members.iter().filter(|m| set.remove(m)).count();
+
+        // Empty sets are deleted, like in Redis
+        if set.is_empty() {
+            ks.remove(&self.key);
+        }
+
+        Ok(Frame::Integer(removed as u64))
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut parts = vec![Bytes::from("ZREM"), Bytes::from(self.key.clone())];
+        parts.extend(self.members.iter().cloned());
+        command_frame(parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cmd::test_util::run, db::Keyspace};
+
+    #[test]
+    fn zadd_and_zrange_with_scores() {
+        let mut ks = Keyspace::default();
+        assert_eq!(run(&mut ks, "ZADD board 10 alice 5 bob 7.5 carol"), "3");
+        assert_eq!(run(&mut ks, "ZADD board CH 12 bob 1 dave"), "2");
+        assert_eq!(run(&mut ks, "ZRANGE board 0 -1"), "dave carol alice bob");
+        assert_eq!(
+            run(&mut ks, "ZRANGE board 1 2 WITHSCORES"),
+            "carol 7.5 alice 10"
+        );
+        assert_eq!(run(&mut ks, "ZRANGE board 5 10"), "");
+    }
+
+    #[test]
+    fn zrange_by_score_with_limit() {
+        let mut ks = Keyspace::default();
+        run(&mut ks, "ZADD s 1 a 2 b 3 c 4 d 5 e");
+        assert_eq!(run(&mut ks, "ZRANGE s (1 4 BYSCORE"), "b c d");
+        assert_eq!(run(&mut ks, "ZRANGE s -inf +inf BYSCORE LIMIT 1 2"), "b c");
+        assert_eq!(
+            run(&mut ks, "ZRANGE s 4 +inf BYSCORE WITHSCORES"),
+            "d 4 e 5"
+        );
+        assert!(run(&mut ks, "ZRANGE s 0 1 LIMIT 0 1").starts_with("error"));
+    }
+
+    #[test]
+    fn zscore_zrank_and_zrem() {
+        let mut ks = Keyspace::default();
+        run(&mut ks, "ZADD s 1 a 2 b 3 c");
+        assert_eq!(run(&mut ks, "ZSCORE s b"), "2");
+        assert_eq!(run(&mut ks, "ZRANK s c"), "2");
+        assert_eq!(run(&mut ks, "ZRANK s nope"), "(nil)");
+        assert_eq!(run(&mut ks, "ZREM s a b nope"), "2");
+        assert_eq!(run(&mut ks, "ZRANK s c"), "0");
+        assert_eq!(run(&mut ks, "ZREM s c"), "1");
+        assert!(ks.is_empty());
+    }
+
+    #[test]
+    fn wrong_type_is_rejected() {
+        let mut ks = Keyspace::default();
+        run(&mut ks, "SET k v");
+        assert!(run(&mut ks, "ZADD k 1 a").contains("WRONGTYPE"));
+        assert!(run(&mut ks, "GET k") == "v");
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use tokio::sync::Semaphore;
+
+/// When the append-only file is flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// After every write command: slow, but nothing is lost
+    Always,
+    /// Once per second: up to a second of writes may be lost
+    EverySec,
+    /// Let the OS decide
+    No,
+}
+
+impl FromStr for AppendFsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySec),
+            "no" => Ok(AppendFsync::No),
+            other => Err(format!("invalid appendfsync value: {other}")),
+        }
+    }
+}
+
+/// Which keys are evicted when `maxmemory` is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict nothing: commands adding data fail with an OOM error
+    NoEviction,
+    /// Least recently used key
+    AllKeysLru,
+    /// Least recently used key among those with an expiration
+    VolatileLru,
+    /// Least frequently used key
+    AllKeysLfu,
+    /// Any key
+    AllKeysRandom,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "volatile-lru" => Ok(EvictionPolicy::VolatileLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            other => Err(format!("invalid maxmemory-policy value: {other}")),
+        }
+    }
+}
+
+impl EvictionPolicy {
+    /// Name of the policy in the config file
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+        }
+    }
+}
+
+/// Server settings
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address to listen on
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Connections beyond this many are refused with an error
+    pub maxclients: usize,
+    /// Directory where persistence files are written
+    pub dir: PathBuf,
+    /// Name of the snapshot file inside `dir`
+    pub dbfilename: String,
+    /// Take a snapshot this often if the keyspace changed. `None` disables it.
+    pub save_interval: Option<Duration>,
+    /// Log every write to the append-only file, and load it at startup
+    pub appendonly: bool,
+    /// Name of the append-only file inside `dir`
+    pub appendfilename: String,
+    pub appendfsync: AppendFsync,
+    /// Number of independently locked parts the keyspace is split into
+    pub shards: usize,
+    /// Primary to replicate at startup, as `(host, port)`
+    pub replicaof: Option<(String, u16)>,
+    /// Approximate memory limit of the keyspace in bytes, 0 for none
+    pub maxmemory: usize,
+    pub maxmemory_policy: EvictionPolicy,
+    /// Number of random keys compared to pick each evicted key
+    pub maxmemory_samples: usize,
+    /// Steps a script may run before it is aborted, see `script`
+    pub script_max_steps: u64,
+    /// Password of the `default` user: clients must AUTH with it first
+    pub requirepass: Option<String>,
+    /// Password sent with AUTH to the primary, before SYNC
+    pub masterauth: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 6379,
+            maxclients: 10_000,
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save_interval: None,
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: AppendFsync::EverySec,
+            shards: 16,
+            replicaof: None,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            maxmemory_samples: 5,
+            script_max_steps: 1_000_000,
+            requirepass: None,
+            masterauth: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read a config file in the redis.conf format: one `directive value`
+    /// per line, `#` starting a comment. Unset directives keep their default.
+    pub fn from_file(path: &Path) -> crate::Result<Config> {
+        let text = fs::read_to_string(path)?;
+        let mut config = Config::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            config
+                .set(name, value.trim())
+                .map_err(|err| format!("{}:{}: {}", path.display(), number + 1, err))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Set the directive `name` from its textual `value`
+    pub fn set(&mut self, name: &str, value: &str) -> crate::Result<()> {
+        // Values may be quoted, e.g. `save ""`
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+
+        match &name.to_lowercase()[..] {
+            "bind" => self.bind = value.parse()?,
+            "port" => self.port = value.parse()?,
+            "maxclients" => self.maxclients = parse_maxclients(value)?,
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => self.dbfilename = value.to_string(),
+            // `save <seconds> [changes]`: only the period is used
+            "save" => {
+                self.save_interval = match value.split_whitespace().next() {
+                    Some(seconds) => Some(Duration::from_secs(seconds.parse()?)),
+                    None => None,
+                }
+            }
+            "appendonly" => self.appendonly = parse_yes_no(value)?,
+            "appendfilename" => self.appendfilename = value.to_string(),
+            "appendfsync" => self.appendfsync = value.parse()?,
+            "shards" => self.shards = value.parse()?,
+            // `replicaof <host> <port>`
+            "replicaof" => {
+                let (host, port) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or("replicaof expects a host and a port")?;
+                self.replicaof = Some((host.to_string(), port.trim().parse()?));
+            }
+            "maxmemory" => self.maxmemory = parse_memory(value)?,
+            "maxmemory-policy" => self.maxmemory_policy = value.parse()?,
+            "maxmemory-samples" => self.maxmemory_samples = value.parse()?,
+            "script-max-steps" => self.script_max_steps = value.parse()?,
+            // An empty password disables it
+            "requirepass" => {
+                self.requirepass = Some(value.to_string()).filter(|value| !value.is_empty())
+            }
+            "masterauth" => {
+                self.masterauth = Some(value.to_string()).filter(|value| !value.is_empty())
+            }
+            _ => return Err(format!("unknown directive '{}'", name).into()),
+        }
+        Ok(())
+    }
+
+    /// Full path of the snapshot file
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// Full path of the append-only file
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+}
+
+/// Parse a size like `100mb`: `k`, `m` and `g` are powers of 1000, `kb`,
+/// `mb` and `gb` powers of 1024
+fn parse_memory(value: &str) -> crate::Result<usize> {
+    let value = value.to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &value[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        unit => return Err(format!("invalid memory unit: {unit}").into()),
+    };
+    Ok(digits.parse::<usize>()? * unit)
+}
+
+/// Parse `maxclients`, which can't exceed the permits of a semaphore
+fn parse_maxclients(value: &str) -> crate::Result<usize> {
+    let maxclients = value.parse()?;
+    if maxclients > Semaphore::MAX_PERMITS {
+        return Err(format!("maxclients can't exceed {}", Semaphore::MAX_PERMITS).into());
+    }
+    Ok(maxclients)
+}
+
+fn parse_yes_no(value: &str) -> crate::Result<bool> {
+    match &value.to_lowercase()[..] {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("expected yes or no, got '{}'", value).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives_override_defaults() {
+        let path = std::env::temp_dir().join(format!("redis-clone-{}.conf", std::process::id()));
+        fs::write(
+            &path,
+            "# a comment\n\
+             bind 0.0.0.0\n\
+             port 7000\n\
+             \n\
+             save 300 10\n\
+             appendonly yes\n\
+             appendfsync always\n\
+             dbfilename \"backup.rdb\"\n",
+        )
+        .unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.bind, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.save_interval, Some(Duration::from_secs(300)));
+        assert!(config.appendonly);
+        assert_eq!(config.appendfsync, AppendFsync::Always);
+        assert_eq!(config.dbfilename, "backup.rdb");
+        assert_eq!(config.maxclients, 10_000);
+    }
+
+    #[test]
+    fn bad_directives_are_rejected() {
+        let mut config = Config::default();
+        assert!(config.set("port", "not-a-port").is_err());
+        assert!(config.set("appendonly", "maybe").is_err());
+        assert!(config.set("unknown", "1").is_err());
+        assert!(config.set("replicaof", "localhost").is_err());
+
+        config.set("replicaof", "10.0.0.1 6380").unwrap();
+        assert_eq!(config.replicaof, Some(("10.0.0.1".to_string(), 6380)));
+
+        assert!(config.set("maxmemory", "10tb").is_err());
+        assert!(config.set("maxmemory-policy", "volatile-ttl").is_err());
+
+        config.set("maxmemory", "100mb").unwrap();
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        config.set("maxmemory", "2k").unwrap();
+        assert_eq!(config.maxmemory, 2000);
+        config.set("maxmemory-policy", "allkeys-lru").unwrap();
+        assert_eq!(config.maxmemory_policy, EvictionPolicy::AllKeysLru);
+
+        assert!(config.set("maxclients", "18446744073709551615").is_err());
+        config.set("maxclients", "20").unwrap();
+        assert_eq!(config.maxclients, 20);
+
+        assert!(config.set("script-max-steps", "-1").is_err());
+        config.set("script-max-steps", "5000").unwrap();
+        assert_eq!(config.script_max_steps, 5000);
+
+        config.set("save", "\"\"").unwrap();
+        assert_eq!(config.save_interval, None);
+
+        config.set("requirepass", "secret").unwrap();
+        assert_eq!(config.requirepass.as_deref(), Some("secret"));
+        config.set("requirepass", "\"\"").unwrap();
+        assert_eq!(config.requirepass, None);
+    }
+}
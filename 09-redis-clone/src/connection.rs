@@ -1,5 +1,3 @@
-#![allow(dead_code, unused_variables)]
-
 use std::io::{self, Cursor};
 
 use bytes::{Buf, BytesMut};
@@ -9,18 +7,41 @@ use tokio::{io::AsyncReadExt, net::TcpStream};
 use mini_redis::frame::Error::Incomplete;
 use mini_redis::{Frame, Result as RedisResult};
 
-struct Connection {
+use crate::aof;
+
+/// Send and receive `Frame` values from a remote peer.
+///
+/// Unlike the mini-redis one, nested arrays can be written, as EXEC replies
+/// with an array of the replies of each queued command.
+#[derive(Debug)]
+pub struct Connection {
     stream: BufWriter<TcpStream>,
     buffer: BytesMut,
+    // Traffic since the last `take_traffic` call
+    bytes_read: u64,
+    bytes_written: u64,
 }
 
 impl Connection {
-    // Read a frame from the connection
+    /// Create a connection over `stream`
     pub fn new(stream: TcpStream) -> Self {
         // Allocate buffer of 4K
         let stream = BufWriter::new(stream);
         let buffer = BytesMut::with_capacity(4096);
-        Connection { stream, buffer }
+        Connection {
+            stream,
+            buffer,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+
+    /// Bytes read and written since the previous call, for server stats
+    pub fn take_traffic(&mut self) -> (u64, u64) {
+        let traffic = (self.bytes_read, self.bytes_written);
+        self.bytes_read = 0;
+        self.bytes_written = 0;
+        traffic
     }
 
     /// Read a frame from the connection
@@ -33,7 +54,9 @@ impl Connection {
 
             // Try to read more data from the stream
             // If 0 bytes read, check if the buffer has data for clean shutdown
-            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            let read = self.stream.read_buf(&mut self.buffer).await?;
+            self.bytes_read += read as u64;
+            if read == 0 {
                 // If no data on buffer, the client closed cleanly.
                 // Else, the connection was interrupted.
                 if self.buffer.is_empty() {
@@ -47,36 +70,104 @@ impl Connection {
 
     /// Write a frame to the connection
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.write_value(frame).await?;
+        self.stream.flush().await?;
+
+        Ok(())
+    }
+
+    /// Write several frames with a single flush, e.g. a pipeline of commands
+    pub async fn write_frames(&mut self, frames: &[Frame]) -> io::Result<()> {
+        for frame in frames {
+            self.write_value(frame).await?;
+        }
+        self.stream.flush().await?;
+
+        Ok(())
+    }
+
+    /// Write several frames like `write_frames`, while reading what the
+    /// peer sends meanwhile and handing each frame read to `on_frame`.
+    ///
+    /// A peer that stops reading while its replies aren't read, like a
+    /// server whose output buffer is full, can't block a long pipeline.
+    pub async fn write_frames_reading(
+        &mut self,
+        frames: &[Frame],
+        mut on_frame: impl FnMut(Frame),
+    ) -> RedisResult<()> {
+        let mut data = vec![];
+        for frame in frames {
+            aof::encode(frame, &mut data);
+        }
+        // Whatever earlier writes left in the buffer goes first
+        self.stream.flush().await?;
+
+        let (mut reader, mut writer) = self.stream.get_mut().split();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            tokio::select! {
+                written = writer.write(rest) => {
+                    let written = written?;
+                    if written == 0 {
+                        return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+                    }
+                    rest = &rest[written..];
+                    self.bytes_written += written as u64;
+                }
+                read = reader.read_buf(&mut self.buffer) => {
+                    let read = read?;
+                    if read == 0 {
+                        return Err("connection reset by peer".into());
+                    }
+                    self.bytes_read += read as u64;
+                    while let Some(frame) = parse_frame(&mut self.buffer)? {
+                        on_frame(frame);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a frame to the buffered stream, without flushing it
+    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
         match frame {
             Frame::Simple(val) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
+                self.write_bytes(b"+").await?;
+                self.write_bytes(val.as_bytes()).await?;
+                self.write_bytes(b"\r\n").await?;
             }
             Frame::Error(val) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
+                self.write_bytes(b"-").await?;
+                self.write_bytes(val.as_bytes()).await?;
+                self.write_bytes(b"\r\n").await?;
             }
             Frame::Integer(val) => {
-                self.stream.write_u8(b':').await?;
+                self.write_bytes(b":").await?;
                 self.write_decimal(*val).await?;
             }
             Frame::Null => {
-                self.stream.write_all(b"$-1\r\n").await?;
+                self.write_bytes(b"$-1\r\n").await?;
             }
             Frame::Bulk(val) => {
                 let len = val.len();
 
-                self.stream.write_u8(b'$').await?;
+                self.write_bytes(b"$").await?;
                 self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
+                self.write_bytes(val).await?;
+                self.write_bytes(b"\r\n").await?;
             }
-            Frame::Array(_val) => unimplemented!(),
-        }
+            Frame::Array(val) => {
+                self.write_bytes(b"*").await?;
+                self.write_decimal(val.len() as u64).await?;
 
-        self.stream.flush().await?;
+                // Recursive async calls must be boxed
+                for entry in val {
+                    Box::pin(self.write_value(entry)).await?;
+                }
+            }
+        }
 
         Ok(())
     }
@@ -86,43 +177,54 @@ impl Connection {
         use std::io::Write;
 
         // Convert the value to a string
-        let mut buf = [0u8; 12];
+        let mut buf = [0u8; 20];
         let mut buf = Cursor::new(&mut buf[..]);
         write!(&mut buf, "{}", val)?;
 
         let pos = buf.position() as usize;
-        self.stream.write_all(&buf.get_ref()[..pos]).await?;
-        self.stream.write_all(b"\r\n").await?;
+        self.write_bytes(&buf.get_ref()[..pos]).await?;
+        self.write_bytes(b"\r\n").await?;
 
         Ok(())
     }
 
+    /// Write raw bytes to the buffered stream, counting them
+    async fn write_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data).await?;
+        self.bytes_written += data.len() as u64;
+        Ok(())
+    }
     /// Try to parse a frame from the buffer. Empty it if successful.
     fn parse_frame(&mut self) -> RedisResult<Option<Frame>> {
-        // Create a T:Buf type
-        let mut buf = Cursor::new(&self.buffer[..]);
+        parse_frame(&mut self.buffer)
+    }
+}
 
-        // Check if a frame can be parsed from the buffer
-        match Frame::check(&mut buf) {
-            Ok(_) => {
-                // Get the len of the frame (position of the cursor)
-                let len = buf.position() as usize;
+/// Parse a frame from the start of `buffer`, and remove it from there
+fn parse_frame(buffer: &mut BytesMut) -> RedisResult<Option<Frame>> {
+    // Create a T:Buf type
+    let mut buf = Cursor::new(&buffer[..]);
 
-                // Reset the internal cursor
-                buf.set_position(0);
+    // Check if a frame can be parsed from the buffer
+    match Frame::check(&mut buf) {
+        Ok(_) => {
+            // Get the len of the frame (position of the cursor)
+            let len = buf.position() as usize;
 
-                // Parse the frame
-                let frame = Frame::parse(&mut buf)?;
+            // Reset the internal cursor
+            buf.set_position(0);
 
-                // Discard the frame from the buffer
-                self.buffer.advance(len);
+            // Parse the frame
+            let frame = Frame::parse(&mut buf)?;
 
-                // Return the frame
-                Ok(Some(frame))
-            }
-            // Not enough data to parse, or an error has occurred
-            Err(Incomplete) => Ok(None),
-            Err(e) => Err(e.into()),
+            // Discard the frame from the buffer
+            buffer.advance(len);
+
+            // Return the frame
+            Ok(Some(frame))
         }
+        // Not enough data to parse, or an error has occurred
+        Err(Incomplete) => Ok(None),
+        Err(e) => Err(e.into()),
     }
 }
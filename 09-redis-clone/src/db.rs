@@ -0,0 +1,1377 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeSet, HashMap},
+    fs,
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher},
+    io::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::sync::{mpsc, Notify};
+use tracing::{error, info};
+
+use crate::{
+    acl::Acl,
+    aof::{self, Aof},
+    clients::Clients,
+    cmd::{self, command_frame},
+    list::List,
+    pubsub::PubSub,
+    replication::Replication,
+    script::Scripts,
+    snapshot,
+    sorted_set::SortedSet,
+    stats::Stats,
+    stream::Stream,
+    Command, Config, EvictionPolicy,
+};
+
+/// Current wall-clock time in milliseconds since the epoch
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Convert a monotonic deadline into wall-clock milliseconds since the epoch
+pub(crate) fn to_unix_ms(at: Instant) -> u64 {
+    now_ms() + at.saturating_duration_since(Instant::now()).as_millis() as u64
+}
+
+/// Convert wall-clock milliseconds into a deadline. Past times map to now.
+pub(crate) fn from_unix_ms(ms: u64) -> Instant {
+    Instant::now() + Duration::from_millis(ms.saturating_sub(now_ms()))
+}
+
+/// Error returned when a command would exceed `maxmemory` and nothing can be
+/// evicted
+pub const OOM: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+/// Error returned when a command is run against a key of another type
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Value stored under a key
+#[derive(Debug)]
+pub enum Value {
+    String(Bytes),
+    SortedSet(SortedSet),
+    Stream(Stream),
+    List(List),
+}
+
+impl Value {
+    /// Name of the type, as reported by the TYPE command
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+            Value::List(_) => "list",
+        }
+    }
+
+    /// Rough number of bytes used by the value
+    pub fn approx_size(&self) -> usize {
+        match self {
+            Value::String(data) => data.len(),
+            Value::SortedSet(set) => set.approx_size(),
+            Value::Stream(stream) => stream.approx_size(),
+            Value::List(list) => list.approx_size(),
+        }
+    }
+}
+
+/// Approximate bookkeeping bytes of a key, besides its name and value
+const ENTRY_OVERHEAD: usize = 96;
+
+/// LFU counter of a new key, so it is not the first one evicted
+const LFU_INIT: u8 = 5;
+
+/// The higher, the more accesses it takes to increment the LFU counter
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// The LFU counter of a key decreases by one per period without access
+const LFU_DECAY_PERIOD: Duration = Duration::from_secs(60);
+
+/// Random keys drawn per wanted sample with `volatile-lru`, as only keys
+/// with an expiration count
+const VOLATILE_TRIES: usize = 10;
+
+/// Approximate size of an entry for `key` holding `value`. The key is stored
+/// three times: in the map, in the list used for sampling and in the SCAN
+/// order.
+fn entry_size(key: &str, value: &Value) -> usize {
+    3 * key.len() + value.approx_size() + ENTRY_OVERHEAD
+}
+
+/// Position of `key` in SCAN order. The hasher has fixed keys, so the order
+/// is the same in every shard and across restarts.
+pub fn scan_hash(key: &str) -> u64 {
+    BuildHasherDefault::<DefaultHasher>::default().hash_one(key)
+}
+
+/// Modification counter of a key that some connection WATCHes
+#[derive(Debug, Default)]
+struct Watched {
+    version: u64,
+    // Number of connections watching the key
+    watchers: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+    // Approximate bytes used, as of the last command on the key
+    size: usize,
+    // Position of the key in `Keyspace::keys`
+    slot: usize,
+    // Access data for the LRU and LFU eviction policies
+    last_access: Instant,
+    frequency: u8,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Logarithmic access counter, decayed by the time since the last access
+    fn decayed_frequency(&self, now: Instant) -> u8 {
+        let periods = now.duration_since(self.last_access).as_secs() / LFU_DECAY_PERIOD.as_secs();
+        self.frequency
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+/// One shard of the keyspace. Commands are applied to the shard holding
+/// their key while holding its `Db` lock.
+///
+/// Expired keys are hidden as soon as their deadline passes, and removed
+/// either when accessed for writing or by `purge_expired`.
+#[derive(Debug)]
+pub struct Keyspace {
+    entries: HashMap<String, Entry>,
+    // Every key, so eviction can sample random ones
+    keys: Vec<String>,
+    // Every key by `scan_hash`, so SCAN can resume from a cursor
+    scan_order: BTreeSet<(u64, String)>,
+    // Keys with an expiration, ordered by deadline
+    expirations: BTreeSet<(Instant, String)>,
+    // Only watched keys are tracked, so writes to other keys stay cheap
+    watched: HashMap<String, Watched>,
+    // Connections blocked until the key is written, see `Waiter`
+    blocked: HashMap<String, Vec<Arc<Notify>>>,
+    // Number of writes since the last snapshot
+    dirty: u64,
+    // Approximate bytes used by every entry
+    used_memory: usize,
+    // State of the xorshift generator used for sampling
+    seed: u64,
+}
+
+impl Default for Keyspace {
+    fn default() -> Keyspace {
+        Keyspace {
+            entries: HashMap::new(),
+            keys: Vec::new(),
+            scan_order: BTreeSet::new(),
+            expirations: BTreeSet::new(),
+            watched: HashMap::new(),
+            blocked: HashMap::new(),
+            dirty: 0,
+            used_memory: 0,
+            seed: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+}
+
+impl Keyspace {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(Instant::now()))
+            .map(|entry| &entry.value)
+    }
+
+    /// Get a value to modify it. Counts as a write for WATCH.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.remove_if_expired(key);
+        if self.entries.contains_key(key) {
+            self.touch(key);
+        }
+        self.entries.get_mut(key).map(|entry| &mut entry.value)
+    }
+
+    /// Store `value` under `key`, clearing any previous expiration.
+    pub fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        self.remove_if_expired(&key);
+        self.touch(&key);
+
+        let old = self.remove_entry(&key);
+        self.add_entry(key, value, None);
+        old.map(|entry| entry.value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.remove_if_expired(key);
+        let removed = self.remove_entry(key)?;
+        self.touch(key);
+        Some(removed.value)
+    }
+
+    /// Iterate over every key whose `scan_hash` is at least `cursor`, in
+    /// hash order, with its hash. Expired keys are included.
+    pub fn scan_from(&self, cursor: u64) -> impl Iterator<Item = (u64, &str)> {
+        self.scan_order
+            .range((cursor, String::new())..)
+            .map(|(hash, key)| (*hash, key.as_str()))
+    }
+
+    /// Number of keys, including expired ones not purged yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Set (or clear with `None`) the expiration of `key`.
+    ///
+    /// Returns `false` if the key does not exist.
+    pub fn set_expiry(&mut self, key: &str, expires_at: Option<Instant>) -> bool {
+        self.remove_if_expired(key);
+        if !self.entries.contains_key(key) {
+            return false;
+        }
+        self.touch(key);
+
+        let entry = self.entries.get_mut(key).unwrap();
+        if let Some(at) = entry.expires_at {
+            self.expirations.remove(&(at, key.to_string()));
+        }
+        if let Some(at) = expires_at {
+            self.expirations.insert((at, key.to_string()));
+        }
+        entry.expires_at = expires_at;
+        true
+    }
+
+    /// Number of keys with an expiration
+    pub fn expires(&self) -> usize {
+        self.expirations.len()
+    }
+
+    /// Approximate bytes used by the keys and values
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// Deadline of `key`, if it has one
+    pub fn expires_at(&self, key: &str) -> Option<Instant> {
+        self.entries.get(key).and_then(|entry| entry.expires_at)
+    }
+
+    /// Iterate over every live key with its value and expiration
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value, Option<Instant>)> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key, &entry.value, entry.expires_at))
+    }
+
+    /// Remove every key whose deadline is at or before `now`.
+    ///
+    /// Returns the number of removed keys.
+    pub fn purge_expired(&mut self, now: Instant) -> usize {
+        let mut purged = 0;
+        while let Some((at, key)) = self.expirations.first().cloned() {
+            if at > now {
+                break;
+            }
+            self.remove_entry(&key);
+            self.touch(&key);
+            purged += 1;
+        }
+        purged
+    }
+
+    /// Record that a command used `key`: refresh its LRU and LFU data, and
+    /// its size, which the command may have changed.
+    pub fn record_access(&mut self, key: &str) {
+        let random = self.next_random();
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+
+        let now = Instant::now();
+        entry.frequency = entry.decayed_frequency(now);
+        // Each access is less likely to count as the counter grows, so it
+        // reaches 255 only after about a million accesses
+        let base = entry.frequency.saturating_sub(LFU_INIT) as f64;
+        let chance = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+        if (random >> 11) as f64 / (1u64 << 53) as f64 <= chance {
+            entry.frequency = entry.frequency.saturating_add(1);
+        }
+        entry.last_access = now;
+
+        let size = entry_size(key, &entry.value);
+        self.used_memory = self.used_memory - entry.size + size;
+        entry.size = size;
+    }
+
+    /// Pick the key `policy` would evict among `samples` random keys, the
+    /// way Redis approximates LRU and LFU.
+    ///
+    /// Returns the key with its score: the higher, the better a candidate,
+    /// so candidates of several shards can be compared. `None` if the policy
+    /// allows no eviction here.
+    pub fn eviction_candidate(
+        &mut self,
+        policy: EvictionPolicy,
+        samples: usize,
+    ) -> Option<(u64, String)> {
+        if self.keys.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut candidates = Vec::with_capacity(samples);
+        match policy {
+            EvictionPolicy::NoEviction => return None,
+            EvictionPolicy::VolatileLru => {
+                for _ in 0..samples * VOLATILE_TRIES {
+                    let key = self.random_key();
+                    if self.entries[&key].expires_at.is_some() {
+                        candidates.push(key);
+                        if candidates.len() == samples {
+                            break;
+                        }
+                    }
+                }
+                // Few keys have an expiration: take the next one to expire
+                if candidates.is_empty() {
+                    candidates.extend(self.expirations.first().map(|(_, key)| key.clone()));
+                }
+            }
+            _ => candidates.extend((0..samples.max(1)).map(|_| self.random_key())),
+        }
+
+        let mut best = None;
+        for key in candidates {
+            let entry = &self.entries[&key];
+            let idle = now.duration_since(entry.last_access).as_millis() as u64;
+            let score = match policy {
+                // Least frequent first, then least recent
+                EvictionPolicy::AllKeysLfu => {
+                    let rarity = (u8::MAX - entry.decayed_frequency(now)) as u64;
+                    rarity << 48 | idle.min((1 << 48) - 1)
+                }
+                EvictionPolicy::AllKeysRandom => self.next_random(),
+                _ => idle,
+            };
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, key));
+            }
+        }
+        best
+    }
+
+    /// Remove `key` to free memory. Returns `false` if it does not exist.
+    pub fn evict(&mut self, key: &str) -> bool {
+        let evicted = self.remove_entry(key).is_some();
+        if evicted {
+            self.touch(key);
+        }
+        evicted
+    }
+
+    fn remove_if_expired(&mut self, key: &str) {
+        if self
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(Instant::now()))
+        {
+            self.remove_entry(key);
+        }
+    }
+
+    /// Add an entry for `key`, which must not exist
+    fn add_entry(&mut self, key: String, value: Value, expires_at: Option<Instant>) {
+        let size = entry_size(&key, &value);
+        self.used_memory += size;
+        if let Some(at) = expires_at {
+            self.expirations.insert((at, key.clone()));
+        }
+        self.keys.push(key.clone());
+        self.scan_order.insert((scan_hash(&key), key.clone()));
+
+        let entry = Entry {
+            value,
+            expires_at,
+            size,
+            slot: self.keys.len() - 1,
+            last_access: Instant::now(),
+            frequency: LFU_INIT,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Remove the entry of `key` and its expiration
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.used_memory -= entry.size;
+        if let Some(at) = entry.expires_at {
+            self.expirations.remove(&(at, key.to_string()));
+        }
+
+        self.scan_order.remove(&(scan_hash(key), key.to_string()));
+
+        // The last key takes the free slot
+        self.keys.swap_remove(entry.slot);
+        if let Some(moved) = self.keys.get(entry.slot) {
+            self.entries.get_mut(moved).unwrap().slot = entry.slot;
+        }
+        Some(entry)
+    }
+
+    /// Any key, possibly expired. The keyspace must not be empty.
+    fn random_key(&mut self) -> String {
+        let index = self.next_random() % self.keys.len() as u64;
+        self.keys[index as usize].clone()
+    }
+
+    /// Next value of the xorshift generator
+    fn next_random(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    /// Get the sorted set stored at `key`, if any.
+    pub fn sorted_set(&self, key: &str) -> crate::Result<Option<&SortedSet>> {
+        match self.get(key) {
+            Some(Value::SortedSet(set)) => Ok(Some(set)),
+            Some(_) => Err(WRONGTYPE.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the sorted set stored at `key`, creating an empty one if missing.
+    pub fn sorted_set_mut(&mut self, key: &str) -> crate::Result<&mut SortedSet> {
+        self.remove_if_expired(key);
+        if !self.entries.contains_key(key) {
+            let set = Value::SortedSet(SortedSet::new());
+            self.add_entry(key.to_string(), set, None);
+        }
+        if !matches!(self.entries[key].value, Value::SortedSet(_)) {
+            return Err(WRONGTYPE.into());
+        }
+        self.touch(key);
+
+        match &mut self.entries.get_mut(key).unwrap().value {
+            Value::SortedSet(set) => Ok(set),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get the stream stored at `key`, if any.
+    pub fn stream(&self, key: &str) -> crate::Result<Option<&Stream>> {
+        match self.get(key) {
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(WRONGTYPE.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the stream stored at `key`, creating an empty one if missing.
+    pub fn stream_mut(&mut self, key: &str) -> crate::Result<&mut Stream> {
+        if self.stream(key)?.is_none() {
+            self.insert(key.to_string(), Value::Stream(Stream::new()));
+        }
+        match self.get_mut(key) {
+            Some(Value::Stream(stream)) => Ok(stream),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get the stream stored at `key` to modify it, if any.
+    pub fn existing_stream_mut(&mut self, key: &str) -> crate::Result<Option<&mut Stream>> {
+        match self.get_mut(key) {
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(WRONGTYPE.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the list stored at `key`, if any.
+    pub fn list(&self, key: &str) -> crate::Result<Option<&List>> {
+        match self.get(key) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(WRONGTYPE.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the list stored at `key`, creating an empty one if missing.
+    pub fn list_mut(&mut self, key: &str) -> crate::Result<&mut List> {
+        if self.list(key)?.is_none() {
+            self.insert(key.to_string(), Value::List(List::new()));
+        }
+        match self.get_mut(key) {
+            Some(Value::List(list)) => Ok(list),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Start watching `key`. Returns its current version.
+    pub fn watch(&mut self, key: &str) -> u64 {
+        let watched = self.watched.entry(key.to_string()).or_default();
+        watched.watchers += 1;
+        watched.version
+    }
+
+    /// Stop watching `key` (once per previous `watch` call)
+    pub fn unwatch(&mut self, key: &str) {
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// Current version of a watched key. It changes on every write.
+    pub fn version(&self, key: &str) -> Option<u64> {
+        self.watched.get(key).map(|watched| watched.version)
+    }
+
+    /// Notify `waker` on every write to `key`, until `unblock` is called
+    pub fn block(&mut self, key: &str, waker: &Arc<Notify>) {
+        self.blocked
+            .entry(key.to_string())
+            .or_default()
+            .push(waker.clone());
+    }
+
+    /// Stop notifying `waker` of writes to `key` (once per previous `block`
+    /// call). The connections blocked after it are notified, as they may
+    /// now be first in line.
+    pub fn unblock(&mut self, key: &str, waker: &Arc<Notify>) {
+        if let Some(wakers) = self.blocked.get_mut(key) {
+            if let Some(index) = wakers.iter().position(|other| Arc::ptr_eq(other, waker)) {
+                wakers.remove(index);
+                for waker in &wakers[index..] {
+                    waker.notify_one();
+                }
+            }
+            if wakers.is_empty() {
+                self.blocked.remove(key);
+            }
+        }
+    }
+
+    /// Number of connections blocked on `key` before `waker`, which serves
+    /// them in arrival order
+    pub fn blocked_before(&self, key: &str, waker: &Arc<Notify>) -> usize {
+        let wakers = self.blocked.get(key).map_or(&[][..], |wakers| &wakers[..]);
+        wakers
+            .iter()
+            .position(|other| Arc::ptr_eq(other, waker))
+            .unwrap_or(wakers.len())
+    }
+
+    /// Number of writes since the last snapshot
+    pub fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    /// Take the keys of `other` in place of ours, e.g. after a full sync from
+    /// a primary. Every watched key counts as modified.
+    fn replace(&mut self, other: Keyspace) {
+        self.entries = other.entries;
+        self.keys = other.keys;
+        self.scan_order = other.scan_order;
+        self.expirations = other.expirations;
+        self.used_memory = other.used_memory;
+        for watched in self.watched.values_mut() {
+            watched.version += 1;
+        }
+        for waker in self.blocked.values().flatten() {
+            waker.notify_one();
+        }
+        self.dirty += 1;
+    }
+
+    /// Split into `count` shards, each key going to the shard `index_of` picks
+    fn into_shards(self, count: usize, index_of: impl Fn(&str) -> usize) -> Vec<Keyspace> {
+        let mut shards: Vec<_> = (0..count).map(|_| Keyspace::default()).collect();
+        for (key, entry) in self.entries {
+            let shard = &mut shards[index_of(&key)];
+            shard.add_entry(key, entry.value, entry.expires_at);
+        }
+        shards
+    }
+
+    /// Record a write to `key`, for snapshots and the connections watching
+    /// it or blocked on it
+    fn touch(&mut self, key: &str) {
+        self.dirty += 1;
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.version += 1;
+        }
+        // Woken connections check the key once the write is done, as they
+        // need the lock
+        for waker in self.blocked.get(key).into_iter().flatten() {
+            waker.notify_one();
+        }
+    }
+}
+
+/// A connection waiting for writes to some keys, e.g. for XREAD BLOCK.
+/// Dropping it stops the wait.
+#[derive(Debug)]
+pub struct Waiter {
+    db: Db,
+    keys: Vec<String>,
+    waker: Arc<Notify>,
+}
+
+impl Waiter {
+    /// Start waiting for writes to `keys`. Writes from now on are noticed,
+    /// even those before `changed` is called.
+    pub fn new(db: &Db, keys: Vec<String>) -> Waiter {
+        let waker = Arc::new(Notify::new());
+        for key in &keys {
+            db.lock(key).block(key, &waker);
+        }
+        Waiter {
+            db: db.clone(),
+            keys,
+            waker,
+        }
+    }
+
+    /// Wait until one of the keys is written. Writes since the last call
+    /// complete it right away.
+    pub async fn changed(&self) {
+        self.waker.notified().await;
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        for key in &self.keys {
+            self.db.lock(key).unblock(key, &self.waker);
+        }
+    }
+}
+
+/// Shared handle to the server state. Cloning it is cheap.
+#[derive(Debug, Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+}
+
+// Note: a std::sync::Mutex is used to only block the current thread
+// and not the entire set of tokio tasks with tokio::sync::Mutex,
+// as tokio can manage it without generating races.
+#[derive(Debug)]
+struct Shared {
+    // The keyspace split by key hash, so connections working on different
+    // keys rarely wait for each other
+    shards: Box<[Mutex<Keyspace>]>,
+    // Picks the shard of a key
+    hasher: RandomState,
+    // Sum of the memory used by the shards, kept up to date by `Db` methods
+    used_memory: AtomicUsize,
+    pubsub: Mutex<PubSub>,
+    clients: Mutex<Clients>,
+    stats: Stats,
+    config: Config,
+    // A background save is writing the snapshot file
+    saving: AtomicBool,
+    // Set when `config.appendonly` is on
+    aof: Option<Mutex<Aof>>,
+    // A BGREWRITEAOF is writing the new append-only file
+    rewriting: AtomicBool,
+    replication: Replication,
+    // Writes of the EXEC or EVAL running, held back to be propagated as one
+    // MULTI/EXEC block. Only set while every shard is locked.
+    batch: Mutex<Option<Vec<Frame>>>,
+    // Compiled scripts of EVAL and SCRIPT LOAD
+    scripts: Mutex<Scripts>,
+    // Users of AUTH and ACL
+    acl: Mutex<Acl>,
+}
+
+impl Default for Db {
+    fn default() -> Db {
+        Db::new()
+    }
+}
+
+/// Every shard, locked. Index it with `Db::shard_index`.
+pub type Shards<'a> = Vec<MutexGuard<'a, Keyspace>>;
+
+impl Db {
+    /// Create an empty database with the default config, without persistence
+    pub fn new() -> Db {
+        Db::with_keyspace(Config::default(), Keyspace::default(), None)
+    }
+
+    /// Create the database for `config`, loading the persisted keyspace.
+    ///
+    /// With `appendonly`, the append-only file is the source of truth when it
+    /// exists. Otherwise it is created from the snapshot file, if present.
+    pub fn open(config: Config) -> crate::Result<Db> {
+        let keyspace = if config.appendonly {
+            load_aof(&config)?
+        } else {
+            snapshot::load(&config.snapshot_path())?.unwrap_or_default()
+        };
+
+        let aof = if config.appendonly {
+            Some(Aof::open(&config.aof_path(), config.appendfsync)?)
+        } else {
+            None
+        };
+
+        Ok(Db::with_keyspace(config, keyspace, aof))
+    }
+
+    fn with_keyspace(config: Config, keyspace: Keyspace, aof: Option<Aof>) -> Db {
+        let hasher = RandomState::new();
+        let count = config.shards.max(1);
+        let index_of = |key: &str| (hasher.hash_one(key) % count as u64) as usize;
+
+        // Loaded keys are already on disk, so shards start clean
+        let shards: Box<[_]> = keyspace
+            .into_shards(count, index_of)
+            .into_iter()
+            .map(Mutex::new)
+            .collect();
+        let used_memory = shards
+            .iter()
+            .map(|shard| lock_unpoisoned(shard).used_memory())
+            .sum();
+
+        Db {
+            shared: Arc::new(Shared {
+                shards,
+                hasher,
+                used_memory: AtomicUsize::new(used_memory),
+                pubsub: Mutex::default(),
+                clients: Mutex::default(),
+                stats: Stats::default(),
+                saving: AtomicBool::new(false),
+                aof: aof.map(Mutex::new),
+                rewriting: AtomicBool::new(false),
+                replication: Replication::default(),
+                batch: Mutex::default(),
+                scripts: Mutex::default(),
+                acl: Mutex::new(Acl::new(config.requirepass.as_deref())),
+                config,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.shared.config
+    }
+
+    /// Index of the shard holding `key`
+    pub fn shard_index(&self, key: &str) -> usize {
+        (self.shared.hasher.hash_one(key) % self.shared.shards.len() as u64) as usize
+    }
+
+    /// Lock the shard holding `key`. Never hold the guard across an `.await`.
+    /// A panic while a shard was locked does not make it unusable.
+    pub fn lock(&self, key: &str) -> MutexGuard<'_, Keyspace> {
+        lock_unpoisoned(&self.shared.shards[self.shard_index(key)])
+    }
+
+    /// Lock every shard, for operations that must see the whole keyspace at
+    /// once. Shards are always locked in the same order, so concurrent
+    /// callers cannot deadlock.
+    pub fn lock_all(&self) -> Shards<'_> {
+        self.shared.shards.iter().map(lock_unpoisoned).collect()
+    }
+
+    /// Lock the pub/sub channel registry
+    pub fn pubsub(&self) -> MutexGuard<'_, PubSub> {
+        self.shared.pubsub.lock().unwrap()
+    }
+
+    /// Lock the registry of connected clients
+    pub fn clients(&self) -> MutexGuard<'_, Clients> {
+        self.shared.clients.lock().unwrap()
+    }
+
+    /// Forget the closed connection of client `id`, and drop its watches on
+    /// `watched`. Also runs while a panicking connection unwinds, so locks
+    /// poisoned by the panic are taken anyway instead of panicking again.
+    pub(crate) fn release_client(&self, id: u64, watched: impl IntoIterator<Item = String>) {
+        for key in watched {
+            lock_unpoisoned(&self.shared.shards[self.shard_index(&key)]).unwatch(&key);
+        }
+        lock_unpoisoned(&self.shared.clients).unregister(id);
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.shared.stats
+    }
+
+    pub(crate) fn replication(&self) -> &Replication {
+        &self.shared.replication
+    }
+
+    /// Lock the script cache. Never lock shards while holding it.
+    pub fn scripts(&self) -> MutexGuard<'_, Scripts> {
+        self.shared.scripts.lock().unwrap()
+    }
+
+    /// Lock the users. Never lock shards or clients while holding it.
+    pub fn acl(&self) -> MutexGuard<'_, Acl> {
+        self.shared.acl.lock().unwrap()
+    }
+
+    /// Apply `cmd` to the locked shard `ks` holding its key. A successful
+    /// write is logged to the append-only file and sent to the replicas.
+    ///
+    /// It does not enforce `maxmemory`, which needs every shard: prefer
+    /// `execute` or `apply_locked`. Use it instead of `Command::apply` so
+    /// writes are persisted.
+    pub fn apply(&self, ks: &mut Keyspace, cmd: Command) -> Frame {
+        let logged = self.write_frame(&cmd);
+        let key = cmd.key().map(str::to_string);
+        let used_before = ks.used_memory();
+
+        let response = cmd.apply(ks);
+        if let Some(key) = key {
+            ks.record_access(&key);
+        }
+        self.track_memory(used_before, ks.used_memory());
+
+        self.log_write(logged, &response);
+        response
+    }
+
+    /// Frame to log for `cmd`, if it is a write and someone needs it.
+    /// Computed before applying: relative expirations are resolved now.
+    fn write_frame(&self, cmd: &Command) -> Option<Frame> {
+        if self.shared.aof.is_some() || self.shared.replication.has_replicas() {
+            cmd.to_write_frame()
+        } else {
+            None
+        }
+    }
+
+    /// Propagate the `logged` frame of a command that replied `response`,
+    /// unless it failed
+    fn log_write(&self, logged: Option<Frame>, response: &Frame) {
+        if let Some(frame) = logged {
+            if matches!(response, Frame::Error(_)) {
+                return;
+            }
+            if let Some(frame) = cmd::complete_write_frame(frame, response) {
+                self.propagate(&frame);
+            }
+        }
+    }
+
+    /// Check if `cmd` must first free memory, as it may add data while
+    /// memory use is over `maxmemory`
+    fn must_free_memory(&self, cmd: &Command) -> bool {
+        let maxmemory = self.config().maxmemory;
+        // Replicas mirror their primary, which evicts for them
+        cmd.adds_memory() && maxmemory != 0 && self.used_memory() > maxmemory && !self.is_replica()
+    }
+
+    /// Evict keys until memory use is under `maxmemory`, picking the best
+    /// candidate of every shard each time.
+    ///
+    /// Fails with an OOM error if the policy finds nothing to evict.
+    fn free_memory(&self, shards: &mut Shards<'_>) -> crate::Result<()> {
+        let config = self.config();
+        while self.used_memory() > config.maxmemory {
+            let best = shards
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, ks)| {
+                    let candidate =
+                        ks.eviction_candidate(config.maxmemory_policy, config.maxmemory_samples);
+                    candidate.map(|(score, key)| (score, index, key))
+                })
+                .max();
+            let Some((_, index, key)) = best else {
+                return Err(OOM.into());
+            };
+
+            let ks = &mut shards[index];
+            let used_before = ks.used_memory();
+            ks.evict(&key);
+            self.track_memory(used_before, ks.used_memory());
+            self.stats().record_evicted();
+
+            self.propagate(&command_frame([Bytes::from("DEL"), key.into()]));
+        }
+        Ok(())
+    }
+
+    /// Log a write to the append-only file and send it to the replicas.
+    /// Called under the shard lock, so writes to a key reach them in order.
+    fn propagate(&self, frame: &Frame) {
+        if let Some(batch) = &mut *self.shared.batch.lock().unwrap() {
+            batch.push(frame.clone());
+            return;
+        }
+        self.send_write(frame);
+    }
+
+    /// Write `frame` to the append-only file and the replicas right away
+    fn send_write(&self, frame: &Frame) {
+        if let Some(aof) = &self.shared.aof {
+            // The write already happened in memory: report and keep going
+            if let Err(err) = aof.lock().unwrap().append(frame) {
+                error!(cause = %err, "append-only file write failed");
+            }
+        }
+        self.shared.replication.propagate(frame);
+    }
+
+    /// Approximate bytes used by the whole keyspace
+    pub fn used_memory(&self) -> usize {
+        self.shared.used_memory.load(Ordering::SeqCst)
+    }
+
+    /// Account for a shard whose memory use went from `before` to `after`
+    fn track_memory(&self, before: usize, after: usize) {
+        if after >= before {
+            self.shared
+                .used_memory
+                .fetch_add(after - before, Ordering::SeqCst);
+        } else {
+            self.shared
+                .used_memory
+                .fetch_sub(before - after, Ordering::SeqCst);
+        }
+    }
+
+    /// Apply `cmd` with every shard already locked, e.g. inside EXEC
+    pub fn apply_locked(&self, shards: &mut Shards<'_>, cmd: Command) -> Frame {
+        if self.must_free_memory(&cmd) {
+            if let Err(err) = self.free_memory(shards) {
+                return Frame::Error(err.to_string());
+            }
+        }
+        if !cmd.spans_shards() {
+            let index = cmd.key().map_or(0, |key| self.shard_index(key));
+            return self.apply(&mut shards[index], cmd);
+        }
+
+        let logged = self.write_frame(&cmd);
+        let used = |shards: &Shards<'_>| shards.iter().map(|ks| ks.used_memory()).sum();
+        let used_before = used(shards);
+
+        let response = cmd.apply_sharded(shards, |key| self.shard_index(key));
+        self.track_memory(used_before, used(shards));
+
+        self.log_write(logged, &response);
+        response
+    }
+
+    /// Run `f` with every shard already locked, propagating the writes it
+    /// makes as one MULTI/EXEC block, so replicas and the append-only file
+    /// apply all of them or none. A block run inside another joins it.
+    pub fn atomically<'a, T>(
+        &self,
+        shards: &mut Shards<'a>,
+        f: impl FnOnce(&mut Shards<'a>) -> T,
+    ) -> T {
+        if self.shared.batch.lock().unwrap().is_some() {
+            return f(shards);
+        }
+
+        *self.shared.batch.lock().unwrap() = Some(Vec::new());
+        let result = f(shards);
+        let writes = self.shared.batch.lock().unwrap().take().unwrap_or_default();
+
+        // A single write is atomic on its own
+        if writes.len() > 1 {
+            self.send_write(&command_frame([Bytes::from("MULTI")]));
+        }
+        for frame in &writes {
+            self.send_write(frame);
+        }
+        if writes.len() > 1 {
+            self.send_write(&command_frame([Bytes::from("EXEC")]));
+        }
+        result
+    }
+
+    /// Apply `cmds` as one transaction, like a primary's MULTI/EXEC block:
+    /// no other connection sees part of it
+    pub fn execute_all(&self, cmds: Vec<Command>) -> Vec<Frame> {
+        let mut shards = self.lock_all();
+        self.atomically(&mut shards, |shards| {
+            cmds.into_iter()
+                .map(|cmd| self.apply_locked(shards, cmd))
+                .collect()
+        })
+    }
+
+    /// Lock the shard of the command's key, or every shard for commands
+    /// spanning several keys, apply it and release the lock
+    pub fn execute(&self, cmd: Command) -> Frame {
+        if cmd.spans_shards() {
+            return self.apply_locked(&mut self.lock_all(), cmd);
+        }
+
+        // Only writes over the limit pay for locking every shard. Nothing
+        // else is locked yet, so the lock order holds.
+        if self.must_free_memory(&cmd) {
+            if let Err(err) = self.free_memory(&mut self.lock_all()) {
+                return Frame::Error(err.to_string());
+            }
+        }
+
+        // Commands without a key only produce errors: any shard does
+        let index = cmd.key().map_or(0, |key| self.shard_index(key));
+        let mut ks = lock_unpoisoned(&self.shared.shards[index]);
+        self.apply(&mut ks, cmd)
+    }
+
+    /// Apply a blocking command waiting with `waiter` if it has something to
+    /// reply, see `Command::is_ready`, or give it back
+    pub fn apply_if_ready(&self, mut cmd: Command, waiter: &Waiter) -> Result<Frame, Command> {
+        let mut shards = self.lock_all();
+        if !cmd.is_ready(&shards, |key| self.shard_index(key), &waiter.waker) {
+            return Err(cmd);
+        }
+        Ok(self.apply_locked(&mut shards, cmd))
+    }
+
+    /// Replace the whole keyspace, e.g. with the one received from a primary.
+    ///
+    /// Our own replicas are disconnected so they sync again from the new
+    /// keyspace.
+    pub fn replace(&self, keyspace: Keyspace) {
+        let mut shards = self.lock_all();
+        let loaded = keyspace.into_shards(shards.len(), |key| self.shard_index(key));
+        for (shard, loaded) in shards.iter_mut().zip(loaded) {
+            let used_before = shard.used_memory();
+            shard.replace(loaded);
+            self.track_memory(used_before, shard.used_memory());
+        }
+        self.shared.replication.disconnect_replicas();
+        drop(shards);
+
+        // The log must now rebuild the new keyspace
+        if self.shared.aof.is_some() {
+            if let Err(err) = self.bgrewriteaof() {
+                error!(cause = %err, "append-only file rewrite after sync failed");
+            }
+        }
+    }
+
+    /// Snapshot the keyspace for a new replica, and register the replica to
+    /// receive every write applied after the snapshot.
+    pub fn sync_replica(&self) -> (Vec<u8>, mpsc::Receiver<Frame>) {
+        // With every shard locked, no write can fall between the two
+        let shards = self.lock_all();
+        let snapshot = snapshot::encode(shards.iter().map(|ks| &**ks));
+        (snapshot, self.shared.replication.add_replica())
+    }
+
+    /// REPLICAOF: follow the primary at `host:port`, or stop following one
+    /// with `None`.
+    pub fn replicaof(&self, primary: Option<(String, u16)>) {
+        self.shared.replication.set_primary(self, primary);
+    }
+
+    /// Check if the server is a read-only replica
+    pub fn is_replica(&self) -> bool {
+        self.shared.replication.is_replica()
+    }
+
+    /// Number of writes since the last snapshot, over all shards
+    pub fn dirty(&self) -> u64 {
+        self.shared
+            .shards
+            .iter()
+            .map(|shard| lock_unpoisoned(shard).dirty())
+            .sum()
+    }
+
+    /// Remove expired keys, locking one shard at a time.
+    ///
+    /// Returns the number of removed keys.
+    pub fn purge_expired(&self, now: Instant) -> usize {
+        let purged = self
+            .shared
+            .shards
+            .iter()
+            .map(|shard| {
+                let mut ks = lock_unpoisoned(shard);
+                let used_before = ks.used_memory();
+                let purged = ks.purge_expired(now);
+                self.track_memory(used_before, ks.used_memory());
+                purged
+            })
+            .sum();
+        self.shared.stats.record_expired(purged as u64);
+        purged
+    }
+
+    /// Flush the append-only file to disk, for `appendfsync everysec`.
+    ///
+    /// The fsync runs on a duplicated handle, so writers are not blocked.
+    pub fn sync_aof(&self) -> crate::Result<()> {
+        if let Some(aof) = &self.shared.aof {
+            let file = aof.lock().unwrap().file()?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// BGREWRITEAOF: replace the append-only file with the shortest log that
+    /// rebuilds the current keyspace.
+    ///
+    /// The new log is generated under the lock and written in the background.
+    /// Writes received meanwhile go to both the old file and a buffer, which
+    /// is appended to the new file right before it replaces the old one.
+    pub fn bgrewriteaof(&self) -> crate::Result<()> {
+        let Some(aof) = &self.shared.aof else {
+            return Err("ERR append only file is disabled".into());
+        };
+        if self.shared.rewriting.swap(true, Ordering::SeqCst) {
+            return Err("ERR Background append only file rewriting already in progress".into());
+        }
+
+        let data = {
+            let shards = self.lock_all();
+            aof.lock().unwrap().start_rewrite();
+            aof::rewrite(shards.iter().map(|ks| &**ks))
+        };
+
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let aof = db.shared.aof.as_ref().unwrap();
+            let tmp = db.config().aof_path().with_extension("rewrite");
+
+            let result =
+                write_synced(&tmp, &data).and_then(|()| aof.lock().unwrap().finish_rewrite(&tmp));
+            if let Err(err) = result {
+                error!(cause = %err, "append-only file rewrite failed");
+                aof.lock().unwrap().abort_rewrite();
+                let _ = fs::remove_file(&tmp);
+            }
+            db.shared.rewriting.store(false, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+
+    /// Serialize the keyspace under the lock and reset the dirty counters
+    fn dump(&self) -> Vec<u8> {
+        let mut shards = self.lock_all();
+        for ks in shards.iter_mut() {
+            ks.dirty = 0;
+        }
+        snapshot::encode(shards.iter().map(|ks| &**ks))
+    }
+
+    /// SAVE: write the snapshot file, blocking until done
+    pub fn save(&self) -> crate::Result<()> {
+        if self.shared.saving.swap(true, Ordering::SeqCst) {
+            return Err("ERR Background save already in progress".into());
+        }
+
+        let data = self.dump();
+        let result = snapshot::write(&self.config().snapshot_path(), &data);
+        self.shared.saving.store(false, Ordering::SeqCst);
+        result.map_err(|err| format!("ERR {}", err).into())
+    }
+
+    /// Flush persistence before exiting: wait for background saves and
+    /// rewrites, fsync the append-only file, and write a last snapshot if
+    /// snapshots are enabled.
+    pub async fn shutdown(&self) -> crate::Result<()> {
+        while self.shared.saving.load(Ordering::SeqCst)
+            || self.shared.rewriting.load(Ordering::SeqCst)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        self.sync_aof()?;
+        if self.config().save_interval.is_some() {
+            self.save()?;
+            info!(path = %self.config().snapshot_path().display(), "snapshot saved");
+        }
+        Ok(())
+    }
+
+    /// BGSAVE: serialize the keyspace now and write the file in the background.
+    ///
+    /// Only the serialization happens under the lock; the (slow) disk write
+    /// runs on the blocking thread pool.
+    pub fn bgsave(&self) -> crate::Result<()> {
+        if self.shared.saving.swap(true, Ordering::SeqCst) {
+            return Err("ERR Background save already in progress".into());
+        }
+
+        let data = self.dump();
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = snapshot::write(&db.config().snapshot_path(), &data) {
+                error!(cause = %err, "background save failed");
+            }
+            db.shared.saving.store(false, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+}
+
+/// Lock `mutex` even if a thread panicked while holding it
+fn lock_unpoisoned<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Rebuild the keyspace from the append-only file, creating the file from
+/// the snapshot on the first start with `appendonly` on.
+fn load_aof(config: &Config) -> crate::Result<Keyspace> {
+    let path = config.aof_path();
+    if !path.exists() {
+        let ks = snapshot::load(&config.snapshot_path())?.unwrap_or_default();
+        write_synced(&path, &aof::rewrite([&ks]))?;
+        return Ok(ks);
+    }
+
+    let mut ks = Keyspace::default();
+    let valid = aof::replay(&path, &mut ks)?;
+    // Drop a half-written command so new ones are appended after valid data
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)?
+        .set_len(valid)?;
+    Ok(ks)
+}
+
+/// Write `data` to `path` and flush it to disk
+fn write_synced(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(data)?;
+    file.sync_data()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn shards_stay_usable_after_a_panic() {
+        let db = Db::new();
+        let poisoner = db.clone();
+        let panicked = std::thread::spawn(move || {
+            let _ks = poisoner.lock("key");
+            panic!("while holding the shard");
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        db.lock("key")
+            .insert("key".into(), Value::String("v".into()));
+        assert!(db.lock_all()[db.shard_index("key")].get("key").is_some());
+    }
+
+    #[test]
+    fn loaded_keys_land_in_their_shard() {
+        let mut ks = Keyspace::default();
+        for i in 0..100 {
+            ks.insert(format!("key:{}", i), Value::String("v".into()));
+        }
+        ks.set_expiry("key:7", Some(Instant::now() + Duration::from_secs(60)));
+
+        let db = Db::with_keyspace(Config::default(), ks, None);
+        let shards = db.lock_all();
+        assert_eq!(shards.len(), Config::default().shards);
+        assert_eq!(shards.iter().map(|ks| ks.len()).sum::<usize>(), 100);
+        assert!(shards.iter().filter(|ks| !ks.is_empty()).count() > 1);
+
+        for i in 0..100 {
+            let key = format!("key:{}", i);
+            assert!(shards[db.shard_index(&key)].get(&key).is_some());
+        }
+        assert!(shards[db.shard_index("key:7")]
+            .expires_at("key:7")
+            .is_some());
+    }
+
+    #[test]
+    fn purge_and_dirty_cover_every_shard() {
+        let db = Db::new();
+        let past = Instant::now();
+        for i in 0..50 {
+            let key = format!("key:{}", i);
+            let mut ks = db.lock(&key);
+            ks.insert(key.clone(), Value::String("v".into()));
+            ks.set_expiry(&key, Some(past));
+        }
+
+        assert_eq!(db.dirty(), 100);
+        assert_eq!(db.purge_expired(Instant::now()), 50);
+        assert!(db.lock_all().iter().all(|ks| ks.is_empty()));
+    }
+
+    #[test]
+    fn used_memory_follows_every_change() {
+        let db = Db::new();
+        let shard_total = |db: &Db| {
+            db.lock_all()
+                .iter()
+                .map(|ks| ks.used_memory())
+                .sum::<usize>()
+        };
+
+        for i in 0..20 {
+            let cmd = Command::from_frame(command_frame([
+                Bytes::from("SET"),
+                Bytes::from(format!("key:{}", i)),
+                Bytes::from("value"),
+            ]));
+            db.execute(cmd.unwrap());
+        }
+        let used = db.used_memory();
+        assert!(used > 0);
+        assert_eq!(used, shard_total(&db));
+
+        // Growing a value grows the count
+        let cmd = command_frame([Bytes::from("SET"), "key:0".into(), "a longer value".into()]);
+        db.execute(Command::from_frame(cmd).unwrap());
+        assert_eq!(
+            db.used_memory(),
+            used + "a longer value".len() - "value".len()
+        );
+
+        let past = Bytes::from_static(b"0");
+        for i in 0..20 {
+            let key = Bytes::from(format!("key:{}", i));
+            let cmd = command_frame([Bytes::from("PEXPIREAT"), key, past.clone()]);
+            db.execute(Command::from_frame(cmd).unwrap());
+        }
+        assert_eq!(db.used_memory(), 0);
+        assert_eq!(shard_total(&db), 0);
+    }
+}
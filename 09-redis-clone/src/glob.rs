@@ -0,0 +1,143 @@
+/// Match `text` against a Redis-style glob `pattern`.
+///
+/// Supported syntax:
+/// - `*` matches any sequence of bytes (including none)
+/// - `?` matches exactly one byte
+/// - `[abc]`, `[a-z]` and `[^abc]` match one byte in (or not in) the class
+/// - `\x` matches `x` literally
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+
+    // Position to resume from when a match fails after a `*`:
+    // (pattern index after the star, text index the star is consuming up to)
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    backtrack = Some((p + 1, t));
+                    p += 1;
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                b'[' => {
+                    let (matched, next) = match_class(pattern, p, text[t]);
+                    if matched {
+                        p = next;
+                        t += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == text[t] {
+                        p += 2;
+                        t += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == text[t] {
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch: let the last star swallow one more byte, or fail
+        match backtrack {
+            Some((star_p, star_t)) => {
+                p = star_p;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+
+    // Only stars may be left in the pattern
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Match `c` against the class starting at `pattern[start] == b'['`.
+///
+/// Returns whether it matched and the index right after the closing `]`.
+/// An unterminated class extends to the end of the pattern, like in Redis.
+fn match_class(pattern: &[u8], start: usize, c: u8) -> (bool, usize) {
+    let mut i = start + 1;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != b']' {
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            // Ranges may be given in any order: [z-a] == [a-z]
+            let (lo, hi) = (
+                pattern[i].min(pattern[i + 2]),
+                pattern[i].max(pattern[i + 2]),
+            );
+            matched |= (lo..=hi).contains(&c);
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+
+    // Skip the closing bracket if there is one
+    (matched != negate, (i + 1).min(pattern.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        glob_match(pattern.as_bytes(), text.as_bytes())
+    }
+
+    #[test]
+    fn literals_and_wildcards() {
+        assert!(matches("news", "news"));
+        assert!(!matches("news", "news.tech"));
+        assert!(matches("news.*", "news.tech"));
+        assert!(matches("news.*", "news."));
+        assert!(matches("*", ""));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("*a*b*c", "xxaxxbxxbxc"));
+        assert!(!matches("*a*b*c", "xxaxxbxx"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(matches("h[b-a]llo", "hallo"));
+        assert!(!matches("h[a-b]llo", "hcllo"));
+        assert!(matches("key:[0-9]*", "key:42"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(r"h\*llo", "h*llo"));
+        assert!(!matches(r"h\*llo", "hello"));
+        assert!(matches(r"h[\]]llo", "h]llo"));
+    }
+}
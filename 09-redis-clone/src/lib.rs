@@ -1 +1,33 @@
 mod connection;
+
+pub mod acl;
+pub mod aof;
+pub mod client;
+pub mod clients;
+pub mod cmd;
+mod config;
+pub mod db;
+pub mod glob;
+pub mod list;
+mod parse;
+pub mod pubsub;
+pub mod replication;
+pub mod script;
+pub mod server;
+mod shutdown;
+pub mod snapshot;
+pub mod sorted_set;
+pub mod stats;
+pub mod stream;
+
+pub use client::Client;
+pub use cmd::Command;
+pub use config::{AppendFsync, Config, EvictionPolicy};
+pub use connection::Connection;
+pub use db::Db;
+
+/// Error type shared by the library (same shape as mini-redis).
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Result alias for library operations.
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+/// Approximate bookkeeping bytes of an element, besides its own bytes
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// End of a list that elements are pushed to or popped from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+/// A list of binary strings, cheap to change at both ends
+#[derive(Debug, Default)]
+pub struct List {
+    items: VecDeque<Bytes>,
+    // Total length of the elements, for memory accounting
+    bytes: usize,
+}
+
+impl List {
+    pub fn new() -> List {
+        List::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Rough number of bytes used by the list
+    pub fn approx_size(&self) -> usize {
+        self.bytes + self.len() * ELEMENT_OVERHEAD
+    }
+
+    pub fn push(&mut self, end: End, value: Bytes) {
+        self.bytes += value.len();
+        match end {
+            End::Left => self.items.push_front(value),
+            End::Right => self.items.push_back(value),
+        }
+    }
+
+    pub fn pop(&mut self, end: End) -> Option<Bytes> {
+        let value = match end {
+            End::Left => self.items.pop_front(),
+            End::Right => self.items.pop_back(),
+        }?;
+        self.bytes -= value.len();
+        Some(value)
+    }
+
+    /// Elements from `start` to `stop` included. Negative indexes count
+    /// from the end, like in Redis.
+    pub fn range(&self, start: i64, stop: i64) -> impl Iterator<Item = &Bytes> {
+        let len = self.len() as i64;
+        let index = |i: i64| if i < 0 { len + i } else { i };
+        let start = index(start).max(0);
+        let stop = index(stop).min(len - 1);
+        let count = if start > stop { 0 } else { stop - start + 1 };
+        self.items.iter().skip(start as usize).take(count as usize)
+    }
+
+    /// Every element, from left to right
+    pub fn iter(&self) -> impl Iterator<Item = &Bytes> {
+        self.items.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: &[&str]) -> List {
+        let mut list = List::new();
+        for item in items {
+            list.push(End::Right, Bytes::from(item.to_string()));
+        }
+        list
+    }
+
+    fn range(list: &List, start: i64, stop: i64) -> Vec<&[u8]> {
+        list.range(start, stop).map(|item| &item[..]).collect()
+    }
+
+    #[test]
+    fn push_and_pop_at_both_ends() {
+        let mut list = list(&["b"]);
+        list.push(End::Left, "a".into());
+        list.push(End::Right, "c".into());
+        assert_eq!(range(&list, 0, -1), [b"a", b"b", b"c"]);
+        assert_eq!(list.approx_size(), 3 + 3 * ELEMENT_OVERHEAD);
+
+        assert_eq!(list.pop(End::Right), Some("c".into()));
+        assert_eq!(list.pop(End::Left), Some("a".into()));
+        assert_eq!(list.pop(End::Left), Some("b".into()));
+        assert_eq!(list.pop(End::Left), None);
+        assert!(list.is_empty());
+        assert_eq!(list.approx_size(), 0);
+    }
+
+    #[test]
+    fn ranges_accept_negative_indexes() {
+        let list = list(&["a", "b", "c", "d"]);
+        assert_eq!(range(&list, 1, 2), [b"b", b"c"]);
+        assert_eq!(range(&list, -2, -1), [b"c", b"d"]);
+        assert_eq!(range(&list, -100, 0), [b"a"]);
+        assert_eq!(range(&list, 2, 100), [b"c", b"d"]);
+        assert!(range(&list, 3, 1).is_empty());
+        assert!(range(&list, 5, 10).is_empty());
+        assert!(range(&List::new(), 0, -1).is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+use std::vec;
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+/// Cursor over the arguments of a command frame.
+///
+/// Commands are sent as an array of bulk strings; each `next_*` call consumes
+/// one entry and converts it into the requested type.
+pub(crate) struct Parse {
+    parts: vec::IntoIter<Frame>,
+}
+
+impl Parse {
+    /// Create a new `Parse` from a command frame. Only arrays are accepted.
+    pub(crate) fn new(frame: Frame) -> crate::Result<Parse> {
+        match frame {
+            Frame::Array(parts) => Ok(Parse {
+                parts: parts.into_iter(),
+            }),
+            frame => Err(format!("ERR protocol error; expected array, got {:?}", frame).into()),
+        }
+    }
+
+    /// Number of arguments not consumed yet
+    pub(crate) fn remaining(&self) -> usize {
+        self.parts.len()
+    }
+
+    fn next(&mut self) -> crate::Result<Frame> {
+        self.parts
+            .next()
+            .ok_or_else(|| "ERR wrong number of arguments".into())
+    }
+
+    /// Return the next entry as raw bytes
+    pub(crate) fn next_bytes(&mut self) -> crate::Result<Bytes> {
+        match self.next()? {
+            Frame::Simple(s) => Ok(Bytes::from(s.into_bytes())),
+            Frame::Bulk(data) => Ok(data),
+            frame => Err(format!("ERR protocol error; expected bulk, got {:?}", frame).into()),
+        }
+    }
+
+    /// Return the next entry as a UTF-8 string
+    pub(crate) fn next_string(&mut self) -> crate::Result<String> {
+        let data = self.next_bytes()?;
+        String::from_utf8(data.to_vec()).map_err(|_| "ERR invalid UTF-8 string".into())
+    }
+
+    /// Return the next entry as a signed integer
+    pub(crate) fn next_int(&mut self) -> crate::Result<i64> {
+        const MSG: &str = "ERR value is not an integer or out of range";
+
+        match self.next()? {
+            Frame::Integer(v) => i64::try_from(v).map_err(|_| MSG.into()),
+            Frame::Simple(s) => s.parse().map_err(|_| MSG.into()),
+            Frame::Bulk(data) => std::str::from_utf8(&data)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| MSG.into()),
+            _ => Err(MSG.into()),
+        }
+    }
+
+    /// Return the next entry as a float. NaN is rejected.
+    pub(crate) fn next_float(&mut self) -> crate::Result<f64> {
+        let data = self.next_string()?;
+        parse_float(&data).ok_or_else(|| "ERR value is not a valid float".into())
+    }
+
+    /// Ensure every argument was consumed
+    pub(crate) fn finish(&mut self) -> crate::Result<()> {
+        if self.parts.next().is_none() {
+            Ok(())
+        } else {
+            Err("ERR syntax error".into())
+        }
+    }
+}
+
+/// Parse a float the way Redis does (accepting `inf`, `+inf` and `-inf`)
+pub(crate) fn parse_float(src: &str) -> Option<f64> {
+    match src.parse::<f64>() {
+        Ok(value) if !value.is_nan() => Some(value),
+        _ => None,
+    }
+}
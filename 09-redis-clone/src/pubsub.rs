@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use crate::glob::glob_match;
+
+/// Messages a slow subscriber can fall behind before dropping some
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Registry of the broadcast channels used by PUBLISH/SUBSCRIBE.
+///
+/// There is one `broadcast` channel per channel name and one per pattern.
+/// Pattern channels also carry the name of the channel the message was
+/// published to, as it is part of the `pmessage` reply.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: HashMap<String, broadcast::Sender<Bytes>>,
+    patterns: HashMap<String, broadcast::Sender<(String, Bytes)>>,
+}
+
+impl PubSub {
+    /// Subscribe to messages published on `channel`
+    pub fn subscribe(&mut self, channel: &str) -> broadcast::Receiver<Bytes> {
+        match self.channels.get(channel) {
+            Some(tx) => tx.subscribe(),
+            None => {
+                let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
+                self.channels.insert(channel.to_string(), tx);
+                rx
+            }
+        }
+    }
+
+    /// Subscribe to messages published on any channel matching `pattern`
+    pub fn psubscribe(&mut self, pattern: &str) -> broadcast::Receiver<(String, Bytes)> {
+        match self.patterns.get(pattern) {
+            Some(tx) => tx.subscribe(),
+            None => {
+                let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
+                self.patterns.insert(pattern.to_string(), tx);
+                rx
+            }
+        }
+    }
+
+    /// Forget `channel` if its last subscriber is gone. Called once a
+    /// connection dropped its receiver.
+    pub fn unsubscribe(&mut self, channel: &str) {
+        if self
+            .channels
+            .get(channel)
+            .is_some_and(|tx| tx.receiver_count() == 0)
+        {
+            self.channels.remove(channel);
+        }
+    }
+
+    /// Forget `pattern` if its last subscriber is gone
+    pub fn punsubscribe(&mut self, pattern: &str) {
+        if self
+            .patterns
+            .get(pattern)
+            .is_some_and(|tx| tx.receiver_count() == 0)
+        {
+            self.patterns.remove(pattern);
+        }
+    }
+
+    /// Publish `message` on `channel`.
+    ///
+    /// Returns the number of subscribers (direct and by pattern) that got it.
+    pub fn publish(&mut self, channel: &str, message: Bytes) -> usize {
+        let mut receivers = 0;
+
+        if let Some(tx) = self.channels.get(channel) {
+            match tx.send(message.clone()) {
+                Ok(n) => receivers += n,
+                // Every subscriber is gone, forget the channel
+                Err(_) => {
+                    self.channels.remove(channel);
+                }
+            }
+        }
+
+        let mut dead = vec![];
+        for (pattern, tx) in &self.patterns {
+            if !glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                continue;
+            }
+            match tx.send((channel.to_string(), message.clone())) {
+                Ok(n) => receivers += n,
+                Err(_) => dead.push(pattern.clone()),
+            }
+        }
+        for pattern in dead {
+            self.patterns.remove(&pattern);
+        }
+
+        receivers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_unsubscribe_forgets_the_channel() {
+        let mut pubsub = PubSub::default();
+        let first = pubsub.subscribe("news");
+        let second = pubsub.subscribe("news");
+        let pattern = pubsub.psubscribe("news.*");
+
+        drop(first);
+        pubsub.unsubscribe("news");
+        assert!(pubsub.channels.contains_key("news"));
+
+        drop(second);
+        pubsub.unsubscribe("news");
+        assert!(pubsub.channels.is_empty());
+
+        drop(pattern);
+        pubsub.punsubscribe("news.*");
+        assert!(pubsub.patterns.is_empty());
+    }
+}
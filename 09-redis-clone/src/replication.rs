@@ -0,0 +1,230 @@
+//! Primary/replica replication.
+//!
+//! A replica connects to its primary and sends SYNC. The primary replies
+//! with a snapshot of its keyspace as a bulk string, then streams every
+//! write command it applies, as they would be logged to the append-only
+//! file. Replicas reject writes from their own clients. With `masterauth`,
+//! the replica sends AUTH before SYNC.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::{net::TcpStream, sync::mpsc, task::JoinHandle, time};
+use tracing::{info, warn};
+
+use crate::{cmd::command_frame, snapshot, Command, Connection, Db};
+
+/// Error returned to clients writing to a replica
+pub const READONLY: &str = "READONLY You can't write against a read only replica.";
+
+/// Pause before reconnecting to an unreachable primary
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Write commands queued for a replica that doesn't keep up, at most. Past
+/// that, it is dropped and syncs again, like Redis's
+/// `client-output-buffer-limit replica`.
+const REPLICA_BACKLOG: usize = 10_000;
+
+/// Replication state of a server: the replicas it feeds and the primary it
+/// follows, if any
+#[derive(Debug, Default)]
+pub(crate) struct Replication {
+    replicas: Mutex<Vec<mpsc::Sender<Frame>>>,
+    // Checked on every write, to skip building frames nobody reads
+    has_replicas: AtomicBool,
+    primary: Mutex<Option<Primary>>,
+    is_replica: AtomicBool,
+}
+
+/// Link to the primary being followed. Dropping it stops following.
+#[derive(Debug)]
+struct Primary {
+    host: String,
+    port: u16,
+    // Set while synchronized with the primary
+    connected: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Primary {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Replication {
+    pub(crate) fn has_replicas(&self) -> bool {
+        self.has_replicas.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn replica_count(&self) -> usize {
+        self.replicas.lock().unwrap().len()
+    }
+
+    /// Register a replica and return the feed of write commands it must
+    /// apply. Call it with every shard locked, right after taking the
+    /// snapshot sent to the replica, so it gets every later write once.
+    pub(crate) fn add_replica(&self) -> mpsc::Receiver<Frame> {
+        let (tx, rx) = mpsc::channel(REPLICA_BACKLOG);
+        self.replicas.lock().unwrap().push(tx);
+        self.has_replicas.store(true, Ordering::SeqCst);
+        rx
+    }
+
+    /// Send a write command to every replica, forgetting disconnected ones
+    /// and those too far behind
+    pub(crate) fn propagate(&self, frame: &Frame) {
+        let mut replicas = self.replicas.lock().unwrap();
+        replicas.retain(|tx| match tx.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                warn!(backlog = REPLICA_BACKLOG, "replica too slow, dropping it");
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        });
+        self.has_replicas
+            .store(!replicas.is_empty(), Ordering::SeqCst);
+    }
+
+    /// Close the feeds of every replica, so they sync again from scratch
+    pub(crate) fn disconnect_replicas(&self) {
+        self.replicas.lock().unwrap().clear();
+        self.has_replicas.store(false, Ordering::SeqCst);
+    }
+
+    /// Check if this server follows a primary, and must reject writes
+    pub(crate) fn is_replica(&self) -> bool {
+        self.is_replica.load(Ordering::SeqCst)
+    }
+
+    /// `(host, port, synchronized)` of the primary being followed
+    pub(crate) fn primary(&self) -> Option<(String, u16, bool)> {
+        let primary = self.primary.lock().unwrap();
+        primary.as_ref().map(|primary| {
+            let connected = primary.connected.load(Ordering::SeqCst);
+            (primary.host.clone(), primary.port, connected)
+        })
+    }
+
+    /// Follow the primary at `host:port` from now on, or stop following
+    /// with `None`. The keyspace is kept either way until the first sync.
+    pub(crate) fn set_primary(&self, db: &Db, target: Option<(String, u16)>) {
+        let primary = target.map(|(host, port)| {
+            let connected = Arc::new(AtomicBool::new(false));
+            let task = tokio::spawn(follow(db.clone(), host.clone(), port, connected.clone()));
+            Primary {
+                host,
+                port,
+                connected,
+                task,
+            }
+        });
+
+        self.is_replica.store(primary.is_some(), Ordering::SeqCst);
+        // Dropping the previous link stops its task
+        *self.primary.lock().unwrap() = primary;
+    }
+}
+
+/// Replicate the primary at `host:port` until the task is aborted,
+/// reconnecting and syncing again whenever the link breaks.
+async fn follow(db: Db, host: String, port: u16, connected: Arc<AtomicBool>) {
+    loop {
+        if let Err(err) = sync_from(&db, &host, port, &connected).await {
+            warn!(%host, port, cause = %err, "replication link lost");
+        }
+        connected.store(false, Ordering::SeqCst);
+        time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+/// Full sync from the primary, then apply its write commands as they come
+async fn sync_from(db: &Db, host: &str, port: u16, connected: &AtomicBool) -> crate::Result<()> {
+    let socket = TcpStream::connect((host, port)).await?;
+    let mut connection = Connection::new(socket);
+    if let Some(password) = &db.config().masterauth {
+        let auth = command_frame([Bytes::from("AUTH"), Bytes::from(password.clone())]);
+        connection.write_frame(&auth).await?;
+        match connection.read_frame().await? {
+            Some(Frame::Simple(_)) => {}
+            Some(Frame::Error(err)) => return Err(err.into()),
+            _ => return Err("unexpected reply to AUTH".into()),
+        }
+    }
+    connection
+        .write_frame(&command_frame([Bytes::from("SYNC")]))
+        .await?;
+
+    let data = match connection.read_frame().await? {
+        Some(Frame::Bulk(data)) => data,
+        Some(Frame::Error(err)) => return Err(err.into()),
+        Some(frame) => return Err(format!("unexpected reply to SYNC: {}", frame).into()),
+        None => return Err("connection closed by primary".into()),
+    };
+    let keyspace = snapshot::decode(&data)?;
+    info!(%host, port, keys = keyspace.len(), "synchronized with primary");
+    db.replace(keyspace);
+    connected.store(true, Ordering::SeqCst);
+
+    // Commands of a MULTI/EXEC block, applied together once EXEC arrives
+    let mut block: Option<Vec<Command>> = None;
+    while let Some(frame) = connection.read_frame().await? {
+        // Applied like client commands, so they reach the append-only file
+        // and our own replicas
+        let replies = match (Command::from_frame(frame)?, &mut block) {
+            (Command::Multi, _) => {
+                block = Some(Vec::new());
+                continue;
+            }
+            (Command::Exec, Some(_)) => db.execute_all(block.take().unwrap_or_default()),
+            (cmd, Some(cmds)) => {
+                cmds.push(cmd);
+                continue;
+            }
+            (cmd, None) => vec![db.execute(cmd)],
+        };
+        for reply in replies {
+            if let Frame::Error(err) = reply {
+                warn!(cause = %err, "replicated command failed");
+            }
+        }
+    }
+    Err("connection closed by primary".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_replicas_are_dropped() {
+        let replication = Replication::default();
+        let mut writes = replication.add_replica();
+        let frame = command_frame([Bytes::from("DEL"), Bytes::from("key")]);
+
+        for _ in 0..REPLICA_BACKLOG {
+            replication.propagate(&frame);
+        }
+        assert_eq!(replication.replica_count(), 1);
+        replication.propagate(&frame);
+        assert_eq!(replication.replica_count(), 0);
+        assert!(!replication.has_replicas());
+
+        // What was queued is still sent, then the feed ends
+        for _ in 0..REPLICA_BACKLOG {
+            assert!(writes.try_recv().is_ok());
+        }
+        assert!(matches!(
+            writes.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        ));
+    }
+}
@@ -0,0 +1,575 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use crate::script::parser::{BinaryOp, Expr, ExprKind, Stmt, StmtKind};
+
+/// Longest string a script may build
+const MAX_STRING_LEN: usize = 64 * 1024 * 1024;
+
+/// Bytes of string built per step charged
+const BYTES_PER_STEP: usize = 1024;
+
+/// Deepest list nesting converted to a reply. Lists may contain themselves.
+const MAX_REPLY_DEPTH: usize = 64;
+
+/// Value of a script variable. Lists are shared, like Lua tables: pushing
+/// to a list is seen through every variable holding it.
+#[derive(Debug, Clone)]
+pub(crate) enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Str(Bytes),
+    List(Rc<RefCell<Vec<Value>>>),
+}
+
+impl Value {
+    /// `nil`, `false`, 0, the empty string and the empty list are false
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(value) => *value,
+            Value::Int(value) => *value != 0,
+            Value::Str(value) => !value.is_empty(),
+            Value::List(items) => !items.borrow().is_empty(),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+        }
+    }
+
+    /// Lists are only equal to themselves
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Convert the returned value into the reply of the script. `true` is 1
+    /// and `false` is nil, like for Lua scripts. Replies cannot hold negative
+    /// integers, which are sent as strings.
+    fn into_frame(self, depth: usize) -> std::result::Result<Frame, String> {
+        if depth > MAX_REPLY_DEPTH {
+            return Err("returned value is too deeply nested".to_string());
+        }
+        let frame = match self {
+            Value::Nil | Value::Bool(false) => Frame::Null,
+            Value::Bool(true) => Frame::Integer(1),
+            Value::Int(value) => match u64::try_from(value) {
+                Ok(value) => Frame::Integer(value),
+                Err(_) => Frame::Bulk(Bytes::from(value.to_string())),
+            },
+            Value::Str(value) => Frame::Bulk(value),
+            Value::List(items) => {
+                let items = items.borrow().clone();
+                let frames = items
+                    .into_iter()
+                    .map(|item| item.into_frame(depth + 1))
+                    .collect::<std::result::Result<_, _>>()?;
+                Frame::Array(frames)
+            }
+        };
+        Ok(frame)
+    }
+
+    /// Argument of `call`
+    fn to_arg(&self) -> Option<Bytes> {
+        match self {
+            Value::Int(value) => Some(Bytes::from(value.to_string())),
+            Value::Str(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Why the script stopped early
+pub(crate) enum Failure {
+    /// An error of the script itself, with its line
+    Error(usize, String),
+    /// An error reply of a command run with `call`, returned as is
+    Reply(String),
+}
+
+/// What running a block leads to
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+type Result<T> = std::result::Result<T, Failure>;
+
+/// Runs a script. `call` runs a command given as its arguments.
+pub(crate) struct Interpreter<'a> {
+    // Innermost scope last
+    scopes: Vec<HashMap<String, Value>>,
+    steps: u64,
+    max_steps: u64,
+    call: &'a mut dyn FnMut(Vec<Bytes>) -> Frame,
+    // Every list created, emptied once done as lists may contain themselves
+    lists: Vec<Rc<RefCell<Vec<Value>>>>,
+}
+
+impl Drop for Interpreter<'_> {
+    fn drop(&mut self) {
+        // Break reference cycles, which would leak
+        for list in &self.lists {
+            list.borrow_mut().clear();
+        }
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub(crate) fn new(
+        keys: &[Bytes],
+        args: &[Bytes],
+        max_steps: u64,
+        call: &'a mut dyn FnMut(Vec<Bytes>) -> Frame,
+    ) -> Interpreter<'a> {
+        let mut interpreter = Interpreter {
+            scopes: vec![],
+            steps: 0,
+            max_steps,
+            call,
+            lists: vec![],
+        };
+        let mut strings =
+            |values: &[Bytes]| interpreter.list(values.iter().cloned().map(Value::Str).collect());
+        let globals = HashMap::from([
+            ("KEYS".to_string(), strings(keys)),
+            ("ARGV".to_string(), strings(args)),
+        ]);
+        interpreter.scopes.push(globals);
+        interpreter
+    }
+
+    fn list(&mut self, items: Vec<Value>) -> Value {
+        let list = Rc::new(RefCell::new(items));
+        self.lists.push(list.clone());
+        Value::List(list)
+    }
+
+    /// Convert a command reply. Error replies are handled by the caller.
+    fn reply_value(&mut self, frame: Frame) -> Value {
+        match frame {
+            Frame::Simple(value) | Frame::Error(value) => Value::Str(Bytes::from(value)),
+            Frame::Integer(value) => Value::Int(value as i64),
+            Frame::Bulk(value) => Value::Str(value),
+            Frame::Null => Value::Nil,
+            Frame::Array(frames) => {
+                let items = frames
+                    .into_iter()
+                    .map(|frame| self.reply_value(frame))
+                    .collect();
+                self.list(items)
+            }
+        }
+    }
+
+    /// Run the statements of a script and convert what it returns into a reply
+    pub(crate) fn run(&mut self, stmts: &[Stmt]) -> Result<Frame> {
+        let value = match self.block(stmts)? {
+            Flow::Return(value) => value,
+            Flow::Next => Value::Nil,
+            // Only parsed inside loops, but a stray one ends the script
+            Flow::Break | Flow::Continue => Value::Nil,
+        };
+        let line = stmts.last().map_or(1, |stmt| stmt.line);
+        value
+            .into_frame(0)
+            .map_err(|message| Failure::Error(line, message))
+    }
+
+    /// Count `count` steps, failing past the limit
+    fn step(&mut self, count: u64, line: usize) -> Result<()> {
+        self.steps += count;
+        if self.steps > self.max_steps {
+            return Err(Failure::Error(
+                line,
+                format!("script exceeded the limit of {} steps", self.max_steps),
+            ));
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<Flow> {
+        self.scopes.push(HashMap::new());
+        let flow = self.statements(stmts);
+        self.scopes.pop();
+        flow
+    }
+
+    fn statements(&mut self, stmts: &[Stmt]) -> Result<Flow> {
+        for stmt in stmts {
+            match self.statement(stmt)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<Flow> {
+        let line = stmt.line;
+        self.step(1, line)?;
+
+        match &stmt.kind {
+            StmtKind::Let(name, value) => {
+                let value = self.eval(value)?;
+                self.scopes.last_mut().unwrap().insert(name.clone(), value);
+            }
+            StmtKind::Assign(name, value) => {
+                let value = self.eval(value)?;
+                *self.variable(name, line)? = value;
+            }
+            StmtKind::SetIndex(name, index, value) => {
+                let index = self.eval(index)?;
+                let value = self.eval(value)?;
+                let Value::List(items) = self.variable(name, line)?.clone() else {
+                    return Err(error(line, format!("cannot index '{}': not a list", name)));
+                };
+                let mut items = items.borrow_mut();
+                match position(&index).and_then(|position| items.get_mut(position)) {
+                    Some(item) => *item = value,
+                    None => return Err(error(line, "list index out of range")),
+                }
+            }
+            StmtKind::If(branches, otherwise) => {
+                for (condition, body) in branches {
+                    if self.eval(condition)?.is_true() {
+                        return self.block(body);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.block(body);
+                }
+            }
+            StmtKind::While(condition, body) => {
+                while self.eval(condition)?.is_true() {
+                    match self.block(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            StmtKind::ForIn(name, list, body) => {
+                let Value::List(items) = self.eval(list)? else {
+                    return Err(error(line, "for ... in expects a list or a range"));
+                };
+                // Iterate over a copy, so the body may change the list
+                let items = items.borrow().clone();
+                for item in items {
+                    self.step(1, line)?;
+                    match self.iteration(name, item, body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            StmtKind::ForRange(name, start, end, body) => {
+                let start = self.int(start)?;
+                let end = self.int(end)?;
+                for i in start..end {
+                    self.step(1, line)?;
+                    match self.iteration(name, Value::Int(i), body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Continue => return Ok(Flow::Continue),
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Expr(expr) => {
+                self.eval(expr)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Run a loop body with the loop variable set to `value`
+    fn iteration(&mut self, name: &str, value: Value, body: &[Stmt]) -> Result<Flow> {
+        self.scopes.push(HashMap::from([(name.to_string(), value)]));
+        let flow = self.statements(body);
+        self.scopes.pop();
+        flow
+    }
+
+    /// Find the variable `name` in the innermost scope defining it
+    fn variable(&mut self, name: &str, line: usize) -> Result<&mut Value> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+            .ok_or_else(|| error(line, format!("undefined variable '{}'", name)))
+    }
+
+    fn int(&mut self, expr: &Expr) -> Result<i64> {
+        match self.eval(expr)? {
+            Value::Int(value) => Ok(value),
+            value => Err(error(
+                expr.line,
+                format!("expected an int, got {}", value.type_name()),
+            )),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value> {
+        let line = expr.line;
+        self.step(1, line)?;
+
+        let value = match &expr.kind {
+            ExprKind::Nil => Value::Nil,
+            ExprKind::Bool(value) => Value::Bool(*value),
+            ExprKind::Int(value) => Value::Int(*value),
+            ExprKind::Str(value) => Value::Str(value.clone()),
+            ExprKind::Var(name) => self.variable(name, line)?.clone(),
+            ExprKind::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| self.eval(item))
+                    .collect::<Result<_>>()?;
+                self.list(items)
+            }
+            ExprKind::Index(target, index) => {
+                let target = self.eval(target)?;
+                let index = self.eval(index)?;
+                match &target {
+                    Value::List(items) => {
+                        let items = items.borrow();
+                        position(&index)
+                            .and_then(|position| items.get(position).cloned())
+                            .unwrap_or(Value::Nil)
+                    }
+                    Value::Str(value) => position(&index)
+                        .filter(|position| *position < value.len())
+                        .map_or(Value::Nil, |position| {
+                            Value::Str(value.slice(position..position + 1))
+                        }),
+                    _ => {
+                        let message =
+                            format!("cannot index a value of type {}", target.type_name());
+                        return Err(error(line, message));
+                    }
+                }
+            }
+            ExprKind::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.builtin(name, args, line)?
+            }
+            ExprKind::Not(operand) => Value::Bool(!self.eval(operand)?.is_true()),
+            ExprKind::Neg(operand) => {
+                let value = self.int(operand)?;
+                Value::Int(
+                    value
+                        .checked_neg()
+                        .ok_or_else(|| error(line, "integer overflow"))?,
+                )
+            }
+            ExprKind::And(left, right) => {
+                let left = self.eval(left)?;
+                if left.is_true() {
+                    self.eval(right)?
+                } else {
+                    left
+                }
+            }
+            ExprKind::Or(left, right) => {
+                let left = self.eval(left)?;
+                if left.is_true() {
+                    left
+                } else {
+                    self.eval(right)?
+                }
+            }
+            ExprKind::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                self.binary(*op, left, right, line)?
+            }
+        };
+        Ok(value)
+    }
+
+    fn binary(&mut self, op: BinaryOp, left: Value, right: Value, line: usize) -> Result<Value> {
+        let value = match (op, &left, &right) {
+            (BinaryOp::Eq, _, _) => Value::Bool(left.equals(&right)),
+            (BinaryOp::Ne, _, _) => Value::Bool(!left.equals(&right)),
+            (BinaryOp::Add, Value::Str(a), Value::Str(b)) => {
+                let len = a.len() + b.len();
+                if len > MAX_STRING_LEN {
+                    return Err(error(line, "string is too long"));
+                }
+                self.step((len / BYTES_PER_STEP) as u64, line)?;
+                let mut joined = Vec::with_capacity(len);
+                joined.extend_from_slice(a);
+                joined.extend_from_slice(b);
+                Value::Str(Bytes::from(joined))
+            }
+            (_, Value::Int(a), Value::Int(b)) => {
+                let (a, b) = (*a, *b);
+                let result = match op {
+                    BinaryOp::Add => a.checked_add(b),
+                    BinaryOp::Sub => a.checked_sub(b),
+                    BinaryOp::Mul => a.checked_mul(b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                        return Err(error(line, "division by zero"))
+                    }
+                    BinaryOp::Div => a.checked_div(b),
+                    BinaryOp::Rem => a.checked_rem(b),
+                    _ => return Ok(Value::Bool(compare(op, a.cmp(&b)))),
+                };
+                Value::Int(result.ok_or_else(|| error(line, "integer overflow"))?)
+            }
+            (
+                BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge,
+                Value::Str(a),
+                Value::Str(b),
+            ) => Value::Bool(compare(op, a.cmp(b))),
+            _ => {
+                let message = format!(
+                    "unsupported operand types for {:?}: {} and {}",
+                    op,
+                    left.type_name(),
+                    right.type_name()
+                );
+                return Err(error(line, message));
+            }
+        };
+        Ok(value)
+    }
+
+    fn builtin(&mut self, name: &str, args: Vec<Value>, line: usize) -> Result<Value> {
+        let arity = |expected: usize| {
+            if args.len() == expected {
+                Ok(())
+            } else {
+                let message = format!(
+                    "{}() takes {} arguments, {} given",
+                    name,
+                    expected,
+                    args.len()
+                );
+                Err(error(line, message))
+            }
+        };
+
+        let value = match name {
+            "call" => {
+                if args.is_empty() {
+                    return Err(error(line, "call() needs a command name"));
+                }
+                let argv = args
+                    .iter()
+                    .map(Value::to_arg)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| error(line, "call() arguments must be strings or ints"))?;
+                match (self.call)(argv) {
+                    Frame::Error(err) => return Err(Failure::Reply(err)),
+                    frame => self.reply_value(frame),
+                }
+            }
+            "len" => {
+                arity(1)?;
+                match &args[0] {
+                    Value::Str(value) => Value::Int(value.len() as i64),
+                    Value::List(items) => Value::Int(items.borrow().len() as i64),
+                    value => {
+                        let message = format!("len() of a value of type {}", value.type_name());
+                        return Err(error(line, message));
+                    }
+                }
+            }
+            "push" => {
+                arity(2)?;
+                let Value::List(items) = &args[0] else {
+                    return Err(error(line, "push() expects a list"));
+                };
+                items.borrow_mut().push(args[1].clone());
+                Value::Nil
+            }
+            "tonumber" => {
+                arity(1)?;
+                match &args[0] {
+                    Value::Int(value) => Value::Int(*value),
+                    Value::Str(value) => std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.parse().ok())
+                        .map_or(Value::Nil, Value::Int),
+                    _ => Value::Nil,
+                }
+            }
+            "tostring" => {
+                arity(1)?;
+                match &args[0] {
+                    Value::Nil => Value::Str(Bytes::from("nil")),
+                    Value::Bool(value) => Value::Str(Bytes::from(value.to_string())),
+                    Value::Int(value) => Value::Str(Bytes::from(value.to_string())),
+                    Value::Str(value) => Value::Str(value.clone()),
+                    Value::List(_) => return Err(error(line, "tostring() of a list")),
+                }
+            }
+            "error" => {
+                arity(1)?;
+                let message = match &args[0] {
+                    Value::Str(value) => String::from_utf8_lossy(value).into_owned(),
+                    value => {
+                        return Err(error(line, format!("error() of a {}", value.type_name())))
+                    }
+                };
+                return Err(Failure::Reply(message));
+            }
+            _ => return Err(error(line, format!("unknown function '{}'", name))),
+        };
+        Ok(value)
+    }
+}
+
+fn error(line: usize, message: impl Into<String>) -> Failure {
+    Failure::Error(line, message.into())
+}
+
+/// Position designated by `index`, if it is a non-negative int. It may be
+/// past the end.
+fn position(index: &Value) -> Option<usize> {
+    match index {
+        Value::Int(index) => usize::try_from(*index).ok(),
+        _ => None,
+    }
+}
+
+fn compare(op: BinaryOp, ordering: std::cmp::Ordering) -> bool {
+    match op {
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::Le => ordering.is_le(),
+        BinaryOp::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    }
+}
@@ -0,0 +1,294 @@
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Int(i64),
+    Str(Bytes),
+    Ident(String),
+    // Keywords
+    Let,
+    If,
+    Elif,
+    Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
+    Return,
+    Nil,
+    True,
+    False,
+    And,
+    Or,
+    Not,
+    // Punctuation
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Semicolon,
+    Assign,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    DotDot,
+    Eof,
+}
+
+impl Token {
+    /// How the token is shown in error messages
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Token::Int(value) => value.to_string(),
+            Token::Str(_) => "string".to_string(),
+            Token::Ident(name) => format!("'{}'", name),
+            Token::Eof => "end of script".to_string(),
+            token => format!("'{}'", symbol(token)),
+        }
+    }
+}
+
+fn symbol(token: &Token) -> &'static str {
+    match token {
+        Token::Let => "let",
+        Token::If => "if",
+        Token::Elif => "elif",
+        Token::Else => "else",
+        Token::While => "while",
+        Token::For => "for",
+        Token::In => "in",
+        Token::Break => "break",
+        Token::Continue => "continue",
+        Token::Return => "return",
+        Token::Nil => "nil",
+        Token::True => "true",
+        Token::False => "false",
+        Token::And => "and",
+        Token::Or => "or",
+        Token::Not => "not",
+        Token::LParen => "(",
+        Token::RParen => ")",
+        Token::LBracket => "[",
+        Token::RBracket => "]",
+        Token::LBrace => "{",
+        Token::RBrace => "}",
+        Token::Comma => ",",
+        Token::Semicolon => ";",
+        Token::Assign => "=",
+        Token::Eq => "==",
+        Token::Ne => "!=",
+        Token::Lt => "<",
+        Token::Le => "<=",
+        Token::Gt => ">",
+        Token::Ge => ">=",
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Star => "*",
+        Token::Slash => "/",
+        Token::Percent => "%",
+        Token::DotDot => "..",
+        Token::Int(_) | Token::Str(_) | Token::Ident(_) | Token::Eof => "",
+    }
+}
+
+fn keyword(word: &str) -> Option<Token> {
+    let token = match word {
+        "let" => Token::Let,
+        "if" => Token::If,
+        "elif" => Token::Elif,
+        "else" => Token::Else,
+        "while" => Token::While,
+        "for" => Token::For,
+        "in" => Token::In,
+        "break" => Token::Break,
+        "continue" => Token::Continue,
+        "return" => Token::Return,
+        "nil" => Token::Nil,
+        "true" => Token::True,
+        "false" => Token::False,
+        "and" => Token::And,
+        "or" => Token::Or,
+        "not" => Token::Not,
+        _ => return None,
+    };
+    Some(token)
+}
+
+/// Split `source` into tokens, each with its line number. The last token
+/// is always `Eof`.
+pub(crate) fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let bytes = source.as_bytes();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b'\n' => {
+                line += 1;
+                i += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            // Comments run to the end of the line
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'0'..=b'9' => {
+                let start = i;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let value = source[start..i]
+                    .parse()
+                    .map_err(|_| (line, format!("integer {} is too large", &source[start..i])))?;
+                tokens.push((Token::Int(value), line));
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let word = &source[start..i];
+                let token = keyword(word).unwrap_or_else(|| Token::Ident(word.to_string()));
+                tokens.push((token, line));
+                continue;
+            }
+            b'"' | b'\'' => {
+                let (value, end) = string(bytes, i, line)?;
+                tokens.push((Token::Str(value), line));
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+
+        let next = bytes.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            (b'=', Some(b'=')) => (Token::Eq, 2),
+            (b'!', Some(b'=')) => (Token::Ne, 2),
+            (b'<', Some(b'=')) => (Token::Le, 2),
+            (b'>', Some(b'=')) => (Token::Ge, 2),
+            (b'.', Some(b'.')) => (Token::DotDot, 2),
+            (b'=', _) => (Token::Assign, 1),
+            (b'<', _) => (Token::Lt, 1),
+            (b'>', _) => (Token::Gt, 1),
+            (b'(', _) => (Token::LParen, 1),
+            (b')', _) => (Token::RParen, 1),
+            (b'[', _) => (Token::LBracket, 1),
+            (b']', _) => (Token::RBracket, 1),
+            (b'{', _) => (Token::LBrace, 1),
+            (b'}', _) => (Token::RBrace, 1),
+            (b',', _) => (Token::Comma, 1),
+            (b';', _) => (Token::Semicolon, 1),
+            (b'+', _) => (Token::Plus, 1),
+            (b'-', _) => (Token::Minus, 1),
+            (b'*', _) => (Token::Star, 1),
+            (b'/', _) => (Token::Slash, 1),
+            (b'%', _) => (Token::Percent, 1),
+            _ => {
+                let c = source[i..].chars().next().unwrap();
+                return Err((line, format!("unexpected character '{}'", c)));
+            }
+        };
+        tokens.push((token, line));
+        i += len;
+    }
+
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+/// Read the string literal opening at `bytes[start]`. Returns its value and
+/// the index after the closing quote.
+fn string(bytes: &[u8], start: usize, line: usize) -> Result<(Bytes, usize), (usize, String)> {
+    let quote = bytes[start];
+    let mut value = vec![];
+    let mut i = start + 1;
+
+    loop {
+        match bytes.get(i) {
+            None | Some(b'\n') => return Err((line, "unterminated string".to_string())),
+            Some(&c) if c == quote => return Ok((Bytes::from(value), i + 1)),
+            Some(b'\\') => {
+                let escaped = match bytes.get(i + 1) {
+                    Some(b'n') => b'\n',
+                    Some(b'r') => b'\r',
+                    Some(b't') => b'\t',
+                    Some(b'0') => 0,
+                    Some(&c @ (b'\\' | b'"' | b'\'')) => c,
+                    _ => return Err((line, "invalid escape sequence".to_string())),
+                };
+                value.push(escaped);
+                i += 2;
+            }
+            Some(&c) => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    #[test]
+    fn tokens_and_lines() {
+        assert_eq!(
+            tokens("let x = 10 # set x\nx >= 'a\\n'"),
+            [
+                Token::Let,
+                Token::Ident("x".into()),
+                Token::Assign,
+                Token::Int(10),
+                Token::Ident("x".into()),
+                Token::Ge,
+                Token::Str(Bytes::from("a\n")),
+                Token::Eof,
+            ]
+        );
+        let lines: Vec<_> = tokenize("a\n\nb")
+            .unwrap()
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(lines, [1, 3, 3]);
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        assert_eq!(tokenize("x\n'open").unwrap_err().0, 2);
+        assert!(tokenize("a $ b").is_err());
+        assert!(tokenize("99999999999999999999").is_err());
+    }
+}
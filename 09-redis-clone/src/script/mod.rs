@@ -0,0 +1,294 @@
+//! Server-side scripts, run atomically by EVAL and EVALSHA.
+//!
+//! Scripts are written in a small language instead of Lua:
+//!
+//! ```text
+//! # Move up to ARGV[0] members from one leaderboard to another
+//! let moved = 0
+//! for member in call("ZRANGE", KEYS[0], 0, tonumber(ARGV[0]) - 1) {
+//!     let score = call("ZSCORE", KEYS[0], member)
+//!     call("ZADD", KEYS[1], score, member)
+//!     call("ZREM", KEYS[0], member)
+//!     moved = moved + 1
+//! }
+//! return moved
+//! ```
+//!
+//! - Values are `nil`, booleans, 64-bit ints, binary strings and lists.
+//!   Lists are shared, so `push(list, value)` is seen through every
+//!   variable holding the list.
+//! - `let name = value` declares a variable in the current block, and
+//!   `name = value` or `name[index] = value` assign an existing one.
+//!   `KEYS` and `ARGV` are lists of strings. Indexes start at 0.
+//! - `if cond { } elif cond { } else { }`, `while cond { }`,
+//!   `for item in list { }`, `for i in start..end { }`, `break`,
+//!   `continue` and `return value`. Statements may end with `;`.
+//! - `nil`, `false`, 0, `""` and `[]` are false. `and` and `or` return
+//!   one of their operands, like in Lua.
+//! - `+` adds ints or joins strings. `-`, `*`, `/` and `%` only work on
+//!   ints, comparisons on two ints or two strings.
+//! - `call(name, args...)` runs a keyspace command and returns its reply:
+//!   a string, an int, a list or `nil`. An error reply aborts the script
+//!   and is its reply. `error(message)` aborts with a custom error.
+//!   `len`, `push`, `tonumber` and `tostring` are the other functions.
+//!
+//! Every statement and expression evaluated is a step, and a script fails
+//! once it exceeds `Config::script_max_steps`, so it cannot hold the
+//! keyspace forever. Scripts cannot do anything else than run commands.
+
+mod interp;
+mod lexer;
+mod parser;
+
+use std::{collections::HashMap, sync::Arc};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+
+use interp::{Failure, Interpreter};
+use parser::Stmt;
+
+/// Error returned by EVALSHA for an unknown script
+pub const NOSCRIPT: &str = "NOSCRIPT No matching script. Please use EVAL.";
+
+/// A compiled script
+#[derive(Debug)]
+pub struct Program {
+    stmts: Vec<Stmt>,
+}
+
+impl Program {
+    /// Parse a script. Errors tell the line they occur on.
+    pub fn compile(source: &str) -> crate::Result<Program> {
+        let stmts =
+            lexer::tokenize(source)
+                .and_then(parser::parse)
+                .map_err(|(line, message)| {
+                    format!("ERR Error compiling script (line {}): {}", line, message)
+                })?;
+        Ok(Program { stmts })
+    }
+
+    /// Run the script with `KEYS` and `ARGV` set to `keys` and `args`, and
+    /// return its reply. `call` runs a command given as its arguments.
+    pub fn run(
+        &self,
+        keys: &[Bytes],
+        args: &[Bytes],
+        max_steps: u64,
+        mut call: impl FnMut(Vec<Bytes>) -> Frame,
+    ) -> Frame {
+        let mut interpreter = Interpreter::new(keys, args, max_steps, &mut call);
+        match interpreter.run(&self.stmts) {
+            Ok(frame) => frame,
+            Err(Failure::Error(line, message)) => Frame::Error(format!(
+                "ERR Error running script (line {}): {}",
+                line, message
+            )),
+            Err(Failure::Reply(err)) => Frame::Error(err),
+        }
+    }
+}
+
+/// SHA1 digest of a script in hex, which identifies it for EVALSHA
+pub fn sha1_hex(source: &str) -> String {
+    sha1_smol::Sha1::from(source).digest().to_string()
+}
+
+/// Cache of compiled scripts by SHA1, filled by EVAL and SCRIPT LOAD
+#[derive(Debug, Default)]
+pub struct Scripts {
+    programs: HashMap<String, Arc<Program>>,
+}
+
+impl Scripts {
+    /// Compile `source` unless it is cached. Returns its SHA1 and program.
+    pub fn load(&mut self, source: &str) -> crate::Result<(String, Arc<Program>)> {
+        let sha = sha1_hex(source);
+        if let Some(program) = self.programs.get(&sha) {
+            return Ok((sha, program.clone()));
+        }
+
+        let program = Arc::new(Program::compile(source)?);
+        self.programs.insert(sha.clone(), program.clone());
+        Ok((sha, program))
+    }
+
+    /// Program of the script with this SHA1, in any case
+    pub fn get(&self, sha: &str) -> Option<Arc<Program>> {
+        self.programs.get(&sha.to_lowercase()).cloned()
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.programs.contains_key(&sha.to_lowercase())
+    }
+
+    /// Forget every script
+    pub fn flush(&mut self) {
+        self.programs.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `source` against a fake command runner that echoes its arguments
+    /// joined by spaces, or fails for FAIL
+    fn run(source: &str, keys: &[&str], args: &[&str]) -> String {
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| Bytes::from(key.to_string()))
+            .collect();
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| Bytes::from(arg.to_string()))
+            .collect();
+        let program = match Program::compile(source) {
+            Ok(program) => program,
+            Err(err) => return err.to_string(),
+        };
+        let frame = program.run(&keys, &args, 10_000, |argv| {
+            let line: Vec<_> = argv
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            match &line[..] {
+                [name, ..] if name == "FAIL" => Frame::Error("ERR failed".into()),
+                [name, n] if name == "COUNT" => Frame::Integer(n.parse().unwrap()),
+                _ => Frame::Bulk(Bytes::from(line.join(" "))),
+            }
+        });
+        render(frame)
+    }
+
+    fn render(frame: Frame) -> String {
+        match frame {
+            Frame::Array(parts) => {
+                let parts: Vec<_> = parts.into_iter().map(render).collect();
+                format!("[{}]", parts.join(", "))
+            }
+            frame => frame.to_string(),
+        }
+    }
+
+    #[test]
+    fn variables_and_arithmetic() {
+        assert_eq!(run("let x = 2\nlet y = x * 3 + 1\nreturn y", &[], &[]), "7");
+        assert_eq!(run("return 7 / 2 + 7 % 2", &[], &[]), "4");
+        assert_eq!(run("return -5", &[], &[]), "-5");
+        assert_eq!(run("return 'a' + \"b\"", &[], &[]), "ab");
+        assert_eq!(
+            run("return [1, 'two', nil, true]", &[], &[]),
+            "[1, two, (nil), 1]"
+        );
+        assert_eq!(run("return KEYS[1] + ARGV[0]", &["a", "b"], &["c"]), "bc");
+        assert_eq!(run("return KEYS[5]", &[], &[]), "(nil)");
+        assert_eq!(run("return nil or 0 or 'x'", &[], &[]), "x");
+        assert_eq!(run("", &[], &[]), "(nil)");
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+            let total = 0
+            for i in 0..100 {
+                if i % 2 == 0 { continue }
+                elif i > 10 { break }
+                total = total + i
+            }
+            let n = 0
+            while n < 3 { n = n + 1 }
+            return [total, n]
+        ";
+        assert_eq!(run(source, &[], &[]), "[25, 3]");
+
+        let source = "
+            let found = nil
+            for key in KEYS { if key == 'b' { found = key; break } }
+            return found
+        ";
+        assert_eq!(run(source, &["a", "b", "c"], &[]), "b");
+    }
+
+    #[test]
+    fn lists_are_shared() {
+        let source = "
+            let a = []
+            let b = a
+            push(b, 1); push(a, 2)
+            a[0] = 10
+            return [len(b), b[0], len('abc')]
+        ";
+        assert_eq!(run(source, &[], &[]), "[2, 10, 3]");
+        assert!(run("let a = []\npush(a, a)\nreturn a", &[], &[]).contains("too deeply nested"));
+    }
+
+    #[test]
+    fn calls_run_commands() {
+        assert_eq!(run("return call('GET', KEYS[0])", &["k"], &[]), "GET k");
+        assert_eq!(run("return call('COUNT', 3) + 1", &[], &[]), "4");
+        assert_eq!(run("return tonumber(ARGV[0]) * 2", &[], &["21"]), "42");
+        assert_eq!(run("return tostring(1) + 'x'", &[], &[]), "1x");
+        // Errors of commands are the reply, unchanged
+        assert_eq!(run("call('FAIL')\nreturn 1", &[], &[]), "error: ERR failed");
+        assert_eq!(
+            run("error('MYERR bad input')", &[], &[]),
+            "error: MYERR bad input"
+        );
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let err = run("let x = 1\nreturn x +", &[], &[]);
+        assert!(
+            err.starts_with("ERR Error compiling script (line 2)"),
+            "{}",
+            err
+        );
+        let err = run("let x = 1\nreturn y", &[], &[]);
+        assert!(err.contains("(line 2): undefined variable 'y'"), "{}", err);
+        assert!(run("return 1 / 0", &[], &[]).contains("division by zero"));
+        assert!(run("return 1 + 'a'", &[], &[]).contains("unsupported operand"));
+        assert!(run("return nope()", &[], &[]).contains("unknown function"));
+        // Variables do not outlive their block
+        assert!(run("if true { let x = 1 }\nreturn x", &[], &[]).contains("undefined"));
+    }
+
+    #[test]
+    fn steps_are_limited() {
+        let err = run("while true {}", &[], &[]);
+        assert!(err.contains("exceeded the limit of 10000 steps"), "{}", err);
+        let err = run("for i in 0..1000000000 {}", &[], &[]);
+        assert!(err.contains("exceeded the limit"), "{}", err);
+        // Building long strings costs steps
+        let err = run("let s = 'x'\nwhile true { s = s + s }", &[], &[]);
+        assert!(err.contains("exceeded the limit"), "{}", err);
+    }
+
+    #[test]
+    fn cache_by_sha1() {
+        // The digest Redis computes for the same script
+        assert_eq!(
+            sha1_hex("return 1"),
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db"
+        );
+
+        let mut scripts = Scripts::default();
+        let (sha, _) = scripts.load("return 1").unwrap();
+        assert!(scripts.contains(&sha.to_uppercase()));
+        assert!(scripts.get(&sha).is_some());
+        assert!(scripts.load("return (").is_err());
+        assert_eq!(scripts.len(), 1);
+        scripts.flush();
+        assert!(scripts.is_empty());
+    }
+}
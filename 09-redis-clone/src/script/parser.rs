@@ -0,0 +1,488 @@
+use bytes::Bytes;
+
+use crate::script::lexer::Token;
+
+/// Deepest nesting of blocks and expressions a script may use, so running
+/// it cannot overflow the stack
+const MAX_DEPTH: usize = 100;
+
+#[derive(Debug)]
+pub(crate) struct Stmt {
+    pub(crate) kind: StmtKind,
+    pub(crate) line: usize,
+}
+
+#[derive(Debug)]
+pub(crate) enum StmtKind {
+    Let(String, Expr),
+    Assign(String, Expr),
+    // name[index] = value
+    SetIndex(String, Expr, Expr),
+    // `if` and `elif` branches in order, then `else`
+    If(Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>),
+    While(Expr, Vec<Stmt>),
+    // for name in list { ... }
+    ForIn(String, Expr, Vec<Stmt>),
+    // for name in start..end { ... }, end excluded
+    ForRange(String, Expr, Expr, Vec<Stmt>),
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Debug)]
+pub(crate) struct Expr {
+    pub(crate) kind: ExprKind,
+    pub(crate) line: usize,
+}
+
+#[derive(Debug)]
+pub(crate) enum ExprKind {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Str(Bytes),
+    Var(String),
+    List(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    // Only evaluate the right side if needed
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Parse a whole script
+pub(crate) fn parse(tokens: Vec<(Token, usize)>) -> Result<Vec<Stmt>> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        depth: 0,
+    };
+    let mut stmts = vec![];
+    while parser.peek() != &Token::Eof {
+        stmts.push(parser.statement()?);
+    }
+    Ok(stmts)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    depth: usize,
+}
+
+type Result<T> = std::result::Result<T, (usize, String)>;
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        // `Eof` stays the current token once reached
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// Consume the next token if it is `token`
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<()> {
+        if self.accept(&token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&token.describe()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Token::Ident(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> (usize, String) {
+        let found = self.peek().describe();
+        (
+            self.line(),
+            format!("expected {}, found {}", expected, found),
+        )
+    }
+
+    /// Count one more level of nesting, failing past `MAX_DEPTH`
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err((self.line(), "script is too deeply nested".to_string()));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        let line = self.line();
+        let kind = match self.peek() {
+            Token::Let => {
+                self.advance();
+                let name = self.ident()?;
+                self.expect(Token::Assign)?;
+                StmtKind::Let(name, self.expression()?)
+            }
+            Token::If => {
+                self.advance();
+                let mut branches = vec![(self.expression()?, self.block()?)];
+                let mut otherwise = None;
+                loop {
+                    if self.accept(&Token::Elif) {
+                        branches.push((self.expression()?, self.block()?));
+                    } else if self.accept(&Token::Else) {
+                        otherwise = Some(self.block()?);
+                        break;
+                    } else {
+                        break;
+                    }
+                }
+                StmtKind::If(branches, otherwise)
+            }
+            Token::While => {
+                self.advance();
+                StmtKind::While(self.expression()?, self.block()?)
+            }
+            Token::For => {
+                self.advance();
+                let name = self.ident()?;
+                self.expect(Token::In)?;
+                let first = self.expression()?;
+                if self.accept(&Token::DotDot) {
+                    let end = self.expression()?;
+                    StmtKind::ForRange(name, first, end, self.block()?)
+                } else {
+                    StmtKind::ForIn(name, first, self.block()?)
+                }
+            }
+            Token::Break => {
+                self.advance();
+                StmtKind::Break
+            }
+            Token::Continue => {
+                self.advance();
+                StmtKind::Continue
+            }
+            Token::Return => {
+                self.advance();
+                let value = match self.peek() {
+                    Token::Semicolon | Token::RBrace | Token::Eof => None,
+                    _ => Some(self.expression()?),
+                };
+                StmtKind::Return(value)
+            }
+            _ => {
+                let expr = self.expression()?;
+                if self.accept(&Token::Assign) {
+                    let value = self.expression()?;
+                    match expr.kind {
+                        ExprKind::Var(name) => StmtKind::Assign(name, value),
+                        ExprKind::Index(target, index) => match target.kind {
+                            ExprKind::Var(name) => StmtKind::SetIndex(name, *index, value),
+                            _ => return Err((line, "invalid assignment target".to_string())),
+                        },
+                        _ => return Err((line, "invalid assignment target".to_string())),
+                    }
+                } else {
+                    StmtKind::Expr(expr)
+                }
+            }
+        };
+
+        // Statements may be separated by semicolons
+        while self.accept(&Token::Semicolon) {}
+        Ok(Stmt { kind, line })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        self.enter()?;
+        self.expect(Token::LBrace)?;
+        let mut stmts = vec![];
+        while !self.accept(&Token::RBrace) {
+            if self.peek() == &Token::Eof {
+                return Err(self.unexpected("'}'"));
+            }
+            stmts.push(self.statement()?);
+        }
+        self.depth -= 1;
+        Ok(stmts)
+    }
+
+    fn expression(&mut self) -> Result<Expr> {
+        self.enter()?;
+        let expr = self.or();
+        self.depth -= 1;
+        expr
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut left = self.and()?;
+        while self.peek() == &Token::Or {
+            let line = self.line();
+            self.advance();
+            let right = self.and()?;
+            left = Expr {
+                kind: ExprKind::Or(Box::new(left), Box::new(right)),
+                line,
+            };
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut left = self.not()?;
+        while self.peek() == &Token::And {
+            let line = self.line();
+            self.advance();
+            let right = self.not()?;
+            left = Expr {
+                kind: ExprKind::And(Box::new(left), Box::new(right)),
+                line,
+            };
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.peek() == &Token::Not {
+            let line = self.line();
+            self.advance();
+            self.enter()?;
+            let operand = self.not()?;
+            self.depth -= 1;
+            return Ok(Expr {
+                kind: ExprKind::Not(Box::new(operand)),
+                line,
+            });
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.sum()?;
+        let op = match self.peek() {
+            Token::Eq => BinaryOp::Eq,
+            Token::Ne => BinaryOp::Ne,
+            Token::Lt => BinaryOp::Lt,
+            Token::Le => BinaryOp::Le,
+            Token::Gt => BinaryOp::Gt,
+            Token::Ge => BinaryOp::Ge,
+            _ => return Ok(left),
+        };
+        let line = self.line();
+        self.advance();
+        let right = self.sum()?;
+        Ok(binary(op, left, right, line))
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        let mut left = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Token::Plus => BinaryOp::Add,
+                Token::Minus => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            let line = self.line();
+            self.advance();
+            let right = self.term()?;
+            left = binary(op, left, right, line);
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Token::Star => BinaryOp::Mul,
+                Token::Slash => BinaryOp::Div,
+                Token::Percent => BinaryOp::Rem,
+                _ => return Ok(left),
+            };
+            let line = self.line();
+            self.advance();
+            let right = self.unary()?;
+            left = binary(op, left, right, line);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.peek() == &Token::Minus {
+            let line = self.line();
+            self.advance();
+            self.enter()?;
+            let operand = self.unary()?;
+            self.depth -= 1;
+            return Ok(Expr {
+                kind: ExprKind::Neg(Box::new(operand)),
+                line,
+            });
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        while self.peek() == &Token::LBracket {
+            let line = self.line();
+            self.advance();
+            let index = self.expression()?;
+            self.expect(Token::RBracket)?;
+            expr = Expr {
+                kind: ExprKind::Index(Box::new(expr), Box::new(index)),
+                line,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let line = self.line();
+        let kind = match self.peek().clone() {
+            Token::Nil => ExprKind::Nil,
+            Token::True => ExprKind::Bool(true),
+            Token::False => ExprKind::Bool(false),
+            Token::Int(value) => ExprKind::Int(value),
+            Token::Str(value) => ExprKind::Str(value),
+            Token::Ident(name) => {
+                self.advance();
+                let kind = if self.accept(&Token::LParen) {
+                    ExprKind::Call(name, self.list(Token::RParen)?)
+                } else {
+                    ExprKind::Var(name)
+                };
+                return Ok(Expr { kind, line });
+            }
+            Token::LBracket => {
+                self.advance();
+                let kind = ExprKind::List(self.list(Token::RBracket)?);
+                return Ok(Expr { kind, line });
+            }
+            Token::LParen => {
+                self.advance();
+                let expr = self.expression()?;
+                self.expect(Token::RParen)?;
+                return Ok(expr);
+            }
+            _ => return Err(self.unexpected("an expression")),
+        };
+        self.advance();
+        Ok(Expr { kind, line })
+    }
+
+    /// Comma separated expressions up to `end`, which is consumed
+    fn list(&mut self, end: Token) -> Result<Vec<Expr>> {
+        let mut items = vec![];
+        while !self.accept(&end) {
+            items.push(self.expression()?);
+            if !self.accept(&Token::Comma) {
+                self.expect(end)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr, line: usize) -> Expr {
+    Expr {
+        kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+        line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::lexer::tokenize;
+
+    fn parse_source(source: &str) -> Result<Vec<Stmt>> {
+        parse(tokenize(source)?)
+    }
+
+    #[test]
+    fn precedence() {
+        let stmts = parse_source("return 1 + 2 * 3 == 7 and not false").unwrap();
+        let StmtKind::Return(Some(expr)) = &stmts[0].kind else {
+            panic!("expected a return");
+        };
+        let ExprKind::And(left, _) = &expr.kind else {
+            panic!("and binds loosest");
+        };
+        let ExprKind::Binary(BinaryOp::Eq, sum, _) = &left.kind else {
+            panic!("then comparisons");
+        };
+        assert!(matches!(sum.kind, ExprKind::Binary(BinaryOp::Add, _, _)));
+    }
+
+    #[test]
+    fn statements() {
+        let source = "
+            let total = 0
+            for key in KEYS { total = total + 1; }
+            for i in 0..10 { if i == 5 { break } elif i > 7 { continue } else { total = total + i } }
+            while false {}
+            items[0] = call('GET', KEYS[0])
+            return
+        ";
+        let stmts = parse_source(source).unwrap();
+        assert_eq!(stmts.len(), 6);
+        assert!(matches!(stmts[2].kind, StmtKind::ForRange(..)));
+        assert!(matches!(stmts[4].kind, StmtKind::SetIndex(..)));
+        assert!(matches!(stmts[5].kind, StmtKind::Return(None)));
+        assert_eq!(stmts[5].line, 7);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let (line, message) = parse_source("let x = 1\nif x {").unwrap_err();
+        assert_eq!(line, 2);
+        assert_eq!(message, "expected '}', found end of script");
+        assert!(parse_source("let = 1").is_err());
+        assert!(parse_source("1 = 2").is_err());
+        assert!(parse_source("call(1,").is_err());
+        assert!(parse_source(&"(".repeat(1000)).is_err());
+    }
+}
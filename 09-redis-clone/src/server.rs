@@ -0,0 +1,817 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::{
+    net::TcpListener,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, Semaphore,
+    },
+    time,
+};
+use tokio_stream::{Stream, StreamExt, StreamMap};
+use tracing::{debug, info, warn};
+
+use crate::{
+    acl::{User, NOAUTH},
+    clients::ConnectedClient,
+    cmd::Eval,
+    db::{Shards, Waiter},
+    replication::READONLY,
+    shutdown::Shutdown,
+    AppendFsync, Command, Config, Connection, Db,
+};
+
+/// How often expired keys are purged from memory
+const PURGE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the append-only file is flushed with `appendfsync everysec`
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pause after a failed accept before trying again
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accept connections on `listener` until `shutdown` completes, serving each
+/// one in its own task.
+///
+/// The keyspace is loaded from the append-only file or the snapshot file of
+/// `config`, if they exist. On shutdown, no new connection is accepted,
+/// commands being processed are finished and persistence files are flushed
+/// before returning.
+pub async fn run(
+    listener: TcpListener,
+    config: Config,
+    shutdown: impl Future,
+) -> crate::Result<()> {
+    let db = Db::open(config)?;
+
+    tokio::spawn(purge_expired_keys(db.clone()));
+    if let Some(interval) = db.config().save_interval {
+        tokio::spawn(save_periodically(db.clone(), interval));
+    }
+    if db.config().appendonly && db.config().appendfsync == AppendFsync::EverySec {
+        tokio::spawn(fsync_periodically(db.clone()));
+    }
+    if let Some(primary) = db.config().replicaof.clone() {
+        db.replicaof(Some(primary));
+    }
+
+    // Dropping the sender tells every connection to stop, and each one
+    // holds a clone of `shutdown_complete_tx` until it is done
+    let (notify_shutdown, _) = broadcast::channel(1);
+    let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
+
+    tokio::select! {
+        _ = accept(&listener, &db, &notify_shutdown, &shutdown_complete_tx) => {}
+        _ = shutdown => info!("shutting down"),
+    }
+
+    drop(listener);
+    drop(notify_shutdown);
+    drop(shutdown_complete_tx);
+    // Resolves once every connection dropped its sender
+    let _ = shutdown_complete_rx.recv().await;
+
+    db.shutdown().await?;
+    info!("persistence flushed, bye");
+    Ok(())
+}
+
+/// Accept connections forever, up to `maxclients` at a time
+async fn accept(
+    listener: &TcpListener,
+    db: &Db,
+    notify_shutdown: &broadcast::Sender<()>,
+    shutdown_complete: &mpsc::Sender<()>,
+) {
+    // Configs built in code skip the check of `Config::set`
+    let maxclients = db.config().maxclients.min(Semaphore::MAX_PERMITS);
+    let limit = Arc::new(Semaphore::new(maxclients));
+
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                // e.g. too many open files: wait for some to be closed
+                warn!(cause = %err, "failed to accept connection");
+                time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+
+        db.stats().record_connection();
+        let Ok(permit) = limit.clone().try_acquire_owned() else {
+            warn!(%peer, "refusing connection: max number of clients reached");
+            db.stats().record_rejected_connection();
+            tokio::spawn(async move {
+                let response = Frame::Error("ERR max number of clients reached".into());
+                let _ = Connection::new(socket).write_frame(&response).await;
+            });
+            continue;
+        };
+
+        // Without a password, new connections run as the default user
+        let user = db.acl().default_login().map(str::to_string);
+
+        // Create a new task to process the request
+        // Note: concurrent tasks are not necessarily parallel (green-threads)
+        let mut handler = Handler {
+            connection: Connection::new(socket),
+            db: db.clone(),
+            client: db.clients().register(peer, user),
+            transaction: None,
+            watched: HashMap::new(),
+            shutdown: Shutdown::new(notify_shutdown.subscribe()),
+            _shutdown_complete: shutdown_complete.clone(),
+        };
+        debug!(%peer, "accepted connection");
+        tokio::spawn(async move {
+            if let Err(err) = handler.run().await {
+                warn!(%peer, cause = %err, "connection error");
+            }
+            debug!(%peer, "connection closed");
+            drop(permit);
+        });
+    }
+}
+
+/// Remove expired keys in the background, so they do not sit in memory
+/// until someone touches them.
+async fn purge_expired_keys(db: Db) {
+    let mut interval = time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        db.purge_expired(Instant::now());
+    }
+}
+
+/// Snapshot the keyspace every `period`, if anything changed since the last save
+async fn save_periodically(db: Db, period: Duration) {
+    let mut interval = time::interval(period);
+    // The first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if db.dirty() == 0 {
+            continue;
+        }
+        if let Err(err) = db.bgsave() {
+            warn!(cause = %err, "periodic save failed");
+        }
+    }
+}
+
+/// Flush the append-only file to disk once per second
+async fn fsync_periodically(db: Db) {
+    let mut interval = time::interval(FSYNC_INTERVAL);
+    loop {
+        interval.tick().await;
+        let db = db.clone();
+        if let Ok(Err(err)) = tokio::task::spawn_blocking(move || db.sync_aof()).await {
+            warn!(cause = %err, "append-only file fsync failed");
+        }
+    }
+}
+
+/// Stream of reply frames for one subscribed channel or pattern
+type Messages = Pin<Box<dyn Stream<Item = Frame> + Send>>;
+
+/// Sleep until `deadline`, or forever without one
+async fn sleep_until(deadline: Option<time::Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Per-connection state
+struct Handler {
+    // Connection used to read/write redis frames
+    connection: Connection,
+    db: Db,
+    // Entry of the connection in the client registry
+    client: Arc<ConnectedClient>,
+    // Set between MULTI and EXEC/DISCARD
+    transaction: Option<Transaction>,
+    // Keys under WATCH and their version when the watch started
+    watched: HashMap<String, u64>,
+    // Tells the connection to stop once its current command is done
+    shutdown: Shutdown,
+    // Dropped with the handler, which lets the server know it is done
+    _shutdown_complete: mpsc::Sender<()>,
+}
+
+/// Cleans up when the connection task ends, even by panicking: a dead
+/// connection must not stay in CLIENT LIST or keep its keys watched.
+impl Drop for Handler {
+    fn drop(&mut self) {
+        let watched = mem::take(&mut self.watched).into_keys();
+        self.db.release_client(self.client.id(), watched);
+    }
+}
+
+/// Commands queued after MULTI
+#[derive(Default)]
+struct Transaction {
+    queue: Vec<Command>,
+    // A command failed to parse or was refused while queuing: EXEC must abort
+    failed: bool,
+}
+
+/// Channels and patterns the connection is subscribed to
+#[derive(Default)]
+struct Subscriptions {
+    channels: StreamMap<String, Messages>,
+    patterns: StreamMap<String, Messages>,
+}
+
+impl Subscriptions {
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+impl Handler {
+    /// Serve commands until the peer closes the connection, the client is
+    /// killed or the server shuts down. A command being processed always
+    /// completes.
+    async fn run(&mut self) -> crate::Result<()> {
+        while !self.shutdown.is_shutdown() {
+            let maybe_frame = tokio::select! {
+                res = self.connection.read_frame() => res?,
+                _ = self.shutdown.recv() => return Ok(()),
+                _ = self.client.killed() => return Ok(()),
+            };
+            let Some(frame) = maybe_frame else {
+                return Ok(());
+            };
+
+            let cmd = Command::from_frame(frame);
+            self.record_command(cmd.as_ref().map_or("unknown", Command::get_name));
+            let cmd = cmd.and_then(|cmd| self.authorize(cmd));
+
+            let response = match cmd {
+                // Replicas only change through their primary
+                Ok(cmd) if cmd.is_write() && self.db.is_replica() => {
+                    if let Some(transaction) = &mut self.transaction {
+                        transaction.failed = true;
+                    }
+                    Frame::Error(READONLY.into())
+                }
+                Ok(cmd) if self.transaction.is_some() && !cmd.is_queueable() => {
+                    self.transaction.as_mut().unwrap().failed = true;
+                    Frame::Error(format!(
+                        "ERR '{}' is not allowed in this context",
+                        cmd.get_name()
+                    ))
+                }
+                // Inside MULTI, commands are queued until EXEC
+                Ok(cmd) if self.transaction.is_some() && !cmd.is_transaction_control() => {
+                    self.transaction.as_mut().unwrap().queue.push(cmd);
+                    Frame::Simple("QUEUED".into())
+                }
+                Ok(Command::Multi) => self.multi(),
+                Ok(Command::Exec) => self.exec(),
+                Ok(Command::Discard) => self.discard(),
+                Ok(Command::Watch(cmd)) => self.watch(cmd.keys),
+                Ok(Command::Unwatch) => {
+                    self.unwatch();
+                    Frame::Simple("OK".into())
+                }
+                Ok(Command::Publish(cmd)) => cmd
+                    .apply(&self.db)
+                    .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                Ok(Command::Save) => match self.db.save() {
+                    Ok(()) => Frame::Simple("OK".into()),
+                    Err(err) => Frame::Error(err.to_string()),
+                },
+                Ok(Command::BgSave) => match self.db.bgsave() {
+                    Ok(()) => Frame::Simple("Background saving started".into()),
+                    Err(err) => Frame::Error(err.to_string()),
+                },
+                Ok(Command::BgRewriteAof) => match self.db.bgrewriteaof() {
+                    Ok(()) => Frame::Simple("Background append only file rewriting started".into()),
+                    Err(err) => Frame::Error(err.to_string()),
+                },
+                Ok(Command::Info(cmd)) => cmd.apply(&self.db, &self.db.lock_all()),
+                Ok(Command::Ping(cmd)) => cmd.apply(),
+                Ok(Command::Eval(cmd)) => self.eval(cmd, &mut self.db.lock_all()),
+                Ok(Command::Script(cmd)) => cmd
+                    .apply(&self.db)
+                    .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                Ok(Command::Client(cmd)) => cmd
+                    .apply(&self.db, &self.client)
+                    .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                Ok(Command::Auth(cmd)) => cmd
+                    .apply(&self.db, &self.client)
+                    .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                Ok(Command::Acl(cmd)) => cmd
+                    .apply(&self.db, &self.client)
+                    .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                Ok(Command::ReplicaOf(cmd)) => {
+                    self.db.replicaof(cmd.primary);
+                    Frame::Simple("OK".into())
+                }
+                // The connection now belongs to a replica
+                Ok(Command::Sync) => return self.serve_replica().await,
+                Ok(cmd) if cmd.block_timeout().is_some() => match self.block(cmd).await {
+                    Some(response) => response,
+                    None => return Ok(()),
+                },
+                Ok(cmd) if cmd.is_subscription() => {
+                    if !self.subscriber_mode(cmd).await? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                // The lock is released as soon as the command is applied
+                Ok(cmd) => self.db.execute(cmd),
+                Err(err) => {
+                    if let Some(transaction) = &mut self.transaction {
+                        transaction.failed = true;
+                    }
+                    Frame::Error(err.to_string())
+                }
+            };
+
+            // Write the response to the client
+            self.connection.write_frame(&response).await?;
+            self.update_client_info();
+        }
+
+        Ok(())
+    }
+
+    /// Run a blocking command (XREAD BLOCK...) once it has something to
+    /// reply, or reply nil at its timeout. `None` if the connection must
+    /// close meanwhile.
+    async fn block(&mut self, mut cmd: Command) -> Option<Frame> {
+        let timeout = cmd.block_timeout().unwrap();
+        let deadline = (!timeout.is_zero()).then(|| time::Instant::now() + timeout);
+        // Waiting starts before the first check, so no write is missed
+        let waiter = Waiter::new(&self.db, cmd.blocking_keys());
+
+        loop {
+            cmd = match self.db.apply_if_ready(cmd, &waiter) {
+                Ok(response) => return Some(response),
+                Err(cmd) => cmd,
+            };
+            tokio::select! {
+                _ = waiter.changed() => {}
+                _ = sleep_until(deadline) => return Some(Frame::Null),
+                _ = self.shutdown.recv() => return None,
+                _ = self.client.killed() => return None,
+            }
+        }
+    }
+
+    /// Check that the user of the connection may run `cmd`. Before AUTH,
+    /// only AUTH is allowed.
+    fn authorize(&self, cmd: Command) -> crate::Result<Command> {
+        if let Command::Auth(_) = cmd {
+            return Ok(cmd);
+        }
+        let user = self.user().ok_or(NOAUTH)?;
+        user.check(&cmd)?;
+        Ok(cmd)
+    }
+
+    /// The user the connection runs as, `None` before AUTH
+    fn user(&self) -> Option<Arc<User>> {
+        let name = self.client.info().user.clone()?;
+        self.db.acl().get(&name)
+    }
+
+    /// Run a script as the user of the connection
+    fn eval(&self, cmd: Eval, shards: &mut Shards<'_>) -> Frame {
+        match self.user() {
+            Some(user) => cmd.apply(&self.db, shards, &user),
+            None => Frame::Error(NOAUTH.into()),
+        }
+    }
+
+    /// Count a command received from the client
+    fn record_command(&mut self, name: &str) {
+        self.db.stats().record_command();
+
+        let mut info = self.client.info();
+        info.commands += 1;
+        info.last_command = name.to_string();
+        info.last_interaction = Instant::now();
+    }
+
+    /// Report the traffic and state of the connection, after a reply
+    fn update_client_info(&mut self) {
+        let (read, written) = self.connection.take_traffic();
+        self.db.stats().record_traffic(read, written);
+
+        let mut info = self.client.info();
+        info.net_input_bytes += read;
+        info.net_output_bytes += written;
+        info.multi = self
+            .transaction
+            .as_ref()
+            .map(|transaction| transaction.queue.len());
+    }
+
+    /// MULTI: start queuing commands
+    fn multi(&mut self) -> Frame {
+        if self.transaction.is_some() {
+            return Frame::Error("ERR MULTI calls can not be nested".into());
+        }
+        self.transaction = Some(Transaction::default());
+        Frame::Simple("OK".into())
+    }
+
+    /// EXEC: run the queued commands atomically and reply with all their replies.
+    ///
+    /// Every shard stays locked for the whole transaction, so no other
+    /// connection can observe or modify it halfway. If a watched key was
+    /// modified since WATCH, nothing is run and the reply is a null.
+    fn exec(&mut self) -> Frame {
+        let Some(transaction) = self.transaction.take() else {
+            return Frame::Error("ERR EXEC without MULTI".into());
+        };
+
+        let mut shards = self.db.lock_all();
+
+        let watched = mem::take(&mut self.watched);
+        let dirty = watched
+            .iter()
+            .any(|(key, version)| shards[self.db.shard_index(key)].version(key) != Some(*version));
+        for key in watched.keys() {
+            shards[self.db.shard_index(key)].unwatch(key);
+        }
+
+        if transaction.failed {
+            return Frame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".into(),
+            );
+        }
+        if dirty {
+            return Frame::Null;
+        }
+
+        let replies = self.db.atomically(&mut shards, |shards| {
+            transaction
+                .queue
+                .into_iter()
+                .map(|cmd| match cmd {
+                    Command::Publish(cmd) => cmd
+                        .apply(&self.db)
+                        .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                    Command::Info(cmd) => cmd.apply(&self.db, shards),
+                    Command::Ping(cmd) => cmd.apply(),
+                    Command::Eval(cmd) => self.eval(cmd, shards),
+                    Command::Script(cmd) => cmd
+                        .apply(&self.db)
+                        .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                    Command::Client(cmd) => cmd
+                        .apply(&self.db, &self.client)
+                        .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                    Command::Auth(cmd) => cmd
+                        .apply(&self.db, &self.client)
+                        .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                    Command::Acl(cmd) => cmd
+                        .apply(&self.db, &self.client)
+                        .unwrap_or_else(|err| Frame::Error(err.to_string())),
+                    // The watches were already dropped above
+                    Command::Unwatch => Frame::Simple("OK".into()),
+                    cmd => self.db.apply_locked(shards, cmd),
+                })
+                .collect()
+        });
+        Frame::Array(replies)
+    }
+
+    /// DISCARD: drop the queued commands
+    fn discard(&mut self) -> Frame {
+        if self.transaction.take().is_none() {
+            return Frame::Error("ERR DISCARD without MULTI".into());
+        }
+        self.unwatch();
+        Frame::Simple("OK".into())
+    }
+
+    /// WATCH: make the next EXEC fail if any of `keys` is modified before it
+    fn watch(&mut self, keys: Vec<String>) -> Frame {
+        if self.transaction.is_some() {
+            return Frame::Error("ERR WATCH inside MULTI is not allowed".into());
+        }
+
+        for key in keys {
+            if let Entry::Vacant(entry) = self.watched.entry(key) {
+                let version = self.db.lock(entry.key()).watch(entry.key());
+                entry.insert(version);
+            }
+        }
+        Frame::Simple("OK".into())
+    }
+
+    /// Forget every watched key
+    fn unwatch(&mut self) {
+        if self.watched.is_empty() {
+            return;
+        }
+
+        for key in mem::take(&mut self.watched).keys() {
+            self.db.lock(key).unwatch(key);
+        }
+    }
+
+    /// Send the keyspace to a replica that sent SYNC, then every write
+    /// command applied from then on, until the replica disconnects.
+    async fn serve_replica(&mut self) -> crate::Result<()> {
+        let (snapshot, mut writes) = self.db.sync_replica();
+        info!(addr = %self.client.addr(), "replica connected");
+        self.connection
+            .write_frame(&Frame::Bulk(Bytes::from(snapshot)))
+            .await?;
+
+        loop {
+            let frame = tokio::select! {
+                frame = writes.recv() => frame,
+                _ = self.shutdown.recv() => return Ok(()),
+                _ = self.client.killed() => return Ok(()),
+                // Replicas send nothing else; only watch for the disconnection
+                frame = self.connection.read_frame() => match frame? {
+                    Some(_) => continue,
+                    None => return Ok(()),
+                },
+            };
+            // The feed is closed when the replica must sync again
+            let Some(frame) = frame else {
+                return Ok(());
+            };
+
+            // Send everything already queued with a single flush
+            let mut frames = vec![frame];
+            while let Ok(frame) = writes.try_recv() {
+                frames.push(frame);
+            }
+            self.connection.write_frames(&frames).await?;
+            self.update_client_info();
+        }
+    }
+
+    /// Run the connection in subscriber mode, starting with `cmd`.
+    ///
+    /// Only subscription commands and PING are accepted while there are
+    /// active subscriptions; published messages are forwarded as they
+    /// arrive. Returns `false` if the peer closed the connection, the client
+    /// was killed or the server is shutting down.
+    async fn subscriber_mode(&mut self, cmd: Command) -> crate::Result<bool> {
+        let mut subs = Subscriptions::default();
+        let result = self.serve_subscriptions(&mut subs, cmd).await;
+
+        // Drop the receivers first, so channels nobody else listens to are
+        // forgotten
+        let channels: Vec<_> = subs.channels.keys().cloned().collect();
+        let patterns: Vec<_> = subs.patterns.keys().cloned().collect();
+        drop(subs);
+        let mut pubsub = self.db.pubsub();
+        for channel in channels {
+            pubsub.unsubscribe(&channel);
+        }
+        for pattern in patterns {
+            pubsub.punsubscribe(&pattern);
+        }
+
+        result
+    }
+
+    /// Serve subscriber mode until the last subscription is dropped
+    async fn serve_subscriptions(
+        &mut self,
+        subs: &mut Subscriptions,
+        cmd: Command,
+    ) -> crate::Result<bool> {
+        self.apply_subscription(subs, cmd).await?;
+        self.update_client_info();
+
+        while subs.count() > 0 {
+            tokio::select! {
+                Some((_, message)) = subs.channels.next() => {
+                    self.connection.write_frame(&message).await?;
+                }
+                Some((_, message)) = subs.patterns.next() => {
+                    self.connection.write_frame(&message).await?;
+                }
+                _ = self.shutdown.recv() => return Ok(false),
+                _ = self.client.killed() => return Ok(false),
+                frame = self.connection.read_frame() => {
+                    let Some(frame) = frame? else {
+                        return Ok(false);
+                    };
+
+                    let cmd = Command::from_frame(frame);
+                    self.record_command(cmd.as_ref().map_or("unknown", Command::get_name));
+                    match cmd.and_then(|cmd| self.authorize(cmd)) {
+                        Ok(cmd) => self.apply_subscription(subs, cmd).await?,
+                        Err(err) => {
+                            let response = Frame::Error(err.to_string());
+                            self.connection.write_frame(&response).await?;
+                        }
+                    }
+                }
+            }
+            self.update_client_info();
+        }
+
+        Ok(true)
+    }
+
+    /// Apply a command received in subscriber mode
+    async fn apply_subscription(
+        &mut self,
+        subs: &mut Subscriptions,
+        cmd: Command,
+    ) -> crate::Result<()> {
+        match cmd {
+            Command::Subscribe(cmd) => {
+                for channel in cmd.channels {
+                    let rx = self.db.pubsub().subscribe(&channel);
+                    subs.channels
+                        .insert(channel.clone(), channel_messages(channel.clone(), rx));
+                    let response = self.confirmation("subscribe", Some(channel), subs);
+                    self.connection.write_frame(&response).await?;
+                }
+            }
+            Command::PSubscribe(cmd) => {
+                for pattern in cmd.patterns {
+                    let rx = self.db.pubsub().psubscribe(&pattern);
+                    subs.patterns
+                        .insert(pattern.clone(), pattern_messages(pattern.clone(), rx));
+                    let response = self.confirmation("psubscribe", Some(pattern), subs);
+                    self.connection.write_frame(&response).await?;
+                }
+            }
+            Command::Unsubscribe(cmd) => {
+                // No arguments means every channel
+                let channels = match cmd.channels {
+                    channels if channels.is_empty() => subs.channels.keys().cloned().collect(),
+                    channels => channels,
+                };
+                if channels.is_empty() {
+                    let response = self.confirmation("unsubscribe", None, subs);
+                    self.connection.write_frame(&response).await?;
+                }
+                for channel in channels {
+                    subs.channels.remove(&channel);
+                    self.db.pubsub().unsubscribe(&channel);
+                    let response = self.confirmation("unsubscribe", Some(channel), subs);
+                    self.connection.write_frame(&response).await?;
+                }
+            }
+            Command::PUnsubscribe(cmd) => {
+                let patterns = match cmd.patterns {
+                    patterns if patterns.is_empty() => subs.patterns.keys().cloned().collect(),
+                    patterns => patterns,
+                };
+                if patterns.is_empty() {
+                    let response = self.confirmation("punsubscribe", None, subs);
+                    self.connection.write_frame(&response).await?;
+                }
+                for pattern in patterns {
+                    subs.patterns.remove(&pattern);
+                    self.db.pubsub().punsubscribe(&pattern);
+                    let response = self.confirmation("punsubscribe", Some(pattern), subs);
+                    self.connection.write_frame(&response).await?;
+                }
+            }
+            Command::Ping(cmd) => {
+                let response = cmd.apply_subscribed();
+                self.connection.write_frame(&response).await?;
+            }
+            cmd => {
+                let response = Frame::Error(format!(
+                    "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed in this context",
+                    cmd.get_name()
+                ));
+                self.connection.write_frame(&response).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build a `[kind, name, count]` (un)subscription reply, recording the
+    /// new subscription counts for CLIENT LIST
+    fn confirmation(&self, kind: &str, name: Option<String>, subs: &Subscriptions) -> Frame {
+        let mut info = self.client.info();
+        info.subscriptions = subs.channels.len();
+        info.patterns = subs.patterns.len();
+
+        let name = match name {
+            Some(name) => Frame::Bulk(Bytes::from(name)),
+            None => Frame::Null,
+        };
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from(kind.to_string())),
+            name,
+            Frame::Integer(subs.count() as u64),
+        ])
+    }
+}
+
+/// Turn a channel receiver into a stream of `message` replies
+fn channel_messages(channel: String, mut rx: broadcast::Receiver<Bytes>) -> Messages {
+    Box::pin(async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok(message) => yield Frame::Array(vec![
+                    Frame::Bulk(Bytes::from_static(b"message")),
+                    Frame::Bulk(Bytes::from(channel.clone())),
+                    Frame::Bulk(message),
+                ]),
+                // A slow subscriber skips the messages it missed
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Turn a pattern receiver into a stream of `pmessage` replies
+fn pattern_messages(pattern: String, mut rx: broadcast::Receiver<(String, Bytes)>) -> Messages {
+    Box::pin(async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok((channel, message)) => yield Frame::Array(vec![
+                    Frame::Bulk(Bytes::from_static(b"pmessage")),
+                    Frame::Bulk(Bytes::from(pattern.clone())),
+                    Frame::Bulk(Bytes::from(channel)),
+                    Frame::Bulk(message),
+                ]),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{panic, thread};
+
+    use tokio::net::TcpStream;
+
+    use super::*;
+    use crate::cmd::command_frame;
+
+    async fn call(conn: &mut Connection, line: &str) -> Option<Frame> {
+        let frame = command_frame(line.split(' ').map(|arg| Bytes::from(arg.to_string())));
+        conn.write_frame(&frame).await.ok()?;
+        conn.read_frame().await.ok()?
+    }
+
+    #[tokio::test]
+    async fn poisoned_shards_keep_serving() {
+        let db = Db::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = db.clone();
+        tokio::spawn(async move {
+            let (notify_shutdown, _) = broadcast::channel(1);
+            let (shutdown_complete, _rx) = mpsc::channel(1);
+            accept(&listener, &server, &notify_shutdown, &shutdown_complete).await;
+        });
+
+        let mut watcher = Connection::new(TcpStream::connect(addr).await.unwrap());
+        call(&mut watcher, "WATCH key").await.unwrap();
+
+        // A panic while holding the shard of the key poisons it
+        let poisoner = db.clone();
+        let result = thread::spawn(move || {
+            let _ks = poisoner.lock("key");
+            panic::panic_any("poisoning the shard");
+        })
+        .join();
+        assert!(result.is_err());
+
+        // Commands on that shard still work, and so does the cleanup of the
+        // connection once it closes
+        assert!(matches!(
+            call(&mut watcher, "GET key").await,
+            Some(Frame::Null)
+        ));
+        drop(watcher);
+        let mut conn = Connection::new(TcpStream::connect(addr).await.unwrap());
+        call(&mut conn, "SET key v").await.unwrap();
+        while db.clients().len() > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let list = call(&mut conn, "CLIENT LIST").await.unwrap().to_string();
+        assert_eq!(list.lines().count(), 1, "{}", list);
+        assert_eq!(db.clients().len(), 1);
+    }
+}
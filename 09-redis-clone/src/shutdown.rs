@@ -0,0 +1,36 @@
+use tokio::sync::broadcast;
+
+/// Listens for the server shutdown signal.
+///
+/// Shutdown is signalled with a `broadcast::Receiver`: the server drops the
+/// sender, and every connection sees its receiver close. Only one value is
+/// ever "sent", so once shut down, the state is remembered.
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    is_shutdown: bool,
+    notify: broadcast::Receiver<()>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(notify: broadcast::Receiver<()>) -> Shutdown {
+        Shutdown {
+            is_shutdown: false,
+            notify,
+        }
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.is_shutdown
+    }
+
+    /// Wait for the shutdown notice, returning right away if already received
+    pub(crate) async fn recv(&mut self) {
+        if self.is_shutdown {
+            return;
+        }
+
+        // Cannot receive a "lag error" as only one value is ever sent
+        let _ = self.notify.recv().await;
+        self.is_shutdown = true;
+    }
+}
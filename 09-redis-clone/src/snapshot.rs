@@ -0,0 +1,413 @@
+//! Binary snapshot of the keyspace, in the spirit of Redis' RDB files.
+//!
+//! Layout (integers are little endian):
+//!
+//! ```text
+//! "RCLDB" | version: u16 | entry* | 0xff
+//! entry = type: u8 | expires_at_ms: u64 (0 = never) | key | value
+//! string value = len: u32 | bytes
+//! zset value   = count: u32 | (member: len u32 + bytes | score: f64)*
+//! stream value = last_id | count: u32 | (id | fields: u32 | (field | value)*)*
+//!                | groups: u32 | group*
+//! group        = name | last_delivered: id | pending: u32
+//!                | (id | consumer | delivered_at_ms: u64 | deliveries: u64)*
+//!                | consumers: u32 | (name | seen_at_ms: u64)*
+//! id           = ms: u64 | seq: u64
+//! list value   = count: u32 | (len: u32 | bytes)*
+//! ```
+//!
+//! Strings (keys, members, fields, names) are a len: u32 followed by the bytes.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::{
+    db::{from_unix_ms, now_ms, to_unix_ms, Keyspace, Value},
+    list::{End, List},
+    sorted_set::SortedSet,
+    stream::{Pending, Stream, StreamId},
+};
+
+const MAGIC: &[u8] = b"RCLDB";
+const VERSION: u16 = 1;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_SORTED_SET: u8 = 1;
+const TYPE_STREAM: u8 = 2;
+const TYPE_LIST: u8 = 3;
+const EOF: u8 = 0xff;
+
+/// Serialize every live key of the keyspace `shards`
+pub fn encode<'a>(shards: impl IntoIterator<Item = &'a Keyspace>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.put_slice(MAGIC);
+    buf.put_u16_le(VERSION);
+
+    for (key, value, expires_at) in shards.into_iter().flat_map(Keyspace::iter) {
+        let kind = match value {
+            Value::String(_) => TYPE_STRING,
+            Value::SortedSet(_) => TYPE_SORTED_SET,
+            Value::Stream(_) => TYPE_STREAM,
+            Value::List(_) => TYPE_LIST,
+        };
+        buf.put_u8(kind);
+        buf.put_u64_le(expires_at.map_or(0, to_unix_ms));
+        put_bytes(&mut buf, key.as_bytes());
+
+        match value {
+            Value::String(data) => put_bytes(&mut buf, data),
+            Value::SortedSet(set) => {
+                buf.put_u32_le(set.len() as u32);
+                for (member, score) in set.iter() {
+                    put_bytes(&mut buf, member);
+                    buf.put_f64_le(score);
+                }
+            }
+            Value::Stream(stream) => put_stream(&mut buf, stream),
+            Value::List(list) => {
+                buf.put_u32_le(list.len() as u32);
+                for item in list.iter() {
+                    put_bytes(&mut buf, item);
+                }
+            }
+        }
+    }
+
+    buf.put_u8(EOF);
+    buf
+}
+
+/// Rebuild a keyspace from a snapshot. Keys that expired meanwhile are skipped.
+pub fn decode(mut data: &[u8]) -> crate::Result<Keyspace> {
+    let buf = &mut data;
+
+    if take(buf, MAGIC.len())? != MAGIC {
+        return Err("not a snapshot file".into());
+    }
+    let version = get_u16(buf)?;
+    if version != VERSION {
+        return Err(format!("unsupported snapshot version {}", version).into());
+    }
+
+    let mut ks = Keyspace::default();
+    let now = now_ms();
+    loop {
+        let kind = get_u8(buf)?;
+        if kind == EOF {
+            break;
+        }
+
+        let expires_at = get_u64(buf)?;
+        let key = String::from_utf8(get_bytes(buf)?.to_vec())?;
+        let value = match kind {
+            TYPE_STRING => Value::String(get_bytes(buf)?),
+            TYPE_SORTED_SET => {
+                let mut set = SortedSet::new();
+                for _ in 0..get_u32(buf)? {
+                    let member = get_bytes(buf)?;
+                    set.insert(member, get_f64(buf)?);
+                }
+                Value::SortedSet(set)
+            }
+            TYPE_STREAM => Value::Stream(get_stream(buf)?),
+            TYPE_LIST => {
+                let mut list = List::new();
+                for _ in 0..get_u32(buf)? {
+                    list.push(End::Right, get_bytes(buf)?);
+                }
+                Value::List(list)
+            }
+            kind => return Err(format!("unknown value type {}", kind).into()),
+        };
+
+        if expires_at == 0 {
+            ks.insert(key, value);
+        } else if expires_at > now {
+            ks.insert(key.clone(), value);
+            ks.set_expiry(&key, Some(from_unix_ms(expires_at)));
+        }
+    }
+
+    Ok(ks)
+}
+
+/// Write `data` to `path` atomically: a crash never leaves a partial file.
+///
+/// The data reaches the disk before the rename, and the rename before this
+/// returns, so a power loss leaves either the old or the new snapshot.
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    sync_dir(path)
+}
+
+/// Flush the directory holding `path`, so that entries renamed into it are
+/// on disk. Directories can't be opened for that on Windows.
+fn sync_dir(path: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Load the snapshot at `path`, if there is one
+pub fn load(path: &Path) -> crate::Result<Option<Keyspace>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(decode(&data)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.put_u32_le(data.len() as u32);
+    buf.put_slice(data);
+}
+
+fn put_id(buf: &mut Vec<u8>, id: StreamId) {
+    buf.put_u64_le(id.ms);
+    buf.put_u64_le(id.seq);
+}
+
+fn put_stream(buf: &mut Vec<u8>, stream: &Stream) {
+    put_id(buf, stream.last_id());
+    buf.put_u32_le(stream.len() as u32);
+    for (id, fields) in stream.iter() {
+        put_id(buf, id);
+        buf.put_u32_le(fields.len() as u32);
+        for (field, value) in fields {
+            put_bytes(buf, field);
+            put_bytes(buf, value);
+        }
+    }
+
+    let groups: Vec<_> = stream.groups().collect();
+    buf.put_u32_le(groups.len() as u32);
+    for (name, group) in groups {
+        put_bytes(buf, name.as_bytes());
+        put_id(buf, group.last_delivered());
+        buf.put_u32_le(group.pending().len() as u32);
+        for (id, pending) in group.pending() {
+            put_id(buf, *id);
+            put_bytes(buf, pending.consumer.as_bytes());
+            buf.put_u64_le(pending.delivered_at);
+            buf.put_u64_le(pending.deliveries);
+        }
+        let consumers: Vec<_> = group.consumers().collect();
+        buf.put_u32_le(consumers.len() as u32);
+        for (name, _, seen_at) in consumers {
+            put_bytes(buf, name.as_bytes());
+            buf.put_u64_le(seen_at);
+        }
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> crate::Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err("snapshot file is truncated".into());
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+fn get_u8(buf: &mut &[u8]) -> crate::Result<u8> {
+    Ok(take(buf, 1)?.get_u8())
+}
+
+fn get_u16(buf: &mut &[u8]) -> crate::Result<u16> {
+    Ok(take(buf, 2)?.get_u16_le())
+}
+
+fn get_u32(buf: &mut &[u8]) -> crate::Result<u32> {
+    Ok(take(buf, 4)?.get_u32_le())
+}
+
+fn get_u64(buf: &mut &[u8]) -> crate::Result<u64> {
+    Ok(take(buf, 8)?.get_u64_le())
+}
+
+fn get_f64(buf: &mut &[u8]) -> crate::Result<f64> {
+    Ok(take(buf, 8)?.get_f64_le())
+}
+
+fn get_id(buf: &mut &[u8]) -> crate::Result<StreamId> {
+    Ok(StreamId {
+        ms: get_u64(buf)?,
+        seq: get_u64(buf)?,
+    })
+}
+
+fn get_string(buf: &mut &[u8]) -> crate::Result<String> {
+    Ok(String::from_utf8(get_bytes(buf)?.to_vec())?)
+}
+
+fn get_stream(buf: &mut &[u8]) -> crate::Result<Stream> {
+    let mut stream = Stream::new();
+    let last_id = get_id(buf)?;
+    for _ in 0..get_u32(buf)? {
+        let id = get_id(buf)?;
+        let fields = (0..get_u32(buf)?)
+            .map(|_| Ok((get_bytes(buf)?, get_bytes(buf)?)))
+            .collect::<crate::Result<_>>()?;
+        stream.insert(id, fields);
+    }
+    stream.set_last_id(last_id);
+
+    for _ in 0..get_u32(buf)? {
+        let name = get_string(buf)?;
+        stream.create_group(&name, get_id(buf)?);
+        let group = stream.group_mut(&name).unwrap();
+        for _ in 0..get_u32(buf)? {
+            let id = get_id(buf)?;
+            let pending = Pending {
+                consumer: get_string(buf)?,
+                delivered_at: get_u64(buf)?,
+                deliveries: get_u64(buf)?,
+            };
+            group.set_pending(id, pending);
+        }
+        for _ in 0..get_u32(buf)? {
+            let name = get_string(buf)?;
+            group.add_consumer(&name, get_u64(buf)?);
+        }
+    }
+    Ok(stream)
+}
+
+fn get_bytes(buf: &mut &[u8]) -> crate::Result<Bytes> {
+    let len = get_u32(buf)? as usize;
+    Ok(Bytes::copy_from_slice(take(buf, len)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::stream::NewId;
+
+    #[test]
+    fn round_trip_keeps_values_and_expiries() {
+        let mut ks = Keyspace::default();
+        ks.insert("name".into(), Value::String("redis".into()));
+
+        let mut set = SortedSet::new();
+        set.insert("a".into(), 1.5);
+        set.insert("b".into(), -2.0);
+        ks.insert("board".into(), Value::SortedSet(set));
+
+        ks.insert("session".into(), Value::String("token".into()));
+        ks.set_expiry("session", Some(Instant::now() + Duration::from_secs(60)));
+
+        let mut list = List::new();
+        list.push(End::Right, "first".into());
+        list.push(End::Right, "second".into());
+        ks.insert("queue".into(), Value::List(list));
+
+        let ks = decode(&encode([&ks])).unwrap();
+        assert_eq!(ks.len(), 4);
+        assert!(matches!(ks.get("name"), Some(Value::String(v)) if v == "redis"));
+
+        let set = ks.sorted_set("board").unwrap().unwrap();
+        assert_eq!(set.score(b"a"), Some(1.5));
+        assert_eq!(set.rank(b"b"), Some(0));
+
+        let list: Vec<_> = ks.list("queue").unwrap().unwrap().iter().cloned().collect();
+        assert_eq!(list, ["first", "second"]);
+
+        let remaining = ks.expires_at("session").unwrap() - Instant::now();
+        assert!(remaining > Duration::from_secs(58));
+        assert!(ks.expires_at("name").is_none());
+    }
+
+    #[test]
+    fn round_trip_keeps_streams_and_groups() {
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            let fields = vec![("n".into(), ms.to_string().into())];
+            stream.add(NewId::Seq(ms), 0, fields).unwrap();
+        }
+        stream.create_group("workers", StreamId::MIN);
+        stream
+            .read_group("workers", "alice", 2, false, 100)
+            .unwrap();
+        stream
+            .group_mut("workers")
+            .unwrap()
+            .ack(StreamId { ms: 1, seq: 0 });
+        stream.read_group("workers", "bob", 1, false, 200).unwrap();
+        stream
+            .group_mut("workers")
+            .unwrap()
+            .add_consumer("idle", 300);
+        let mut ks = Keyspace::default();
+        ks.insert("jobs".into(), Value::Stream(stream));
+
+        let ks = decode(&encode([&ks])).unwrap();
+        let stream = ks.stream("jobs").unwrap().unwrap();
+        assert_eq!(stream.len(), 3);
+        assert_eq!(stream.last_id(), StreamId { ms: 3, seq: 0 });
+        let fields = stream.get(StreamId { ms: 2, seq: 0 }).unwrap();
+        assert_eq!(fields, &vec![("n".into(), "2".into())]);
+
+        let group = stream.group("workers").unwrap();
+        assert_eq!(group.last_delivered(), StreamId { ms: 3, seq: 0 });
+        let pending: Vec<_> = group
+            .pending()
+            .iter()
+            .map(|(id, pending)| {
+                (
+                    id.to_string(),
+                    pending.consumer.as_str(),
+                    pending.delivered_at,
+                )
+            })
+            .collect();
+        assert_eq!(
+            pending,
+            [("2-0".into(), "alice", 100), ("3-0".into(), "bob", 200)]
+        );
+        let consumers: Vec<_> = group.consumers().collect();
+        assert_eq!(
+            consumers,
+            [("alice", 1, 100), ("bob", 1, 200), ("idle", 0, 300)]
+        );
+    }
+
+    #[test]
+    fn expired_keys_are_dropped() {
+        let mut data = encode([&Keyspace::default()]);
+        data.pop();
+        data.put_u8(TYPE_STRING);
+        data.put_u64_le(1_000); // 1970
+        put_bytes(&mut data, b"old");
+        put_bytes(&mut data, b"value");
+        data.put_u8(EOF);
+
+        assert!(decode(&data).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let data = encode([&Keyspace::default()]);
+        assert!(decode(b"garbage").is_err());
+        assert!(decode(&data[..data.len() - 1]).is_err());
+
+        let mut other_version = data.clone();
+        other_version[MAGIC.len()] = 9;
+        assert!(decode(&other_version).is_err());
+    }
+}
@@ -0,0 +1,476 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use bytes::Bytes;
+
+/// Bound of a score range, as used by `ZRANGE ... BYSCORE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    /// Check if `score` is above (or at) this bound used as a minimum
+    fn above_min(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(min) => score >= min,
+            ScoreBound::Exclusive(min) => score > min,
+        }
+    }
+
+    /// Check if `score` is below (or at) this bound used as a maximum
+    fn below_max(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        }
+    }
+}
+
+/// Approximate bookkeeping bytes of a member, besides its own bytes: the
+/// tree node and the entry of the score map
+const MEMBER_OVERHEAD: usize = 64;
+
+type Link = Option<Box<Node>>;
+
+/// Node of the treap. Ordered by (score, member), heap-ordered by priority.
+#[derive(Debug)]
+struct Node {
+    score: f64,
+    member: Bytes,
+    priority: u64,
+    // Number of nodes in the subtree rooted here, used for rank lookups
+    size: usize,
+    left: Link,
+    right: Link,
+}
+
+impl Node {
+    fn cmp_key(&self, score: f64, member: &[u8]) -> Ordering {
+        self.score
+            .total_cmp(&score)
+            .then_with(|| self.member[..].cmp(member))
+    }
+
+    fn update(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+fn size(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+/// Split a tree into the nodes lower than (score, member) and the rest.
+fn split(link: Link, score: f64, member: &[u8]) -> (Link, Link) {
+    match link {
+        None => (None, None),
+        Some(mut node) => {
+            if node.cmp_key(score, member) == Ordering::Less {
+                let (left, right) = split(node.right.take(), score, member);
+                node.right = left;
+                node.update();
+                (Some(node), right)
+            } else {
+                let (left, right) = split(node.left.take(), score, member);
+                node.left = right;
+                node.update();
+                (left, Some(node))
+            }
+        }
+    }
+}
+
+/// Join two trees where every node of `left` is lower than every node of `right`.
+fn merge(left: Link, right: Link) -> Link {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update();
+                Some(right)
+            }
+        }
+    }
+}
+
+/// Remove the node (score, member) from the tree. Returns whether it was found.
+fn remove(link: &mut Link, score: f64, member: &[u8]) -> bool {
+    let Some(node) = link else {
+        return false;
+    };
+
+    let removed = match node.cmp_key(score, member) {
+        Ordering::Greater => remove(&mut node.left, score, member),
+        Ordering::Less => remove(&mut node.right, score, member),
+        Ordering::Equal => {
+            let mut node = link.take().unwrap();
+            *link = merge(node.left.take(), node.right.take());
+            return true;
+        }
+    };
+
+    if removed {
+        node.update();
+    }
+    removed
+}
+
+/// Set of unique members ordered by score (ties broken by member bytes).
+///
+/// Members are kept in a treap augmented with subtree sizes, so inserts,
+/// removals and rank lookups take O(log n) expected time. A hash map from
+/// member to score makes `score` lookups O(1).
+#[derive(Debug)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    root: Link,
+    // State of the xorshift generator used for node priorities
+    seed: u64,
+    // Total length of the members, for memory accounting
+    member_bytes: usize,
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        SortedSet::new()
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        SortedSet {
+            scores: HashMap::new(),
+            root: None,
+            seed: 0x2545_f491_4f6c_dd1d,
+            member_bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Rough number of bytes used by the set
+    pub fn approx_size(&self) -> usize {
+        self.member_bytes + self.len() * MEMBER_OVERHEAD
+    }
+
+    /// Score of `member`, if present
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add `member` with `score`, or update its score if already present.
+    ///
+    /// Returns `true` if the member was newly added.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let added = match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                remove(&mut self.root, old, &member);
+                false
+            }
+            None => {
+                self.member_bytes += member.len();
+                true
+            }
+        };
+
+        let node = Box::new(Node {
+            score,
+            member,
+            priority: self.next_priority(),
+            size: 1,
+            left: None,
+            right: None,
+        });
+        let (left, right) = split(self.root.take(), node.score, &node.member);
+        self.root = merge(merge(left, Some(node)), right);
+
+        added
+    }
+
+    /// Remove `member`. Returns `true` if it was present.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.member_bytes -= member.len();
+                remove(&mut self.root, score, member)
+            }
+            None => false,
+        }
+    }
+
+    /// 0-based position of `member` in ascending score order
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+
+        let mut rank = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            match node.cmp_key(score, member) {
+                Ordering::Greater => link = &node.left,
+                Ordering::Less => {
+                    rank += size(&node.left) + 1;
+                    link = &node.right;
+                }
+                Ordering::Equal => return Some(rank + size(&node.left)),
+            }
+        }
+        None
+    }
+
+    /// Iterate over (member, score) pairs in ascending order, starting at rank `start`.
+    pub fn iter_from(&self, start: usize) -> Iter<'_> {
+        let mut iter = Iter { stack: Vec::new() };
+
+        let mut skip = start;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let left = size(&node.left);
+            match skip.cmp(&left) {
+                Ordering::Less => {
+                    iter.stack.push(node);
+                    link = &node.left;
+                }
+                Ordering::Equal => {
+                    iter.stack.push(node);
+                    break;
+                }
+                Ordering::Greater => {
+                    skip -= left + 1;
+                    link = &node.right;
+                }
+            }
+        }
+        iter
+    }
+
+    /// Iterate over every (member, score) pair in ascending order
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_from(0)
+    }
+
+    /// Members with a rank between `start` and `stop` (both inclusive)
+    pub fn range_by_rank(&self, start: usize, stop: usize) -> impl Iterator<Item = (&Bytes, f64)> {
+        let count = (stop + 1).saturating_sub(start);
+        self.iter_from(start).take(count)
+    }
+
+    /// Members with a score between `min` and `max`
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.iter_from(self.count_below(min))
+            .take_while(move |(_, score)| max.below_max(*score))
+    }
+
+    /// Number of members with a score lower than `min`
+    fn count_below(&self, min: ScoreBound) -> usize {
+        let mut count = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            if min.above_min(node.score) {
+                link = &node.left;
+            } else {
+                count += size(&node.left) + 1;
+                link = &node.right;
+            }
+        }
+        count
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+/// In-order iterator over a `SortedSet`
+pub struct Iter<'a> {
+    // Nodes still to yield, the next one on top
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+
+        let mut link = &node.right;
+        while let Some(child) = link {
+            self.stack.push(child);
+            link = &child.left;
+        }
+
+        Some((&node.member, node.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference implementation: a Vec kept sorted by (score, member)
+    #[derive(Default)]
+    struct Naive {
+        items: Vec<(f64, Bytes)>,
+    }
+
+    impl Naive {
+        fn insert(&mut self, member: Bytes, score: f64) -> bool {
+            let added = !self.remove(&member);
+            self.items.push((score, member));
+            self.items
+                .sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+            added
+        }
+
+        fn remove(&mut self, member: &[u8]) -> bool {
+            let len = self.items.len();
+            self.items.retain(|(_, m)| m != member);
+            len != self.items.len()
+        }
+    }
+
+    // Small deterministic generator so the test needs no extra crates
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+    }
+
+    fn collect<'a>(iter: impl Iterator<Item = (&'a Bytes, f64)>) -> Vec<(f64, Bytes)> {
+        iter.map(|(member, score)| (score, member.clone()))
+            .collect()
+    }
+
+    fn assert_same(set: &SortedSet, naive: &Naive) {
+        assert_eq!(set.len(), naive.items.len());
+        assert_eq!(collect(set.iter()), naive.items);
+
+        for (rank, (score, member)) in naive.items.iter().enumerate() {
+            assert_eq!(set.rank(member), Some(rank));
+            assert_eq!(set.score(member), Some(*score));
+        }
+    }
+
+    #[test]
+    fn matches_sorted_vec_under_random_operations() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut set = SortedSet::new();
+        let mut naive = Naive::default();
+
+        for _ in 0..2000 {
+            let member = Bytes::from(format!("m{}", rng.next(200)));
+            // Few distinct scores so ties on score are frequent
+            let score = rng.next(50) as f64 - 25.0;
+
+            if rng.next(4) == 0 {
+                assert_eq!(set.remove(&member), naive.remove(&member));
+            } else {
+                assert_eq!(
+                    set.insert(member.clone(), score),
+                    naive.insert(member, score)
+                );
+            }
+        }
+
+        assert_same(&set, &naive);
+    }
+
+    #[test]
+    fn range_by_rank_matches_sorted_vec() {
+        let mut rng = Rng(42);
+        let mut set = SortedSet::new();
+        let mut naive = Naive::default();
+        for i in 0..300 {
+            let member = Bytes::from(format!("m{i}"));
+            let score = rng.next(1000) as f64 / 10.0;
+            set.insert(member.clone(), score);
+            naive.insert(member, score);
+        }
+
+        for _ in 0..200 {
+            let start = rng.next(320) as usize;
+            let stop = rng.next(320) as usize;
+            let expected: Vec<_> = naive
+                .items
+                .iter()
+                .skip(start)
+                .take((stop + 1).saturating_sub(start))
+                .cloned()
+                .collect();
+            assert_eq!(collect(set.range_by_rank(start, stop)), expected);
+        }
+    }
+
+    #[test]
+    fn range_by_score_matches_sorted_vec() {
+        let mut rng = Rng(7);
+        let mut set = SortedSet::new();
+        let mut naive = Naive::default();
+        for i in 0..300 {
+            let member = Bytes::from(format!("m{i}"));
+            let score = rng.next(40) as f64;
+            set.insert(member.clone(), score);
+            naive.insert(member, score);
+        }
+
+        let bound = |rng: &mut Rng| {
+            let value = rng.next(44) as f64 - 2.0;
+            if rng.next(2) == 0 {
+                ScoreBound::Inclusive(value)
+            } else {
+                ScoreBound::Exclusive(value)
+            }
+        };
+
+        for _ in 0..200 {
+            let (min, max) = (bound(&mut rng), bound(&mut rng));
+            let expected: Vec<_> = naive
+                .items
+                .iter()
+                .filter(|(score, _)| min.above_min(*score) && max.below_max(*score))
+                .cloned()
+                .collect();
+            assert_eq!(collect(set.range_by_score(min, max)), expected);
+        }
+    }
+
+    #[test]
+    fn infinite_bounds_cover_everything() {
+        let mut set = SortedSet::new();
+        set.insert(Bytes::from("a"), f64::NEG_INFINITY);
+        set.insert(Bytes::from("b"), 0.0);
+        set.insert(Bytes::from("c"), f64::INFINITY);
+
+        let all = ScoreBound::Inclusive(f64::NEG_INFINITY);
+        let none = ScoreBound::Exclusive(f64::NEG_INFINITY);
+        let top = ScoreBound::Inclusive(f64::INFINITY);
+
+        assert_eq!(set.range_by_score(all, top).count(), 3);
+        assert_eq!(set.range_by_score(none, top).count(), 2);
+        assert_eq!(set.rank(b"c"), Some(2));
+    }
+}
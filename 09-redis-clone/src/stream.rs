@@ -0,0 +1,520 @@
+//! Append-only log of entries, each a list of field-value pairs under a
+//! unique, increasing ID, with consumer groups sharing out the entries.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    ops::Bound,
+    str::FromStr,
+};
+
+use bytes::Bytes;
+
+/// Approximate bookkeeping bytes of an entry, besides its fields
+const ENTRY_OVERHEAD: usize = 48;
+
+/// Approximate bytes of a pending entry of a consumer group
+const PENDING_OVERHEAD: usize = 64;
+
+/// Error returned for malformed IDs
+pub const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// Error returned when no ID is left after the last one
+const TOO_LARGE: &str =
+    "ERR The stream has exhausted the last possible ID, unable to add more items";
+
+/// ID of a stream entry: the time it was added in ms, and a sequence number
+/// among entries of the same ms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// The next possible ID, if any
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    /// Parse `ms-seq`, or `ms` alone with `seq` as the sequence number
+    pub fn parse_or(s: &str, seq: u64) -> crate::Result<StreamId> {
+        match s.split_once('-') {
+            Some((ms, exact)) => Ok(StreamId {
+                ms: ms.parse().map_err(|_| INVALID_ID)?,
+                seq: exact.parse().map_err(|_| INVALID_ID)?,
+            }),
+            None => Ok(StreamId {
+                ms: s.parse().map_err(|_| INVALID_ID)?,
+                seq,
+            }),
+        }
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<StreamId> {
+        StreamId::parse_or(s, 0)
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// ID requested by XADD
+#[derive(Debug, Clone, Copy)]
+pub enum NewId {
+    /// `*`: the current time, or after the last ID if the clock is behind
+    Auto,
+    /// `ms-*`: the next sequence number for this ms
+    Seq(u64),
+    /// `ms-seq`
+    Exact(StreamId),
+}
+
+/// Fields of an entry, in the order they were given
+pub type Fields = Vec<(Bytes, Bytes)>;
+
+/// Delivery of an entry to a consumer, until it is acknowledged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    pub consumer: String,
+    /// Unix time of the last delivery, in ms
+    pub delivered_at: u64,
+    /// Number of times the entry was delivered
+    pub deliveries: u64,
+}
+
+/// Consumer of a group
+#[derive(Debug, Default)]
+struct Consumer {
+    // IDs delivered to this consumer and not acknowledged yet
+    pending: BTreeSet<StreamId>,
+    // Unix time of the last read, in ms
+    seen_at: u64,
+}
+
+/// Consumer group: every entry is delivered to one of its consumers, and
+/// stays pending until acknowledged
+#[derive(Debug, Default)]
+pub struct Group {
+    last_delivered: StreamId,
+    pending: BTreeMap<StreamId, Pending>,
+    consumers: BTreeMap<String, Consumer>,
+}
+
+impl Group {
+    /// ID of the last entry delivered to the group
+    pub fn last_delivered(&self) -> StreamId {
+        self.last_delivered
+    }
+
+    /// Make the group deliver the entries after `id` next (XGROUP SETID)
+    pub fn set_last_delivered(&mut self, id: StreamId) {
+        self.last_delivered = id;
+    }
+
+    /// Entries delivered and not acknowledged, by ID
+    pub fn pending(&self) -> &BTreeMap<StreamId, Pending> {
+        &self.pending
+    }
+
+    /// Every consumer with the number of its pending entries and the last
+    /// time it read, in name order
+    pub fn consumers(&self) -> impl Iterator<Item = (&str, usize, u64)> {
+        self.consumers
+            .iter()
+            .map(|(name, consumer)| (name.as_str(), consumer.pending.len(), consumer.seen_at))
+    }
+
+    /// Get `name`, creating it if needed, and mark it as seen `now`
+    fn consumer(&mut self, name: &str, now: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.to_string()).or_default();
+        consumer.seen_at = now;
+        consumer
+    }
+
+    /// Add a consumer, e.g. when loading a snapshot
+    pub fn add_consumer(&mut self, name: &str, seen_at: u64) {
+        self.consumer(name, seen_at);
+    }
+
+    /// Make `id` pending for `pending.consumer`, who replaces the consumer
+    /// it was pending for
+    pub fn set_pending(&mut self, id: StreamId, pending: Pending) {
+        if let Some(old) = self.pending.get(&id) {
+            if let Some(consumer) = self.consumers.get_mut(&old.consumer) {
+                consumer.pending.remove(&id);
+            }
+        }
+        self.consumers
+            .entry(pending.consumer.clone())
+            .or_default()
+            .pending
+            .insert(id);
+        self.pending.insert(id, pending);
+    }
+
+    /// Acknowledge `id`. Returns `false` if it was not pending.
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        let Some(pending) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&pending.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+}
+
+/// A stream: entries ordered by ID, and its consumer groups
+#[derive(Debug, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    // Greater than every ID, even if the entries were deleted
+    last_id: StreamId,
+    groups: BTreeMap<String, Group>,
+    // Total length of the fields, for memory accounting
+    field_bytes: usize,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Rough number of bytes used by the stream
+    pub fn approx_size(&self) -> usize {
+        let pending: usize = self.groups.values().map(|group| group.pending.len()).sum();
+        self.field_bytes + self.len() * ENTRY_OVERHEAD + pending * PENDING_OVERHEAD
+    }
+
+    /// ID of the last entry ever added
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Add an entry, with an ID given by `id` and the current Unix time in
+    /// ms, `now`. Returns the ID.
+    pub fn add(&mut self, id: NewId, now: u64, fields: Fields) -> crate::Result<StreamId> {
+        let last = self.last_id;
+        let id = match id {
+            NewId::Auto if now > last.ms => StreamId { ms: now, seq: 0 },
+            NewId::Auto => last.next().ok_or(TOO_LARGE)?,
+            NewId::Seq(ms) if ms == last.ms => {
+                last.next().filter(|id| id.ms == ms).ok_or(TOO_LARGE)?
+            }
+            NewId::Seq(ms) => StreamId { ms, seq: 0 },
+            NewId::Exact(id) => id,
+        };
+
+        if id == StreamId::MIN {
+            return Err("ERR The ID specified in XADD must be greater than 0-0".into());
+        }
+        if id <= last {
+            return Err(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .into(),
+            );
+        }
+
+        self.insert(id, fields);
+        Ok(id)
+    }
+
+    /// Insert an entry with an ID greater than every other, e.g. when
+    /// loading a snapshot
+    pub fn insert(&mut self, id: StreamId, fields: Fields) {
+        self.field_bytes += fields
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum::<usize>();
+        self.entries.insert(id, fields);
+        self.last_id = self.last_id.max(id);
+    }
+
+    /// Raise the last ID, so new entries get greater IDs
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = self.last_id.max(id);
+    }
+
+    /// Fields of the entry `id`
+    pub fn get(&self, id: StreamId) -> Option<&Fields> {
+        self.entries.get(&id)
+    }
+
+    /// Entries between `start` and `end`, in ID order
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+    ) -> impl DoubleEndedIterator<Item = (StreamId, &Fields)> {
+        use Bound::*;
+
+        // BTreeMap::range panics on reversed bounds
+        let empty = match (start, end) {
+            (Included(start), Included(end)) => start > end,
+            (Included(start) | Excluded(start), Included(end) | Excluded(end)) => start >= end,
+            _ => false,
+        };
+        let (start, end) = if empty {
+            (Excluded(StreamId::MAX), Unbounded)
+        } else {
+            (start, end)
+        };
+        self.entries
+            .range((start, end))
+            .map(|(id, fields)| (*id, fields))
+    }
+
+    /// Every entry, in ID order
+    pub fn iter(&self) -> impl Iterator<Item = (StreamId, &Fields)> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut Group> {
+        self.groups.get_mut(name)
+    }
+
+    /// Every group, in name order
+    pub fn groups(&self) -> impl Iterator<Item = (&str, &Group)> {
+        self.groups
+            .iter()
+            .map(|(name, group)| (name.as_str(), group))
+    }
+
+    /// Create a group that delivers the entries after `last_delivered`.
+    /// Returns `false` if it already exists.
+    pub fn create_group(&mut self, name: &str, last_delivered: StreamId) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        let group = Group {
+            last_delivered,
+            ..Group::default()
+        };
+        self.groups.insert(name.to_string(), group);
+        true
+    }
+
+    /// Deliver up to `count` entries never delivered to the group `name` to
+    /// `consumer`, at Unix time `now` in ms. Unless `noack`, they stay
+    /// pending until acknowledged. `None` if the group does not exist.
+    pub fn read_group(
+        &mut self,
+        name: &str,
+        consumer: &str,
+        count: usize,
+        noack: bool,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Fields)>> {
+        let group = self.groups.get_mut(name)?;
+        group.consumer(consumer, now);
+
+        let entries: Vec<_> = self
+            .entries
+            .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+            .take(count)
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect();
+        if let Some((last, _)) = entries.last() {
+            group.last_delivered = *last;
+        }
+        if !noack {
+            for (id, _) in &entries {
+                let pending = Pending {
+                    consumer: consumer.to_string(),
+                    delivered_at: now,
+                    deliveries: 1,
+                };
+                group.set_pending(*id, pending);
+            }
+        }
+        Some(entries)
+    }
+
+    /// Deliver again up to `count` entries pending for `consumer` in the
+    /// group `name`, with IDs greater than `after`. `None` if the group does
+    /// not exist.
+    pub fn read_pending(
+        &mut self,
+        name: &str,
+        consumer: &str,
+        after: StreamId,
+        count: usize,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Option<Fields>)>> {
+        let group = self.groups.get_mut(name)?;
+        let ids: Vec<_> = group
+            .consumer(consumer, now)
+            .pending
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .take(count)
+            .copied()
+            .collect();
+
+        let mut entries = Vec::with_capacity(ids.len());
+        for id in ids {
+            let pending = group.pending.get_mut(&id).unwrap();
+            pending.delivered_at = now;
+            pending.deliveries += 1;
+            entries.push((id, self.entries.get(&id).cloned()));
+        }
+        Some(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &str) -> Fields {
+        vec![(Bytes::from("v"), Bytes::from(value.to_string()))]
+    }
+
+    fn id(s: &str) -> StreamId {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ids_always_increase() {
+        let mut stream = Stream::new();
+        assert_eq!(
+            stream.add(NewId::Auto, 100, fields("a")).unwrap(),
+            id("100-0")
+        );
+        assert_eq!(
+            stream.add(NewId::Auto, 100, fields("b")).unwrap(),
+            id("100-1")
+        );
+        // The clock went back
+        assert_eq!(
+            stream.add(NewId::Auto, 50, fields("c")).unwrap(),
+            id("100-2")
+        );
+        assert_eq!(
+            stream.add(NewId::Seq(100), 0, fields("d")).unwrap(),
+            id("100-3")
+        );
+        assert_eq!(
+            stream.add(NewId::Seq(200), 0, fields("e")).unwrap(),
+            id("200-0")
+        );
+        assert!(stream
+            .add(NewId::Exact(id("200-0")), 0, fields("f"))
+            .is_err());
+        assert!(stream.add(NewId::Seq(150), 0, fields("f")).is_err());
+        assert_eq!(stream.len(), 5);
+
+        let mut stream = Stream::new();
+        assert!(stream
+            .add(NewId::Exact(StreamId::MIN), 0, fields("a"))
+            .is_err());
+        assert_eq!(
+            stream.add(NewId::Seq(0), 0, fields("a")).unwrap(),
+            id("0-1")
+        );
+        let last = StreamId {
+            ms: 7,
+            seq: u64::MAX,
+        };
+        stream.add(NewId::Exact(last), 0, fields("b")).unwrap();
+        assert!(stream.add(NewId::Seq(7), 0, fields("c")).is_err());
+        assert_eq!(stream.add(NewId::Auto, 0, fields("c")).unwrap(), id("8-0"));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut stream = Stream::new();
+        for ms in 1..=5 {
+            stream.add(NewId::Seq(ms), 0, fields("x")).unwrap();
+        }
+        let ids = |start, end| -> Vec<_> {
+            stream
+                .range(start, end)
+                .map(|(id, _)| id.to_string())
+                .collect()
+        };
+        use Bound::*;
+        assert_eq!(ids(Included(id("2")), Excluded(id("4"))), ["2-0", "3-0"]);
+        assert_eq!(ids(Excluded(id("4")), Unbounded), ["5-0"]);
+        assert!(ids(Included(id("4")), Included(id("2"))).is_empty());
+        assert!(ids(Excluded(id("3")), Excluded(id("3"))).is_empty());
+        assert_eq!(ids(Included(id("3")), Included(id("3"))), ["3-0"]);
+    }
+
+    #[test]
+    fn groups_track_pending_entries_per_consumer() {
+        let mut stream = Stream::new();
+        for ms in 1..=4 {
+            stream.add(NewId::Seq(ms), 0, fields("job")).unwrap();
+        }
+        assert!(stream.create_group("workers", StreamId::MIN));
+        assert!(!stream.create_group("workers", StreamId::MIN));
+        assert!(stream.read_group("nope", "alice", 10, false, 0).is_none());
+
+        let read = stream.read_group("workers", "alice", 3, false, 10).unwrap();
+        assert_eq!(read.len(), 3);
+        let read = stream.read_group("workers", "bob", 3, false, 20).unwrap();
+        assert_eq!(read[0].0, id("4"));
+        assert!(stream
+            .read_group("workers", "bob", 3, false, 20)
+            .unwrap()
+            .is_empty());
+
+        let group = stream.group_mut("workers").unwrap();
+        assert!(group.ack(id("2")));
+        assert!(!group.ack(id("2")));
+        let consumers: Vec<_> = group.consumers().collect();
+        assert_eq!(consumers, [("alice", 2, 10), ("bob", 1, 20)]);
+
+        // Alice reads her history again
+        let read = stream
+            .read_pending("workers", "alice", StreamId::MIN, 10, 30)
+            .unwrap();
+        let ids: Vec<_> = read.iter().map(|(id, _)| id.to_string()).collect();
+        assert_eq!(ids, ["1-0", "3-0"]);
+        let pending = &stream.group("workers").unwrap().pending()[&id("3")];
+        assert_eq!((pending.deliveries, pending.delivered_at), (2, 30));
+
+        // Claiming moves an entry to another consumer
+        let group = stream.group_mut("workers").unwrap();
+        let claimed = Pending {
+            consumer: "bob".to_string(),
+            delivered_at: 40,
+            deliveries: 3,
+        };
+        group.set_pending(id("3"), claimed);
+        let consumers: Vec<_> = group.consumers().collect();
+        assert_eq!(consumers, [("alice", 1, 30), ("bob", 2, 20)]);
+    }
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{call, connect, start_server, start_server_with, temp_dir};
+use mini_redis::Frame;
+use redis_clone::{client::Cmd, Client, Config};
+
+async fn start_server_with_password(password: &str) -> std::net::SocketAddr {
+    let config = Config {
+        dir: temp_dir(),
+        requirepass: Some(password.to_string()),
+        ..Config::default()
+    };
+    start_server_with(config).await.0
+}
+
+#[tokio::test]
+async fn requirepass_needs_auth_first() {
+    let addr = start_server_with_password("secret").await;
+    let mut conn = connect(addr).await;
+
+    assert!(call(&mut conn, "GET name").await[0].contains("NOAUTH"));
+    assert!(call(&mut conn, "ACL WHOAMI").await[0].contains("NOAUTH"));
+    assert!(call(&mut conn, "AUTH wrong").await[0].contains("WRONGPASS"));
+    assert!(call(&mut conn, "AUTH default wrong").await[0].contains("WRONGPASS"));
+
+    assert_eq!(call(&mut conn, "AUTH secret").await, ["OK"]);
+    assert_eq!(call(&mut conn, "SET name redis").await, ["OK"]);
+    assert_eq!(call(&mut conn, "ACL WHOAMI").await, ["default"]);
+
+    let client = Client::connect(addr).await.unwrap();
+    assert!(client.get("name").await.is_err());
+    client.auth(Some("default"), "secret").await.unwrap();
+    assert_eq!(client.get("name").await.unwrap(), Some("redis".into()));
+}
+
+#[tokio::test]
+async fn client_authenticates_again_after_reconnecting() {
+    let addr = start_server_with_password("secret").await;
+    let client = Client::connect(addr).await.unwrap();
+    client.auth(None, "secret").await.unwrap();
+    client.set("name", "redis").await.unwrap();
+
+    // Drop the connection of the client from another one
+    let id = match client.call(Cmd::new("CLIENT").arg("ID")).await.unwrap() {
+        Frame::Integer(id) => id,
+        frame => panic!("unexpected reply: {:?}", frame),
+    };
+    let mut admin = connect(addr).await;
+    call(&mut admin, "AUTH secret").await;
+    assert_eq!(
+        call(&mut admin, &format!("CLIENT KILL ID {}", id)).await,
+        ["1"]
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(client.get("name").await.unwrap(), Some("redis".into()));
+}
+
+#[tokio::test]
+async fn subscribers_log_in_like_their_client() {
+    let addr = start_server_with_password("secret").await;
+    let client = Client::connect(addr).await.unwrap();
+    assert!(client.subscribe(&["news"]).await.is_err());
+
+    client.auth(None, "secret").await.unwrap();
+    let mut subscriber = client.subscribe(&["news"]).await.unwrap();
+    let mut patterns = client.psubscribe(&["news.*"]).await.unwrap();
+    assert_eq!(client.publish("news", "hello").await.unwrap(), 1);
+    assert_eq!(client.publish("news.sport", "ace").await.unwrap(), 1);
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!(message.content, "hello");
+    let message = patterns.next_message().await.unwrap().unwrap();
+    assert_eq!(message.content, "ace");
+}
+
+#[tokio::test]
+async fn auth_without_password_is_an_error() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    // Connections run as the default user right away
+    assert_eq!(call(&mut conn, "ACL WHOAMI").await, ["default"]);
+    assert!(call(&mut conn, "AUTH secret").await[0].contains("without any password configured"));
+    assert!(call(&mut conn, "AUTH nobody secret").await[0].contains("WRONGPASS"));
+}
+
+#[tokio::test]
+async fn users_are_limited_to_their_commands_and_keys() {
+    let addr = start_server().await;
+    let mut admin = connect(addr).await;
+    let mut conn = connect(addr).await;
+
+    let line = "ACL SETUSER cache on >pw ~cache:* +@read +@string +@transaction -keys";
+    assert_eq!(call(&mut admin, line).await, ["OK"]);
+    assert_eq!(call(&mut conn, "AUTH cache pw").await, ["OK"]);
+
+    assert_eq!(call(&mut conn, "SET cache:a 1").await, ["OK"]);
+    assert_eq!(call(&mut conn, "GET cache:a").await, ["1"]);
+    assert_eq!(call(&mut conn, "EXISTS cache:a cache:b").await, ["1"]);
+    let reply = call(&mut conn, "GET users").await;
+    assert!(reply[0].contains("NOPERM No permissions to access a key"));
+    let reply = call(&mut conn, "EXISTS cache:a users").await;
+    assert!(reply[0].contains("NOPERM"));
+    let reply = call(&mut conn, "DEL cache:a").await;
+    assert!(reply[0].contains("NOPERM User cache has no permissions to run the 'del' command"));
+    assert!(call(&mut conn, "KEYS *").await[0].contains("NOPERM"));
+    assert!(call(&mut conn, "ACL LIST").await[0].contains("NOPERM"));
+
+    // Refused commands abort the transaction
+    call(&mut conn, "MULTI").await;
+    call(&mut conn, "SET cache:a 2").await;
+    assert!(call(&mut conn, "SET users 2").await[0].contains("NOPERM"));
+    assert!(call(&mut conn, "EXEC").await[0].contains("EXECABORT"));
+    assert_eq!(call(&mut conn, "GET cache:a").await, ["1"]);
+
+    // Scripts may only call what the user may run
+    call(&mut admin, "ACL SETUSER cache +eval").await;
+    let client = Client::connect(addr).await.unwrap();
+    client.auth(Some("cache"), "pw").await.unwrap();
+    let script = "return call('GET', KEYS[0])";
+    let reply = client.eval(script, &["cache:a"], &[]).await.unwrap();
+    assert_eq!(reply.to_string(), "1");
+    let script = "return call('DEL', KEYS[0])";
+    let err = client.eval(script, &["cache:a"], &[]).await.unwrap_err();
+    assert!(err.to_string().contains("NOPERM"));
+    assert_eq!(call(&mut conn, "GET cache:a").await, ["1"]);
+
+    // Changes apply to connections already authenticated
+    call(&mut admin, "ACL SETUSER cache allkeys").await;
+    assert_eq!(call(&mut conn, "GET users").await, ["(nil)"]);
+    call(&mut admin, "ACL SETUSER cache off").await;
+    assert!(call(&mut connect(addr).await, "AUTH cache pw").await[0].contains("WRONGPASS"));
+}
+
+#[tokio::test]
+async fn deleted_users_are_disconnected() {
+    let addr = start_server().await;
+    let mut admin = connect(addr).await;
+    let mut conn = connect(addr).await;
+
+    call(&mut admin, "ACL SETUSER alice on >pw allcommands allkeys").await;
+    assert_eq!(call(&mut admin, "ACL USERS").await, ["alice", "default"]);
+    let list = call(&mut admin, "ACL LIST").await;
+    assert_eq!(list[1], "user default on nopass ~* +@all");
+    assert!(list[0].starts_with("user alice on #"));
+    assert!(!list[0].contains("pw "));
+
+    call(&mut conn, "AUTH alice pw").await;
+    let clients = call(&mut admin, "CLIENT LIST").await.remove(0);
+    assert!(clients.contains("user=alice"));
+
+    let reply = call(&mut admin, "ACL DELUSER default").await;
+    assert!(reply[0].contains("cannot be removed"));
+    assert_eq!(call(&mut admin, "ACL DELUSER alice nobody").await, ["1"]);
+    assert!(conn.read_frame().await.unwrap().is_none());
+    assert!(call(&mut admin, "ACL SETUSER bob +@nope").await[0].contains("Syntax error"));
+    assert_eq!(call(&mut admin, "ACL USERS").await, ["default"]);
+}
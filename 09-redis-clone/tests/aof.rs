@@ -0,0 +1,111 @@
+mod common;
+
+use std::{fs, time::Duration};
+
+use common::{call, connect, start_server_with, temp_dir};
+use redis_clone::{AppendFsync, Config};
+use tokio::time::sleep;
+
+fn aof_config() -> Config {
+    Config {
+        dir: temp_dir(),
+        appendonly: true,
+        appendfsync: AppendFsync::Always,
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn writes_are_replayed_after_restart() {
+    let config = aof_config();
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET name redis").await;
+    call(&mut conn, "SET name valkey").await;
+    call(&mut conn, "ZADD board 10 alice 5 bob").await;
+    call(&mut conn, "ZREM board bob").await;
+    call(&mut conn, "SET session token EX 60").await;
+    call(&mut conn, "SET flash gone").await;
+    call(&mut conn, "EXPIRE flash 0").await;
+    call(&mut conn, "SET old renamed").await;
+    call(&mut conn, "RENAME old new").await;
+    call(&mut conn, "SET a 1").await;
+    call(&mut conn, "DEL a").await;
+    // Failed writes are not logged
+    call(&mut conn, "ZADD name 1 x").await;
+    server.abort();
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET name").await, ["valkey"]);
+    assert_eq!(
+        call(&mut conn, "ZRANGE board 0 -1 WITHSCORES").await,
+        ["alice", "10"]
+    );
+    assert_eq!(call(&mut conn, "GET session").await, ["token"]);
+    assert_eq!(call(&mut conn, "GET flash").await, ["(nil)"]);
+    assert_eq!(call(&mut conn, "GET new").await, ["renamed"]);
+    assert_eq!(call(&mut conn, "EXISTS old a").await, ["0"]);
+}
+
+#[tokio::test]
+async fn snapshot_seeds_a_new_append_only_file() {
+    let mut config = aof_config();
+    config.appendonly = false;
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET name redis").await;
+    assert_eq!(call(&mut conn, "SAVE").await, ["OK"]);
+    server.abort();
+
+    config.appendonly = true;
+    let (addr, _server) = start_server_with(config.clone()).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET name").await, ["redis"]);
+    assert!(config.aof_path().exists());
+}
+
+#[tokio::test]
+async fn bgrewriteaof_compacts_the_log() {
+    let config = aof_config();
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let mut conn = connect(addr).await;
+    for i in 0..100 {
+        call(&mut conn, &format!("SET counter {}", i)).await;
+    }
+    call(&mut conn, "ZADD board 10 alice").await;
+    let before = fs::metadata(config.aof_path()).unwrap().len();
+
+    assert_eq!(
+        call(&mut conn, "BGREWRITEAOF").await,
+        ["Background append only file rewriting started"]
+    );
+    while fs::metadata(config.aof_path()).unwrap().len() >= before {
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    // Writes after the rewrite land in the new file
+    call(&mut conn, "SET after rewrite").await;
+    server.abort();
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET counter").await, ["99"]);
+    assert_eq!(call(&mut conn, "ZSCORE board alice").await, ["10"]);
+    assert_eq!(call(&mut conn, "GET after").await, ["rewrite"]);
+}
+
+#[tokio::test]
+async fn bgrewriteaof_requires_appendonly() {
+    let config = Config {
+        dir: temp_dir(),
+        ..Config::default()
+    };
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert!(call(&mut conn, "BGREWRITEAOF").await[0].contains("disabled"));
+}
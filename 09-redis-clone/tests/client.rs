@@ -0,0 +1,236 @@
+mod common;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::{start_server, start_server_with_shutdown, temp_dir};
+use mini_redis::Frame;
+use redis_clone::{
+    client::{Cmd, Message, Pool},
+    server, Client, Config,
+};
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn typed_commands() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    assert_eq!(client.get("name").await.unwrap(), None);
+    client.set("name", "redis").await.unwrap();
+    assert_eq!(client.get("name").await.unwrap(), Some("redis".into()));
+
+    client
+        .set_expires("flash", "gone", Duration::from_millis(20))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(client.get("flash").await.unwrap(), None);
+    assert!(client.expire("name", 100).await.unwrap());
+    assert!(!client.expire("missing", 100).await.unwrap());
+
+    let added = client
+        .zadd("board", [(10.0, "alice"), (5.0, "bob"), (7.5, "carol")])
+        .await
+        .unwrap();
+    assert_eq!(added, 3);
+    assert_eq!(client.zscore("board", "carol").await.unwrap(), Some(7.5));
+    assert_eq!(client.zrank("board", "alice").await.unwrap(), Some(2));
+    assert_eq!(client.zrank("board", "dave").await.unwrap(), None);
+    assert_eq!(
+        client.zrange("board", 0, -1).await.unwrap(),
+        ["bob", "carol", "alice"]
+    );
+    assert_eq!(
+        client.zrange_with_scores("board", 0, 0).await.unwrap(),
+        [(Bytes::from("bob"), 5.0)]
+    );
+    assert_eq!(
+        client.zrange_by_score("board", "(5", "+inf").await.unwrap(),
+        ["carol", "alice"]
+    );
+    assert_eq!(client.zrem("board", ["bob", "dave"]).await.unwrap(), 1);
+
+    assert_eq!(client.key_type("board").await.unwrap(), "zset");
+    client.rename("board", "ranking").await.unwrap();
+    assert_eq!(client.exists(&["board", "ranking"]).await.unwrap(), 1);
+    assert_eq!(client.keys("r*").await.unwrap(), ["ranking"]);
+    let (cursor, mut keys) = client.scan(0, "*", 100).await.unwrap();
+    keys.sort();
+    assert_eq!((cursor, keys), (0, vec!["name".into(), "ranking".into()]));
+    assert_eq!(client.del(&["ranking", "nope"]).await.unwrap(), 1);
+    // The expired key counts until it is purged
+    assert!((1..=2).contains(&client.dbsize().await.unwrap()));
+
+    // Error replies become errors
+    let err = client.zadd("name", [(1.0, "x")]).await.unwrap_err();
+    assert!(err.to_string().starts_with("WRONGTYPE"));
+}
+
+#[tokio::test]
+async fn clones_share_a_pipelined_connection() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    let mut tasks = vec![];
+    for task in 0..50 {
+        let client = client.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..20 {
+                let key = format!("key:{}:{}", task, i);
+                client.set(&key, i as u64).await.unwrap();
+                assert_eq!(client.get(&key).await.unwrap(), Some(i.to_string().into()));
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn pipelines_and_transactions() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    let mut pipeline = client.pipeline();
+    pipeline
+        .add(Cmd::new("SET").arg("a").arg(1u64))
+        .add(Cmd::new("GET").arg("a"))
+        .add(Cmd::new("ZADD").arg("a").arg(1.0).arg("x"));
+    let replies = pipeline.execute().await.unwrap();
+    assert_eq!(replies.len(), 3);
+    assert!(matches!(&replies[1], Frame::Bulk(value) if value == "1"));
+    assert!(matches!(&replies[2], Frame::Error(err) if err.starts_with("WRONGTYPE")));
+
+    let mut transaction = client.pipeline();
+    transaction
+        .atomic()
+        .add(Cmd::new("SET").arg("b").arg("2"))
+        .add(Cmd::new("GET").arg("b"));
+    let replies = transaction.execute().await.unwrap();
+    assert!(matches!(&replies[1], Frame::Bulk(value) if value == "2"));
+
+    let mut aborted = client.pipeline();
+    aborted.atomic().add(Cmd::new("SET").arg("c"));
+    assert!(aborted.execute().await.is_err());
+    assert_eq!(client.get("c").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn watched_transactions() {
+    let client = Client::connect(start_server().await).await.unwrap();
+    let other = Client::connect(client.addr()).await.unwrap();
+
+    // An empty transaction is not an aborted one
+    assert_eq!(client.pipeline().exec().await.unwrap().unwrap().len(), 0);
+
+    client.watch(&["balance"]).await.unwrap();
+    other.set("balance", 100u64).await.unwrap();
+    let mut transaction = client.pipeline();
+    transaction.add(Cmd::new("SET").arg("balance").arg(50u64));
+    assert!(transaction.exec().await.unwrap().is_none());
+    assert_eq!(client.get("balance").await.unwrap(), Some("100".into()));
+
+    // EXEC dropped the watch
+    other.set("balance", 200u64).await.unwrap();
+    assert_eq!(transaction.exec().await.unwrap().unwrap().len(), 1);
+
+    client.watch(&["balance"]).await.unwrap();
+    client.unwatch().await.unwrap();
+    other.set("balance", 300u64).await.unwrap();
+    assert!(transaction.exec().await.unwrap().is_some());
+    assert_eq!(client.get("balance").await.unwrap(), Some("50".into()));
+}
+
+#[tokio::test]
+async fn pipelines_bigger_than_the_socket_buffers() {
+    let client = Client::connect(start_server().await).await.unwrap();
+    let value = Bytes::from(vec![b'x'; 512 * 1024]);
+    client.set("big", value.clone()).await.unwrap();
+
+    // Megabytes both ways: the replies must be read while the commands are
+    // still being written
+    let mut pipeline = client.pipeline();
+    for i in 0..64u64 {
+        pipeline
+            .add(Cmd::new("SET").arg(format!("key:{}", i)).arg(value.clone()))
+            .add(Cmd::new("GET").arg("big"));
+    }
+    let replies = tokio::time::timeout(Duration::from_secs(10), pipeline.execute())
+        .await
+        .expect("the pipeline is stuck")
+        .unwrap();
+    assert_eq!(replies.len(), 128);
+    assert!(matches!(&replies[127], Frame::Bulk(reply) if *reply == value));
+}
+
+#[tokio::test]
+async fn pool_hands_out_working_clients() {
+    let pool = Pool::connect(start_server().await, 4).await.unwrap();
+    assert_eq!(pool.size(), 4);
+
+    let mut tasks = vec![];
+    for i in 0..16u64 {
+        let client = pool.get();
+        tasks.push(tokio::spawn(async move {
+            client.set(&format!("key:{}", i), i).await.unwrap();
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    for i in 0..16u64 {
+        let value = pool.get().get(&format!("key:{}", i)).await.unwrap();
+        assert_eq!(value, Some(i.to_string().into()));
+    }
+}
+
+#[tokio::test]
+async fn reconnects_after_server_restart() {
+    let config = Config {
+        dir: temp_dir(),
+        appendonly: true,
+        ..Config::default()
+    };
+    let (addr, shutdown, server) = start_server_with_shutdown(config.clone()).await;
+    let client = Client::connect(addr).await.unwrap();
+    client.set("name", "redis").await.unwrap();
+
+    shutdown.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    // Same address, and the data is back from the append-only file
+    let listener = TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(server::run(listener, config, std::future::pending::<()>()));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(client.get("name").await.unwrap(), Some("redis".into()));
+}
+
+#[tokio::test]
+async fn subscribers_receive_messages() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    let mut subscriber = client.subscribe(&["news"]).await.unwrap();
+    subscriber.psubscribe(&["sport.*"]).await.unwrap();
+    assert_eq!(subscriber.channels(), ["news"]);
+    assert_eq!(subscriber.patterns(), ["sport.*"]);
+
+    assert_eq!(client.publish("news", "hello").await.unwrap(), 1);
+    assert_eq!(client.publish("sport.tennis", "ace").await.unwrap(), 1);
+
+    assert_eq!(
+        subscriber.next_message().await.unwrap(),
+        Some(Message {
+            channel: "news".into(),
+            pattern: None,
+            content: "hello".into(),
+        })
+    );
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!(message.pattern.as_deref(), Some("sport.*"));
+    assert_eq!(message.channel, "sport.tennis");
+
+    subscriber.unsubscribe(&[]).await.unwrap();
+    assert!(subscriber.channels().is_empty());
+    assert_eq!(client.publish("news", "again").await.unwrap(), 0);
+}
@@ -0,0 +1,104 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use mini_redis::Frame;
+use redis_clone::{server, Config, Connection};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    task::JoinHandle,
+};
+
+/// Create an empty directory for the files of one test.
+///
+/// It lives under the target directory and is named after the test binary,
+/// so each run reuses the directories of the last one instead of leaving
+/// more behind.
+pub fn temp_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let name = format!(
+        "{}-{}",
+        env!("CARGO_CRATE_NAME"),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    );
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Start a server on a random local port, with persistence files in a temp dir
+pub async fn start_server() -> SocketAddr {
+    let config = Config {
+        dir: temp_dir(),
+        ..Config::default()
+    };
+    start_server_with(config).await.0
+}
+
+/// Start a server with `config`. Abort the handle to stop accepting clients.
+pub async fn start_server_with(
+    config: Config,
+) -> (SocketAddr, JoinHandle<redis_clone::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = tokio::spawn(server::run(listener, config, std::future::pending::<()>()));
+    (addr, handle)
+}
+
+/// Start a server with `config` that shuts down gracefully when the returned
+/// sender is used (or dropped).
+pub async fn start_server_with_shutdown(
+    config: Config,
+) -> (
+    SocketAddr,
+    oneshot::Sender<()>,
+    JoinHandle<redis_clone::Result<()>>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = oneshot::channel();
+    let handle = tokio::spawn(server::run(listener, config, rx));
+    (addr, tx, handle)
+}
+
+pub async fn connect(addr: SocketAddr) -> Connection {
+    Connection::new(TcpStream::connect(addr).await.unwrap())
+}
+
+/// Send a command given as a space separated line
+pub async fn send(conn: &mut Connection, line: &str) {
+    let frame = Frame::Array(
+        line.split_whitespace()
+            .map(|arg| Frame::Bulk(arg.to_string().into()))
+            .collect(),
+    );
+    conn.write_frame(&frame).await.unwrap();
+}
+
+/// Read a reply, flattened into strings
+pub async fn recv(conn: &mut Connection) -> Vec<String> {
+    fn flatten(frame: Frame, out: &mut Vec<String>) {
+        match frame {
+            Frame::Array(parts) => parts.into_iter().for_each(|part| flatten(part, out)),
+            frame => out.push(frame.to_string()),
+        }
+    }
+
+    let mut out = vec![];
+    flatten(conn.read_frame().await.unwrap().unwrap(), &mut out);
+    out
+}
+
+/// Send a command and read its reply
+pub async fn call(conn: &mut Connection, line: &str) -> Vec<String> {
+    send(conn, line).await;
+    recv(conn).await
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{call, connect, start_server};
+
+/// Value of `field` in an INFO reply
+fn field(info: &str, field: &str) -> Option<String> {
+    info.lines()
+        .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+        .map(str::to_string)
+}
+
+#[tokio::test]
+async fn info_reports_every_section() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET a 1").await;
+    call(&mut conn, "SET b 2 EX 100").await;
+
+    let info = call(&mut conn, "INFO").await.remove(0);
+    for title in ["# Server", "# Clients", "# Memory", "# Stats", "# Keyspace"] {
+        assert!(info.contains(title), "missing {} in {}", title, info);
+    }
+    assert_eq!(field(&info, "connected_clients").unwrap(), "1");
+    assert_eq!(field(&info, "db0").unwrap(), "keys=2,expires=1");
+    assert!(field(&info, "used_memory").unwrap().parse::<u64>().unwrap() > 0);
+
+    // Only the requested sections
+    let info = call(&mut conn, "INFO keyspace").await.remove(0);
+    assert!(info.starts_with("# Keyspace"));
+    assert!(!info.contains("# Server"));
+}
+
+#[tokio::test]
+async fn stats_count_commands_and_traffic() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    let before = call(&mut conn, "INFO stats").await.remove(0);
+    call(&mut conn, "SET key value").await;
+    call(&mut conn, "GET key").await;
+    let after = call(&mut conn, "INFO stats").await.remove(0);
+
+    let count = |info: &str, name| field(info, name).unwrap().parse::<u64>().unwrap();
+    assert_eq!(
+        count(&after, "total_commands_processed") - count(&before, "total_commands_processed"),
+        3
+    );
+    assert!(count(&after, "total_net_input_bytes") > count(&before, "total_net_input_bytes"));
+    assert!(count(&after, "total_net_output_bytes") > count(&before, "total_net_output_bytes"));
+    assert_eq!(count(&after, "total_connections_received"), 1);
+}
+
+#[tokio::test]
+async fn client_list_shows_names_and_state() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+    let mut other = connect(addr).await;
+
+    assert_eq!(call(&mut conn, "CLIENT GETNAME").await, ["(nil)"]);
+    assert_eq!(call(&mut conn, "CLIENT SETNAME worker-1").await, ["OK"]);
+    assert_eq!(call(&mut conn, "CLIENT GETNAME").await, ["worker-1"]);
+    assert!(call(&mut conn, "CLIENT SETNAME").await[0].contains("wrong number of arguments"));
+    call(&mut other, "SUBSCRIBE news").await;
+
+    let list = call(&mut conn, "CLIENT LIST").await.remove(0);
+    let lines: Vec<_> = list.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains("name=worker-1"));
+    assert!(lines[0].contains("cmd=client"));
+    assert!(lines[1].contains("flags=P sub=1"));
+}
+
+#[tokio::test]
+async fn killed_clients_are_disconnected() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+    let mut victim = connect(addr).await;
+    let mut subscriber = connect(addr).await;
+
+    let id = call(&mut victim, "CLIENT ID").await.remove(0);
+    call(&mut subscriber, "SUBSCRIBE news").await;
+
+    assert_eq!(
+        call(&mut conn, &format!("CLIENT KILL ID {}", id)).await,
+        ["1"]
+    );
+    assert!(victim.read_frame().await.unwrap().is_none());
+
+    // The old form takes the address, which CLIENT LIST reports
+    let list = call(&mut conn, "CLIENT LIST").await.remove(0);
+    let line = list.lines().find(|line| line.contains("flags=P")).unwrap();
+    let addr = line
+        .split(' ')
+        .find_map(|part| part.strip_prefix("addr="))
+        .unwrap();
+    assert_eq!(
+        call(&mut conn, &format!("CLIENT KILL {}", addr)).await,
+        ["OK"]
+    );
+    assert!(subscriber.read_frame().await.unwrap().is_none());
+    assert!(call(&mut conn, &format!("CLIENT KILL {}", addr)).await[0].contains("No such client"));
+
+    // The killer itself is skipped by default
+    assert_eq!(call(&mut conn, "CLIENT KILL SKIPME yes").await, ["0"]);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let info = call(&mut conn, "INFO clients").await.remove(0);
+    assert_eq!(field(&info, "connected_clients").unwrap(), "1");
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::{call, connect, start_server};
+use mini_redis::Frame;
+use redis_clone::Connection;
+
+/// Run SCAN from `cursor` and return the next cursor and the keys
+async fn scan(conn: &mut Connection, cursor: u64, options: &str) -> (u64, Vec<String>) {
+    let reply = call(conn, &format!("SCAN {} {}", cursor, options)).await;
+    (reply[0].parse().unwrap(), reply[1..].to_vec())
+}
+
+#[tokio::test]
+async fn keys_span_every_shard() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    for i in 0..50 {
+        call(&mut conn, &format!("SET user:{} x", i)).await;
+    }
+    call(&mut conn, "ZADD board 1 alice").await;
+
+    assert_eq!(call(&mut conn, "DBSIZE").await, ["51"]);
+    let mut keys = call(&mut conn, "KEYS user:?").await;
+    keys.sort();
+    assert_eq!(keys.len(), 10);
+    assert_eq!(keys[0], "user:0");
+    assert_eq!(call(&mut conn, "KEYS user:[1-2]0").await.len(), 2);
+    assert_eq!(call(&mut conn, "KEYS *").await.len(), 51);
+
+    assert_eq!(
+        call(&mut conn, "EXISTS user:1 user:2 board nope").await,
+        ["3"]
+    );
+    assert_eq!(call(&mut conn, "DEL user:1 user:2 nope").await, ["2"]);
+    assert_eq!(call(&mut conn, "DBSIZE").await, ["49"]);
+
+    // The new name is likely in another shard
+    assert_eq!(call(&mut conn, "RENAME board ranking").await, ["OK"]);
+    assert_eq!(call(&mut conn, "TYPE ranking").await, ["zset"]);
+    assert_eq!(call(&mut conn, "TYPE board").await, ["none"]);
+    assert!(call(&mut conn, "RENAME board ranking").await[0].contains("no such key"));
+}
+
+#[tokio::test]
+async fn scan_returns_keys_present_for_the_whole_iteration() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+    let mut writer = connect(addr).await;
+
+    for i in 0..500 {
+        call(&mut conn, &format!("SET stable:{} x", i)).await;
+        call(&mut conn, &format!("SET doomed:{} x", i)).await;
+    }
+
+    let mut seen = vec![];
+    let mut cursor = 0;
+    let mut page = 0;
+    loop {
+        let (next, keys) = scan(&mut conn, cursor, "COUNT 25").await;
+        seen.extend(keys);
+
+        // Keys come and go between pages
+        call(&mut writer, &format!("DEL doomed:{}", page)).await;
+        call(&mut writer, &format!("SET new:{} x", page)).await;
+        page += 1;
+
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+
+    let stable: Vec<_> = seen
+        .iter()
+        .filter(|key| key.starts_with("stable:"))
+        .collect();
+    let unique: HashSet<_> = stable.iter().collect();
+    assert_eq!(stable.len(), 500);
+    assert_eq!(unique.len(), 500);
+}
+
+#[tokio::test]
+async fn scan_filters_by_pattern_and_type() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    for i in 0..100 {
+        call(&mut conn, &format!("SET user:{} x", i)).await;
+        call(&mut conn, &format!("ZADD board:{} 1 x", i)).await;
+    }
+
+    let mut users = HashSet::new();
+    let mut boards = HashSet::new();
+    let mut cursor = 0;
+    loop {
+        let (next, keys) = scan(&mut conn, cursor, "MATCH user:1* COUNT 30").await;
+        users.extend(keys);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    cursor = 0;
+    loop {
+        let (next, keys) = scan(&mut conn, cursor, "TYPE zset").await;
+        boards.extend(keys);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+
+    // user:1 and user:10 to user:19
+    assert_eq!(users.len(), 11);
+    assert_eq!(boards.len(), 100);
+    assert!(boards.iter().all(|key| key.starts_with("board:")));
+}
+
+#[tokio::test]
+async fn scan_reply_shape() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET key value").await;
+
+    common::send(&mut conn, "SCAN 0").await;
+    let Some(Frame::Array(parts)) = conn.read_frame().await.unwrap() else {
+        panic!("SCAN must reply with an array");
+    };
+    assert!(
+        matches!(&parts[..], [Frame::Bulk(cursor), Frame::Array(keys)]
+        if &cursor[..] == b"0" && keys.len() == 1)
+    );
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{call, connect, start_server, start_server_with, temp_dir};
+use redis_clone::{AppendFsync, Client, Config};
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn push_and_pop() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    assert_eq!(client.rpush("jobs", ["b", "c"]).await.unwrap(), 2);
+    assert_eq!(client.lpush("jobs", ["a"]).await.unwrap(), 3);
+    assert_eq!(client.lrange("jobs", 0, -1).await.unwrap(), ["a", "b", "c"]);
+    assert_eq!(client.lpop("jobs").await.unwrap(), Some("a".into()));
+    assert_eq!(client.rpop("jobs").await.unwrap(), Some("c".into()));
+    assert_eq!(client.llen("jobs").await.unwrap(), 1);
+
+    // An element is there already: no waiting
+    let popped = client.blpop(&["none", "jobs"], Duration::ZERO).await;
+    assert_eq!(popped.unwrap(), Some(("jobs".into(), "b".into())));
+    assert_eq!(client.exists(&["jobs"]).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn blpop_waits_for_a_push() {
+    let addr = start_server().await;
+
+    let waiting = tokio::spawn(async move {
+        let client = Client::connect(addr).await.unwrap();
+        client.blpop(&["a", "b"], Duration::ZERO).await.unwrap()
+    });
+    sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+
+    let client = Client::connect(addr).await.unwrap();
+    client.rpush("b", ["job"]).await.unwrap();
+    assert_eq!(waiting.await.unwrap(), Some(("b".into(), "job".into())));
+    assert_eq!(client.llen("b").await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn blpop_times_out() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    let start = Instant::now();
+    let popped = client.brpop(&["jobs"], Duration::from_millis(100)).await;
+    assert_eq!(popped.unwrap(), None);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // A push after the timeout stays in the list
+    client.rpush("jobs", ["late"]).await.unwrap();
+    assert_eq!(client.llen("jobs").await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn waiters_are_served_in_arrival_order() {
+    let addr = start_server().await;
+
+    let mut workers = vec![];
+    for id in 0..4 {
+        workers.push(tokio::spawn(async move {
+            let client = Client::connect(addr).await.unwrap();
+            let (_, job) = client
+                .blpop(&["jobs"], Duration::ZERO)
+                .await
+                .unwrap()
+                .unwrap();
+            (id, job)
+        }));
+        // Let each worker block before the next one
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let client = Client::connect(addr).await.unwrap();
+    // One element at a time, then several at once
+    client.rpush("jobs", ["job0"]).await.unwrap();
+    client.rpush("jobs", ["job1"]).await.unwrap();
+    client.rpush("jobs", ["job2", "job3"]).await.unwrap();
+
+    for (id, worker) in workers.into_iter().enumerate() {
+        let (worker_id, job) = worker.await.unwrap();
+        assert_eq!(worker_id, id);
+        assert_eq!(job, format!("job{}", id));
+    }
+}
+
+#[tokio::test]
+async fn blocking_pops_are_replayed_as_pops() {
+    let config = Config {
+        dir: temp_dir(),
+        appendonly: true,
+        appendfsync: AppendFsync::Always,
+        ..Config::default()
+    };
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let waiting = tokio::spawn(async move {
+        let client = Client::connect(addr).await.unwrap();
+        client.brpop(&["jobs"], Duration::ZERO).await.unwrap()
+    });
+    sleep(Duration::from_millis(50)).await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "LPUSH jobs a").await;
+    assert_eq!(waiting.await.unwrap(), Some(("jobs".into(), "a".into())));
+    call(&mut conn, "LPUSH jobs b c").await;
+    assert_eq!(call(&mut conn, "BRPOP jobs 0").await, ["jobs", "b"]);
+    // Timeouts are not logged
+    assert_eq!(call(&mut conn, "BLPOP none 0.01").await, ["(nil)"]);
+    server.abort();
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "LRANGE jobs 0 -1").await, ["c"]);
+}
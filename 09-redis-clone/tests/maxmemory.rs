@@ -0,0 +1,145 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use common::{call, connect, start_server_with, temp_dir};
+use redis_clone::{Config, Connection, EvictionPolicy};
+
+const MAXMEMORY: usize = 20_000;
+
+/// A value large enough for a few hundred keys to exceed `MAXMEMORY`
+fn value() -> String {
+    "x".repeat(100)
+}
+
+async fn start(policy: EvictionPolicy) -> SocketAddr {
+    let config = Config {
+        dir: temp_dir(),
+        maxmemory: MAXMEMORY,
+        maxmemory_policy: policy,
+        ..Config::default()
+    };
+    start_server_with(config).await.0
+}
+
+/// Value of `field` in an INFO reply
+async fn info_field(conn: &mut Connection, field: &str) -> usize {
+    let info = call(conn, "INFO").await.remove(0);
+    info.lines()
+        .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+async fn exists(conn: &mut Connection, key: &str) -> bool {
+    call(conn, &format!("GET {}", key)).await != ["(nil)"]
+}
+
+#[tokio::test]
+async fn noeviction_rejects_writes_over_the_limit() {
+    let addr = start(EvictionPolicy::NoEviction).await;
+    let mut conn = connect(addr).await;
+
+    let mut written = 0;
+    for i in 0..1000 {
+        let reply = call(&mut conn, &format!("SET key:{} {}", i, value())).await;
+        if reply[0].contains("OOM") {
+            break;
+        }
+        written += 1;
+    }
+    assert!(written > 10 && written < 1000, "{} keys written", written);
+    assert!(call(&mut conn, "ZADD board 1 alice").await[0].contains("OOM"));
+
+    // Reads and deletions still work, and free room for new writes
+    assert_eq!(call(&mut conn, "GET key:0").await, [value()]);
+    assert_eq!(call(&mut conn, "DEL key:0 key:1 key:2").await, ["3"]);
+    assert_eq!(call(&mut conn, "SET key:0 small").await, ["OK"]);
+    assert_eq!(info_field(&mut conn, "evicted_keys").await, 0);
+}
+
+#[tokio::test]
+async fn allkeys_lru_keeps_recently_used_keys() {
+    let addr = start(EvictionPolicy::AllKeysLru).await;
+    let mut conn = connect(addr).await;
+
+    call(&mut conn, &format!("SET hot {}", value())).await;
+    for i in 0..500 {
+        assert_eq!(
+            call(&mut conn, &format!("SET key:{} {}", i, value())).await,
+            ["OK"]
+        );
+        call(&mut conn, "GET hot").await;
+    }
+
+    assert!(exists(&mut conn, "hot").await);
+    assert!(!exists(&mut conn, "key:0").await);
+    assert!(exists(&mut conn, "key:499").await);
+    // At most the last write goes over the limit
+    assert!(info_field(&mut conn, "used_memory").await < MAXMEMORY + 500);
+    assert!(info_field(&mut conn, "evicted_keys").await > 0);
+}
+
+#[tokio::test]
+async fn volatile_lru_only_evicts_keys_with_a_ttl() {
+    let addr = start(EvictionPolicy::VolatileLru).await;
+    let mut conn = connect(addr).await;
+
+    for i in 0..20 {
+        call(&mut conn, &format!("SET kept:{} {}", i, value())).await;
+    }
+    for i in 0..200 {
+        let reply = call(&mut conn, &format!("SET session:{} {} EX 100", i, value())).await;
+        assert_eq!(reply, ["OK"]);
+    }
+    for i in 0..20 {
+        assert!(exists(&mut conn, &format!("kept:{}", i)).await);
+    }
+
+    // Once no key has a TTL left, writes fail
+    let mut failed = false;
+    for i in 0..1000 {
+        let reply = call(&mut conn, &format!("SET other:{} {}", i, value())).await;
+        if reply[0].contains("OOM") {
+            failed = true;
+            break;
+        }
+    }
+    assert!(failed);
+    assert!(exists(&mut conn, "kept:0").await);
+}
+
+#[tokio::test]
+async fn allkeys_lfu_keeps_frequently_used_keys() {
+    let addr = start(EvictionPolicy::AllKeysLfu).await;
+    let mut conn = connect(addr).await;
+
+    call(&mut conn, &format!("SET popular {}", value())).await;
+    for _ in 0..200 {
+        call(&mut conn, "GET popular").await;
+    }
+    for i in 0..500 {
+        call(&mut conn, &format!("SET key:{} {}", i, value())).await;
+    }
+
+    assert!(exists(&mut conn, "popular").await);
+    assert!(info_field(&mut conn, "used_memory").await < MAXMEMORY + 500);
+}
+
+#[tokio::test]
+async fn allkeys_random_stays_under_the_limit() {
+    let addr = start(EvictionPolicy::AllKeysRandom).await;
+    let mut conn = connect(addr).await;
+
+    for i in 0..500 {
+        let reply = call(&mut conn, &format!("ZADD board:{} 1 {}", i, value())).await;
+        assert_eq!(reply, ["1"]);
+    }
+
+    assert!(info_field(&mut conn, "used_memory").await < MAXMEMORY + 500);
+    let evicted = info_field(&mut conn, "evicted_keys").await;
+    assert!(evicted > 400, "{} keys evicted", evicted);
+    let info = call(&mut conn, "INFO memory").await.remove(0);
+    assert!(info.contains("maxmemory_policy:allkeys-random"));
+}
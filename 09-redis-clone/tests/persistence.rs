@@ -0,0 +1,133 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{call, connect, start_server_with, temp_dir};
+use redis_clone::Config;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn save_survives_restart() {
+    let config = Config {
+        dir: temp_dir(),
+        ..Config::default()
+    };
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET name redis").await;
+    call(&mut conn, "ZADD board 10 alice 5 bob").await;
+    call(&mut conn, "SET session token EX 60").await;
+    call(&mut conn, "SET flash gone PX 50").await;
+    assert_eq!(call(&mut conn, "SAVE").await, ["OK"]);
+    server.abort();
+
+    // Let the short lived key expire while the server is down
+    sleep(Duration::from_millis(100)).await;
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET name").await, ["redis"]);
+    assert_eq!(
+        call(&mut conn, "ZRANGE board 0 -1 WITHSCORES").await,
+        ["bob", "5", "alice", "10"]
+    );
+    assert_eq!(call(&mut conn, "GET session").await, ["token"]);
+    assert_eq!(call(&mut conn, "GET flash").await, ["(nil)"]);
+}
+
+#[tokio::test]
+async fn bgsave_writes_snapshot_in_background() {
+    let config = Config {
+        dir: temp_dir(),
+        ..Config::default()
+    };
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET name redis").await;
+    assert_eq!(
+        call(&mut conn, "BGSAVE").await,
+        ["Background saving started"]
+    );
+
+    // Wait for the file to show up
+    while !config.snapshot_path().exists() {
+        sleep(Duration::from_millis(10)).await;
+    }
+    server.abort();
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET name").await, ["redis"]);
+}
+
+#[tokio::test]
+async fn periodic_snapshot_only_when_dirty() {
+    let config = Config {
+        dir: temp_dir(),
+        save_interval: Some(Duration::from_millis(50)),
+        ..Config::default()
+    };
+
+    let (addr, _server) = start_server_with(config.clone()).await;
+    sleep(Duration::from_millis(150)).await;
+    assert!(!config.snapshot_path().exists());
+
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET name redis").await;
+    sleep(Duration::from_millis(200)).await;
+    assert!(config.snapshot_path().exists());
+}
+
+#[tokio::test]
+async fn keys_expire() {
+    let (addr, _server) = start_server_with(Config {
+        dir: temp_dir(),
+        ..Config::default()
+    })
+    .await;
+    let mut conn = connect(addr).await;
+
+    call(&mut conn, "SET a 1 PX 30").await;
+    call(&mut conn, "SET b 2").await;
+    assert_eq!(call(&mut conn, "EXPIRE b 0").await, ["1"]);
+    assert_eq!(call(&mut conn, "EXPIRE missing 10").await, ["0"]);
+    assert_eq!(call(&mut conn, "GET a").await, ["1"]);
+    assert_eq!(call(&mut conn, "GET b").await, ["(nil)"]);
+
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(call(&mut conn, "GET a").await, ["(nil)"]);
+}
+
+#[tokio::test]
+async fn set_rejects_expire_times_out_of_range() {
+    let (addr, _server) = start_server_with(Config {
+        dir: temp_dir(),
+        ..Config::default()
+    })
+    .await;
+    let mut conn = connect(addr).await;
+
+    for option in [
+        "EX 9223372036854775807",
+        "EXAT 9223372036854775807",
+        "PX 9223372036854775807",
+        "EX 0",
+    ] {
+        let reply = call(&mut conn, &format!("SET k v {}", option)).await;
+        assert_eq!(
+            reply,
+            ["error: ERR invalid expire time in 'set' command"],
+            "{}",
+            option
+        );
+    }
+    // The connection survived, and nothing was written
+    assert_eq!(call(&mut conn, "EXISTS k").await, ["0"]);
+    assert_eq!(
+        call(&mut conn, "SET k v PXAT 9223372036854775807").await,
+        ["OK"]
+    );
+    assert_eq!(call(&mut conn, "GET k").await, ["v"]);
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::{connect, recv, send, start_server};
+
+#[tokio::test]
+async fn publish_reaches_subscriber() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+    let mut publisher = connect(addr).await;
+
+    send(&mut subscriber, "SUBSCRIBE news weather").await;
+    assert_eq!(recv(&mut subscriber).await, ["subscribe", "news", "1"]);
+    assert_eq!(recv(&mut subscriber).await, ["subscribe", "weather", "2"]);
+
+    send(&mut publisher, "PUBLISH news hello").await;
+    assert_eq!(recv(&mut publisher).await, ["1"]);
+    assert_eq!(recv(&mut subscriber).await, ["message", "news", "hello"]);
+
+    send(&mut publisher, "PUBLISH sports ignored").await;
+    assert_eq!(recv(&mut publisher).await, ["0"]);
+}
+
+#[tokio::test]
+async fn pattern_subscription_matches_globs() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+    let mut publisher = connect(addr).await;
+
+    send(&mut subscriber, "PSUBSCRIBE news.* h?llo").await;
+    assert_eq!(recv(&mut subscriber).await, ["psubscribe", "news.*", "1"]);
+    assert_eq!(recv(&mut subscriber).await, ["psubscribe", "h?llo", "2"]);
+
+    send(&mut publisher, "PUBLISH news.tech rust").await;
+    assert_eq!(recv(&mut publisher).await, ["1"]);
+    assert_eq!(
+        recv(&mut subscriber).await,
+        ["pmessage", "news.*", "news.tech", "rust"]
+    );
+
+    send(&mut publisher, "PUBLISH hallo there").await;
+    assert_eq!(recv(&mut publisher).await, ["1"]);
+    assert_eq!(
+        recv(&mut subscriber).await,
+        ["pmessage", "h?llo", "hallo", "there"]
+    );
+
+    send(&mut publisher, "PUBLISH news nothing").await;
+    assert_eq!(recv(&mut publisher).await, ["0"]);
+}
+
+#[tokio::test]
+async fn unsubscribe_leaves_subscriber_mode() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+    let mut publisher = connect(addr).await;
+
+    send(&mut subscriber, "SUBSCRIBE news").await;
+    assert_eq!(recv(&mut subscriber).await, ["subscribe", "news", "1"]);
+
+    // Regular commands are rejected while subscribed
+    send(&mut subscriber, "GET key").await;
+    assert!(recv(&mut subscriber).await[0].contains("only (P)SUBSCRIBE"));
+
+    send(&mut subscriber, "UNSUBSCRIBE").await;
+    assert_eq!(recv(&mut subscriber).await, ["unsubscribe", "news", "0"]);
+
+    send(&mut publisher, "PUBLISH news hello").await;
+    assert_eq!(recv(&mut publisher).await, ["0"]);
+
+    // Back to normal mode
+    send(&mut subscriber, "SET key value").await;
+    assert_eq!(recv(&mut subscriber).await, ["OK"]);
+}
+
+#[tokio::test]
+async fn ping_is_answered_while_subscribed() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+
+    send(&mut subscriber, "SUBSCRIBE news").await;
+    assert_eq!(recv(&mut subscriber).await, ["subscribe", "news", "1"]);
+
+    send(&mut subscriber, "PING").await;
+    assert_eq!(recv(&mut subscriber).await, ["pong", ""]);
+    send(&mut subscriber, "PING hello").await;
+    assert_eq!(recv(&mut subscriber).await, ["pong", "hello"]);
+
+    send(&mut subscriber, "UNSUBSCRIBE").await;
+    assert_eq!(recv(&mut subscriber).await, ["unsubscribe", "news", "0"]);
+
+    send(&mut subscriber, "PING").await;
+    assert_eq!(recv(&mut subscriber).await, ["PONG"]);
+}
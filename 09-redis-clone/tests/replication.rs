@@ -0,0 +1,217 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use common::{call, connect, recv, send, start_server, start_server_with, temp_dir};
+use mini_redis::Frame;
+use redis_clone::{db::Keyspace, server, snapshot, Client, Config, Connection};
+use tokio::net::TcpListener;
+
+/// Call `line` until its reply is `expected`, for state that replicates
+/// asynchronously
+async fn eventually(conn: &mut Connection, line: &str, expected: &[&str]) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let reply = call(conn, line).await;
+        if reply == expected {
+            return;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "{}: got {:?}, expected {:?}",
+            line,
+            reply,
+            expected
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+async fn replicate(replica: &mut Connection, primary: SocketAddr) {
+    let line = format!("REPLICAOF {} {}", primary.ip(), primary.port());
+    assert_eq!(call(replica, &line).await, ["OK"]);
+}
+
+#[tokio::test]
+async fn replica_receives_keyspace_and_writes() {
+    let primary_addr = start_server().await;
+    let replica_addr = start_server().await;
+    let mut primary = connect(primary_addr).await;
+    let mut replica = connect(replica_addr).await;
+
+    // Present before the sync, and dropped from the replica by it
+    call(&mut primary, "SET before sync").await;
+    call(&mut primary, "ZADD board 1 alice 2 bob").await;
+    call(&mut replica, "SET stale value").await;
+
+    replicate(&mut replica, primary_addr).await;
+    eventually(&mut replica, "GET before", &["sync"]).await;
+    assert_eq!(call(&mut replica, "GET stale").await, ["(nil)"]);
+    assert_eq!(
+        call(&mut replica, "ZRANGE board 0 -1").await,
+        ["alice", "bob"]
+    );
+
+    // Writes after the sync are streamed
+    call(&mut primary, "SET after sync").await;
+    call(&mut primary, "SET session token EX 100").await;
+    call(&mut primary, "ZREM board alice").await;
+    eventually(&mut replica, "ZRANGE board 0 -1", &["bob"]).await;
+    assert_eq!(call(&mut replica, "GET after").await, ["sync"]);
+    assert_eq!(call(&mut replica, "GET session").await, ["token"]);
+
+    let info = call(&mut replica, "INFO replication").await.remove(0);
+    assert!(info.contains("role:slave"));
+    assert!(info.contains("master_link_status:up"));
+    let info = call(&mut primary, "INFO replication").await.remove(0);
+    assert!(info.contains("role:master"));
+    assert!(info.contains("connected_slaves:1"));
+}
+
+#[tokio::test]
+async fn transactions_and_scripts_are_replicated_as_blocks() {
+    let primary_addr = start_server().await;
+    let mut primary = connect(primary_addr).await;
+
+    // Stand in for a replica, to see what the primary streams
+    let mut replica = connect(primary_addr).await;
+    send(&mut replica, "SYNC").await;
+    recv(&mut replica).await;
+
+    call(&mut primary, "MULTI").await;
+    call(&mut primary, "SET a 1").await;
+    call(&mut primary, "GET a").await;
+    call(&mut primary, "SET b 2").await;
+    call(&mut primary, "EXEC").await;
+    let client = Client::connect(primary_addr).await.unwrap();
+    let script = "call('SET', KEYS[0], 3) call('SET', KEYS[1], 4)";
+    client.eval(script, &["c", "d"], &[]).await.unwrap();
+    // A single write needs no block
+    call(&mut primary, "SET e 5").await;
+
+    let mut stream = vec![];
+    while stream.last().map(String::as_str) != Some("SET e 5") {
+        stream.push(recv(&mut replica).await.join(" "));
+    }
+    assert_eq!(
+        stream,
+        ["MULTI", "SET a 1", "SET b 2", "EXEC", "MULTI", "SET c 3", "SET d 4", "EXEC", "SET e 5"]
+    );
+}
+
+#[tokio::test]
+async fn replica_applies_blocks_whole() {
+    // A primary sending a transaction in two parts
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let primary_addr = listener.local_addr().unwrap();
+    let replica_addr = start_server().await;
+    let mut replica = connect(replica_addr).await;
+    replicate(&mut replica, primary_addr).await;
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut primary = Connection::new(socket);
+    primary.read_frame().await.unwrap();
+    let empty = snapshot::encode([&Keyspace::default()]);
+    primary
+        .write_frame(&Frame::Bulk(empty.into()))
+        .await
+        .unwrap();
+    let write = |line: &str| {
+        let args = line
+            .split(' ')
+            .map(|arg| Frame::Bulk(arg.to_string().into()));
+        Frame::Array(args.collect())
+    };
+
+    for line in ["SET synced yes", "MULTI", "SET a 1"] {
+        primary.write_frame(&write(line)).await.unwrap();
+    }
+    eventually(&mut replica, "GET synced", &["yes"]).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(call(&mut replica, "GET a").await, ["(nil)"]);
+
+    for line in ["SET b 2", "EXEC"] {
+        primary.write_frame(&write(line)).await.unwrap();
+    }
+    eventually(&mut replica, "GET b", &["2"]).await;
+    assert_eq!(call(&mut replica, "GET a").await, ["1"]);
+}
+
+#[tokio::test]
+async fn replica_is_read_only_until_promoted() {
+    let primary_addr = start_server().await;
+    let replica_addr = start_server().await;
+    let mut primary = connect(primary_addr).await;
+    let mut replica = connect(replica_addr).await;
+
+    replicate(&mut replica, primary_addr).await;
+    assert!(call(&mut replica, "SET key value").await[0].contains("READONLY"));
+    assert!(call(&mut replica, "ZADD board 1 alice").await[0].contains("READONLY"));
+
+    // Writes queued in a transaction abort it
+    call(&mut replica, "MULTI").await;
+    assert!(call(&mut replica, "SET key value").await[0].contains("READONLY"));
+    assert!(call(&mut replica, "EXEC").await[0].contains("EXECABORT"));
+
+    call(&mut primary, "SET key primary").await;
+    eventually(&mut replica, "GET key", &["primary"]).await;
+
+    // Promoted: the data stays, writes are accepted and no longer replicated
+    assert_eq!(call(&mut replica, "REPLICAOF NO ONE").await, ["OK"]);
+    assert_eq!(call(&mut replica, "SET key replica").await, ["OK"]);
+    call(&mut primary, "SET other value").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(call(&mut replica, "GET other").await, ["(nil)"]);
+    assert_eq!(call(&mut replica, "GET key").await, ["replica"]);
+}
+
+#[tokio::test]
+async fn replica_retries_until_the_primary_is_up() {
+    let replica_addr = start_server().await;
+    let mut replica = connect(replica_addr).await;
+
+    // Nothing listens there yet: the replica keeps retrying
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let primary_addr = listener.local_addr().unwrap();
+    drop(listener);
+    replicate(&mut replica, primary_addr).await;
+    let info = call(&mut replica, "INFO replication").await.remove(0);
+    assert!(info.contains("master_link_status:down"));
+
+    let listener = TcpListener::bind(primary_addr).await.unwrap();
+    let config = Config {
+        dir: temp_dir(),
+        ..Config::default()
+    };
+    tokio::spawn(server::run(listener, config, std::future::pending::<()>()));
+    let mut primary = connect(primary_addr).await;
+    call(&mut primary, "SET key value").await;
+
+    eventually(&mut replica, "GET key", &["value"]).await;
+}
+
+#[tokio::test]
+async fn replica_authenticates_with_masterauth() {
+    let config = Config {
+        dir: temp_dir(),
+        requirepass: Some("secret".to_string()),
+        ..Config::default()
+    };
+    let (primary_addr, _) = start_server_with(config).await;
+    let config = Config {
+        dir: temp_dir(),
+        masterauth: Some("secret".to_string()),
+        ..Config::default()
+    };
+    let (replica_addr, _) = start_server_with(config).await;
+    let mut primary = connect(primary_addr).await;
+    let mut replica = connect(replica_addr).await;
+
+    call(&mut primary, "AUTH secret").await;
+    call(&mut primary, "SET name redis").await;
+    replicate(&mut replica, primary_addr).await;
+    eventually(&mut replica, "GET name", &["redis"]).await;
+}
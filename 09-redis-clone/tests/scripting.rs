@@ -0,0 +1,163 @@
+mod common;
+
+use common::{start_server, start_server_with, temp_dir};
+use mini_redis::Frame;
+use redis_clone::{client::Cmd, AppendFsync, Client, Config};
+
+/// A reply flattened into strings
+fn strings(frame: Frame) -> Vec<String> {
+    match frame {
+        Frame::Array(parts) => parts.into_iter().flat_map(strings).collect(),
+        frame => vec![frame.to_string()],
+    }
+}
+
+/// Read-modify-write increment, only safe because scripts are atomic
+const INCR: &str = "
+    let value = tonumber(call('GET', KEYS[0]) or '0') + tonumber(ARGV[0])
+    call('SET', KEYS[0], value)
+    return value
+";
+
+#[tokio::test]
+async fn eval_runs_commands_with_keys_and_args() {
+    let client = Client::connect(start_server().await).await.unwrap();
+    client.set("name", "redis").await.unwrap();
+
+    let reply = client
+        .eval(
+            "return [call('GET', KEYS[0]), ARGV[0], len(KEYS)]",
+            &["name"],
+            &["arg"],
+        )
+        .await
+        .unwrap();
+    assert_eq!(strings(reply), ["redis", "arg", "1"]);
+
+    let script = "
+        for i in 0..len(ARGV) { call('ZADD', KEYS[0], i, ARGV[i]) }
+        return call('ZRANGE', KEYS[0], 0, -1)
+    ";
+    let reply = client.eval(script, &["board"], &["a", "b", "c"]).await;
+    assert_eq!(strings(reply.unwrap()), ["a", "b", "c"]);
+    // Commands spanning shards work too
+    let reply = client.eval("return call('DBSIZE')", &[], &[]).await;
+    assert_eq!(strings(reply.unwrap()), ["2"]);
+
+    assert_eq!(
+        strings(client.eval(INCR, &["n"], &["5"]).await.unwrap()),
+        ["5"]
+    );
+    assert_eq!(client.get("n").await.unwrap(), Some("5".into()));
+}
+
+#[tokio::test]
+async fn scripts_are_atomic() {
+    let addr = start_server().await;
+
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            tokio::spawn(async move {
+                let client = Client::connect(addr).await.unwrap();
+                for _ in 0..25 {
+                    client.eval(INCR, &["counter"], &["1"]).await.unwrap();
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let client = Client::connect(addr).await.unwrap();
+    assert_eq!(client.get("counter").await.unwrap(), Some("200".into()));
+}
+
+#[tokio::test]
+async fn script_load_and_evalsha() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    let sha = client.script_load(INCR).await.unwrap();
+    assert_eq!(sha.len(), 40);
+    let reply = client.evalsha(&sha, &["n"], &["2"]).await.unwrap();
+    assert_eq!(strings(reply), ["2"]);
+    // Case does not matter
+    let reply = client.evalsha(&sha.to_uppercase(), &["n"], &["2"]).await;
+    assert_eq!(strings(reply.unwrap()), ["4"]);
+
+    let exists = client
+        .call(Cmd::new("SCRIPT").arg("EXISTS").arg(&sha).arg("nope"))
+        .await
+        .unwrap();
+    assert_eq!(strings(exists), ["1", "0"]);
+
+    // EVAL caches the script as well
+    client.eval("return 1", &[], &[]).await.unwrap();
+    let sha_of_eval = "e0e1f9fabfc9d4800c877a703b823ac0578ff8db";
+    assert_eq!(
+        strings(client.evalsha(sha_of_eval, &[], &[]).await.unwrap()),
+        ["1"]
+    );
+
+    client.call(Cmd::new("SCRIPT").arg("FLUSH")).await.unwrap();
+    let err = client.evalsha(&sha, &["n"], &["2"]).await.unwrap_err();
+    assert!(err.to_string().starts_with("NOSCRIPT"), "{}", err);
+}
+
+#[tokio::test]
+async fn script_errors() {
+    let config = Config {
+        dir: temp_dir(),
+        script_max_steps: 1000,
+        ..Config::default()
+    };
+    let client = Client::connect(start_server_with(config).await.0)
+        .await
+        .unwrap();
+    let error = |reply: redis_clone::Result<Frame>| reply.unwrap_err().to_string();
+
+    let err = error(client.eval("let x = (", &[], &[]).await);
+    assert!(err.contains("Error compiling script (line 1)"), "{}", err);
+    let err = error(client.eval("while true {}", &[], &[]).await);
+    assert!(err.contains("exceeded the limit of 1000 steps"), "{}", err);
+
+    // Only keyspace commands can be called
+    let err = error(client.eval("call('INFO')", &[], &[]).await);
+    assert!(err.contains("not allowed from scripts"), "{}", err);
+    let err = error(client.eval("call('EVAL', 'return 1', 0)", &[], &[]).await);
+    assert!(err.contains("not allowed from scripts"), "{}", err);
+
+    // Error replies of commands abort the script, which keeps its writes
+    client.set("name", "redis").await.unwrap();
+    let script = "call('SET', 'done', 1)\ncall('ZADD', KEYS[0], 1, 'x')\ncall('SET', 'never', 1)";
+    let err = error(client.eval(script, &["name"], &[]).await);
+    assert!(err.starts_with("WRONGTYPE"), "{}", err);
+    assert_eq!(client.exists(&["done", "never"]).await.unwrap(), 1);
+
+    let eval = |numkeys: i64| Cmd::new("EVAL").arg("return 1").arg(numkeys).arg("a");
+    let err = error(client.call(eval(2)).await);
+    assert!(err.contains("greater than number of args"), "{}", err);
+    let err = error(client.call(eval(-1)).await);
+    assert!(err.contains("can't be negative"), "{}", err);
+}
+
+#[tokio::test]
+async fn script_writes_are_logged() {
+    let config = Config {
+        dir: temp_dir(),
+        appendonly: true,
+        appendfsync: AppendFsync::Always,
+        ..Config::default()
+    };
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let client = Client::connect(addr).await.unwrap();
+    client.eval(INCR, &["n"], &["3"]).await.unwrap();
+    client.eval(INCR, &["n"], &["4"]).await.unwrap();
+    server.abort();
+
+    // Replaying the log does not need the script
+    let (addr, _server) = start_server_with(config).await;
+    let client = Client::connect(addr).await.unwrap();
+    assert_eq!(client.get("n").await.unwrap(), Some("7".into()));
+}
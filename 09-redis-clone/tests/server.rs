@@ -0,0 +1,80 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{call, connect, recv, start_server_with, start_server_with_shutdown, temp_dir};
+use redis_clone::{AppendFsync, Config};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn graceful_shutdown_closes_clients_and_saves() {
+    let config = Config {
+        dir: temp_dir(),
+        save_interval: Some(Duration::from_secs(3600)),
+        ..Config::default()
+    };
+
+    let (addr, shutdown, server) = start_server_with_shutdown(config.clone()).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "SET name redis").await, ["OK"]);
+
+    shutdown.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    // Idle clients are disconnected and the listener is closed
+    assert!(conn.read_frame().await.unwrap().is_none());
+    assert!(TcpStream::connect(addr).await.is_err());
+    assert!(config.snapshot_path().exists());
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET name").await, ["redis"]);
+}
+
+#[tokio::test]
+async fn shutdown_flushes_the_append_only_file() {
+    let config = Config {
+        dir: temp_dir(),
+        appendonly: true,
+        appendfsync: AppendFsync::No,
+        ..Config::default()
+    };
+
+    let (addr, shutdown, server) = start_server_with_shutdown(config.clone()).await;
+    let mut conn = connect(addr).await;
+    call(&mut conn, "SET name redis").await;
+    call(&mut conn, "SUBSCRIBE news").await;
+
+    shutdown.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    // Subscribers are disconnected too
+    assert!(conn.read_frame().await.unwrap().is_none());
+    assert!(!config.snapshot_path().exists());
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET name").await, ["redis"]);
+}
+
+#[tokio::test]
+async fn clients_over_maxclients_are_refused() {
+    let config = Config {
+        dir: temp_dir(),
+        maxclients: 1,
+        ..Config::default()
+    };
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut first = connect(addr).await;
+    assert_eq!(call(&mut first, "SET name redis").await, ["OK"]);
+
+    let mut second = connect(addr).await;
+    assert!(recv(&mut second).await[0].contains("max number of clients"));
+    assert!(second.read_frame().await.unwrap().is_none());
+
+    // The slot is given back when the first client leaves
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut third = connect(addr).await;
+    assert_eq!(call(&mut third, "GET name").await, ["redis"]);
+}
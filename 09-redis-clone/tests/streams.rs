@@ -0,0 +1,213 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{call, connect, recv, send, start_server, start_server_with, temp_dir};
+use redis_clone::{AppendFsync, Client, Config};
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn xadd_and_xrange() {
+    let client = Client::connect(start_server().await).await.unwrap();
+
+    let first = client
+        .xadd("events", "*", [("kind", "login")])
+        .await
+        .unwrap();
+    let second = client
+        .xadd("events", "*", [("kind", "logout")])
+        .await
+        .unwrap();
+    assert_ne!(first, second);
+    assert_eq!(client.xlen("events").await.unwrap(), 2);
+    assert_eq!(client.key_type("events").await.unwrap(), "stream");
+
+    let mut conn = connect(client.addr()).await;
+    assert_eq!(
+        call(&mut conn, "XRANGE events - +").await,
+        [&first[..], "kind", "login", &second[..], "kind", "logout"]
+    );
+    assert_eq!(
+        call(&mut conn, "XREVRANGE events + - COUNT 1").await,
+        [&second[..], "kind", "logout"]
+    );
+}
+
+#[tokio::test]
+async fn typed_stream_reads() {
+    let client = Client::connect(start_server().await).await.unwrap();
+    client.xgroup_create("jobs", "workers", "0").await.unwrap();
+    assert_eq!(
+        client.xpending("jobs", "workers").await.unwrap(),
+        (0, None, vec![])
+    );
+    let first = client.xadd("jobs", "*", [("task", "a")]).await.unwrap();
+    let second = client.xadd("jobs", "*", [("task", "b")]).await.unwrap();
+    let entry = |id: &str, task: &'static str| (id.to_string(), vec![("task".into(), task.into())]);
+
+    assert_eq!(
+        client.xrange("jobs", "-", "+", Some(1)).await.unwrap(),
+        [entry(&first, "a")]
+    );
+    assert_eq!(
+        client.xread(&[("jobs", &first)], None, None).await.unwrap(),
+        [("jobs".to_string(), vec![entry(&second, "b")])]
+    );
+    let timeout = Some(Duration::from_millis(20));
+    assert!(client
+        .xread(&[("jobs", "$")], None, timeout)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let read = client
+        .xreadgroup("workers", "alice", &[("jobs", ">")], Some(1), None)
+        .await
+        .unwrap();
+    assert_eq!(read, [("jobs".to_string(), vec![entry(&first, "a")])]);
+    assert_eq!(
+        client.xpending("jobs", "workers").await.unwrap(),
+        (
+            1,
+            Some((first.clone(), first.clone())),
+            vec![("alice".to_string(), 1)]
+        )
+    );
+    client.xack("jobs", "workers", &[&first]).await.unwrap();
+    let read = client
+        .xreadgroup("workers", "alice", &[("jobs", "0")], None, None)
+        .await
+        .unwrap();
+    assert_eq!(read, [("jobs".to_string(), vec![])]);
+}
+
+#[tokio::test]
+async fn xread_blocks_until_an_entry_is_added() {
+    let addr = start_server().await;
+    let mut reader = connect(addr).await;
+    call(&mut reader, "XADD events 1 kind old").await;
+
+    send(&mut reader, "XREAD BLOCK 0 STREAMS events $").await;
+    // Give the read time to block
+    sleep(Duration::from_millis(50)).await;
+    let writer = Client::connect(addr).await.unwrap();
+    writer
+        .xadd("other", "*", [("kind", "ignored")])
+        .await
+        .unwrap();
+    let id = writer.xadd("events", "*", [("kind", "new")]).await.unwrap();
+
+    assert_eq!(recv(&mut reader).await, ["events", &id[..], "kind", "new"]);
+}
+
+#[tokio::test]
+async fn xread_block_times_out() {
+    let mut conn = connect(start_server().await).await;
+
+    let start = Instant::now();
+    assert_eq!(
+        call(&mut conn, "XREAD BLOCK 100 STREAMS events 0").await,
+        ["(nil)"]
+    );
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    // The connection still works
+    assert_eq!(call(&mut conn, "XLEN events").await, ["0"]);
+}
+
+#[tokio::test]
+async fn consumer_groups_track_pending_entries() {
+    let addr = start_server().await;
+    let client = Client::connect(addr).await.unwrap();
+    client.xgroup_create("jobs", "workers", "$").await.unwrap();
+
+    // A blocked consumer gets the next entry, and only once
+    let mut alice = connect(addr).await;
+    send(
+        &mut alice,
+        "XREADGROUP GROUP workers alice BLOCK 0 STREAMS jobs >",
+    )
+    .await;
+    sleep(Duration::from_millis(50)).await;
+    let first = client.xadd("jobs", "*", [("task", "a")]).await.unwrap();
+    assert_eq!(recv(&mut alice).await, ["jobs", &first[..], "task", "a"]);
+
+    let second = client.xadd("jobs", "*", [("task", "b")]).await.unwrap();
+    let mut bob = connect(addr).await;
+    assert_eq!(
+        call(&mut bob, "XREADGROUP GROUP workers bob STREAMS jobs >").await,
+        ["jobs", &second[..], "task", "b"]
+    );
+    assert_eq!(
+        call(
+            &mut bob,
+            "XREADGROUP GROUP workers bob BLOCK 50 STREAMS jobs >"
+        )
+        .await,
+        ["(nil)"]
+    );
+
+    assert_eq!(
+        call(&mut bob, "XPENDING jobs workers").await,
+        ["2", &first[..], &second[..], "alice", "1", "bob", "1"]
+    );
+    assert_eq!(client.xack("jobs", "workers", &[&first]).await.unwrap(), 1);
+    assert_eq!(client.xack("jobs", "workers", &[&first]).await.unwrap(), 0);
+    assert_eq!(
+        call(&mut bob, "XPENDING jobs workers").await,
+        ["1", &second[..], &second[..], "bob", "1"]
+    );
+    // Bob's history after a restart of the consumer
+    assert_eq!(
+        call(&mut bob, "XREADGROUP GROUP workers bob STREAMS jobs 0").await,
+        ["jobs", &second[..], "task", "b"]
+    );
+
+    let err = client
+        .xgroup_create("jobs", "workers", "$")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("BUSYGROUP"), "{}", err);
+}
+
+#[tokio::test]
+async fn streams_are_replayed_from_the_log() {
+    let config = Config {
+        dir: temp_dir(),
+        appendonly: true,
+        appendfsync: AppendFsync::Always,
+        ..Config::default()
+    };
+
+    let (addr, server) = start_server_with(config.clone()).await;
+    let client = Client::connect(addr).await.unwrap();
+    let mut ids = vec![];
+    for task in ["a", "b", "c"] {
+        ids.push(client.xadd("jobs", "*", [("task", task)]).await.unwrap());
+    }
+    client.xgroup_create("jobs", "workers", "0").await.unwrap();
+    let mut conn = connect(addr).await;
+    call(
+        &mut conn,
+        "XREADGROUP GROUP workers alice COUNT 2 STREAMS jobs >",
+    )
+    .await;
+    client.xack("jobs", "workers", &[&ids[0]]).await.unwrap();
+    server.abort();
+
+    let (addr, _server) = start_server_with(config).await;
+    let mut conn = connect(addr).await;
+    // Same IDs, even those picked by the server
+    let reply = call(&mut conn, "XRANGE jobs - +").await;
+    assert_eq!(
+        reply.iter().step_by(3).collect::<Vec<_>>(),
+        ids.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        call(&mut conn, "XPENDING jobs workers").await,
+        ["1", &ids[1][..], &ids[1][..], "alice", "1"]
+    );
+    assert_eq!(
+        call(&mut conn, "XREADGROUP GROUP workers bob STREAMS jobs >").await,
+        ["jobs", &ids[2][..], "task", "c"]
+    );
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::{call, connect, start_server};
+
+#[tokio::test]
+async fn exec_runs_queued_commands() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    assert_eq!(call(&mut conn, "MULTI").await, ["OK"]);
+    assert_eq!(call(&mut conn, "SET name redis").await, ["QUEUED"]);
+    assert_eq!(call(&mut conn, "ZADD board 1 a 2 b").await, ["QUEUED"]);
+    assert_eq!(call(&mut conn, "GET name").await, ["QUEUED"]);
+    assert_eq!(call(&mut conn, "ZRANGE board 0 -1").await, ["QUEUED"]);
+
+    // Nested array: [OK, 2, redis, [a, b]]
+    assert_eq!(
+        call(&mut conn, "EXEC").await,
+        ["OK", "2", "redis", "a", "b"]
+    );
+}
+
+#[tokio::test]
+async fn discard_drops_queued_commands() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    call(&mut conn, "MULTI").await;
+    call(&mut conn, "SET name redis").await;
+    assert_eq!(call(&mut conn, "DISCARD").await, ["OK"]);
+    assert_eq!(call(&mut conn, "GET name").await, ["(nil)"]);
+    assert!(call(&mut conn, "EXEC").await[0].contains("EXEC without MULTI"));
+}
+
+#[tokio::test]
+async fn errors_while_queuing_abort_exec() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    call(&mut conn, "MULTI").await;
+    call(&mut conn, "SET name redis").await;
+    assert!(call(&mut conn, "SET name").await[0].contains("wrong number"));
+    assert!(call(&mut conn, "EXEC").await[0].contains("EXECABORT"));
+    assert_eq!(call(&mut conn, "GET name").await, ["(nil)"]);
+}
+
+#[tokio::test]
+async fn commands_exec_cannot_run_abort_exec() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    for line in [
+        "SAVE",
+        "BGSAVE",
+        "BGREWRITEAOF",
+        "REPLICAOF 127.0.0.1 1",
+        "SYNC",
+        "SUBSCRIBE news",
+    ] {
+        call(&mut conn, "MULTI").await;
+        call(&mut conn, "SET name redis").await;
+        let reply = call(&mut conn, line).await;
+        assert!(reply[0].contains("not allowed in this context"), "{}", line);
+        assert!(
+            call(&mut conn, "EXEC").await[0].contains("EXECABORT"),
+            "{}",
+            line
+        );
+    }
+    assert_eq!(call(&mut conn, "GET name").await, ["(nil)"]);
+}
+
+#[tokio::test]
+async fn unwatch_is_queued_and_replies_ok() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    call(&mut conn, "WATCH w").await;
+    call(&mut conn, "MULTI").await;
+    call(&mut conn, "SET a 1").await;
+    assert_eq!(call(&mut conn, "UNWATCH").await, ["QUEUED"]);
+    assert_eq!(call(&mut conn, "EXEC").await, ["OK", "OK"]);
+}
+
+#[tokio::test]
+async fn writes_that_change_nothing_do_not_abort_exec() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+
+    call(&mut conn, "WATCH w").await;
+    assert_eq!(call(&mut conn, "DEL w").await, ["0"]);
+    assert_eq!(call(&mut conn, "EXPIRE w 10").await, ["0"]);
+    assert_eq!(call(&mut conn, "ZREM w a").await, ["0"]);
+    call(&mut conn, "MULTI").await;
+    call(&mut conn, "SET a 1").await;
+    assert_eq!(call(&mut conn, "EXEC").await, ["OK"]);
+}
+
+#[tokio::test]
+async fn exec_aborts_when_watched_key_changes() {
+    let addr = start_server().await;
+    let mut conn = connect(addr).await;
+    let mut other = connect(addr).await;
+
+    call(&mut conn, "WATCH balance").await;
+    call(&mut other, "SET balance 100").await;
+
+    call(&mut conn, "MULTI").await;
+    call(&mut conn, "SET balance 50").await;
+    assert_eq!(call(&mut conn, "EXEC").await, ["(nil)"]);
+    assert_eq!(call(&mut conn, "GET balance").await, ["100"]);
+
+    // The watch ended with EXEC, so the next transaction goes through
+    call(&mut conn, "MULTI").await;
+    call(&mut conn, "SET balance 50").await;
+    assert_eq!(call(&mut conn, "EXEC").await, ["OK"]);
+}
+
+/// Increment a counter with the optimistic locking pattern, retrying on conflicts
+async fn increment(addr: std::net::SocketAddr, times: usize) {
+    let mut conn = connect(addr).await;
+
+    for _ in 0..times {
+        loop {
+            call(&mut conn, "WATCH counter").await;
+            let current = call(&mut conn, "GET counter").await[0]
+                .parse::<u64>()
+                .unwrap_or(0);
+
+            call(&mut conn, "MULTI").await;
+            call(&mut conn, &format!("SET counter {}", current + 1)).await;
+            if call(&mut conn, "EXEC").await != ["(nil)"] {
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writers_do_not_lose_updates() {
+    let addr = start_server().await;
+
+    let writers: Vec<_> = (0..8).map(|_| tokio::spawn(increment(addr, 25))).collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    let mut conn = connect(addr).await;
+    assert_eq!(call(&mut conn, "GET counter").await, ["200"]);
+}